@@ -0,0 +1,191 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crc32fast::Hasher;
+
+const LOCAL_HEADER_SIG: u32 = 0x0403_4b50;
+const CENTRAL_HEADER_SIG: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_SIG: u32 = 0x0605_4b50;
+const VERSION: u16 = 20;
+const UTF8_NAMES: u16 = 1 << 11;
+// 1980-01-01 00:00, the earliest DOS timestamp.
+const DOS_DATE: u16 = (1 << 5) | 1;
+const DOS_TIME: u16 = 0;
+
+/// A minimal, uncompressed ZIP writer. Wraps an iterator of `(filename,
+/// contents)` pairs and yields the archive one chunk per entry, followed by the
+/// central directory, so an export never has to be held in memory as a whole.
+pub struct ZipStream<I> {
+    entries: I,
+    offset: u32,
+    count: u16,
+    central: Vec<u8>,
+    finished: bool,
+}
+
+impl<I> ZipStream<I>
+where
+    I: Iterator<Item = (String, Vec<u8>)>,
+{
+    pub fn new(entries: I) -> Self {
+        ZipStream {
+            entries,
+            offset: 0,
+            count: 0,
+            central: Vec::new(),
+            finished: false,
+        }
+    }
+
+    fn write_entry(&mut self, name: &str, data: &[u8]) -> Vec<u8> {
+        let mut hasher = Hasher::new();
+        hasher.update(data);
+        let crc = hasher.finalize();
+        let size = data.len() as u32;
+        let name = name.as_bytes();
+
+        let mut chunk = Vec::with_capacity(30 + name.len() + data.len());
+        put_u32(&mut chunk, LOCAL_HEADER_SIG);
+        put_u16(&mut chunk, VERSION);
+        put_u16(&mut chunk, UTF8_NAMES);
+        put_u16(&mut chunk, 0); // stored, no compression
+        put_u16(&mut chunk, DOS_TIME);
+        put_u16(&mut chunk, DOS_DATE);
+        put_u32(&mut chunk, crc);
+        put_u32(&mut chunk, size);
+        put_u32(&mut chunk, size);
+        put_u16(&mut chunk, name.len() as u16);
+        put_u16(&mut chunk, 0);
+        chunk.extend_from_slice(name);
+        chunk.extend_from_slice(data);
+
+        let central = &mut self.central;
+        put_u32(central, CENTRAL_HEADER_SIG);
+        put_u16(central, VERSION);
+        put_u16(central, VERSION);
+        put_u16(central, UTF8_NAMES);
+        put_u16(central, 0);
+        put_u16(central, DOS_TIME);
+        put_u16(central, DOS_DATE);
+        put_u32(central, crc);
+        put_u32(central, size);
+        put_u32(central, size);
+        put_u16(central, name.len() as u16);
+        put_u16(central, 0); // extra field length
+        put_u16(central, 0); // comment length
+        put_u16(central, 0); // disk number
+        put_u16(central, 0); // internal attributes
+        put_u32(central, 0); // external attributes
+        put_u32(central, self.offset);
+        central.extend_from_slice(name);
+
+        self.offset += chunk.len() as u32;
+        self.count += 1;
+        chunk
+    }
+
+    fn write_end(&mut self) -> Vec<u8> {
+        let mut chunk = std::mem::take(&mut self.central);
+        let central_size = chunk.len() as u32;
+
+        put_u32(&mut chunk, END_OF_CENTRAL_SIG);
+        put_u16(&mut chunk, 0);
+        put_u16(&mut chunk, 0);
+        put_u16(&mut chunk, self.count);
+        put_u16(&mut chunk, self.count);
+        put_u32(&mut chunk, central_size);
+        put_u32(&mut chunk, self.offset);
+        put_u16(&mut chunk, 0);
+        chunk
+    }
+}
+
+impl<I> Iterator for ZipStream<I>
+where
+    I: Iterator<Item = (String, Vec<u8>)>,
+{
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        if self.finished {
+            return None;
+        }
+
+        match self.entries.next() {
+            Some((name, data)) => Some(self.write_entry(&name, &data)),
+            None => {
+                self.finished = true;
+                Some(self.write_end())
+            }
+        }
+    }
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u16(buf: &[u8], at: usize) -> u16 {
+        u16::from_le_bytes([buf[at], buf[at + 1]])
+    }
+
+    fn read_u32(buf: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]])
+    }
+
+    #[test]
+    fn test_zip_layout() {
+        let entries = vec![
+            ("a.md".to_string(), b"hello".to_vec()),
+            ("b.md".to_string(), b"world!".to_vec()),
+        ];
+        let chunks: Vec<Vec<u8>> = ZipStream::new(entries.into_iter()).collect();
+        assert_eq!(3, chunks.len());
+
+        let first = &chunks[0];
+        assert_eq!(LOCAL_HEADER_SIG, read_u32(first, 0));
+        assert_eq!(0x3610_a686, read_u32(first, 14)); // crc32("hello")
+        assert_eq!(5, read_u32(first, 18));
+        assert_eq!(b"a.md", &first[30..34]);
+        assert_eq!(b"hello", &first[34..]);
+
+        let archive: Vec<u8> = chunks.concat();
+        let end = archive.len() - 22;
+        assert_eq!(END_OF_CENTRAL_SIG, read_u32(&archive, end));
+        assert_eq!(2, read_u16(&archive, end + 10));
+
+        let central_offset = read_u32(&archive, end + 16) as usize;
+        assert_eq!(chunks[0].len() + chunks[1].len(), central_offset);
+        assert_eq!(CENTRAL_HEADER_SIG, read_u32(&archive, central_offset));
+    }
+
+    #[test]
+    fn test_empty_zip() {
+        let chunks: Vec<Vec<u8>> = ZipStream::new(Vec::new().into_iter()).collect();
+        assert_eq!(1, chunks.len());
+        assert_eq!(22, chunks[0].len());
+        assert_eq!(END_OF_CENTRAL_SIG, read_u32(&chunks[0], 0));
+    }
+}
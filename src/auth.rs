@@ -0,0 +1,261 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::ApiError;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header;
+use actix_web::{web, Error, HttpResponse};
+use futures::future::{ok, Either, Ready};
+use std::task::{Context, Poll};
+
+/// The key write requests must present as `Authorization: Bearer <key>`.
+pub struct ApiKey(String);
+
+impl ApiKey {
+    pub fn new(key: &str) -> Self {
+        ApiKey(key.to_string())
+    }
+
+    /// Read the key from `RECIPE_BOOK_API_KEY`. Returns `None` when it is
+    /// unset or blank, which leaves writes open.
+    pub fn from_env() -> Option<Self> {
+        std::env::var("RECIPE_BOOK_API_KEY")
+            .ok()
+            .map(|key| key.trim().to_string())
+            .filter(|key| !key.is_empty())
+            .map(ApiKey)
+    }
+
+    /// Compare in constant time so response timing does not reveal how much
+    /// of a guess was right.
+    fn matches(&self, candidate: &str) -> bool {
+        let expected = self.0.as_bytes();
+        let candidate = candidate.as_bytes();
+        expected.len() == candidate.len()
+            && expected
+                .iter()
+                .zip(candidate.iter())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+/// Middleware requiring the `ApiKey` found in app data. A request without a
+/// bearer token gets 401 and one with the wrong token gets 403. Requests
+/// pass through untouched when no key is registered.
+pub struct RequireApiKey;
+
+/// Like `RequireApiKey`, but for admin routes such as `/admin/backup`: with
+/// no key registered they are refused with 403 rather than left open, so a
+/// server started without `RECIPE_BOOK_API_KEY` never exposes them.
+pub struct RequireAdminKey;
+
+impl<S, B> Transform<S> for RequireApiKey
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequireApiKeyMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequireApiKeyMiddleware {
+            service,
+            open_without_key: true,
+        })
+    }
+}
+
+impl<S, B> Transform<S> for RequireAdminKey
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequireApiKeyMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequireApiKeyMiddleware {
+            service,
+            open_without_key: false,
+        })
+    }
+}
+
+pub struct RequireApiKeyMiddleware<S> {
+    service: S,
+    /// Whether requests pass when no `ApiKey` is registered.
+    open_without_key: bool,
+}
+
+impl<S, B> Service for RequireApiKeyMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let api_key = match req.app_data::<web::Data<ApiKey>>() {
+            Some(api_key) => api_key.clone(),
+            None if self.open_without_key => return Either::Left(self.service.call(req)),
+            None => {
+                let resp = HttpResponse::Forbidden().json(ApiError::new(
+                    "admin_disabled",
+                    "Admin endpoints are disabled until RECIPE_BOOK_API_KEY is set.",
+                ));
+                return Either::Right(ok(req.into_response(resp.into_body())));
+            }
+        };
+
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(|token| token.trim().to_string());
+
+        let resp = match token {
+            Some(token) if api_key.matches(&token) => {
+                return Either::Left(self.service.call(req));
+            }
+            Some(_) => HttpResponse::Forbidden()
+                .json(ApiError::new("forbidden", "The API key is not valid.")),
+            None => HttpResponse::Unauthorized()
+                .header(header::WWW_AUTHENTICATE, "Bearer")
+                .json(ApiError::new(
+                    "unauthorized",
+                    "Write requests need an Authorization: Bearer <key> header.",
+                )),
+        };
+        Either::Right(ok(req.into_response(resp.into_body())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+
+    #[test]
+    fn test_matches() {
+        let api_key = ApiKey::new("s3cret");
+        assert!(api_key.matches("s3cret"));
+        assert!(!api_key.matches("s3cre"));
+        assert!(!api_key.matches("s3creT"));
+    }
+
+    #[actix_rt::test]
+    async fn test_require_api_key() {
+        let mut app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(ApiKey::new("s3cret")))
+                .service(
+                    web::resource("/")
+                        .wrap(RequireApiKey)
+                        .route(web::post().to(|| HttpResponse::Ok().finish())),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::post().uri("/").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
+        let req = test::TestRequest::post()
+            .uri("/")
+            .header(header::AUTHORIZATION, "Bearer guess")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+
+        let req = test::TestRequest::post()
+            .uri("/")
+            .header(header::AUTHORIZATION, "Bearer s3cret")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_no_key_leaves_writes_open() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/")
+                    .wrap(RequireApiKey)
+                    .route(web::post().to(|| HttpResponse::Ok().finish())),
+            ),
+        )
+        .await;
+
+        let req = test::TestRequest::post().uri("/").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_require_admin_key() {
+        let route = || {
+            web::resource("/")
+                .wrap(RequireAdminKey)
+                .route(web::post().to(|| HttpResponse::Ok().finish()))
+        };
+
+        let mut app = test::init_service(App::new().service(route())).await;
+        let req = test::TestRequest::post().uri("/").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::FORBIDDEN, resp.status());
+        let body: ApiError = test::read_body_json(resp).await;
+        assert_eq!("admin_disabled", body.code);
+
+        let mut app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(ApiKey::new("s3cret")))
+                .service(route()),
+        )
+        .await;
+        let req = test::TestRequest::post().uri("/").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
+        let req = test::TestRequest::post()
+            .uri("/")
+            .header(header::AUTHORIZATION, "Bearer s3cret")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+}
@@ -0,0 +1,98 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use actix_web::web::Bytes;
+use std::sync::RwLock;
+
+/// Largest `/recipes/all` body kept in memory by default.
+pub const DEFAULT_MAX_BYTES: usize = 8 * 1024 * 1024;
+
+/// The serialized `/recipes/all` JSON, shared by every worker. Each body is
+/// stored with the collection `ETag` it was built for and only served to a
+/// request that computed the same tag, so a write made through any worker,
+/// or straight to the database, is never answered with the old body.
+/// Handlers also `invalidate` it after their own writes to free the memory
+/// early.
+pub struct RecipeCache {
+    max_bytes: usize,
+    entry: RwLock<Option<(String, Bytes)>>,
+}
+
+impl RecipeCache {
+    /// A cache that keeps bodies up to `max_bytes` long. Larger ones are
+    /// never stored.
+    pub fn new(max_bytes: usize) -> Self {
+        RecipeCache {
+            max_bytes,
+            entry: RwLock::new(None),
+        }
+    }
+
+    /// The body built for `etag`, if it is the one held.
+    pub fn get(&self, etag: &str) -> Option<Bytes> {
+        match &*self.entry.read().unwrap() {
+            Some((cached_etag, body)) if cached_etag == etag => Some(body.clone()),
+            _ => None,
+        }
+    }
+
+    /// Hold `body` as the response for `etag`, replacing any earlier one.
+    pub fn store(&self, etag: &str, body: Bytes) {
+        if body.len() > self.max_bytes {
+            return;
+        }
+        *self.entry.write().unwrap() = Some((etag.to_string(), body));
+    }
+
+    pub fn invalidate(&self) {
+        *self.entry.write().unwrap() = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_matches_etag() {
+        let cache = RecipeCache::new(DEFAULT_MAX_BYTES);
+        assert_eq!(None, cache.get("\"a\""));
+
+        cache.store("\"a\"", Bytes::from_static(b"[]"));
+        assert_eq!(Some(Bytes::from_static(b"[]")), cache.get("\"a\""));
+        assert_eq!(None, cache.get("\"b\""));
+
+        cache.store("\"b\"", Bytes::from_static(b"[{}]"));
+        assert_eq!(None, cache.get("\"a\""));
+        assert_eq!(Some(Bytes::from_static(b"[{}]")), cache.get("\"b\""));
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let cache = RecipeCache::new(DEFAULT_MAX_BYTES);
+        cache.store("\"a\"", Bytes::from_static(b"[]"));
+        cache.invalidate();
+        assert_eq!(None, cache.get("\"a\""));
+    }
+
+    #[test]
+    fn test_bodies_over_the_limit_are_not_stored() {
+        let cache = RecipeCache::new(2);
+        cache.store("\"a\"", Bytes::from_static(b"[{}]"));
+        assert_eq!(None, cache.get("\"a\""));
+    }
+}
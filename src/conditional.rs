@@ -0,0 +1,184 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::db::CollectionVersion;
+use actix_web::http::header;
+use actix_web::HttpRequest;
+use chrono::{DateTime, Utc};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// A strong entity tag for the recipe collection in the given state, as
+/// served in `media_type`. Each representation needs its own tag, since a
+/// strong tag promises byte-identical bodies.
+pub fn collection_etag(version: &CollectionVersion, media_type: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    version.count.hash(&mut hasher);
+    version.last_updated.hash(&mut hasher);
+    media_type.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// Whether the request's `If-None-Match` header lists `etag` or `*`, i.e.
+/// the client's cached copy is still current. Weak tags compare by value.
+pub fn not_modified(req: &HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get_all(header::IF_NONE_MATCH)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// The request's `If-Unmodified-Since` time. A header that is not a valid
+/// HTTP date is ignored, as RFC 7232 requires.
+pub fn unmodified_since(req: &HttpRequest) -> Option<DateTime<Utc>> {
+    date_header(req, header::IF_UNMODIFIED_SINCE)
+}
+
+/// Whether the request's `If-Modified-Since` time is no earlier than
+/// `last_modified`, i.e. the client's cached copy is still current. HTTP
+/// dates have whole seconds, so sub-second changes are not seen.
+pub fn not_modified_since(req: &HttpRequest, last_modified: DateTime<Utc>) -> bool {
+    date_header(req, header::IF_MODIFIED_SINCE)
+        .is_some_and(|since| last_modified.timestamp() <= since.timestamp())
+}
+
+/// `time` as an HTTP date for `Last-Modified`, e.g.
+/// "Sat, 01 May 2021 12:00:00 GMT".
+pub fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn date_header(req: &HttpRequest, name: header::HeaderName) -> Option<DateTime<Utc>> {
+    let value = req.headers().get(name)?.to_str().ok()?;
+    DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .map(|since| since.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn version(count: u32, last_updated: Option<&str>) -> CollectionVersion {
+        CollectionVersion {
+            count,
+            last_updated: last_updated.map(|s| s.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_collection_etag() {
+        let json = "application/json";
+        let etag = collection_etag(&version(2, Some("2021-03-01T10:00:00.000000Z")), json);
+
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+        assert_eq!(
+            etag,
+            collection_etag(&version(2, Some("2021-03-01T10:00:00.000000Z")), json)
+        );
+        assert_ne!(
+            etag,
+            collection_etag(&version(1, Some("2021-03-01T10:00:00.000000Z")), json)
+        );
+        assert_ne!(
+            etag,
+            collection_etag(&version(2, Some("2021-03-01T10:00:00.000001Z")), json)
+        );
+        assert_ne!(
+            etag,
+            collection_etag(
+                &version(2, Some("2021-03-01T10:00:00.000000Z")),
+                "application/yaml"
+            )
+        );
+    }
+
+    #[test]
+    fn test_not_modified() {
+        let etag = "\"abc\"";
+
+        let req = TestRequest::default().to_http_request();
+        assert!(!not_modified(&req, etag));
+
+        let req = TestRequest::default()
+            .header(header::IF_NONE_MATCH, "\"xyz\", W/\"abc\"")
+            .to_http_request();
+        assert!(not_modified(&req, etag));
+
+        let req = TestRequest::default()
+            .header(header::IF_NONE_MATCH, "*")
+            .to_http_request();
+        assert!(not_modified(&req, etag));
+
+        let req = TestRequest::default()
+            .header(header::IF_NONE_MATCH, "\"xyz\"")
+            .to_http_request();
+        assert!(!not_modified(&req, etag));
+    }
+
+    #[test]
+    fn test_unmodified_since() {
+        let req = TestRequest::default().to_http_request();
+        assert_eq!(None, unmodified_since(&req));
+
+        let req = TestRequest::default()
+            .header(header::IF_UNMODIFIED_SINCE, "Sat, 01 May 2021 12:00:00 GMT")
+            .to_http_request();
+        assert_eq!(
+            Some("2021-05-01T12:00:00+00:00".to_string()),
+            unmodified_since(&req).map(|since| since.to_rfc3339())
+        );
+
+        let req = TestRequest::default()
+            .header(header::IF_UNMODIFIED_SINCE, "yesterday")
+            .to_http_request();
+        assert_eq!(None, unmodified_since(&req));
+    }
+
+    #[test]
+    fn test_not_modified_since() {
+        let last_modified = DateTime::parse_from_rfc3339("2021-05-01T12:00:00.250000Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!("Sat, 01 May 2021 12:00:00 GMT", http_date(last_modified));
+
+        let req = TestRequest::default().to_http_request();
+        assert!(!not_modified_since(&req, last_modified));
+
+        for (since, expected) in [
+            ("Sat, 01 May 2021 12:00:00 GMT", true),
+            ("Sun, 02 May 2021 08:00:00 GMT", true),
+            ("Sat, 01 May 2021 11:59:59 GMT", false),
+            ("yesterday", false),
+        ]
+        .iter()
+        {
+            let req = TestRequest::default()
+                .header(header::IF_MODIFIED_SINCE, *since)
+                .to_http_request();
+            assert_eq!(
+                *expected,
+                not_modified_since(&req, last_modified),
+                "{}",
+                since
+            );
+        }
+    }
+}
@@ -0,0 +1,370 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::logging::LogFormat;
+use crate::FieldLimits;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+/// File read at startup unless `RECIPE_BOOK_CONFIG` names another one.
+pub const DEFAULT_CONFIG_FILE: &str = "recipe-book.toml";
+
+/// Directory for `/admin/backup` copies, relative to the working directory.
+pub const DEFAULT_BACKUP_DIR: &str = "backups";
+
+/// Server settings. Built-in defaults are overridden by `recipe-book.toml`,
+/// which is in turn overridden by `RECIPE_BOOK_*` environment variables.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: String,
+    pub db_path: String,
+    /// Origins allowed to make cross-origin requests. Empty disables CORS.
+    pub cors_origins: Vec<String>,
+    /// Maximum connections in each worker's database pool. Defaults to
+    /// `db::DEFAULT_POOL_SIZE`.
+    pub pool_size: u32,
+    /// HTTP worker threads; `None` uses one per logical CPU. Every worker
+    /// has its own pool, so the server may hold up to `workers * pool_size`
+    /// database connections.
+    pub workers: Option<usize>,
+    /// Default log filter, used when `RUST_LOG` is unset.
+    pub log_level: String,
+    /// `text` (the default) or `json`.
+    pub log_format: LogFormat,
+    /// Serve Prometheus metrics at `/metrics`.
+    pub metrics_enabled: bool,
+    /// Reject ingredient units missing from the `units` table with 422
+    /// instead of only logging a warning.
+    pub strict_units: bool,
+    /// Largest `/recipes/all` response kept in memory between requests.
+    /// Zero disables the cache.
+    pub recipe_cache_max_bytes: usize,
+    /// Largest JSON request body, and NDJSON import line, accepted.
+    pub max_body_bytes: usize,
+    /// Largest `/recipes/import` body accepted.
+    pub max_import_bytes: usize,
+    /// Where `/admin/backup` writes database copies. Created on first use.
+    pub backup_dir: String,
+    /// Longest recipe name accepted, in characters.
+    pub max_name_length: usize,
+    /// Longest recipe description accepted, in characters.
+    pub max_desc_length: usize,
+    /// Longest step text accepted, in characters.
+    pub max_step_length: usize,
+    /// Longest ingredient name accepted, in characters.
+    pub max_ingredient_length: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        let limits = FieldLimits::default();
+        ServerConfig {
+            bind_address: "127.0.0.1:8080".to_string(),
+            db_path: crate::db::DEFAULT_DATABASE.to_string(),
+            cors_origins: vec![],
+            pool_size: crate::db::DEFAULT_POOL_SIZE,
+            workers: None,
+            log_level: "info".to_string(),
+            log_format: LogFormat::Text,
+            metrics_enabled: true,
+            strict_units: false,
+            recipe_cache_max_bytes: crate::cache::DEFAULT_MAX_BYTES,
+            max_body_bytes: crate::DEFAULT_MAX_BODY_BYTES,
+            max_import_bytes: crate::DEFAULT_MAX_IMPORT_BYTES,
+            backup_dir: DEFAULT_BACKUP_DIR.to_string(),
+            max_name_length: limits.name,
+            max_desc_length: limits.desc,
+            max_step_length: limits.step,
+            max_ingredient_length: limits.ingredient,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Load the config file named by `RECIPE_BOOK_CONFIG`, or
+    /// `recipe-book.toml`, and apply environment overrides. A missing file
+    /// leaves the defaults in place; a malformed one is an error.
+    pub fn load() -> Result<Self> {
+        let path =
+            std::env::var("RECIPE_BOOK_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_FILE.to_string());
+        ServerConfig::load_from(Path::new(&path), |name| std::env::var(name).ok())
+    }
+
+    /// Like `load`, reading overrides through `env` instead of the process
+    /// environment.
+    pub fn load_from<F>(path: &Path, env: F) -> Result<Self>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut config = if path.exists() {
+            let contents = std::fs::read_to_string(path)
+                .with_context(|| format!("Unable to read {}", path.display()))?;
+            ServerConfig::from_toml(&contents)
+                .with_context(|| format!("Invalid config file {}", path.display()))?
+        } else {
+            ServerConfig::default()
+        };
+
+        config.apply_env(env)?;
+        if config.workers == Some(0) {
+            bail!("workers must be at least 1");
+        }
+        Ok(config)
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    /// The `max_*_length` settings, for `Recipe::validate_with_limits`.
+    pub fn field_limits(&self) -> FieldLimits {
+        FieldLimits {
+            name: self.max_name_length,
+            desc: self.max_desc_length,
+            step: self.max_step_length,
+            ingredient: self.max_ingredient_length,
+        }
+    }
+
+    fn apply_env<F>(&mut self, env: F) -> Result<()>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        if let Some(bind_address) = env("RECIPE_BOOK_BIND_ADDRESS") {
+            self.bind_address = bind_address;
+        }
+        if let Some(db_path) = env("RECIPE_BOOK_DB_PATH") {
+            self.db_path = db_path;
+        }
+        if let Some(origins) = env("RECIPE_BOOK_CORS_ORIGINS") {
+            self.cors_origins = origins
+                .split(',')
+                .map(|origin| origin.trim().to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect();
+        }
+        if let Some(pool_size) = env("RECIPE_BOOK_POOL_SIZE") {
+            self.pool_size = pool_size
+                .trim()
+                .parse()
+                .ok()
+                .filter(|size| *size > 0)
+                .ok_or_else(|| anyhow!("Invalid RECIPE_BOOK_POOL_SIZE {:?}", pool_size))?;
+        }
+        if let Some(workers) = env("RECIPE_BOOK_WORKERS") {
+            self.workers = Some(
+                parse_limit(&workers)
+                    .ok_or_else(|| anyhow!("Invalid RECIPE_BOOK_WORKERS {:?}", workers))?,
+            );
+        }
+        if let Some(log_level) = env("RECIPE_BOOK_LOG_LEVEL") {
+            self.log_level = log_level;
+        }
+        if let Some(format) = env("RECIPE_BOOK_LOG_FORMAT") {
+            self.log_format = LogFormat::parse(&format)
+                .ok_or_else(|| anyhow!("Invalid RECIPE_BOOK_LOG_FORMAT {:?}", format))?;
+        }
+        if let Some(enabled) = env("RECIPE_BOOK_METRICS_ENABLED") {
+            self.metrics_enabled = enabled
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("Invalid RECIPE_BOOK_METRICS_ENABLED {:?}", enabled))?;
+        }
+        if let Some(strict) = env("RECIPE_BOOK_STRICT_UNITS") {
+            self.strict_units = strict
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("Invalid RECIPE_BOOK_STRICT_UNITS {:?}", strict))?;
+        }
+        if let Some(max_bytes) = env("RECIPE_BOOK_RECIPE_CACHE_MAX_BYTES") {
+            self.recipe_cache_max_bytes = max_bytes.trim().parse().map_err(|_| {
+                anyhow!("Invalid RECIPE_BOOK_RECIPE_CACHE_MAX_BYTES {:?}", max_bytes)
+            })?;
+        }
+        if let Some(max_bytes) = env("RECIPE_BOOK_MAX_BODY_BYTES") {
+            self.max_body_bytes = parse_limit(&max_bytes)
+                .ok_or_else(|| anyhow!("Invalid RECIPE_BOOK_MAX_BODY_BYTES {:?}", max_bytes))?;
+        }
+        if let Some(max_bytes) = env("RECIPE_BOOK_MAX_IMPORT_BYTES") {
+            self.max_import_bytes = parse_limit(&max_bytes)
+                .ok_or_else(|| anyhow!("Invalid RECIPE_BOOK_MAX_IMPORT_BYTES {:?}", max_bytes))?;
+        }
+        if let Some(backup_dir) = env("RECIPE_BOOK_BACKUP_DIR") {
+            self.backup_dir = backup_dir;
+        }
+        if let Some(max_length) = env("RECIPE_BOOK_MAX_NAME_LENGTH") {
+            self.max_name_length = parse_limit(&max_length)
+                .ok_or_else(|| anyhow!("Invalid RECIPE_BOOK_MAX_NAME_LENGTH {:?}", max_length))?;
+        }
+        if let Some(max_length) = env("RECIPE_BOOK_MAX_DESC_LENGTH") {
+            self.max_desc_length = parse_limit(&max_length)
+                .ok_or_else(|| anyhow!("Invalid RECIPE_BOOK_MAX_DESC_LENGTH {:?}", max_length))?;
+        }
+        if let Some(max_length) = env("RECIPE_BOOK_MAX_STEP_LENGTH") {
+            self.max_step_length = parse_limit(&max_length)
+                .ok_or_else(|| anyhow!("Invalid RECIPE_BOOK_MAX_STEP_LENGTH {:?}", max_length))?;
+        }
+        if let Some(max_length) = env("RECIPE_BOOK_MAX_INGREDIENT_LENGTH") {
+            self.max_ingredient_length = parse_limit(&max_length).ok_or_else(|| {
+                anyhow!("Invalid RECIPE_BOOK_MAX_INGREDIENT_LENGTH {:?}", max_length)
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A positive count, e.g. of bytes or workers.
+fn parse_limit(value: &str) -> Option<usize> {
+    value.trim().parse().ok().filter(|limit| *limit > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn no_env(_: &str) -> Option<String> {
+        None
+    }
+
+    fn write_config(contents: &str) -> String {
+        let name = format!("target/tests/config-{}.toml", rand::random::<u32>());
+        std::fs::create_dir_all("target/tests").unwrap();
+        std::fs::write(&name, contents).unwrap();
+        name
+    }
+
+    #[test]
+    fn test_missing_file_uses_defaults() {
+        let config =
+            ServerConfig::load_from(Path::new("target/tests/missing.toml"), no_env).unwrap();
+        assert_eq!(ServerConfig::default(), config);
+    }
+
+    #[test]
+    fn test_precedence() {
+        let name =
+            write_config("bind_address = \"0.0.0.0:9000\"\ndb_path = \"file.db\"\npool_size = 4\n");
+        let env: HashMap<&str, &str> = vec![
+            ("RECIPE_BOOK_DB_PATH", "env.db"),
+            (
+                "RECIPE_BOOK_CORS_ORIGINS",
+                "https://a.example, https://b.example",
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        let config = ServerConfig::load_from(Path::new(&name), |key| {
+            env.get(key).map(|value| value.to_string())
+        })
+        .unwrap();
+        std::fs::remove_file(&name).unwrap();
+
+        // Set only in the file.
+        assert_eq!("0.0.0.0:9000", config.bind_address);
+        assert_eq!(4, config.pool_size);
+        // Set in both; the environment wins.
+        assert_eq!("env.db", config.db_path);
+        // Set only in the environment.
+        assert_eq!(
+            vec!["https://a.example", "https://b.example"],
+            config.cors_origins
+        );
+        // Set nowhere.
+        assert_eq!(ServerConfig::default().log_level, config.log_level);
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        assert!(ServerConfig::from_toml("pool_size = \"many\"").is_err());
+        assert!(ServerConfig::from_toml("bind = \"0.0.0.0:80\"").is_err());
+        assert!(ServerConfig::from_toml("log_format = \"xml\"").is_err());
+        assert_eq!(
+            Some(2),
+            ServerConfig::from_toml("workers = 2").unwrap().workers
+        );
+
+        let result = ServerConfig::load_from(Path::new("target/tests/missing.toml"), |key| {
+            if key == "RECIPE_BOOK_POOL_SIZE" {
+                Some("0".to_string())
+            } else {
+                None
+            }
+        });
+        assert!(result.is_err());
+
+        let result = ServerConfig::load_from(Path::new("target/tests/missing.toml"), |key| {
+            if key == "RECIPE_BOOK_MAX_BODY_BYTES" {
+                Some("0".to_string())
+            } else {
+                None
+            }
+        });
+        assert!(result.is_err());
+
+        let name = write_config("workers = 0\n");
+        let result = ServerConfig::load_from(Path::new(&name), no_env);
+        std::fs::remove_file(&name).unwrap();
+        assert!(result.is_err());
+
+        for workers in ["0", "-1", "many"].iter() {
+            let result = ServerConfig::load_from(Path::new("target/tests/missing.toml"), |key| {
+                if key == "RECIPE_BOOK_WORKERS" {
+                    Some(workers.to_string())
+                } else {
+                    None
+                }
+            });
+            assert!(result.is_err(), "{}", workers);
+        }
+    }
+
+    #[test]
+    fn test_field_limits() {
+        let config = ServerConfig::from_toml("max_name_length = 50\n").unwrap();
+        assert_eq!(
+            FieldLimits {
+                name: 50,
+                ..FieldLimits::default()
+            },
+            config.field_limits()
+        );
+
+        let config = ServerConfig::load_from(Path::new("target/tests/missing.toml"), |key| {
+            if key == "RECIPE_BOOK_MAX_STEP_LENGTH" {
+                Some("5000".to_string())
+            } else {
+                None
+            }
+        })
+        .unwrap();
+        assert_eq!(5000, config.field_limits().step);
+
+        let result = ServerConfig::load_from(Path::new("target/tests/missing.toml"), |key| {
+            if key == "RECIPE_BOOK_MAX_DESC_LENGTH" {
+                Some("0".to_string())
+            } else {
+                None
+            }
+        });
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,135 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::{IngredientQuantity, Recipe};
+use serde::{Deserialize, Serialize};
+
+/// One step of a recipe with the ingredients it mentions.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct CookStep {
+    pub step_text: String,
+    pub ingredients: Vec<IngredientQuantity>,
+}
+
+/// Pair each step with the ingredients whose names appear in its text,
+/// scaling their quantities by `factor`. Matching ignores case and plurals,
+/// so "Peel the potatoes" picks up "Potato". Steps that mention no
+/// ingredient get an empty list.
+pub fn cook_steps(recipe: &Recipe, factor: f64) -> Vec<CookStep> {
+    recipe
+        .steps
+        .iter()
+        .map(|step| CookStep {
+            step_text: step.text.clone(),
+            ingredients: recipe
+                .ingredients
+                .iter()
+                .filter(|ing_quant| mentions(&step.text, &ing_quant.ingredient))
+                .map(|ing_quant| IngredientQuantity {
+                    quantity: ing_quant.quantity.scale(factor),
+                    ..ing_quant.clone()
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Whether every word of `ingredient`, in order, appears in `text`.
+pub fn mentions(text: &str, ingredient: &str) -> bool {
+    let text = words(text);
+    let name = words(ingredient);
+    !name.is_empty()
+        && text
+            .windows(name.len())
+            .any(|window| window == name.as_slice())
+}
+
+/// Lowercase words with plural endings removed.
+fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| singular(&word.to_lowercase()))
+        .collect()
+}
+
+fn singular(word: &str) -> String {
+    if let Some(stem) = word.strip_suffix("ies") {
+        format!("{}y", stem)
+    } else if ["oes", "ches", "shes", "xes"]
+        .iter()
+        .any(|suffix| word.ends_with(suffix))
+    {
+        word[..word.len() - 2].to_string()
+    } else if word.ends_with('s') && !word.ends_with("ss") && word.len() > 3 {
+        word[..word.len() - 1].to_string()
+    } else {
+        word.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RecipeBuilder;
+
+    #[test]
+    fn test_mentions() {
+        assert!(mentions("Peel the potatoes.", "Potato"));
+        assert!(mentions("Dice the potato", "Potatoes"));
+        assert!(mentions("Fold in the berries", "Berry"));
+        assert!(mentions("Add the olive oil and garlic", "Olive Oil"));
+        assert!(!mentions("Add the oil", "Olive Oil"));
+        assert!(!mentions("Add the eggplant", "Egg"));
+        assert!(!mentions("Whisk the eggs", ""));
+        assert!(mentions("Whisk the eggs", "egg"));
+        assert!(!mentions("Toss the grass", "gras"));
+    }
+
+    #[test]
+    fn test_cook_steps() {
+        let mut recipe = RecipeBuilder::default()
+            .step("Boil the potatoes")
+            .step("Mash with butter and potatoes")
+            .step("Serve")
+            .ingredient("Potato", 2.0, "whole")
+            .ingredient("Butter", 1.0, "tbsp")
+            .build();
+        recipe.ingredients[1].quantity.value_max = Some(2.0);
+
+        let steps = cook_steps(&recipe, 1.5);
+
+        let names = |step: &CookStep| -> Vec<String> {
+            step.ingredients
+                .iter()
+                .map(|ing_quant| ing_quant.ingredient.clone())
+                .collect()
+        };
+        assert_eq!(3, steps.len());
+        assert_eq!("Boil the potatoes", steps[0].step_text);
+        assert_eq!(vec!["Potato"], names(&steps[0]));
+        assert_eq!(3.0, steps[0].ingredients[0].quantity.value);
+        assert_eq!(vec!["Potato", "Butter"], names(&steps[1]));
+        assert_eq!(
+            (1.5, Some(3.0)),
+            (
+                steps[1].ingredients[1].quantity.value,
+                steps[1].ingredients[1].quantity.value_max
+            )
+        );
+        assert!(steps[2].ingredients.is_empty());
+    }
+}
@@ -0,0 +1,213 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, HeaderValue, Method};
+use actix_web::{Error, HttpResponse};
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+const ALLOWED_METHODS: &str = "GET, POST, PUT, PATCH, DELETE";
+const MAX_AGE_SECS: &str = "3600";
+
+/// Middleware allowing cross-origin requests from a fixed list of origins.
+/// A `*` entry allows any origin. Requests from other origins are passed
+/// through without CORS headers, so browsers block the response.
+pub struct Cors {
+    origins: Rc<Vec<String>>,
+}
+
+impl Cors {
+    pub fn new(origins: Vec<String>) -> Self {
+        Cors {
+            origins: Rc::new(origins),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for Cors
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CorsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CorsMiddleware {
+            service,
+            origins: self.origins.clone(),
+        })
+    }
+}
+
+pub struct CorsMiddleware<S> {
+    service: S,
+    origins: Rc<Vec<String>>,
+}
+
+impl<S> CorsMiddleware<S> {
+    fn allowed_origin(&self, req: &ServiceRequest) -> Option<HeaderValue> {
+        let origin = req.headers().get(header::ORIGIN)?;
+        let value = origin.to_str().ok()?;
+        if self
+            .origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed == value)
+        {
+            Some(origin.clone())
+        } else {
+            None
+        }
+    }
+}
+
+impl<S, B> Service for CorsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let origin = match self.allowed_origin(&req) {
+            Some(origin) => origin,
+            None => return self.service.call(req).boxed_local(),
+        };
+
+        let preflight = req.method() == Method::OPTIONS
+            && req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+        if preflight {
+            let allow_headers = req
+                .headers()
+                .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+                .cloned()
+                .unwrap_or_else(|| HeaderValue::from_static("Content-Type"));
+            let resp = HttpResponse::NoContent()
+                .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_ALLOW_METHODS, ALLOWED_METHODS)
+                .header(header::ACCESS_CONTROL_ALLOW_HEADERS, allow_headers)
+                .header(header::ACCESS_CONTROL_MAX_AGE, MAX_AGE_SECS)
+                .header(header::VARY, "Origin")
+                .finish();
+            return ok(req.into_response(resp.into_body())).boxed_local();
+        }
+
+        self.service
+            .call(req)
+            .map(move |res| {
+                res.map(|mut resp| {
+                    let headers = resp.headers_mut();
+                    headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+                    // Appended, so a Vary the handler set, e.g. Accept,
+                    // still applies.
+                    headers.append(header::VARY, HeaderValue::from_static("Origin"));
+                    resp
+                })
+            })
+            .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App};
+
+    #[actix_rt::test]
+    async fn test_cors() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Cors::new(vec!["https://app.example".to_string()]))
+                .route("/", web::get().to(|| HttpResponse::Ok().finish()))
+                .route(
+                    "/negotiated",
+                    web::get().to(|| HttpResponse::Ok().header(header::VARY, "Accept").finish()),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .header(header::ORIGIN, "https://app.example")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            "https://app.example",
+            resp.headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .unwrap()
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/negotiated")
+            .header(header::ORIGIN, "https://app.example")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let mut vary: Vec<&str> = resp
+            .headers()
+            .get_all(header::VARY)
+            .map(|value| value.to_str().unwrap())
+            .collect();
+        vary.sort_unstable();
+        assert_eq!(vec!["Accept", "Origin"], vary);
+
+        let req = test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/")
+            .header(header::ORIGIN, "https://app.example")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        assert_eq!(
+            ALLOWED_METHODS,
+            resp.headers()
+                .get(header::ACCESS_CONTROL_ALLOW_METHODS)
+                .unwrap()
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/")
+            .header(header::ORIGIN, "https://evil.example")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert!(resp
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+    }
+}
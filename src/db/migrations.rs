@@ -0,0 +1,467 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::db::{normalize_ingredient_name, timestamp_now};
+use crate::shopping::DEFAULT_AISLES;
+use anyhow::Result;
+use log::{info, warn};
+use rusqlite::{params, Connection, OptionalExtension, Transaction, TransactionBehavior};
+use std::collections::HashMap;
+
+/// A single schema change, applied inside the migration transaction.
+pub type Migration = fn(&Transaction) -> rusqlite::Result<()>;
+
+/// Every schema change in the order it was introduced. A database at version
+/// `n` has had the first `n` entries applied. Append new migrations to the
+/// end; never reorder or edit ones that have shipped.
+///
+/// The first few tolerate existing tables and columns because databases
+/// created before `schema_version` existed already have some of them.
+pub const MIGRATIONS: &[Migration] = &[
+    initial_schema,
+    recipe_provenance,
+    categories,
+    ingredient_categories,
+    ingredient_calories,
+    recipe_timestamps,
+    recipe_favorites,
+    recipe_image_url,
+    recipe_classification,
+    ratings,
+    recipe_photos,
+    ingredient_normalized_names,
+    recipe_soft_delete,
+    recipe_source,
+    step_details,
+    recipe_yield_description,
+    recipe_search_index,
+    recipe_difficulty,
+    recipe_notes,
+    blank_desc_to_null,
+    ingredient_quantity_ranges,
+    recipe_raw_source,
+    step_images,
+    ingredient_positions,
+];
+
+/// Bring the database up to date, running all pending migrations in one
+/// transaction. Returns the resulting schema version.
+pub fn run(conn: &mut Connection) -> Result<u32> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)",
+        params![],
+    )?;
+
+    // Take the write lock up front so workers starting at the same time
+    // apply migrations one after another rather than racing.
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let current = current_version(&tx)?;
+    let target = MIGRATIONS.len() as u32;
+
+    if current < target {
+        info!(
+            "Migrating database schema from version {} to {}",
+            current, target
+        );
+        for migration in MIGRATIONS.iter().skip(current as usize) {
+            migration(&tx)?;
+        }
+
+        tx.execute("DELETE FROM schema_version", params![])?;
+        tx.execute(
+            "INSERT INTO schema_version (version) VALUES (?)",
+            params![target],
+        )?;
+    }
+
+    tx.commit()?;
+    Ok(target.max(current))
+}
+
+/// The schema version recorded in the database, or 0 if none is recorded.
+pub fn current_version(conn: &Connection) -> rusqlite::Result<u32> {
+    let version: Option<u32> = conn
+        .query_row(
+            "SELECT MAX(version) FROM schema_version",
+            params![],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+
+    Ok(version.unwrap_or(0))
+}
+
+fn initial_schema(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS recipes (id INTEGER PRIMARY KEY ASC, name TEXT, desc TEXT);
+        CREATE TABLE IF NOT EXISTS steps (recipe_id INTEGER, text TEXT, CONSTRAINT COMP_K PRIMARY KEY (recipe_id, text), FOREIGN KEY (recipe_id) REFERENCES recipes (id) ON UPDATE CASCADE ON DELETE CASCADE);
+        CREATE TABLE IF NOT EXISTS ingredients (id INTEGER PRIMARY KEY ASC, name TEXT NOT NULL UNIQUE);
+        CREATE TABLE IF NOT EXISTS recipe_ingredients (recipe_id INTEGER, ingredient_id INTEGER, quantity REAL, unit TEXT, CONSTRAINT COMP_K PRIMARY KEY (recipe_id, ingredient_id), FOREIGN KEY(recipe_id) REFERENCES recipes (id) ON UPDATE CASCADE ON DELETE CASCADE, FOREIGN KEY (ingredient_id) REFERENCES ingredients (id) ON UPDATE CASCADE ON DELETE CASCADE);",
+    )
+}
+
+fn recipe_provenance(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS recipe_provenance (recipe_id INTEGER NOT NULL, source_id INTEGER NOT NULL, kind TEXT NOT NULL, CONSTRAINT COMP_K PRIMARY KEY (recipe_id, source_id, kind), FOREIGN KEY (recipe_id) REFERENCES recipes (id) ON UPDATE CASCADE ON DELETE CASCADE);",
+    )
+}
+
+fn categories(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS categories (id INTEGER PRIMARY KEY ASC, name TEXT NOT NULL UNIQUE, parent_id INTEGER, FOREIGN KEY (parent_id) REFERENCES categories (id) ON UPDATE CASCADE ON DELETE SET NULL);
+        CREATE TABLE IF NOT EXISTS recipe_categories (recipe_id INTEGER, category_id INTEGER, CONSTRAINT COMP_K PRIMARY KEY (recipe_id, category_id), FOREIGN KEY (recipe_id) REFERENCES recipes (id) ON UPDATE CASCADE ON DELETE CASCADE, FOREIGN KEY (category_id) REFERENCES categories (id) ON UPDATE CASCADE ON DELETE CASCADE);",
+    )
+}
+
+fn ingredient_categories(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS ingredient_categories (name TEXT PRIMARY KEY COLLATE NOCASE, aisle TEXT NOT NULL);",
+    )?;
+
+    let mut stmt =
+        tx.prepare("INSERT OR IGNORE INTO ingredient_categories (name, aisle) VALUES (?1, ?2)")?;
+    for (name, aisle) in DEFAULT_AISLES.iter() {
+        stmt.execute(params![name, aisle])?;
+    }
+    stmt.finalize()
+}
+
+fn ingredient_calories(tx: &Transaction) -> rusqlite::Result<()> {
+    add_column_if_missing(tx, "recipe_ingredients", "calories_per_unit", "REAL")
+}
+
+/// Existing recipes have no history, so they are stamped with the time of
+/// the migration.
+fn recipe_timestamps(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE recipes ADD COLUMN created_at TEXT;
+        ALTER TABLE recipes ADD COLUMN updated_at TEXT;",
+    )?;
+
+    let now = timestamp_now();
+    tx.execute(
+        "UPDATE recipes SET created_at = (?1), updated_at = (?1)",
+        params![now],
+    )?;
+    Ok(())
+}
+
+fn recipe_favorites(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE recipes ADD COLUMN favorite INTEGER NOT NULL DEFAULT 0;")
+}
+
+fn recipe_image_url(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE recipes ADD COLUMN image_url TEXT;")
+}
+
+fn recipe_classification(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE recipes ADD COLUMN cuisine TEXT;
+        ALTER TABLE recipes ADD COLUMN course TEXT;",
+    )
+}
+
+fn ratings(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE ratings (recipe_id INTEGER NOT NULL, score INTEGER NOT NULL CHECK (score BETWEEN 1 AND 5), created_at TEXT NOT NULL, FOREIGN KEY (recipe_id) REFERENCES recipes (id) ON UPDATE CASCADE ON DELETE CASCADE);
+        CREATE INDEX ratings_recipe_id ON ratings (recipe_id);",
+    )
+}
+
+fn recipe_photos(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "CREATE TABLE recipe_photos (recipe_id INTEGER NOT NULL, url TEXT NOT NULL, position INTEGER NOT NULL, CONSTRAINT COMP_K PRIMARY KEY (recipe_id, position), FOREIGN KEY (recipe_id) REFERENCES recipes (id) ON UPDATE CASCADE ON DELETE CASCADE);",
+    )
+}
+
+/// Ingredients whose names differ only in case or surrounding whitespace are
+/// merged into the oldest one, which keeps its display name. A recipe that
+/// listed two of the merged spellings keeps only the first.
+fn ingredient_normalized_names(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE ingredients ADD COLUMN normalized_name TEXT;")?;
+
+    let mut stmt = tx.prepare("SELECT id, name FROM ingredients ORDER BY id")?;
+    let ingredients = stmt
+        .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<Vec<(i64, String)>>>()?;
+    stmt.finalize()?;
+
+    let mut kept: HashMap<String, i64> = HashMap::new();
+    for (id, name) in ingredients {
+        let normalized = normalize_ingredient_name(&name);
+        match kept.get(&normalized) {
+            Some(&kept_id) => {
+                tx.execute(
+                    "UPDATE OR IGNORE recipe_ingredients SET ingredient_id = (?1) WHERE ingredient_id = (?2)",
+                    params![kept_id, id],
+                )?;
+                tx.execute(
+                    "DELETE FROM recipe_ingredients WHERE ingredient_id = (?)",
+                    params![id],
+                )?;
+                tx.execute("DELETE FROM ingredients WHERE id = (?)", params![id])?;
+            }
+            None => {
+                tx.execute(
+                    "UPDATE ingredients SET normalized_name = (?1) WHERE id = (?2)",
+                    params![normalized, id],
+                )?;
+                kept.insert(normalized, id);
+            }
+        }
+    }
+
+    tx.execute_batch(
+        "CREATE UNIQUE INDEX ingredients_normalized_name ON ingredients (normalized_name);",
+    )
+}
+
+fn recipe_soft_delete(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE recipes ADD COLUMN deleted_at TEXT;")
+}
+
+fn recipe_source(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE recipes ADD COLUMN source_name TEXT;
+        ALTER TABLE recipes ADD COLUMN source_url TEXT;",
+    )
+}
+
+fn step_details(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE steps ADD COLUMN duration_minutes INTEGER;
+        ALTER TABLE steps ADD COLUMN note TEXT;",
+    )
+}
+
+fn recipe_yield_description(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE recipes ADD COLUMN yield_description TEXT;")
+}
+
+/// Refresh the search index row of the recipe whose id is `{id}`, leaving
+/// none when it is trashed or gone.
+const REINDEX_RECIPE: &str = "DELETE FROM recipe_fts WHERE rowid = {id};
+    INSERT INTO recipe_fts (rowid, name, desc, steps)
+        SELECT id, name, desc, (SELECT group_concat(text, ' ') FROM steps WHERE recipe_id = recipes.id)
+        FROM recipes WHERE id = {id} AND deleted_at IS NULL;";
+
+/// An FTS5 index over recipe names, descriptions and step text, kept in step
+/// by triggers so every write path updates it. SQLite builds without FTS5
+/// skip it and full-text search falls back to `LIKE` matching.
+fn recipe_search_index(tx: &Transaction) -> rusqlite::Result<()> {
+    if let Err(e) = tx.execute_batch(
+        "CREATE VIRTUAL TABLE recipe_fts USING fts5(name, desc, steps, tokenize = 'porter unicode61');",
+    ) {
+        warn!("Full-text search is unavailable: {}", e);
+        return Ok(());
+    }
+
+    let reindex = |id: &str| REINDEX_RECIPE.replace("{id}", id);
+    tx.execute_batch(&format!(
+        "CREATE TRIGGER recipes_fts_insert AFTER INSERT ON recipes BEGIN {} END;
+        CREATE TRIGGER recipes_fts_update AFTER UPDATE ON recipes BEGIN DELETE FROM recipe_fts WHERE rowid = OLD.id; {} END;
+        CREATE TRIGGER recipes_fts_delete AFTER DELETE ON recipes BEGIN DELETE FROM recipe_fts WHERE rowid = OLD.id; END;
+        CREATE TRIGGER steps_fts_insert AFTER INSERT ON steps BEGIN {} END;
+        CREATE TRIGGER steps_fts_update AFTER UPDATE ON steps BEGIN {} {} END;
+        CREATE TRIGGER steps_fts_delete AFTER DELETE ON steps BEGIN {} END;
+        INSERT INTO recipe_fts (rowid, name, desc, steps)
+            SELECT id, name, desc, (SELECT group_concat(text, ' ') FROM steps WHERE recipe_id = recipes.id)
+            FROM recipes WHERE deleted_at IS NULL;",
+        reindex("NEW.id"),
+        reindex("NEW.id"),
+        reindex("NEW.recipe_id"),
+        reindex("OLD.recipe_id"),
+        reindex("NEW.recipe_id"),
+        reindex("OLD.recipe_id"),
+    ))
+}
+
+fn recipe_difficulty(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE recipes ADD COLUMN difficulty TEXT;")
+}
+
+fn recipe_notes(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE recipes ADD COLUMN notes TEXT;")
+}
+
+/// Blank descriptions are stored as NULL from now on; bring older rows in
+/// line so every recipe reads back the same way.
+fn blank_desc_to_null(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("UPDATE recipes SET desc = NULL WHERE trim(desc) = '';")
+}
+
+/// Upper end of ranged quantities such as "2-3 cloves". NULL for exact ones.
+fn ingredient_quantity_ranges(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE recipe_ingredients ADD COLUMN quantity_max REAL;")
+}
+
+fn recipe_raw_source(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch("ALTER TABLE recipes ADD COLUMN raw_source TEXT;")
+}
+
+/// Steps were only ever ordered by text, so existing rows get the position
+/// they have been served in.
+fn step_images(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE steps ADD COLUMN image_url TEXT;
+        ALTER TABLE steps ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+        UPDATE steps SET position = (SELECT COUNT(*) FROM steps AS earlier WHERE earlier.recipe_id = steps.recipe_id AND earlier.text < steps.text);",
+    )
+}
+
+/// Ingredients were ordered by their id in the shared `ingredients` table,
+/// so existing rows get the position they have been served in.
+fn ingredient_positions(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        "ALTER TABLE recipe_ingredients ADD COLUMN position INTEGER NOT NULL DEFAULT 0;
+        UPDATE recipe_ingredients SET position = (SELECT COUNT(*) FROM recipe_ingredients AS earlier WHERE earlier.recipe_id = recipe_ingredients.recipe_id AND earlier.ingredient_id < recipe_ingredients.ingredient_id);",
+    )
+}
+
+/// Add a nullable column unless it is already there. Only needed for columns
+/// that unversioned databases may already have.
+fn add_column_if_missing(
+    tx: &Transaction,
+    table: &str,
+    column: &str,
+    decl: &str,
+) -> rusqlite::Result<()> {
+    let mut stmt = tx.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map(params![], |row| row.get::<_, String>(1))?
+        .filter_map(|x| x.ok())
+        .any(|name| name == column);
+    stmt.finalize()?;
+
+    if !exists {
+        tx.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
+            params![],
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_is_idempotent() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        assert_eq!(MIGRATIONS.len() as u32, run(&mut conn).unwrap());
+        assert_eq!(MIGRATIONS.len() as u32, run(&mut conn).unwrap());
+        assert_eq!(MIGRATIONS.len() as u32, current_version(&conn).unwrap());
+
+        let rows: u32 = conn
+            .query_row("SELECT COUNT(*) FROM schema_version", params![], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(1, rows);
+    }
+
+    #[test]
+    fn test_run_only_applies_pending() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE schema_version (version INTEGER NOT NULL);
+            INSERT INTO schema_version (version) VALUES (4);
+            CREATE TABLE recipes (id INTEGER PRIMARY KEY ASC, name TEXT, desc TEXT);
+            CREATE TABLE ingredients (id INTEGER PRIMARY KEY ASC, name TEXT NOT NULL UNIQUE);
+            CREATE TABLE recipe_ingredients (recipe_id INTEGER, ingredient_id INTEGER, quantity REAL, unit TEXT);
+            CREATE TABLE steps (recipe_id INTEGER, text TEXT);",
+        )
+        .unwrap();
+
+        // Only the later column additions are pending, so the categories
+        // table created by an earlier migration must not appear.
+        run(&mut conn).unwrap();
+        conn.prepare("SELECT calories_per_unit FROM recipe_ingredients")
+            .unwrap();
+        conn.prepare("SELECT created_at, updated_at FROM recipes")
+            .unwrap();
+        let categories_table: Option<String> = conn
+            .query_row(
+                "SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'categories'",
+                params![],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap();
+        assert_eq!(None, categories_table);
+    }
+
+    #[test]
+    fn test_ingredient_names_are_merged() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let tx = conn.transaction().unwrap();
+        // Every migration before ingredient_normalized_names.
+        let position = MIGRATIONS
+            .iter()
+            .position(|migration| {
+                std::ptr::fn_addr_eq(*migration, ingredient_normalized_names as Migration)
+            })
+            .unwrap();
+        for migration in MIGRATIONS.iter().take(position) {
+            migration(&tx).unwrap();
+        }
+        tx.execute_batch(
+            "INSERT INTO recipes (id, name) VALUES (1, 'Cake'), (2, 'Cookies');
+            INSERT INTO ingredients (id, name) VALUES (1, 'Sugar'), (2, 'sugar '), (3, 'Flour');
+            INSERT INTO recipe_ingredients (recipe_id, ingredient_id, quantity, unit) VALUES (1, 1, 1, 'cup'), (1, 2, 2, 'tbsp'), (2, 2, 3, 'tbsp'), (2, 3, 1, 'cup');",
+        )
+        .unwrap();
+        ingredient_normalized_names(&tx).unwrap();
+
+        let mut stmt = tx
+            .prepare("SELECT id, name, normalized_name FROM ingredients ORDER BY id")
+            .unwrap();
+        let ingredients = stmt
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<(i64, String, String)>>>()
+            .unwrap();
+        assert_eq!(
+            vec![
+                (1, "Sugar".to_string(), "sugar".to_string()),
+                (3, "Flour".to_string(), "flour".to_string()),
+            ],
+            ingredients
+        );
+
+        let mut stmt = tx
+            .prepare("SELECT recipe_id, ingredient_id, unit FROM recipe_ingredients ORDER BY recipe_id, ingredient_id")
+            .unwrap();
+        let links = stmt
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<(i64, i64, String)>>>()
+            .unwrap();
+        assert_eq!(
+            vec![
+                (1, 1, "cup".to_string()),
+                (2, 1, "tbsp".to_string()),
+                (2, 3, "cup".to_string()),
+            ],
+            links
+        );
+    }
+}
@@ -15,25 +15,473 @@
 /// You should have received a copy of the GNU Affero General Public License
 /// along with this program.  If not, see <https://www.gnu.org/licenses/>.
 ///
-use crate::Recipe;
+use crate::provenance::ProvenanceLink;
+use crate::ratings::RatingSummary;
+use crate::{
+    Category, DeletedRecipe, Difficulty, Facets, IngredientQuantity, Recipe, RecipePatch,
+    RecipeSummary, Stats,
+};
 use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
 
+mod migrations;
 mod sqlite;
 
-pub trait Repo {
-    fn setup(&self);
-    fn add_recipe(&self, recipe: &Recipe) -> Result<()>;
-    fn delete_recipe(&self, recipe_id: i32) -> Result<()>;
-    fn update_recipe(&self, updated_recipe: &Recipe) -> Result<()>;
-    fn load_recipes(&self) -> Result<Vec<Recipe>>;
+pub use sqlite::DEFAULT_DATABASE;
+
+/// Storage for recipes. Handlers call it from actix's blocking thread pool,
+/// so implementations must be shareable across threads.
+pub trait Repo: Send + Sync {
+    fn setup(&self) -> RepoResult<()>;
+    /// Connections currently checked out of the pool.
+    fn active_connections(&self) -> u32;
+    fn pool_state(&self) -> PoolState;
+    /// The last migration applied to the database.
+    fn schema_version(&self) -> RepoResult<u32>;
+    /// Copy the whole database to a new file at `dest` while it stays
+    /// available to other connections.
+    fn backup(&self, dest: &Path) -> RepoResult<()>;
+    /// Store a new recipe and return the id the backend assigned. Any
+    /// `recipe.id` supplied by the caller is ignored.
+    fn add_recipe(&self, recipe: &Recipe) -> RepoResult<u32>;
+    /// Store several recipes in one transaction, returning their ids in
+    /// order. Either every recipe is stored or none are.
+    fn add_recipes(&self, recipes: &[Recipe]) -> RepoResult<Vec<u32>>;
+    /// Move a recipe to the trash. It disappears from every other query but
+    /// keeps its steps, ingredients, ratings and links until purged.
+    fn delete_recipe(&self, recipe_id: i32) -> RepoResult<()>;
+    /// Trashed recipes, most recently deleted first.
+    fn load_deleted(&self) -> RepoResult<Vec<DeletedRecipe>>;
+    /// Take a recipe out of the trash, returning false if it is not there.
+    fn restore_recipe(&self, recipe_id: i32) -> RepoResult<bool>;
+    /// Permanently remove recipes trashed before `older_than`, returning how
+    /// many were removed.
+    fn purge_deleted(&self, older_than: DateTime<Utc>) -> RepoResult<usize>;
+    /// Remove ingredients no recipe uses, returning how many were removed.
+    /// Deleting or rewriting a recipe's ingredients already does this.
+    fn vacuum_orphan_ingredients(&self) -> RepoResult<usize>;
+    /// Permanently remove every recipe, trashed or not, and every
+    /// ingredient. Only for tests that need an empty repo.
+    #[cfg(any(test, feature = "test-utils"))]
+    fn clear_all(&self) -> RepoResult<()>;
+    /// Replace a stored recipe, returning false if its id does not exist.
+    /// With `unmodified_since`, a recipe changed after that time is left
+    /// alone and the result is `Stale`.
+    fn update_recipe(
+        &self,
+        updated_recipe: &Recipe,
+        unmodified_since: Option<DateTime<Utc>>,
+    ) -> RepoResult<bool>;
+    /// Store a recipe under its own id, creating it if the id is unused and
+    /// replacing it, like `update_recipe`, otherwise. Returns true if it was
+    /// created. An id belonging to a trashed recipe is a `Conflict`.
+    fn upsert_recipe(
+        &self,
+        recipe: &Recipe,
+        unmodified_since: Option<DateTime<Utc>>,
+    ) -> RepoResult<bool>;
+    /// In one transaction, store `kept` over its current version, move
+    /// `remove_id` to the trash and record it as merged into `kept`. With
+    /// `union_categories`, `kept` also gains the removed recipe's
+    /// categories. Returns false, changing nothing, if either recipe is
+    /// missing or trashed.
+    fn merge_recipes(
+        &self,
+        kept: &Recipe,
+        remove_id: i32,
+        union_categories: bool,
+    ) -> RepoResult<bool>;
+    fn patch_recipe(&self, recipe_id: i32, patch: &RecipePatch) -> RepoResult<Option<Recipe>>;
+    fn get_recipe(&self, recipe_id: i32) -> RepoResult<Option<Recipe>>;
+    /// Several recipes at once, in the order their ids are given. Missing
+    /// and trashed ones are left out.
+    fn get_recipes(&self, recipe_ids: &[i32]) -> RepoResult<Vec<Recipe>>;
+    /// When a recipe was last added or changed, or `None` if it is missing,
+    /// trashed or older than the timestamp columns.
+    fn recipe_updated_at(&self, recipe_id: i32) -> RepoResult<Option<DateTime<Utc>>>;
+    /// Recipes using any (or, with `match_all`, every) of the named
+    /// ingredients, compared case-insensitively.
+    fn recipes_containing(
+        &self,
+        ingredients: &[String],
+        match_all: bool,
+    ) -> RepoResult<Vec<Recipe>>;
+    fn load_recipes(&self) -> RepoResult<Vec<Recipe>>;
+    /// Up to `limit` recipes in id order, skipping the first `offset`.
+    fn load_recipes_page(&self, limit: u32, offset: u32) -> RepoResult<Vec<Recipe>>;
+    /// Up to `limit` recipes with ids above `after_id`, in id order. Paging
+    /// by the last id seen neither skips nor repeats recipes when others are
+    /// added or deleted between pages, and does not rescan earlier ones.
+    fn load_recipes_after(&self, after_id: i32, limit: u32) -> RepoResult<Vec<Recipe>>;
+    fn count_recipes(&self) -> RepoResult<u64>;
+    /// Recipes added or changed at or after `since`, in id order, for
+    /// incremental sync. Trashed recipes are left out.
+    fn recipes_modified_since(&self, since: DateTime<Utc>) -> RepoResult<Vec<Recipe>>;
+    /// Recipes lacking `field`, in id order, optionally only those added or
+    /// changed at or after `since`. Trashed recipes are left out.
+    fn recipes_missing(
+        &self,
+        field: MissingField,
+        since: Option<DateTime<Utc>>,
+    ) -> RepoResult<Vec<Recipe>>;
+    /// Recipes whose `fields` contain `query`, ignoring ASCII case.
+    fn search_recipes(&self, query: &str, fields: SearchFields) -> RepoResult<Vec<Recipe>>;
+    /// Recipes whose name, description or steps contain every word of
+    /// `query`, best match first. Backends without a full-text index fall
+    /// back to `search_recipes` over all fields.
+    fn full_text_search(&self, query: &str) -> RepoResult<Vec<Recipe>>;
+    /// Recipes matching every given filter, compared case-insensitively.
+    fn browse_recipes(
+        &self,
+        cuisine: Option<&str>,
+        course: Option<&str>,
+        difficulty: Option<Difficulty>,
+        time: Option<TimeLimit>,
+    ) -> RepoResult<Vec<Recipe>>;
+    fn facets(&self) -> RepoResult<Facets>;
+    fn stats(&self) -> RepoResult<Stats>;
+    /// A uniformly random recipe, optionally limited to one cuisine, or
+    /// `None` when nothing matches.
+    fn random_recipe(&self, cuisine: Option<&str>) -> RepoResult<Option<Recipe>>;
+    fn load_favorites(&self) -> RepoResult<Vec<Recipe>>;
+    /// Star or unstar a recipe, returning false if it does not exist.
+    fn set_favorite(&self, recipe_id: i32, favorite: bool) -> RepoResult<bool>;
+    /// Put a recipe's steps in a new order: `order` lists their current
+    /// positions, so `[2, 0, 1]` moves the last of three steps to the front.
+    /// Returns false if the recipe is missing or trashed, and `Conflict` if
+    /// `order` is not a permutation of its step positions.
+    fn reorder_steps(&self, recipe_id: i32, order: &[u32]) -> RepoResult<bool>;
+    /// Add ingredients after a recipe's existing ones, in one transaction.
+    /// Repeats of an ingredient already listed are added to it in place, as
+    /// when a recipe is stored. Returns false if the recipe is missing or trashed.
+    fn append_ingredients(
+        &self,
+        recipe_id: i32,
+        ingredients: &[IngredientQuantity],
+    ) -> RepoResult<bool>;
+    /// The recipe count and latest modification time, which change whenever
+    /// a recipe is added, edited or deleted.
+    fn collection_version(&self) -> RepoResult<CollectionVersion>;
+    /// Every recipe's list-view fields in id order, without loading steps or
+    /// ingredients.
+    fn load_recipe_summaries(&self) -> RepoResult<Vec<RecipeSummary>>;
+    /// Every category in id order.
+    fn load_categories(&self) -> RepoResult<Vec<Category>>;
+    /// Create a category, optionally nested under `parent_id`. A name already
+    /// in use is a `Conflict`; a parent that does not exist is `NotFound`.
+    fn add_category(&self, name: &str, parent_id: Option<i32>) -> RepoResult<Category>;
+    /// Delete a category, returning false if it does not exist. Recipes in
+    /// it lose the assignment and its subcategories move to the top level.
+    fn delete_category(&self, category_id: i32) -> RepoResult<bool>;
+    /// Assign a recipe to a category or take it out, returning false if
+    /// either does not exist. Repeating either is harmless.
+    fn set_recipe_category(
+        &self,
+        recipe_id: i32,
+        category_id: i32,
+        assigned: bool,
+    ) -> RepoResult<bool>;
+    /// Every `(recipe_id, category_id)` assignment.
+    fn recipe_category_links(&self) -> RepoResult<Vec<(u32, i32)>>;
+    /// Map of lowercase ingredient name to grocery aisle.
+    fn ingredient_aisles(&self) -> RepoResult<HashMap<String, String>>;
+    /// Each ingredient with the number of recipes using it, most used first.
+    fn ingredient_usage(&self) -> RepoResult<Vec<(String, u64)>>;
+    /// Every known ingredient with the number of recipes using it, which
+    /// may be zero.
+    fn all_ingredients(&self, sort: IngredientSort) -> RepoResult<Vec<(String, u64)>>;
+    /// Give the ingredient matching `from` the name `to` in every recipe. If
+    /// `to` already names another ingredient the two are merged; a recipe
+    /// listing both has the quantities added as repeated lines are on save,
+    /// or fails the whole rename with `Conflict` if their units cannot be
+    /// added. Returns false if no ingredient matches `from`.
+    fn rename_ingredient(&self, from: &str, to: &str) -> RepoResult<bool>;
+    fn add_provenance(&self, link: &ProvenanceLink) -> RepoResult<()>;
+    /// Store `copy` as a duplicate of `source_id`, along with its
+    /// `Duplicate` provenance link, in one transaction. Returns the copy's
+    /// id, or `NotFound` if the source is missing or trashed.
+    fn add_duplicate(&self, source_id: i32, copy: &Recipe) -> RepoResult<u32>;
+    fn provenance_sources(&self, recipe_id: i32) -> RepoResult<Vec<ProvenanceLink>>;
+    /// Record a score, returning false if the recipe does not exist.
+    fn add_rating(&self, recipe_id: i32, score: u8) -> RepoResult<bool>;
+    /// `None` if the recipe does not exist.
+    fn rating_summary(&self, recipe_id: i32) -> RepoResult<Option<RatingSummary>>;
+    /// Everything stored, trashed recipes included, for moving to another
+    /// database.
+    fn export_snapshot(&self) -> RepoResult<Snapshot>;
+    /// Write `snapshot` in one transaction, keeping its ids and timestamps.
+    /// A database that already holds recipes or categories is refused with
+    /// `Conflict`.
+    fn import_snapshot(&self, snapshot: &Snapshot) -> RepoResult<()>;
+}
+
+/// Why a `Repo` call failed. Handlers answer `NotFound` with 404,
+/// `Conflict` with 409 and anything else with 500.
+#[derive(Debug)]
+pub enum RepoError {
+    /// The row the call needed does not exist.
+    NotFound,
+    /// The write would break a uniqueness rule, e.g. a duplicate key.
+    Conflict,
+    /// The record changed after the time a conditional write required.
+    Stale,
+    /// The backend itself failed.
+    Backend(anyhow::Error),
+}
+
+pub type RepoResult<T> = std::result::Result<T, RepoError>;
+
+impl fmt::Display for RepoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepoError::NotFound => write!(f, "not found"),
+            RepoError::Conflict => write!(f, "conflicts with an existing record"),
+            RepoError::Stale => write!(f, "modified since the given time"),
+            RepoError::Backend(e) => write!(f, "{:#}", e),
+        }
+    }
+}
+
+impl std::error::Error for RepoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RepoError::Backend(e) => e.source(),
+            _ => None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for RepoError {
+    fn from(e: anyhow::Error) -> Self {
+        RepoError::Backend(e)
+    }
+}
+
+// SQLite's extended result codes for a violated UNIQUE or PRIMARY KEY
+// constraint. Other constraint failures, e.g. CHECK, stay backend errors.
+const SQLITE_CONSTRAINT_PRIMARYKEY: i32 = 1555;
+const SQLITE_CONSTRAINT_UNIQUE: i32 = 2067;
+
+impl From<rusqlite::Error> for RepoError {
+    fn from(e: rusqlite::Error) -> Self {
+        match e {
+            rusqlite::Error::QueryReturnedNoRows => RepoError::NotFound,
+            rusqlite::Error::SqliteFailure(failure, _)
+                if failure.extended_code == SQLITE_CONSTRAINT_UNIQUE
+                    || failure.extended_code == SQLITE_CONSTRAINT_PRIMARYKEY =>
+            {
+                RepoError::Conflict
+            }
+            e => RepoError::Backend(e.into()),
+        }
+    }
+}
+
+impl From<r2d2::Error> for RepoError {
+    fn from(e: r2d2::Error) -> Self {
+        RepoError::Backend(e.into())
+    }
+}
+
+/// Which recipe text a search looks at.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchFields {
+    #[default]
+    Name,
+    Desc,
+    /// Both the name and the description.
+    All,
+}
+
+/// Order of the ingredient list.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum IngredientSort {
+    /// Alphabetical, ignoring case.
+    #[default]
+    Name,
+    /// Most used first, ties broken by name.
+    Usage,
+}
+
+/// A field a recipe can lack, for finding incomplete entries.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingField {
+    /// No description, or an empty one.
+    Desc,
+    /// No ingredients at all.
+    Ingredients,
+    /// No steps at all.
+    Steps,
+}
+
+/// Limit on a recipe's total time, the sum of its step durations. Recipes
+/// with no step durations have an unknown time and only match when
+/// `include_unknown` is set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeLimit {
+    pub max_minutes: u32,
+    pub include_unknown: bool,
+}
+
+/// Connection pool usage, as reported by `/health/detailed`.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct PoolState {
+    /// Open connections, idle or not.
+    pub connections: u32,
+    pub idle_connections: u32,
+    pub max_size: u32,
+}
+
+/// A recipe as stored, with the bookkeeping `Recipe` leaves out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredRecipe {
+    pub recipe: Recipe,
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    /// Set while the recipe is in the trash.
+    pub deleted_at: Option<String>,
+}
+
+/// One score from the `ratings` table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredRating {
+    pub recipe_id: u32,
+    pub score: u8,
+    pub created_at: String,
 }
 
+/// The full contents of a database, as moved by `migrate-to`.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Snapshot {
+    pub recipes: Vec<StoredRecipe>,
+    pub categories: Vec<Category>,
+    /// `(recipe_id, category_id)` pairs.
+    pub recipe_categories: Vec<(u32, i32)>,
+    /// Ingredient name and aisle pairs, the defaults included.
+    pub aisles: Vec<(String, String)>,
+    pub provenance: Vec<ProvenanceLink>,
+    pub ratings: Vec<StoredRating>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollectionVersion {
+    pub count: u32,
+    pub last_updated: Option<String>,
+}
+
+/// The current time as stored in `created_at` and `updated_at`. The fixed
+/// RFC 3339 format with microseconds sorts chronologically as text.
+pub(crate) fn timestamp_now() -> String {
+    format_timestamp(Utc::now())
+}
+
+pub(crate) fn format_timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+/// The key ingredients are matched on, so "Tomato", "tomato" and "tomato "
+/// are stored as one ingredient.
+pub(crate) fn normalize_ingredient_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Connections each repo's pool may hold at once, matching r2d2's default.
+/// Every server worker opens its own repo, so the process as a whole may
+/// hold this many per worker.
+pub const DEFAULT_POOL_SIZE: u32 = 10;
+
+/// Where a repo stores its data and how many connections it may open.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepoConfig {
+    pub name: String,
+    pub pool_size: u32,
+}
+
+impl RepoConfig {
+    pub fn new(name: &str) -> Self {
+        RepoConfig {
+            name: name.to_string(),
+            pool_size: DEFAULT_POOL_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum Backend {
     Sqlite,
 }
 
-pub fn create_repo(db_backend: Backend) -> Box<dyn Repo> {
+pub fn create_repo(db_backend: Backend) -> Result<Box<dyn Repo>> {
     match db_backend {
         Backend::Sqlite => sqlite::create_repo(),
     }
 }
+
+pub fn create_repo_with_name(db_backend: Backend, name: &str) -> Result<Box<dyn Repo>> {
+    match db_backend {
+        Backend::Sqlite => sqlite::create_repo_with_name(name),
+    }
+}
+
+/// Open the repo and bring its schema up to date. Fails if the database
+/// cannot be opened or a migration does not apply.
+pub fn create_repo_with_config(db_backend: Backend, config: &RepoConfig) -> Result<Box<dyn Repo>> {
+    match db_backend {
+        Backend::Sqlite => sqlite::create_repo_with_config(config),
+    }
+}
+
+/// Run the backend's integrity check on the named database, returning any
+/// problems it reports.
+pub fn check_integrity(db_backend: &Backend, name: &str) -> Result<Vec<String>> {
+    match db_backend {
+        Backend::Sqlite => sqlite::quick_check(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    #[test]
+    fn test_repo_error_from_sqlite() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE things (id INTEGER PRIMARY KEY, name TEXT UNIQUE, size INTEGER CHECK (size > 0));
+            INSERT INTO things (id, name, size) VALUES (1, 'a', 1);",
+        )
+        .unwrap();
+
+        let insert =
+            |sql: &str| RepoError::from(conn.execute(sql, rusqlite::NO_PARAMS).unwrap_err());
+        assert!(matches!(
+            insert("INSERT INTO things (id, name, size) VALUES (1, 'b', 1)"),
+            RepoError::Conflict
+        ));
+        assert!(matches!(
+            insert("INSERT INTO things (id, name, size) VALUES (2, 'a', 1)"),
+            RepoError::Conflict
+        ));
+        assert!(matches!(
+            insert("INSERT INTO things (id, name, size) VALUES (2, 'b', 0)"),
+            RepoError::Backend(_)
+        ));
+
+        let missing = conn
+            .query_row(
+                "SELECT name FROM things WHERE id = 2",
+                rusqlite::NO_PARAMS,
+                |row| row.get::<_, String>(0),
+            )
+            .unwrap_err();
+        assert!(matches!(RepoError::from(missing), RepoError::NotFound));
+    }
+}
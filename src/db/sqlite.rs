@@ -15,336 +15,4535 @@
 /// You should have received a copy of the GNU Affero General Public License
 /// along with this program.  If not, see <https://www.gnu.org/licenses/>.
 ///
-use crate::db::Repo;
-use crate::{IngredientQuantity, Quantity, Recipe};
-use anyhow::Result;
+use crate::db::{
+    format_timestamp, migrations, normalize_ingredient_name, timestamp_now, CollectionVersion,
+    IngredientSort, MissingField, PoolState, Repo, RepoConfig, RepoError, RepoResult, SearchFields,
+    Snapshot, StoredRating, StoredRecipe, TimeLimit,
+};
+use crate::normalize;
+use crate::provenance::{ProvenanceKind, ProvenanceLink};
+use crate::ratings::RatingSummary;
+use crate::{
+    Category, DeletedRecipe, Difficulty, FacetCount, Facets, IngredientQuantity, Quantity, Recipe,
+    RecipePatch, RecipeSummary, Stats, Step,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
 use log::error;
 use r2d2_sqlite::{self, SqliteConnectionManager};
-use rusqlite::params;
+use rusqlite::{
+    params, Connection, DatabaseName, OptionalExtension, ToSql, Transaction, TransactionBehavior,
+    NO_PARAMS,
+};
+use std::collections::HashMap;
+use std::path::Path;
 
 pub type Pool = r2d2::Pool<r2d2_sqlite::SqliteConnectionManager>;
 pub type SqliteConn = r2d2::PooledConnection<r2d2_sqlite::SqliteConnectionManager>;
 
-pub fn create_repo() -> Box<dyn Repo> {
-    create_repo_with_name("recipes.db")
+pub const DEFAULT_DATABASE: &str = "recipes.db";
+
+pub fn create_repo() -> Result<Box<dyn Repo>> {
+    create_repo_with_name(DEFAULT_DATABASE)
+}
+
+/// Run `PRAGMA quick_check` on a dedicated connection, returning the problems
+/// found. An empty list means the database is healthy.
+pub fn quick_check(name: &str) -> Result<Vec<String>> {
+    let conn = rusqlite::Connection::open(name)?;
+    let mut stmt = conn.prepare("PRAGMA quick_check")?;
+    let problems = stmt
+        .query_map(params![], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?
+        .into_iter()
+        .filter(|line| line != "ok")
+        .collect();
+
+    Ok(problems)
+}
+
+pub fn create_repo_with_name(name: &str) -> Result<Box<dyn Repo>> {
+    create_repo_with_config(&RepoConfig::new(name))
+}
+
+pub fn create_repo_with_config(config: &RepoConfig) -> Result<Box<dyn Repo>> {
+    let pool = build_pool(config).context("Unable to create connection pool")?;
+
+    let repo: Box<dyn Repo> = Box::new(SqliteRepo { conn_man: pool });
+    repo.setup().context("Unable to set up database")?;
+
+    Ok(repo)
+}
+
+fn build_pool(config: &RepoConfig) -> Result<Pool> {
+    let path = std::path::Path::new(&config.name);
+
+    match path.parent() {
+        Some(parent) => std::fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create database directory {}", parent.display()))?,
+        None => bail!("Database path {} has no parent directory", config.name),
+    }
+
+    let manager = SqliteConnectionManager::file(&config.name).with_init(enable_foreign_keys);
+    let pool = r2d2::Pool::builder()
+        .max_size(config.pool_size)
+        .build(manager)?;
+
+    Ok(pool)
+}
+
+/// Turn on foreign key enforcement, which the schema's cascading deletes rely
+/// on, and confirm it took effect. SQLite ignores the pragma silently, e.g.
+/// inside a transaction or when built without foreign key support, so a
+/// connection that still reports it off is refused rather than handed out.
+fn enable_foreign_keys(conn: &mut Connection) -> rusqlite::Result<()> {
+    conn.execute_batch("PRAGMA foreign_keys=1")?;
+    let enabled: Option<i64> = conn
+        .query_row("PRAGMA foreign_keys", NO_PARAMS, |row| row.get(0))
+        .optional()?;
+    if enabled != Some(1) {
+        error!("SQLite did not enable foreign keys; refusing the connection");
+        return Err(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_MISUSE),
+            Some("foreign keys are not enforced".to_string()),
+        ));
+    }
+    Ok(())
+}
+
+pub struct SqliteRepo {
+    conn_man: Pool,
+}
+
+impl SqliteRepo {
+    /// Check a connection out of the pool. A checkout that times out, or a
+    /// connection the pool refuses, fails as `RepoError::Backend`.
+    fn get_conn(&self) -> RepoResult<SqliteConn> {
+        Ok(self.conn_man.get()?)
+    }
 }
 
-pub fn create_repo_with_name(name: &str) -> Box<dyn Repo> {
-    let path = std::path::Path::new(name);
+impl Repo for SqliteRepo {
+    fn setup(&self) -> RepoResult<()> {
+        let mut conn = self.get_conn()?;
+        migrations::run(&mut conn)?;
+        Ok(())
+    }
+
+    fn active_connections(&self) -> u32 {
+        let state = self.conn_man.state();
+        state.connections - state.idle_connections
+    }
+
+    fn pool_state(&self) -> PoolState {
+        let state = self.conn_man.state();
+        PoolState {
+            connections: state.connections,
+            idle_connections: state.idle_connections,
+            max_size: self.conn_man.max_size(),
+        }
+    }
+
+    fn schema_version(&self) -> RepoResult<u32> {
+        let conn = self.get_conn()?;
+        Ok(migrations::current_version(&conn)?)
+    }
+
+    fn backup(&self, dest: &Path) -> RepoResult<()> {
+        let conn = self.get_conn()?;
+        conn.backup(DatabaseName::Main, dest, None)
+            .with_context(|| format!("Unable to back up to {}", dest.display()))?;
+        Ok(())
+    }
+
+    fn add_recipe(&self, recipe: &Recipe) -> RepoResult<u32> {
+        let mut conn = self.get_conn()?;
+        // do nothing right now
+        let tx = write_transaction(&mut conn)?;
+        let recipe_id = insert_recipe(&tx, recipe)?;
+
+        tx.commit()?;
+        Ok(recipe_id)
+    }
+
+    fn add_recipes(&self, recipes: &[Recipe]) -> RepoResult<Vec<u32>> {
+        let mut conn = self.get_conn()?;
+        let tx = write_transaction(&mut conn)?;
+        let recipe_ids = recipes
+            .iter()
+            .map(|recipe| insert_recipe(&tx, recipe))
+            .collect::<rusqlite::Result<Vec<u32>>>()?;
+
+        tx.commit()?;
+        Ok(recipe_ids)
+    }
+
+    fn update_recipe(
+        &self,
+        updated_recipe: &Recipe,
+        unmodified_since: Option<DateTime<Utc>>,
+    ) -> RepoResult<bool> {
+        let mut conn = self.get_conn()?;
+        let tx = write_transaction(&mut conn)?;
+        if let (Some(recipe_id), Some(since)) = (updated_recipe.id, unmodified_since) {
+            check_unmodified(&tx, recipe_id, since)?;
+        }
+        if !replace_recipe(&tx, updated_recipe)? {
+            return Ok(false);
+        }
+
+        tx.commit()?;
+        Ok(true)
+    }
+
+    fn upsert_recipe(
+        &self,
+        recipe: &Recipe,
+        unmodified_since: Option<DateTime<Utc>>,
+    ) -> RepoResult<bool> {
+        let recipe_id = recipe
+            .id
+            .ok_or_else(|| anyhow!("Upserting a recipe needs its id"))?;
+        let mut conn = self.get_conn()?;
+        let tx = write_transaction(&mut conn)?;
+        if let Some(since) = unmodified_since {
+            check_unmodified(&tx, recipe_id, since)?;
+        }
+
+        let deleted_at: Option<Option<String>> = tx
+            .query_row(
+                "SELECT deleted_at FROM recipes WHERE id = (?)",
+                params![recipe_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let created = match deleted_at {
+            None => {
+                insert_recipe_with_id(&tx, Some(recipe_id), recipe)?;
+                true
+            }
+            Some(None) => {
+                replace_recipe(&tx, recipe)?;
+                false
+            }
+            Some(Some(_)) => return Err(RepoError::Conflict),
+        };
+
+        tx.commit()?;
+        Ok(created)
+    }
+
+    fn merge_recipes(
+        &self,
+        kept: &Recipe,
+        remove_id: i32,
+        union_categories: bool,
+    ) -> RepoResult<bool> {
+        let mut conn = self.get_conn()?;
+        let tx = write_transaction(&mut conn)?;
+        if !replace_recipe(&tx, kept)? {
+            return Ok(false);
+        }
+
+        if union_categories {
+            tx.execute(
+                "INSERT OR IGNORE INTO recipe_categories (recipe_id, category_id) SELECT (?1), category_id FROM recipe_categories WHERE recipe_id = (?2)",
+                params![kept.id, remove_id],
+            )?;
+        }
+        let trashed = tx.execute(
+            "UPDATE recipes SET deleted_at = (?1) WHERE id = (?2) AND deleted_at IS NULL",
+            params![timestamp_now(), remove_id],
+        )?;
+        if trashed == 0 {
+            return Ok(false);
+        }
+        tx.execute(
+            "INSERT OR IGNORE INTO recipe_provenance (recipe_id, source_id, kind) VALUES (?1, ?2, ?3)",
+            params![kept.id, remove_id, ProvenanceKind::Merge.as_str()],
+        )?;
+
+        tx.commit()?;
+        Ok(true)
+    }
+
+    fn patch_recipe(&self, recipe_id: i32, patch: &RecipePatch) -> RepoResult<Option<Recipe>> {
+        let mut conn = self.get_conn()?;
+        let tx = write_transaction(&mut conn)?;
+
+        let exists = tx
+            .query_row(
+                "SELECT 1 FROM recipes WHERE id = (?) AND deleted_at IS NULL",
+                params![recipe_id],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if !exists {
+            return Ok(None);
+        }
+
+        tx.execute(
+            "UPDATE recipes SET updated_at = (?1) WHERE id = (?2)",
+            params![timestamp_now(), recipe_id],
+        )?;
+
+        if let Some(name) = &patch.name {
+            tx.execute(
+                "UPDATE recipes SET name = (?1) WHERE id = (?2)",
+                params![name, recipe_id],
+            )?;
+        }
+
+        if let Some(desc) = &patch.desc {
+            tx.execute(
+                "UPDATE recipes SET desc = (?1) WHERE id = (?2)",
+                params![stored_desc(Some(desc)), recipe_id],
+            )?;
+        }
+
+        if let Some(steps) = &patch.steps {
+            tx.execute(
+                "DELETE FROM steps WHERE recipe_id = (?)",
+                params![recipe_id],
+            )?;
+            insert_steps(&tx, recipe_id, steps)?;
+        }
+
+        if let Some(ingredients) = &patch.ingredients {
+            tx.execute(
+                "DELETE FROM recipe_ingredients WHERE recipe_id = (?)",
+                params![recipe_id],
+            )?;
+            insert_ingredients(&tx, recipe_id, ingredients)?;
+            delete_orphan_ingredients(&tx)?;
+        }
+
+        tx.commit()?;
+        drop(conn);
+
+        self.get_recipe(recipe_id)
+    }
+
+    fn delete_recipe(&self, recipe_id: i32) -> RepoResult<()> {
+        let conn = self.get_conn()?;
+        conn.execute(
+            "UPDATE recipes SET deleted_at = (?1) WHERE id = (?2) AND deleted_at IS NULL",
+            params![timestamp_now(), recipe_id],
+        )?;
+
+        Ok(())
+    }
+
+    fn load_deleted(&self) -> RepoResult<Vec<DeletedRecipe>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, deleted_at FROM recipes WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC, id",
+        )?;
+        let rows = stmt
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<Vec<(i32, String)>>>()?;
+
+        let mut deleted = Vec::new();
+        for (recipe_id, deleted_at) in rows {
+            if let Some(recipe) = load_recipe_where(&conn, "deleted_at IS NOT NULL", recipe_id)? {
+                deleted.push(DeletedRecipe { recipe, deleted_at });
+            }
+        }
+
+        Ok(deleted)
+    }
+
+    fn restore_recipe(&self, recipe_id: i32) -> RepoResult<bool> {
+        let conn = self.get_conn()?;
+        // Bump updated_at so the collection version changes even when the
+        // recipe count ends up where it was.
+        let restored = conn.execute(
+            "UPDATE recipes SET deleted_at = NULL, updated_at = (?1) WHERE id = (?2) AND deleted_at IS NOT NULL",
+            params![timestamp_now(), recipe_id],
+        )?;
+
+        Ok(restored > 0)
+    }
+
+    fn purge_deleted(&self, older_than: DateTime<Utc>) -> RepoResult<usize> {
+        let mut conn = self.get_conn()?;
+        let tx = write_transaction(&mut conn)?;
+        let purged = tx.execute(
+            "DELETE FROM recipes WHERE deleted_at IS NOT NULL AND deleted_at < (?)",
+            params![format_timestamp(older_than)],
+        )?;
+        delete_orphan_ingredients(&tx)?;
+        tx.commit()?;
+
+        Ok(purged)
+    }
+
+    fn vacuum_orphan_ingredients(&self) -> RepoResult<usize> {
+        let mut conn = self.get_conn()?;
+        let tx = write_transaction(&mut conn)?;
+        let removed = delete_orphan_ingredients(&tx)?;
+        tx.commit()?;
+
+        Ok(removed)
+    }
+
+    #[cfg(any(test, feature = "test-utils"))]
+    fn clear_all(&self) -> RepoResult<()> {
+        let mut conn = self.get_conn()?;
+        let tx = write_transaction(&mut conn)?;
+        // Ratings, photos, provenance and category links cascade from recipes.
+        tx.execute_batch(
+            "DELETE FROM recipe_ingredients;
+            DELETE FROM steps;
+            DELETE FROM recipes;
+            DELETE FROM ingredients;",
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn get_recipe(&self, recipe_id: i32) -> RepoResult<Option<Recipe>> {
+        let conn = self.get_conn()?;
+        Ok(load_recipe(&conn, recipe_id)?)
+    }
+
+    fn recipe_updated_at(&self, recipe_id: i32) -> RepoResult<Option<DateTime<Utc>>> {
+        let conn = self.get_conn()?;
+        let updated_at: Option<String> = conn
+            .query_row(
+                "SELECT updated_at FROM recipes WHERE id = (?) AND deleted_at IS NULL",
+                params![recipe_id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten();
+        updated_at.as_deref().map(parse_updated_at).transpose()
+    }
+
+    fn get_recipes(&self, recipe_ids: &[i32]) -> RepoResult<Vec<Recipe>> {
+        let mut conn = self.get_conn()?;
+        // One snapshot for every batch, as in `load_recipes`.
+        let tx = conn.transaction()?;
+        let recipes = load_recipes_by_id(&tx, recipe_ids.to_vec())?;
+        tx.commit()?;
+
+        Ok(recipes)
+    }
+
+    fn recipes_containing(
+        &self,
+        ingredients: &[String],
+        match_all: bool,
+    ) -> RepoResult<Vec<Recipe>> {
+        let mut names: Vec<String> = ingredients
+            .iter()
+            .map(|name| normalize_ingredient_name(name))
+            .filter(|name| !name.is_empty())
+            .collect();
+        names.sort();
+        names.dedup();
+
+        if names.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let placeholders = vec!["?"; names.len()].join(", ");
+        let having = if match_all {
+            format!(" HAVING COUNT(DISTINCT normalized_name) = {}", names.len())
+        } else {
+            String::new()
+        };
+        let query = format!(
+            "SELECT recipe_id FROM recipe_ingredients LEFT JOIN ingredients ON ingredient_id = id WHERE normalized_name IN ({}) GROUP BY recipe_id{} ORDER BY recipe_id",
+            placeholders, having
+        );
+
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&query)?;
+        let recipe_ids: Vec<i32> = stmt
+            .query_map(&names, |row| row.get(0))?
+            .filter_map(|x| x.ok())
+            .collect();
+
+        Ok(load_recipes_by_id(&conn, recipe_ids)?)
+    }
+
+    fn load_recipes(&self) -> RepoResult<Vec<Recipe>> {
+        let mut conn = self.get_conn()?;
+        // Every query below reads from the same snapshot, so a concurrent
+        // delete cannot leave a recipe with its steps already removed.
+        let tx = conn.transaction()?;
+        let recipes = load_all_recipes(&tx)?;
+        tx.commit()?;
+
+        Ok(recipes)
+    }
+
+    fn search_recipes(&self, query: &str, fields: SearchFields) -> RepoResult<Vec<Recipe>> {
+        let pattern = format!("%{}%", escape_like(query.trim()));
+        let condition = match fields {
+            SearchFields::Name => "name LIKE ?1 ESCAPE '\\'",
+            SearchFields::Desc => "desc LIKE ?1 ESCAPE '\\'",
+            SearchFields::All => "name LIKE ?1 ESCAPE '\\' OR desc LIKE ?1 ESCAPE '\\'",
+        };
+
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id FROM recipes WHERE ({}) AND deleted_at IS NULL ORDER BY id",
+            condition
+        ))?;
+        let recipe_ids: Vec<i32> = stmt
+            .query_map(params![pattern], |row| row.get(0))?
+            .filter_map(|x| x.ok())
+            .collect();
+
+        Ok(load_recipes_by_id(&conn, recipe_ids)?)
+    }
+
+    fn full_text_search(&self, query: &str) -> RepoResult<Vec<Recipe>> {
+        let conn = self.get_conn()?;
+        let indexed: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'recipe_fts')",
+            params![],
+            |row| row.get(0),
+        )?;
+        if !indexed {
+            drop(conn);
+            return self.search_recipes(query, SearchFields::All);
+        }
+
+        let fts_query = fts_query(query);
+        if fts_query.is_empty() {
+            return Ok(vec![]);
+        }
+        // bm25 is lower for better matches; a hit in the name counts most.
+        let mut stmt = conn.prepare(
+            "SELECT rowid FROM recipe_fts WHERE recipe_fts MATCH ?1 ORDER BY bm25(recipe_fts, 10.0, 5.0, 1.0), rowid",
+        )?;
+        let recipe_ids: Vec<i32> = stmt
+            .query_map(params![fts_query], |row| row.get(0))?
+            .filter_map(|x| x.ok())
+            .collect();
+
+        Ok(load_recipes_by_id(&conn, recipe_ids)?)
+    }
+
+    fn browse_recipes(
+        &self,
+        cuisine: Option<&str>,
+        course: Option<&str>,
+        difficulty: Option<Difficulty>,
+        time: Option<TimeLimit>,
+    ) -> RepoResult<Vec<Recipe>> {
+        let conn = self.get_conn()?;
+        // SUM skips steps without a duration and is NULL when none have one,
+        // leaving the comparison NULL and `?5` to decide.
+        let mut stmt = conn.prepare(
+            "SELECT id FROM recipes WHERE (?1 IS NULL OR cuisine = ?1 COLLATE NOCASE) AND (?2 IS NULL OR course = ?2 COLLATE NOCASE) AND (?3 IS NULL OR difficulty = ?3) AND (?4 IS NULL OR COALESCE((SELECT SUM(duration_minutes) FROM steps WHERE recipe_id = recipes.id) <= ?4, ?5)) AND deleted_at IS NULL ORDER BY id",
+        )?;
+        let recipe_ids: Vec<i32> = stmt
+            .query_map(
+                params![
+                    cuisine,
+                    course,
+                    difficulty.map(|d| d.as_str()),
+                    time.map(|t| t.max_minutes),
+                    time.is_some_and(|t| t.include_unknown),
+                ],
+                |row| row.get(0),
+            )?
+            .filter_map(|x| x.ok())
+            .collect();
+
+        Ok(load_recipes_by_id(&conn, recipe_ids)?)
+    }
+
+    fn facets(&self) -> RepoResult<Facets> {
+        let conn = self.get_conn()?;
+        Ok(Facets {
+            cuisines: facet_counts(&conn, "cuisine")?,
+            courses: facet_counts(&conn, "course")?,
+        })
+    }
+
+    fn stats(&self) -> RepoResult<Stats> {
+        let conn = self.get_conn()?;
+        let total_recipes: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM recipes WHERE deleted_at IS NULL",
+            params![],
+            |row| row.get(0),
+        )?;
+        let total_ingredients: i64 = conn.query_row(
+            "SELECT COUNT(DISTINCT ingredient_id) FROM recipe_ingredients JOIN recipes ON recipe_id = id WHERE deleted_at IS NULL",
+            params![],
+            |row| row.get(0),
+        )?;
+        let average_steps: Option<f64> = conn.query_row(
+            "SELECT AVG((SELECT COUNT(*) FROM steps WHERE recipe_id = recipes.id)) FROM recipes WHERE deleted_at IS NULL",
+            params![],
+            |row| row.get(0),
+        )?;
+        let most_used_unit: Option<String> = conn
+            .query_row(
+                "SELECT unit FROM recipe_ingredients JOIN recipes ON recipe_id = id WHERE unit IS NOT NULL AND deleted_at IS NULL GROUP BY unit ORDER BY COUNT(*) DESC, unit LIMIT 1",
+                params![],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(Stats {
+            total_recipes: total_recipes as u64,
+            total_ingredients: total_ingredients as u64,
+            average_steps,
+            most_used_unit,
+        })
+    }
+
+    fn random_recipe(&self, cuisine: Option<&str>) -> RepoResult<Option<Recipe>> {
+        let conn = self.get_conn()?;
+        let recipe_id: Option<i32> = conn
+            .query_row(
+                "SELECT id FROM recipes WHERE (?1 IS NULL OR cuisine = ?1 COLLATE NOCASE) AND deleted_at IS NULL ORDER BY RANDOM() LIMIT 1",
+                params![cuisine],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match recipe_id {
+            Some(recipe_id) => Ok(load_recipe(&conn, recipe_id)?),
+            None => Ok(None),
+        }
+    }
+
+    fn load_favorites(&self) -> RepoResult<Vec<Recipe>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn
+            .prepare("SELECT id FROM recipes WHERE favorite AND deleted_at IS NULL ORDER BY id")?;
+        let recipe_ids: Vec<i32> = stmt
+            .query_map(params![], |row| row.get(0))?
+            .filter_map(|x| x.ok())
+            .collect();
+
+        Ok(load_recipes_by_id(&conn, recipe_ids)?)
+    }
+
+    fn set_favorite(&self, recipe_id: i32, favorite: bool) -> RepoResult<bool> {
+        let conn = self.get_conn()?;
+        let changed = conn.execute(
+            "UPDATE recipes SET favorite = (?1), updated_at = (?2) WHERE id = (?3) AND deleted_at IS NULL",
+            params![favorite, timestamp_now(), recipe_id],
+        )?;
+
+        Ok(changed > 0)
+    }
+
+    fn reorder_steps(&self, recipe_id: i32, order: &[u32]) -> RepoResult<bool> {
+        let mut conn = self.get_conn()?;
+        let tx = write_transaction(&mut conn)?;
+        let changed = tx.execute(
+            "UPDATE recipes SET updated_at = (?1) WHERE id = (?2) AND deleted_at IS NULL",
+            params![timestamp_now(), recipe_id],
+        )?;
+        if changed == 0 {
+            return Ok(false);
+        }
+
+        let step_count: u32 = tx.query_row(
+            "SELECT COUNT(*) FROM steps WHERE recipe_id = (?)",
+            params![recipe_id],
+            |row| row.get(0),
+        )?;
+        if order.len() != step_count as usize {
+            return Err(RepoError::Conflict);
+        }
+        // Park every step at a negative position first so no step is moved
+        // onto one that has not been moved yet.
+        tx.execute(
+            "UPDATE steps SET position = -1 - position WHERE recipe_id = (?)",
+            params![recipe_id],
+        )?;
+        let mut stmt = tx.prepare(
+            "UPDATE steps SET position = (?1) WHERE recipe_id = (?2) AND position = -1 - (?3)",
+        )?;
+        for (position, old_position) in order.iter().enumerate() {
+            if stmt.execute(params![position as u32, recipe_id, old_position])? != 1 {
+                return Err(RepoError::Conflict);
+            }
+        }
+        stmt.finalize()?;
+
+        tx.commit()?;
+        Ok(true)
+    }
+
+    fn append_ingredients(
+        &self,
+        recipe_id: i32,
+        ingredients: &[IngredientQuantity],
+    ) -> RepoResult<bool> {
+        let mut conn = self.get_conn()?;
+        let tx = write_transaction(&mut conn)?;
+        let changed = tx.execute(
+            "UPDATE recipes SET updated_at = (?1) WHERE id = (?2) AND deleted_at IS NULL",
+            params![timestamp_now(), recipe_id],
+        )?;
+        if changed == 0 {
+            return Ok(false);
+        }
+
+        // Rewriting the whole list lets repeats merge into existing rows.
+        let mut combined = load_ingredients(&tx, recipe_id as u32)?;
+        combined.extend_from_slice(ingredients);
+        tx.execute(
+            "DELETE FROM recipe_ingredients WHERE recipe_id = (?)",
+            params![recipe_id],
+        )?;
+        insert_ingredients(&tx, recipe_id, &combined)?;
+
+        tx.commit()?;
+        Ok(true)
+    }
+
+    fn load_recipes_page(&self, limit: u32, offset: u32) -> RepoResult<Vec<Recipe>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id FROM recipes WHERE deleted_at IS NULL ORDER BY id LIMIT ?1 OFFSET ?2",
+        )?;
+        let recipe_ids: Vec<i32> = stmt
+            .query_map(params![limit, offset], |row| row.get(0))?
+            .filter_map(|x| x.ok())
+            .collect();
+
+        Ok(load_recipes_by_id(&conn, recipe_ids)?)
+    }
+
+    fn load_recipes_after(&self, after_id: i32, limit: u32) -> RepoResult<Vec<Recipe>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id FROM recipes WHERE id > ?1 AND deleted_at IS NULL ORDER BY id LIMIT ?2",
+        )?;
+        let recipe_ids: Vec<i32> = stmt
+            .query_map(params![after_id, limit], |row| row.get(0))?
+            .filter_map(|x| x.ok())
+            .collect();
+
+        Ok(load_recipes_by_id(&conn, recipe_ids)?)
+    }
+
+    fn recipes_modified_since(&self, since: DateTime<Utc>) -> RepoResult<Vec<Recipe>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id FROM recipes WHERE updated_at >= ?1 AND deleted_at IS NULL ORDER BY id",
+        )?;
+        let recipe_ids: Vec<i32> = stmt
+            .query_map(params![format_timestamp(since)], |row| row.get(0))?
+            .filter_map(|x| x.ok())
+            .collect();
+
+        Ok(load_recipes_by_id(&conn, recipe_ids)?)
+    }
+
+    fn recipes_missing(
+        &self,
+        field: MissingField,
+        since: Option<DateTime<Utc>>,
+    ) -> RepoResult<Vec<Recipe>> {
+        let condition = match field {
+            MissingField::Desc => "(desc IS NULL OR desc = '')",
+            MissingField::Ingredients => {
+                "NOT EXISTS (SELECT 1 FROM recipe_ingredients WHERE recipe_id = recipes.id)"
+            }
+            MissingField::Steps => "NOT EXISTS (SELECT 1 FROM steps WHERE recipe_id = recipes.id)",
+        };
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id FROM recipes WHERE {} AND (?1 IS NULL OR updated_at >= ?1) AND deleted_at IS NULL ORDER BY id",
+            condition
+        ))?;
+        let recipe_ids: Vec<i32> = stmt
+            .query_map(params![since.map(format_timestamp)], |row| row.get(0))?
+            .filter_map(|x| x.ok())
+            .collect();
+
+        Ok(load_recipes_by_id(&conn, recipe_ids)?)
+    }
+
+    fn count_recipes(&self) -> RepoResult<u64> {
+        let conn = self.get_conn()?;
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM recipes WHERE deleted_at IS NULL",
+            params![],
+            |row| row.get(0),
+        )?;
+
+        Ok(count as u64)
+    }
+
+    fn collection_version(&self) -> RepoResult<CollectionVersion> {
+        let conn = self.get_conn()?;
+        let version = conn.query_row(
+            "SELECT COUNT(*), MAX(updated_at) FROM recipes WHERE deleted_at IS NULL",
+            params![],
+            |row| {
+                Ok(CollectionVersion {
+                    count: row.get(0)?,
+                    last_updated: row.get(1)?,
+                })
+            },
+        )?;
+
+        Ok(version)
+    }
+
+    fn load_recipe_summaries(&self) -> RepoResult<Vec<RecipeSummary>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, name, desc, image_url FROM recipes WHERE deleted_at IS NULL ORDER BY id",
+        )?;
+        let summaries = stmt
+            .query_map(params![], |row| {
+                Ok(RecipeSummary {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    desc: row.get(2)?,
+                    image_url: row.get(3)?,
+                })
+            })?
+            .filter_map(|x| x.ok())
+            .collect();
+
+        Ok(summaries)
+    }
+
+    fn load_categories(&self) -> RepoResult<Vec<Category>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare("SELECT id, name, parent_id FROM categories ORDER BY id")?;
+        let categories = stmt
+            .query_map(params![], |row| {
+                Ok(Category {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    parent_id: row.get(2)?,
+                })
+            })?
+            .filter_map(|x| x.ok())
+            .collect();
+
+        Ok(categories)
+    }
+
+    fn add_category(&self, name: &str, parent_id: Option<i32>) -> RepoResult<Category> {
+        let mut conn = self.get_conn()?;
+        let tx = write_transaction(&mut conn)?;
+        if let Some(parent_id) = parent_id {
+            tx.query_row(
+                "SELECT id FROM categories WHERE id = (?)",
+                params![parent_id],
+                |row| row.get::<_, i32>(0),
+            )?;
+        }
+        tx.execute(
+            "INSERT INTO categories (name, parent_id) VALUES (?1, ?2)",
+            params![name, parent_id],
+        )?;
+        let category = Category {
+            id: tx.last_insert_rowid() as i32,
+            name: name.to_string(),
+            parent_id,
+        };
+        tx.commit()?;
+
+        Ok(category)
+    }
+
+    fn delete_category(&self, category_id: i32) -> RepoResult<bool> {
+        let conn = self.get_conn()?;
+        let deleted = conn.execute(
+            "DELETE FROM categories WHERE id = (?)",
+            params![category_id],
+        )?;
+
+        Ok(deleted > 0)
+    }
+
+    fn set_recipe_category(
+        &self,
+        recipe_id: i32,
+        category_id: i32,
+        assigned: bool,
+    ) -> RepoResult<bool> {
+        let mut conn = self.get_conn()?;
+        let tx = write_transaction(&mut conn)?;
+        let exists: bool = tx.query_row(
+            "SELECT EXISTS (SELECT 1 FROM recipes WHERE id = ?1 AND deleted_at IS NULL) AND EXISTS (SELECT 1 FROM categories WHERE id = ?2)",
+            params![recipe_id, category_id],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Ok(false);
+        }
+
+        if assigned {
+            tx.execute(
+                "INSERT OR IGNORE INTO recipe_categories (recipe_id, category_id) VALUES (?1, ?2)",
+                params![recipe_id, category_id],
+            )?;
+        } else {
+            tx.execute(
+                "DELETE FROM recipe_categories WHERE recipe_id = ?1 AND category_id = ?2",
+                params![recipe_id, category_id],
+            )?;
+        }
+        tx.commit()?;
+
+        Ok(true)
+    }
+
+    fn recipe_category_links(&self) -> RepoResult<Vec<(u32, i32)>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare("SELECT recipe_id, category_id FROM recipe_categories JOIN recipes ON recipe_id = id WHERE deleted_at IS NULL")?;
+        let links = stmt
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .filter_map(|x| x.ok())
+            .collect();
+
+        Ok(links)
+    }
+
+    fn ingredient_aisles(&self) -> RepoResult<HashMap<String, String>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare("SELECT name, aisle FROM ingredient_categories")?;
+        let aisles = stmt
+            .query_map(params![], |row| {
+                Ok((row.get::<_, String>(0)?.to_lowercase(), row.get(1)?))
+            })?
+            .filter_map(|x| x.ok())
+            .collect();
+
+        Ok(aisles)
+    }
+
+    fn ingredient_usage(&self) -> RepoResult<Vec<(String, u64)>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT ingredients.name, COUNT(DISTINCT recipe_id) AS uses FROM recipe_ingredients JOIN ingredients ON ingredient_id = ingredients.id JOIN recipes ON recipe_id = recipes.id WHERE deleted_at IS NULL GROUP BY ingredient_id ORDER BY uses DESC, ingredients.name",
+        )?;
+        let usage = stmt
+            .query_map(params![], |row| {
+                Ok((row.get(0)?, row.get::<_, i64>(1)? as u64))
+            })?
+            .collect::<rusqlite::Result<Vec<(String, u64)>>>()?;
+
+        Ok(usage)
+    }
+
+    fn all_ingredients(&self, sort: IngredientSort) -> RepoResult<Vec<(String, u64)>> {
+        let order = match sort {
+            IngredientSort::Name => "ingredients.name COLLATE NOCASE",
+            IngredientSort::Usage => "uses DESC, ingredients.name COLLATE NOCASE",
+        };
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT ingredients.name, COUNT(DISTINCT recipes.id) AS uses FROM ingredients LEFT JOIN recipe_ingredients ON ingredient_id = ingredients.id LEFT JOIN recipes ON recipe_id = recipes.id AND deleted_at IS NULL GROUP BY ingredients.id ORDER BY {}",
+            order
+        ))?;
+        let ingredients = stmt
+            .query_map(params![], |row| {
+                Ok((row.get(0)?, row.get::<_, i64>(1)? as u64))
+            })?
+            .collect::<rusqlite::Result<Vec<(String, u64)>>>()?;
+
+        Ok(ingredients)
+    }
+
+    fn rename_ingredient(&self, from: &str, to: &str) -> RepoResult<bool> {
+        let mut conn = self.get_conn()?;
+        let tx = write_transaction(&mut conn)?;
+
+        let find = |name: &str| {
+            tx.query_row(
+                "SELECT id FROM ingredients WHERE normalized_name = (?)",
+                params![normalize_ingredient_name(name)],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()
+        };
+        let from_id = match find(from)? {
+            Some(id) => id,
+            None => return Ok(false),
+        };
+        let kept_id = find(to)?.unwrap_or(from_id);
+
+        tx.execute(
+            "UPDATE recipes SET updated_at = (?1) WHERE id IN (SELECT recipe_id FROM recipe_ingredients WHERE ingredient_id IN ((?2), (?3)))",
+            params![timestamp_now(), from_id, kept_id],
+        )?;
+        if kept_id != from_id {
+            // Recipes listing both fold them together the way repeated
+            // lines are on save: into the first line, in its unit.
+            let mut both = tx.prepare(
+                "SELECT kept.recipe_id, kept.quantity, kept.unit, kept.quantity_max, kept.position, other.quantity, other.unit, other.quantity_max, other.position FROM recipe_ingredients AS kept JOIN recipe_ingredients AS other ON kept.recipe_id = other.recipe_id WHERE kept.ingredient_id = (?1) AND other.ingredient_id = (?2)",
+            )?;
+            // A stored line as `(position, line)`, read from the columns
+            // starting at `first`.
+            fn line(
+                row: &rusqlite::Row,
+                first: usize,
+                name: &str,
+            ) -> rusqlite::Result<(u32, IngredientQuantity)> {
+                Ok((
+                    row.get(first + 3)?,
+                    IngredientQuantity {
+                        ingredient: name.to_string(),
+                        quantity: Quantity {
+                            value: row.get(first)?,
+                            value_max: row.get(first + 2)?,
+                            unit: row.get(first + 1)?,
+                        },
+                        calories_per_unit: None,
+                    },
+                ))
+            }
+            let shared = both
+                .query_map(params![kept_id, from_id], |row| {
+                    Ok((row.get::<_, i64>(0)?, line(row, 1, to)?, line(row, 5, to)?))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            drop(both);
+            for (recipe_id, kept, other) in shared {
+                let mut lines = vec![kept, other];
+                lines.sort_by_key(|(position, _)| *position);
+                let position = lines[0].0;
+                let lines: Vec<IngredientQuantity> =
+                    lines.into_iter().map(|(_, ing_quant)| ing_quant).collect();
+                let merged = match normalize::merge_duplicate_ingredients(&lines).as_slice() {
+                    [merged] => merged.quantity.clone(),
+                    _ => return Err(RepoError::Conflict),
+                };
+                tx.execute(
+                    "UPDATE recipe_ingredients SET quantity = (?1), unit = (?2), quantity_max = (?3), position = (?4) WHERE recipe_id = (?5) AND ingredient_id = (?6)",
+                    params![merged.value, merged.unit, merged.value_max, position, recipe_id, kept_id],
+                )?;
+            }
+
+            // Recipes already listing the target keep that row; the PK
+            // clash leaves their row for `from` behind to be dropped.
+            tx.execute(
+                "UPDATE OR IGNORE recipe_ingredients SET ingredient_id = (?1) WHERE ingredient_id = (?2)",
+                params![kept_id, from_id],
+            )?;
+            tx.execute(
+                "DELETE FROM recipe_ingredients WHERE ingredient_id = (?)",
+                params![from_id],
+            )?;
+            tx.execute("DELETE FROM ingredients WHERE id = (?)", params![from_id])?;
+        }
+        tx.execute(
+            "UPDATE ingredients SET name = (?1), normalized_name = (?2) WHERE id = (?3)",
+            params![to, normalize_ingredient_name(to), kept_id],
+        )?;
+        tx.commit()?;
+
+        Ok(true)
+    }
+
+    fn add_provenance(&self, link: &ProvenanceLink) -> RepoResult<()> {
+        let conn = self.get_conn()?;
+        conn.execute(
+            "INSERT OR IGNORE INTO recipe_provenance (recipe_id, source_id, kind) VALUES (?1, ?2, ?3)",
+            params![link.recipe_id, link.source_id, link.kind.as_str()],
+        )?;
+        Ok(())
+    }
+
+    fn add_duplicate(&self, source_id: i32, copy: &Recipe) -> RepoResult<u32> {
+        let mut conn = self.get_conn()?;
+        let tx = write_transaction(&mut conn)?;
+        tx.query_row(
+            "SELECT id FROM recipes WHERE id = (?) AND deleted_at IS NULL",
+            params![source_id],
+            |row| row.get::<_, i32>(0),
+        )?;
+        let copy_id = insert_recipe(&tx, copy)?;
+        tx.execute(
+            "INSERT INTO recipe_provenance (recipe_id, source_id, kind) VALUES (?1, ?2, ?3)",
+            params![copy_id, source_id, ProvenanceKind::Duplicate.as_str()],
+        )?;
+
+        tx.commit()?;
+        Ok(copy_id)
+    }
+
+    fn provenance_sources(&self, recipe_id: i32) -> RepoResult<Vec<ProvenanceLink>> {
+        let conn = self.get_conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT source_id, kind FROM recipe_provenance WHERE recipe_id = (?) ORDER BY rowid",
+        )?;
+        let links = stmt
+            .query_map(params![recipe_id], |row| {
+                Ok((row.get::<_, i32>(0)?, row.get::<_, String>(1)?))
+            })?
+            .filter_map(|x| x.ok())
+            .filter_map(|(source_id, kind)| {
+                Some(ProvenanceLink {
+                    recipe_id,
+                    source_id,
+                    kind: ProvenanceKind::parse(&kind)?,
+                })
+            })
+            .collect();
+
+        Ok(links)
+    }
+
+    fn add_rating(&self, recipe_id: i32, score: u8) -> RepoResult<bool> {
+        let conn = self.get_conn()?;
+        let inserted = conn.execute(
+            "INSERT INTO ratings (recipe_id, score, created_at) SELECT id, ?2, ?3 FROM recipes WHERE id = ?1 AND deleted_at IS NULL",
+            params![recipe_id, score, timestamp_now()],
+        )?;
+
+        Ok(inserted > 0)
+    }
+
+    fn rating_summary(&self, recipe_id: i32) -> RepoResult<Option<RatingSummary>> {
+        let conn = self.get_conn()?;
+        let summary = conn
+            .query_row(
+                "SELECT (SELECT AVG(score) FROM ratings WHERE recipe_id = id), (SELECT COUNT(*) FROM ratings WHERE recipe_id = id) FROM recipes WHERE id = ? AND deleted_at IS NULL",
+                params![recipe_id],
+                |row| {
+                    Ok(RatingSummary {
+                        average: row.get(0)?,
+                        count: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        Ok(summary)
+    }
+
+    fn export_snapshot(&self) -> RepoResult<Snapshot> {
+        let mut conn = self.get_conn()?;
+        // One read transaction, so a write landing mid-export cannot leave
+        // the pieces out of step with each other.
+        let tx = conn.transaction()?;
+
+        let mut stmt = tx.prepare("SELECT id, created_at, updated_at, deleted_at FROM recipes")?;
+        type Times = (Option<String>, Option<String>, Option<String>);
+        let mut times: HashMap<u32, Times> = stmt
+            .query_map(params![], |row| {
+                Ok((row.get(0)?, (row.get(1)?, row.get(2)?, row.get(3)?)))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        let recipes = load_recipes_in(&tx, "1", None)?
+            .into_iter()
+            .map(|recipe| {
+                let (created_at, updated_at, deleted_at) = times
+                    .remove(&recipe.id.unwrap_or_default())
+                    .unwrap_or_default();
+                StoredRecipe {
+                    recipe,
+                    created_at,
+                    updated_at,
+                    deleted_at,
+                }
+            })
+            .collect();
+
+        let mut stmt = tx.prepare("SELECT id, name, parent_id FROM categories ORDER BY id")?;
+        let categories = stmt
+            .query_map(params![], |row| {
+                Ok(Category {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    parent_id: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let mut stmt = tx.prepare(
+            "SELECT recipe_id, category_id FROM recipe_categories ORDER BY recipe_id, category_id",
+        )?;
+        let recipe_categories = stmt
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let mut stmt = tx.prepare("SELECT name, aisle FROM ingredient_categories ORDER BY name")?;
+        let aisles = stmt
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let mut stmt =
+            tx.prepare("SELECT recipe_id, source_id, kind FROM recipe_provenance ORDER BY rowid")?;
+        let provenance = stmt
+            .query_map(params![], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?))
+            })?
+            .collect::<rusqlite::Result<Vec<(i32, i32, String)>>>()?
+            .into_iter()
+            .map(|(recipe_id, source_id, kind)| {
+                Ok(ProvenanceLink {
+                    recipe_id,
+                    source_id,
+                    kind: ProvenanceKind::parse(&kind)
+                        .ok_or_else(|| anyhow!("Unknown provenance kind {:?}", kind))?,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        let mut stmt =
+            tx.prepare("SELECT recipe_id, score, created_at FROM ratings ORDER BY rowid")?;
+        let ratings = stmt
+            .query_map(params![], |row| {
+                Ok(StoredRating {
+                    recipe_id: row.get(0)?,
+                    score: row.get(1)?,
+                    created_at: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(Snapshot {
+            recipes,
+            categories,
+            recipe_categories,
+            aisles,
+            provenance,
+            ratings,
+        })
+    }
+
+    fn import_snapshot(&self, snapshot: &Snapshot) -> RepoResult<()> {
+        let mut conn = self.get_conn()?;
+        let tx = write_transaction(&mut conn)?;
+        let occupied: bool = tx.query_row(
+            "SELECT EXISTS (SELECT 1 FROM recipes) OR EXISTS (SELECT 1 FROM categories)",
+            params![],
+            |row| row.get(0),
+        )?;
+        if occupied {
+            return Err(RepoError::Conflict);
+        }
+
+        insert_snapshot(&tx, snapshot)?;
+
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+/// Write every row of `snapshot`, keeping its ids.
+fn insert_snapshot(tx: &Transaction, snapshot: &Snapshot) -> rusqlite::Result<()> {
+    // Parents are linked in a second pass, as a category may come before
+    // its parent.
+    let mut stmt = tx.prepare("INSERT INTO categories (id, name) VALUES (?1, ?2)")?;
+    for category in snapshot.categories.iter() {
+        stmt.execute(params![category.id, category.name])?;
+    }
+    let mut stmt = tx.prepare("UPDATE categories SET parent_id = (?1) WHERE id = (?2)")?;
+    for category in snapshot.categories.iter() {
+        if let Some(parent_id) = category.parent_id {
+            stmt.execute(params![parent_id, category.id])?;
+        }
+    }
+
+    let mut stmt = tx.prepare(
+        "UPDATE recipes SET created_at = (?1), updated_at = (?2), deleted_at = (?3) WHERE id = (?4)",
+    )?;
+    for stored in snapshot.recipes.iter() {
+        let recipe_id = insert_recipe_with_id(tx, stored.recipe.id, &stored.recipe)?;
+        stmt.execute(params![
+            stored.created_at,
+            stored.updated_at,
+            stored.deleted_at,
+            recipe_id
+        ])?;
+    }
+
+    let mut stmt =
+        tx.prepare("INSERT INTO recipe_categories (recipe_id, category_id) VALUES (?1, ?2)")?;
+    for (recipe_id, category_id) in snapshot.recipe_categories.iter() {
+        stmt.execute(params![recipe_id, category_id])?;
+    }
+
+    let mut stmt =
+        tx.prepare("INSERT OR REPLACE INTO ingredient_categories (name, aisle) VALUES (?1, ?2)")?;
+    for (name, aisle) in snapshot.aisles.iter() {
+        stmt.execute(params![name, aisle])?;
+    }
+
+    let mut stmt = tx.prepare(
+        "INSERT INTO recipe_provenance (recipe_id, source_id, kind) VALUES (?1, ?2, ?3)",
+    )?;
+    for link in snapshot.provenance.iter() {
+        stmt.execute(params![link.recipe_id, link.source_id, link.kind.as_str()])?;
+    }
+
+    let mut stmt =
+        tx.prepare("INSERT INTO ratings (recipe_id, score, created_at) VALUES (?1, ?2, ?3)")?;
+    for rating in snapshot.ratings.iter() {
+        stmt.execute(params![rating.recipe_id, rating.score, rating.created_at])?;
+    }
+    stmt.finalize()
+}
+
+fn insert_steps<I: ToSql>(tx: &Transaction, recipe_id: I, steps: &[Step]) -> rusqlite::Result<()> {
+    let mut stmt = tx.prepare(
+        "INSERT INTO steps (recipe_id, text, duration_minutes, note, image_url, position) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+    )?;
+    for (position, step) in steps.iter().enumerate() {
+        stmt.execute(params![
+            recipe_id,
+            step.text,
+            step.duration_minutes,
+            step.note,
+            step.image_url,
+            position as u32
+        ])?;
+    }
+    stmt.finalize()
+}
+
+/// Begin a transaction holding the write lock from the start. A deferred one
+/// can read first, e.g. the full-text index's settings while its triggers
+/// are prepared, and SQLite then fails the upgrade to writing with "database
+/// is locked" instead of waiting out another writer.
+fn write_transaction(conn: &mut Connection) -> rusqlite::Result<Transaction<'_>> {
+    conn.transaction_with_behavior(TransactionBehavior::Immediate)
+}
+
+/// Turn free text into an FTS5 query matching every word, quoting each so
+/// FTS5 operators and punctuation are searched for literally.
+fn fts_query(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A description as stored: blank ones become NULL, so a recipe reads back
+/// with `desc: None` whether it was sent with `""` or without one.
+fn stored_desc(desc: Option<&str>) -> Option<&str> {
+    desc.filter(|desc| !desc.trim().is_empty())
+}
+
+/// Fail with `Stale` if the recipe was updated after `since`. HTTP dates
+/// have whole seconds, so so does the comparison. A missing recipe, or one
+/// without an update time, passes.
+fn check_unmodified(tx: &Transaction, recipe_id: u32, since: DateTime<Utc>) -> RepoResult<()> {
+    let updated_at: Option<String> = tx
+        .query_row(
+            "SELECT updated_at FROM recipes WHERE id = (?)",
+            params![recipe_id],
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+    if let Some(updated_at) = updated_at {
+        if parse_updated_at(&updated_at)?.timestamp() > since.timestamp() {
+            return Err(RepoError::Stale);
+        }
+    }
+    Ok(())
+}
+
+fn parse_updated_at(updated_at: &str) -> RepoResult<DateTime<Utc>> {
+    let updated_at = DateTime::parse_from_rfc3339(updated_at)
+        .with_context(|| format!("Invalid updated_at {:?}", updated_at))?;
+    Ok(updated_at.with_timezone(&Utc))
+}
+
+/// Overwrite a stored recipe's fields, steps, photos and ingredients with
+/// `updated_recipe`, leaving its favorite flag alone, and its raw source
+/// too unless a new one is given. Returns false if its id does not name a
+/// recipe outside the trash.
+fn replace_recipe(tx: &Transaction, updated_recipe: &Recipe) -> rusqlite::Result<bool> {
+    let mut stmt = tx.prepare(
+            "UPDATE recipes SET name = (?1), desc = (?2), image_url = (?3), cuisine = (?4), course = (?5), source_name = (?6), source_url = (?7), yield_description = (?8), difficulty = (?9), notes = (?10), updated_at = (?11), raw_source = COALESCE((?13), raw_source) WHERE id = (?12) AND deleted_at IS NULL",
+        )?;
+    let changed = stmt.execute(params![
+        updated_recipe.name,
+        stored_desc(updated_recipe.desc.as_deref()),
+        updated_recipe.image_url,
+        updated_recipe.cuisine,
+        updated_recipe.course,
+        updated_recipe.source_name,
+        updated_recipe.source_url,
+        updated_recipe.yield_description,
+        updated_recipe.difficulty.map(|d| d.as_str()),
+        updated_recipe.notes,
+        timestamp_now(),
+        updated_recipe.id,
+        updated_recipe.raw_source
+    ])?;
+    if changed == 0 {
+        return Ok(false);
+    }
+
+    stmt = tx.prepare("DELETE FROM steps WHERE recipe_id = (?)")?;
+    stmt.execute(params![updated_recipe.id])?;
+
+    insert_steps(tx, updated_recipe.id, &updated_recipe.steps)?;
+
+    stmt = tx.prepare("DELETE FROM recipe_photos WHERE recipe_id = (?)")?;
+    stmt.execute(params![updated_recipe.id])?;
+
+    insert_photos(tx, updated_recipe.id, &updated_recipe.photos)?;
+
+    stmt = tx.prepare("DELETE FROM recipe_ingredients WHERE recipe_id = (?)")?;
+    stmt.execute(params![updated_recipe.id])?;
+    stmt.finalize()?;
+
+    insert_ingredients(tx, updated_recipe.id, &updated_recipe.ingredients)?;
+    delete_orphan_ingredients(tx)?;
+
+    Ok(true)
+}
+
+/// Insert a recipe with its steps, ingredients and photos, returning the
+/// assigned id.
+fn insert_recipe(tx: &Transaction, recipe: &Recipe) -> rusqlite::Result<u32> {
+    insert_recipe_with_id(tx, None, recipe)
+}
+
+/// Like `insert_recipe`, storing the recipe under `recipe_id` when given
+/// instead of letting SQLite pick the id.
+fn insert_recipe_with_id(
+    tx: &Transaction,
+    recipe_id: Option<u32>,
+    recipe: &Recipe,
+) -> rusqlite::Result<u32> {
+    tx.execute(
+        "INSERT INTO recipes (id, name, desc, favorite, image_url, cuisine, course, source_name, source_url, yield_description, difficulty, notes, created_at, updated_at, raw_source) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?13, ?14)",
+        params![
+            recipe_id,
+            recipe.name,
+            stored_desc(recipe.desc.as_deref()),
+            recipe.favorite,
+            recipe.image_url,
+            recipe.cuisine,
+            recipe.course,
+            recipe.source_name,
+            recipe.source_url,
+            recipe.yield_description,
+            recipe.difficulty.map(|d| d.as_str()),
+            recipe.notes,
+            timestamp_now(),
+            recipe.raw_source
+        ],
+    )?;
+
+    let recipe_id = tx.last_insert_rowid();
+
+    insert_steps(tx, recipe_id, &recipe.steps)?;
+    insert_ingredients(tx, recipe_id, &recipe.ingredients)?;
+    insert_photos(tx, recipe_id, &recipe.photos)?;
+
+    Ok(recipe_id as u32)
+}
+
+fn insert_photos<I: ToSql>(
+    tx: &Transaction,
+    recipe_id: I,
+    photos: &[String],
+) -> rusqlite::Result<()> {
+    let mut stmt =
+        tx.prepare("INSERT INTO recipe_photos (recipe_id, url, position) VALUES (?1, ?2, ?3)")?;
+    for (position, url) in photos.iter().enumerate() {
+        stmt.execute(params![recipe_id, url, position as i64])?;
+    }
+    stmt.finalize()
+}
+
+fn insert_ingredients<I: ToSql>(
+    tx: &Transaction,
+    recipe_id: I,
+    ingredients: &[IngredientQuantity],
+) -> rusqlite::Result<()> {
+    // The first spelling stored becomes the display name for every variant.
+    // OR IGNORE makes the existence check and insert one atomic step, so
+    // concurrent writers adding the same new ingredient cannot collide.
+    let mut ing_stmt =
+        tx.prepare("INSERT OR IGNORE INTO ingredients (name, normalized_name) VALUES (?1, ?2)")?;
+    let mut quantity_stmt = tx.prepare("INSERT INTO recipe_ingredients (recipe_id, ingredient_id, quantity, unit, calories_per_unit, quantity_max, position) VALUES (?1, (SELECT id FROM ingredients WHERE normalized_name = ?2), ?3, ?4, ?5, ?6, ?7)")?;
+    // One row per ingredient, so repeated lines are added together first.
+    let merged = normalize::merge_duplicate_ingredients(ingredients);
+    for (position, ing_quant) in merged.iter().enumerate() {
+        let normalized = normalize_ingredient_name(&ing_quant.ingredient);
+        ing_stmt.execute(params![ing_quant.ingredient, normalized])?;
+        let quantity = &ing_quant.quantity;
+        quantity_stmt.execute(params![
+            recipe_id,
+            normalized,
+            quantity.value,
+            quantity.unit,
+            ing_quant.calories_per_unit,
+            quantity.value_max,
+            position as u32
+        ])?;
+    }
+
+    ing_stmt.finalize()?;
+    quantity_stmt.finalize()
+}
+
+/// Remove ingredient rows no recipe refers to any more, returning how many
+/// were removed.
+fn delete_orphan_ingredients(tx: &Transaction) -> rusqlite::Result<usize> {
+    tx.execute(
+        "DELETE FROM ingredients WHERE id NOT IN (SELECT ingredient_id FROM recipe_ingredients WHERE ingredient_id IS NOT NULL)",
+        params![],
+    )
+}
+
+fn load_recipe(conn: &Connection, recipe_id: i32) -> rusqlite::Result<Option<Recipe>> {
+    load_recipe_where(conn, "deleted_at IS NULL", recipe_id)
+}
+
+/// Load a recipe if its row also satisfies `condition`.
+fn load_recipe_where(
+    conn: &Connection,
+    condition: &str,
+    recipe_id: i32,
+) -> rusqlite::Result<Option<Recipe>> {
+    conn.query_row(
+        &format!(
+            "SELECT id, name, desc, favorite, image_url, cuisine, course, source_name, source_url, yield_description, difficulty, notes, raw_source FROM recipes WHERE id = (?) AND {}",
+            condition
+        ),
+        params![recipe_id],
+        |row| {
+            Ok(Recipe {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                desc: row.get(2)?,
+                steps: load_steps(conn, row.get(0)?)?,
+                ingredients: load_ingredients(conn, row.get(0)?)?,
+                favorite: row.get(3)?,
+                image_url: row.get(4)?,
+                cuisine: row.get(5)?,
+                course: row.get(6)?,
+                source_name: row.get(7)?,
+                source_url: row.get(8)?,
+                yield_description: row.get(9)?,
+                difficulty: load_difficulty(row.get(10)?),
+                notes: row.get(11)?,
+                raw_source: row.get(12)?,
+                photos: load_photos(conn, row.get(0)?)?,
+            })
+        },
+    )
+    .optional()
+}
+
+/// A stored difficulty level. Values this version does not know, e.g. ones
+/// written by a newer release, read as no difficulty.
+fn load_difficulty(stored: Option<String>) -> Option<Difficulty> {
+    stored.as_deref().and_then(Difficulty::parse)
+}
+
+/// Escape `%`, `_` and `\` so user input matches literally in a
+/// `LIKE ... ESCAPE '\'` pattern.
+fn escape_like(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Distinct non-empty values of a `recipes` column with their counts. Values
+/// differing only in case are counted together under one spelling.
+fn facet_counts(conn: &Connection, column: &str) -> rusqlite::Result<Vec<FacetCount>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT MIN({0}), COUNT(*) FROM recipes WHERE {0} IS NOT NULL AND {0} != '' AND deleted_at IS NULL GROUP BY {0} COLLATE NOCASE ORDER BY {0} COLLATE NOCASE",
+        column
+    ))?;
+    let counts = stmt
+        .query_map(params![], |row| {
+            Ok(FacetCount {
+                value: row.get(0)?,
+                count: row.get(1)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<FacetCount>>>()?;
+
+    Ok(counts)
+}
+
+/// Ids bound in one `IN (...)` list, well under SQLite's default limit of
+/// 999 variables per statement.
+const MAX_IDS_PER_QUERY: usize = 500;
+
+/// Recipes outside the trash in the order their ids are given, loaded in
+/// batches of `MAX_IDS_PER_QUERY` with a fixed number of queries each.
+/// Missing ids are skipped; repeated ones repeat the recipe.
+fn load_recipes_by_id(conn: &Connection, recipe_ids: Vec<i32>) -> rusqlite::Result<Vec<Recipe>> {
+    let mut found: HashMap<i32, Recipe> = HashMap::new();
+    for batch in recipe_ids.chunks(MAX_IDS_PER_QUERY) {
+        for recipe in load_recipes_in(conn, "deleted_at IS NULL", Some(batch))? {
+            found.insert(recipe.id.unwrap_or_default() as i32, recipe);
+        }
+    }
+
+    Ok(recipe_ids
+        .iter()
+        .filter_map(|recipe_id| found.get(recipe_id).cloned())
+        .collect())
+}
+
+/// Load every recipe with a fixed number of queries, one per table, grouping
+/// child rows by `recipe_id` in memory. Child rows come back in the same
+/// order as the per-recipe loaders return them.
+fn load_all_recipes(conn: &Connection) -> rusqlite::Result<Vec<Recipe>> {
+    load_recipes_in(conn, "deleted_at IS NULL", None)
+}
+
+/// Like `load_all_recipes`, limited to recipes matching `condition` and to
+/// `recipe_ids` when given. Recipes come back in id order.
+fn load_recipes_in(
+    conn: &Connection,
+    condition: &str,
+    recipe_ids: Option<&[i32]>,
+) -> rusqlite::Result<Vec<Recipe>> {
+    let (child_filter, recipe_filter) = match recipe_ids {
+        Some(recipe_ids) => {
+            let placeholders = vec!["?"; recipe_ids.len()].join(", ");
+            (
+                format!("WHERE recipe_id IN ({})", placeholders),
+                format!("AND id IN ({})", placeholders),
+            )
+        }
+        None => (String::new(), String::new()),
+    };
+    let recipe_ids = recipe_ids.unwrap_or(&[]);
+
+    let mut steps: HashMap<u32, Vec<Step>> = HashMap::new();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT recipe_id, text, duration_minutes, note, image_url FROM steps {} ORDER BY recipe_id, position",
+        child_filter
+    ))?;
+    let rows = stmt.query_map(recipe_ids, |row| Ok((row.get(0)?, step_from_row(row, 1)?)))?;
+    for (recipe_id, step) in rows.filter_map(|x| x.ok()) {
+        steps.entry(recipe_id).or_default().push(step);
+    }
+
+    let mut ingredients: HashMap<u32, Vec<IngredientQuantity>> = HashMap::new();
+    let mut stmt = conn.prepare(&format!("SELECT recipe_id, name, quantity, unit, calories_per_unit, quantity_max FROM recipe_ingredients LEFT JOIN ingredients ON ingredient_id = id {} ORDER BY recipe_id, position", child_filter))?;
+    let rows = stmt.query_map(recipe_ids, |row| {
+        Ok((
+            row.get(0)?,
+            IngredientQuantity {
+                ingredient: row.get(1)?,
+                quantity: Quantity {
+                    value: row.get(2)?,
+                    value_max: row.get(5)?,
+                    unit: row.get(3)?,
+                },
+                calories_per_unit: row.get(4)?,
+            },
+        ))
+    })?;
+    for (recipe_id, ing_quant) in rows.filter_map(|x| x.ok()) {
+        ingredients.entry(recipe_id).or_default().push(ing_quant);
+    }
+
+    let mut photos: HashMap<u32, Vec<String>> = HashMap::new();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT recipe_id, url FROM recipe_photos {} ORDER BY recipe_id, position",
+        child_filter
+    ))?;
+    let rows = stmt.query_map(recipe_ids, |row| Ok((row.get(0)?, row.get(1)?)))?;
+    for row in rows {
+        let (recipe_id, url) = row?;
+        photos.entry(recipe_id).or_default().push(url);
+    }
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, name, desc, favorite, image_url, cuisine, course, source_name, source_url, yield_description, difficulty, notes, raw_source FROM recipes WHERE ({}) {} ORDER BY id",
+        condition, recipe_filter
+    ))?;
+    let recipes = stmt
+        .query_map(recipe_ids, |row| {
+            let recipe_id: u32 = row.get(0)?;
+            Ok(Recipe {
+                id: Some(recipe_id),
+                name: row.get(1)?,
+                desc: row.get(2)?,
+                steps: steps.remove(&recipe_id).unwrap_or_default(),
+                ingredients: ingredients.remove(&recipe_id).unwrap_or_default(),
+                favorite: row.get(3)?,
+                image_url: row.get(4)?,
+                cuisine: row.get(5)?,
+                course: row.get(6)?,
+                source_name: row.get(7)?,
+                source_url: row.get(8)?,
+                yield_description: row.get(9)?,
+                difficulty: load_difficulty(row.get(10)?),
+                notes: row.get(11)?,
+                raw_source: row.get(12)?,
+                photos: photos.remove(&recipe_id).unwrap_or_default(),
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<Recipe>>>()?;
+
+    Ok(recipes)
+}
+
+fn load_photos(conn: &Connection, recipe_id: u32) -> rusqlite::Result<Vec<String>> {
+    let mut stmt =
+        conn.prepare("SELECT url FROM recipe_photos WHERE recipe_id = ? ORDER BY position")?;
+
+    let photos = stmt
+        .query_map(params![recipe_id], |row| row.get(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+
+    Ok(photos)
+}
+
+/// A step from the `text`, `duration_minutes`, `note` and `image_url`
+/// columns, in that order starting at `first`.
+fn step_from_row(row: &rusqlite::Row, first: usize) -> rusqlite::Result<Step> {
+    Ok(Step {
+        text: row.get(first)?,
+        duration_minutes: row.get(first + 1)?,
+        note: row.get(first + 2)?,
+        image_url: row.get(first + 3)?,
+    })
+}
+
+fn load_steps(conn: &Connection, recipe_id: u32) -> rusqlite::Result<Vec<Step>> {
+    let mut stmt = conn.prepare(
+        "SELECT text, duration_minutes, note, image_url FROM steps WHERE recipe_id = ? ORDER BY position",
+    )?;
+
+    let steps: Vec<Step> = stmt
+        .query_map(params![recipe_id], |row| step_from_row(row, 0))?
+        .filter_map(|x| x.ok())
+        .collect();
+
+    Ok(steps)
+}
+
+fn load_ingredients(
+    conn: &Connection,
+    recipe_id: u32,
+) -> rusqlite::Result<Vec<IngredientQuantity>> {
+    let mut stmt = conn.prepare("SELECT name, quantity, unit, calories_per_unit, quantity_max FROM recipe_ingredients LEFT JOIN ingredients ON ingredient_id = id WHERE recipe_id = ? ORDER BY position")?;
+    let ingredients = stmt
+        .query_map(params![recipe_id], |row| {
+            Ok(IngredientQuantity {
+                ingredient: row.get(0)?,
+                quantity: Quantity {
+                    value: row.get(1)?,
+                    value_max: row.get(4)?,
+                    unit: row.get(2)?,
+                },
+                calories_per_unit: row.get(3)?,
+            })
+        })?
+        .filter_map(|x| x.ok())
+        .collect();
+
+    Ok(ingredients)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RecipeBuilder;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Numbers the databases made by this test run so each test gets its own.
+    static NEXT_DATABASE: AtomicUsize = AtomicUsize::new(0);
+
+    /// A fresh in-memory database. The name is a shared-cache URI, so
+    /// connections opened with it see the same data for as long as the repo
+    /// holds its pool.
+    fn setup_repo() -> (Box<dyn Repo>, String) {
+        let name = format!(
+            "file:recipes-{}?mode=memory&cache=shared",
+            NEXT_DATABASE.fetch_add(1, Ordering::Relaxed)
+        );
+        (create_repo_with_name(&name).unwrap(), name)
+    }
+
+    /// A database file path no other test uses, for tests that depend on
+    /// file behavior such as locking between connections or data outliving
+    /// every connection.
+    fn file_database_name() -> String {
+        let name = format!(
+            "target/tests/recipes-{}-{}.db",
+            std::process::id(),
+            NEXT_DATABASE.fetch_add(1, Ordering::Relaxed)
+        );
+        cleanup_repo(&name);
+        name
+    }
+
+    /// Like `setup_repo`, backed by a file from `file_database_name`.
+    fn setup_file_repo() -> (Box<dyn Repo>, String) {
+        let name = file_database_name();
+        (create_repo_with_name(&name).unwrap(), name)
+    }
+
+    fn purge_all(repo: &dyn Repo) {
+        repo.purge_deleted(Utc::now() + chrono::Duration::seconds(1))
+            .unwrap();
+    }
+
+    /// Remove a database file. In-memory databases vanish with their repo.
+    fn cleanup_repo(file_loc: &str) {
+        if file_loc.starts_with("file:") {
+            return;
+        }
+        match std::fs::remove_file(file_loc) {
+            Ok(()) => {}
+            Err(e) => error!("Testing error: {}", e),
+        };
+    }
+
+    #[test]
+    fn test_add() {
+        let (repo, name) = setup_repo();
+
+        let recipe = RecipeBuilder::default()
+            .desc("Test Description")
+            .step("Step 1")
+            .ingredient("Potato", 1.0, "whole")
+            .build();
+
+        assert_eq!(Vec::<Recipe>::new(), repo.load_recipes().unwrap());
+        assert_eq!(1, repo.add_recipe(&recipe).unwrap());
+        let stored = Recipe {
+            id: Some(1),
+            ..recipe.clone()
+        };
+        assert_eq!(vec![stored.clone()], repo.load_recipes().unwrap());
+
+        // A caller-supplied id is ignored, even one that is already taken.
+        let recipe_2 = Recipe {
+            id: Some(1),
+            ..recipe.clone()
+        };
+        assert_eq!(2, repo.add_recipe(&recipe_2).unwrap());
+
+        let recipe_3 = Recipe {
+            id: Some(42),
+            ..recipe.clone()
+        };
+        assert_eq!(3, repo.add_recipe(&recipe_3).unwrap());
+        assert_eq!(None, repo.get_recipe(42).unwrap());
+
+        let ids: Vec<Option<u32>> = repo
+            .load_recipes()
+            .unwrap()
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        assert_eq!(vec![Some(1), Some(2), Some(3)], ids);
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_delete() {
+        let (repo, name) = setup_repo();
+
+        let recipe = RecipeBuilder::default()
+            .id(1)
+            .desc("Test Description")
+            .step("Step 1")
+            .ingredient("Potato", 1.0, "whole")
+            .build();
+
+        assert_eq!(Vec::<Recipe>::new(), repo.load_recipes().unwrap());
+        repo.add_recipe(&recipe).unwrap();
+        assert_eq!(vec![recipe.clone()], repo.load_recipes().unwrap());
+
+        repo.delete_recipe(1).unwrap();
+        assert_eq!(Vec::<Recipe>::new(), repo.load_recipes().unwrap());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_update() {
+        let (repo, name) = setup_repo();
+
+        let recipe = RecipeBuilder::default()
+            .id(1)
+            .desc("Test Description")
+            .step("Step 1")
+            .ingredient("Potato", 1.0, "whole")
+            .build();
+
+        assert_eq!(Vec::<Recipe>::new(), repo.load_recipes().unwrap());
+        repo.add_recipe(&recipe).unwrap();
+        assert_eq!(vec![recipe.clone()], repo.load_recipes().unwrap());
+
+        let recipe_2 = Recipe {
+            steps: vec![],
+            ..recipe.clone()
+        };
+
+        assert!(repo.update_recipe(&recipe_2, None).unwrap());
+        assert_eq!(vec![recipe_2.clone()], repo.load_recipes().unwrap());
+
+        let missing = Recipe {
+            id: Some(99),
+            ..recipe_2.clone()
+        };
+        assert!(!repo.update_recipe(&missing, None).unwrap());
+        assert_eq!(vec![recipe_2], repo.load_recipes().unwrap());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_quantity_range_round_trip() {
+        let (repo, name) = setup_repo();
+
+        let mut recipe = RecipeBuilder::default()
+            .ingredient("Garlic", 2.0, "clove")
+            .ingredient("Salt", 1.0, "tsp")
+            .build();
+        recipe.ingredients[0].quantity.value_max = Some(3.0);
+        let recipe_id = repo.add_recipe(&recipe).unwrap();
+
+        let stored = repo.get_recipe(recipe_id as i32).unwrap().unwrap();
+        assert_eq!(recipe.ingredients, stored.ingredients);
+        assert_eq!(
+            recipe.ingredients,
+            repo.load_recipes().unwrap()[0].ingredients
+        );
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_update_unless_modified() {
+        let (repo, name) = setup_repo();
+
+        let recipe_id = repo
+            .add_recipe(&RecipeBuilder::default().name("Toast").build())
+            .unwrap();
+        let stored = repo.get_recipe(recipe_id as i32).unwrap().unwrap();
+        let before = Utc::now() - chrono::Duration::seconds(5);
+        let after = Utc::now() + chrono::Duration::seconds(5);
+
+        let stale = Recipe {
+            name: "Stale toast".to_string(),
+            ..stored.clone()
+        };
+        assert!(matches!(
+            repo.update_recipe(&stale, Some(before)),
+            Err(RepoError::Stale)
+        ));
+        assert!(matches!(
+            repo.upsert_recipe(&stale, Some(before)),
+            Err(RepoError::Stale)
+        ));
+        assert_eq!(
+            "Toast",
+            repo.get_recipe(recipe_id as i32).unwrap().unwrap().name
+        );
+
+        let fresh = Recipe {
+            name: "Buttered toast".to_string(),
+            ..stored
+        };
+        assert!(repo.update_recipe(&fresh, Some(after)).unwrap());
+        assert_eq!(
+            "Buttered toast",
+            repo.get_recipe(recipe_id as i32).unwrap().unwrap().name
+        );
+
+        // Creating a recipe has nothing to conflict with.
+        let created = RecipeBuilder::default().id(40).build();
+        assert!(repo.upsert_recipe(&created, Some(before)).unwrap());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_upsert() {
+        let (repo, name) = setup_repo();
+
+        let recipe = Recipe {
+            id: Some(5),
+            name: "Test Recipe".to_string(),
+            desc: None,
+            steps: vec![Step::new("Step 1")],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        assert!(repo.upsert_recipe(&recipe, None).unwrap());
+        assert_eq!(vec![recipe.clone()], repo.load_recipes().unwrap());
+
+        let renamed = Recipe {
+            name: "Renamed".to_string(),
+            ..recipe.clone()
+        };
+        assert!(!repo.upsert_recipe(&renamed, None).unwrap());
+        assert!(!repo.upsert_recipe(&renamed, None).unwrap());
+        assert_eq!(vec![renamed.clone()], repo.load_recipes().unwrap());
+
+        // New recipes are numbered after the upserted id.
+        assert_eq!(6, repo.add_recipe(&recipe).unwrap());
+
+        repo.delete_recipe(5).unwrap();
+        assert!(matches!(
+            repo.upsert_recipe(&renamed, None),
+            Err(RepoError::Conflict)
+        ));
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_recipes_modified_since() {
+        let (repo, name) = setup_repo();
+
+        let recipe = |name: &str| Recipe {
+            id: None,
+            name: name.to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        let toast_id = repo.add_recipe(&recipe("Toast")).unwrap();
+        repo.add_recipe(&recipe("Tea")).unwrap();
+        let since = Utc::now();
+
+        assert!(repo.recipes_modified_since(since).unwrap().is_empty());
+
+        let toast = Recipe {
+            id: Some(toast_id),
+            ..recipe("Buttered toast")
+        };
+        assert!(repo.update_recipe(&toast, None).unwrap());
+        assert_eq!(vec![toast], repo.recipes_modified_since(since).unwrap());
+        assert_eq!(
+            2,
+            repo.recipes_modified_since(since - chrono::Duration::hours(1))
+                .unwrap()
+                .len()
+        );
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_recipes_missing() {
+        let (repo, name) = setup_repo();
+
+        let complete = RecipeBuilder::default()
+            .name("Toast")
+            .desc("Crisp")
+            .step("Toast the bread")
+            .ingredient("Bread", 1.0, "slice")
+            .build();
+        let blank_desc = RecipeBuilder::default()
+            .name("Tea")
+            .desc("")
+            .step("Steep")
+            .ingredient("Tea leaves", 1.0, "tsp")
+            .build();
+        let no_steps = RecipeBuilder::default()
+            .name("Salad")
+            .ingredient("Lettuce", 1.0, "head")
+            .build();
+        let no_ingredients = RecipeBuilder::default()
+            .name("Boiled water")
+            .desc("Hot")
+            .step("Boil")
+            .build();
+        let ids: Vec<u32> = [&complete, &blank_desc, &no_steps, &no_ingredients]
+            .iter()
+            .map(|recipe| repo.add_recipe(recipe).unwrap())
+            .collect();
+
+        let missing = |field| -> Vec<u32> {
+            repo.recipes_missing(field, None)
+                .unwrap()
+                .iter()
+                .map(|recipe| recipe.id.unwrap())
+                .collect()
+        };
+        assert_eq!(vec![ids[1], ids[2]], missing(MissingField::Desc));
+        assert_eq!(vec![ids[3]], missing(MissingField::Ingredients));
+        assert_eq!(vec![ids[2]], missing(MissingField::Steps));
+
+        let stored = repo.recipes_missing(MissingField::Steps, None).unwrap();
+        assert_eq!(no_steps.ingredients, stored[0].ingredients);
+        assert!(repo
+            .recipes_missing(MissingField::Desc, Some(Utc::now()))
+            .unwrap()
+            .is_empty());
+
+        repo.delete_recipe(ids[2] as i32).unwrap();
+        assert_eq!(vec![ids[1]], missing(MissingField::Desc));
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_patch() {
+        let (repo, name) = setup_repo();
+
+        let recipe = RecipeBuilder::default()
+            .id(1)
+            .desc("Test Description")
+            .step("Step 1")
+            .ingredient("Potato", 1.0, "whole")
+            .build();
+
+        assert_eq!(None, repo.patch_recipe(1, &RecipePatch::default()).unwrap());
+        repo.add_recipe(&recipe).unwrap();
+
+        let rename = RecipePatch {
+            name: Some("Renamed".to_string()),
+            ..RecipePatch::default()
+        };
+        let renamed = Recipe {
+            name: "Renamed".to_string(),
+            ..recipe.clone()
+        };
+        assert_eq!(
+            Some(renamed.clone()),
+            repo.patch_recipe(1, &rename).unwrap()
+        );
+
+        let clear_steps = RecipePatch {
+            steps: Some(vec![]),
+            ..RecipePatch::default()
+        };
+        let cleared = Recipe {
+            steps: vec![],
+            ..renamed
+        };
+        assert_eq!(
+            Some(cleared.clone()),
+            repo.patch_recipe(1, &clear_steps).unwrap()
+        );
+        assert_eq!(vec![cleared], repo.load_recipes().unwrap());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_provenance() {
+        let (repo, name) = setup_repo();
+
+        let recipe = Recipe {
+            id: None,
+            name: "Test Recipe".to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        let source_id = repo.add_recipe(&recipe).unwrap() as i32;
+        let copy_id = repo.add_recipe(&recipe).unwrap() as i32;
+
+        let link = ProvenanceLink {
+            recipe_id: copy_id,
+            source_id,
+            kind: ProvenanceKind::Duplicate,
+        };
+        repo.add_provenance(&link).unwrap();
+
+        assert_eq!(vec![link], repo.provenance_sources(copy_id).unwrap());
+        assert_eq!(
+            Vec::<ProvenanceLink>::new(),
+            repo.provenance_sources(source_id).unwrap()
+        );
+
+        repo.delete_recipe(copy_id).unwrap();
+        purge_all(repo.as_ref());
+        purge_all(repo.as_ref());
+        assert_eq!(
+            Vec::<ProvenanceLink>::new(),
+            repo.provenance_sources(copy_id).unwrap()
+        );
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_quick_check() {
+        let (_repo, name) = setup_repo();
+
+        assert_eq!(Vec::<String>::new(), quick_check(&name).unwrap());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_ingredient_aisles() {
+        let (repo, name) = setup_repo();
+
+        let aisles = repo.ingredient_aisles().unwrap();
+        assert_eq!(Some(&"produce".to_string()), aisles.get("potato"));
+        assert_eq!(None, aisles.get("saffron"));
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_load_recipe_summaries() {
+        let (repo, name) = setup_repo();
+
+        let recipe = Recipe {
+            id: None,
+            name: "Test Recipe".to_string(),
+            desc: Some("Test Description".to_string()),
+            steps: vec![Step::new("Step 1")],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        repo.add_recipe(&recipe).unwrap();
+
+        assert_eq!(
+            vec![RecipeSummary {
+                id: 1,
+                name: "Test Recipe".to_string(),
+                desc: Some("Test Description".to_string()),
+                image_url: None,
+            }],
+            repo.load_recipe_summaries().unwrap()
+        );
+        assert_eq!(Vec::<Category>::new(), repo.load_categories().unwrap());
+        assert_eq!(
+            Vec::<(u32, i32)>::new(),
+            repo.recipe_category_links().unwrap()
+        );
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_recipes_containing() {
+        let (repo, name) = setup_repo();
+
+        let ingredient = |name: &str| IngredientQuantity {
+            ingredient: name.to_string(),
+            quantity: Quantity {
+                value: 1.0,
+                value_max: None,
+                unit: "cup".to_string(),
+            },
+            calories_per_unit: None,
+        };
+        let pancakes = Recipe {
+            id: Some(1),
+            name: "Pancakes".to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![ingredient("Egg"), ingredient("Flour")],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        let omelette = Recipe {
+            id: Some(2),
+            name: "Omelette".to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![
+                ingredient("Egg"),
+                ingredient("Cheese"),
+                ingredient("CRÈME FRAÎCHE"),
+            ],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        repo.add_recipe(&pancakes).unwrap();
+        repo.add_recipe(&omelette).unwrap();
+
+        // Names fold case beyond ASCII, as they do when stored.
+        assert_eq!(
+            vec![omelette.clone()],
+            repo.recipes_containing(&[" Crème Fraîche".to_string()], false)
+                .unwrap()
+        );
+        let search = vec!["egg".to_string(), "FLOUR".to_string()];
+        assert_eq!(
+            vec![pancakes.clone(), omelette],
+            repo.recipes_containing(&search, false).unwrap()
+        );
+        assert_eq!(
+            vec![pancakes],
+            repo.recipes_containing(&search, true).unwrap()
+        );
+        assert_eq!(
+            Vec::<Recipe>::new(),
+            repo.recipes_containing(&["Saffron".to_string()], false)
+                .unwrap()
+        );
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_upgrade_adds_calories_column() {
+        let name = file_database_name();
+        std::fs::create_dir_all("target/tests").unwrap();
+        {
+            let conn = rusqlite::Connection::open(&name).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE recipe_ingredients (recipe_id INTEGER, ingredient_id INTEGER, quantity REAL, unit TEXT, CONSTRAINT COMP_K PRIMARY KEY (recipe_id, ingredient_id));",
+            )
+            .unwrap();
+        }
+
+        let repo = create_repo_with_name(&name).unwrap();
+        let recipe = Recipe {
+            id: Some(1),
+            name: "Test Recipe".to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![IngredientQuantity {
+                ingredient: "Egg".to_string(),
+                quantity: Quantity {
+                    value: 2.0,
+                    value_max: None,
+                    unit: "whole".to_string(),
+                },
+                calories_per_unit: Some(78.0),
+            }],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        repo.add_recipe(&recipe).unwrap();
+        assert_eq!(Some(recipe), repo.get_recipe(1).unwrap());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_favorites() {
+        let (repo, name) = setup_repo();
+
+        let recipe = Recipe {
+            id: None,
+            name: "Test Recipe".to_string(),
+            desc: None,
+            steps: vec![Step::new("Step 1")],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        let first = repo.add_recipe(&recipe).unwrap() as i32;
+        let second = repo.add_recipe(&recipe).unwrap() as i32;
+
+        assert!(repo.load_favorites().unwrap().is_empty());
+
+        assert!(repo.set_favorite(second, true).unwrap());
+        let favorites = repo.load_favorites().unwrap();
+        assert_eq!(1, favorites.len());
+        assert_eq!(Some(second as u32), favorites[0].id);
+        assert!(favorites[0].favorite);
+        assert_eq!(recipe.steps, favorites[0].steps);
+        assert!(!repo.get_recipe(first).unwrap().unwrap().favorite);
+
+        assert!(repo.set_favorite(second, false).unwrap());
+        assert!(repo.load_favorites().unwrap().is_empty());
+
+        assert!(!repo.set_favorite(99, true).unwrap());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_pool_size() {
+        let name = file_database_name();
+        let pool = build_pool(&RepoConfig {
+            name: name.clone(),
+            pool_size: 2,
+        })
+        .unwrap();
+
+        let first = pool.get().unwrap();
+        let second = pool.get().unwrap();
+        assert!(pool.try_get().is_none());
+
+        drop(first);
+        assert!(pool.try_get().is_some());
+
+        drop(second);
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_image_url() {
+        let (repo, name) = setup_repo();
+
+        let recipe = Recipe {
+            id: None,
+            name: "Test Recipe".to_string(),
+            desc: None,
+            steps: vec![Step::new("Step 1")],
+            ingredients: vec![],
+            favorite: false,
+            image_url: Some("https://example.com/toast.jpg".to_string()),
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        let recipe_id = repo.add_recipe(&recipe).unwrap();
+        let stored = repo.get_recipe(recipe_id as i32).unwrap().unwrap();
+        assert_eq!(recipe.image_url, stored.image_url);
+
+        let cleared = Recipe {
+            image_url: None,
+            ..stored
+        };
+        assert!(repo.update_recipe(&cleared, None).unwrap());
+        assert_eq!(vec![cleared], repo.load_recipes().unwrap());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_source() {
+        let (repo, name) = setup_repo();
+
+        let recipe = Recipe {
+            id: None,
+            name: "Test Recipe".to_string(),
+            desc: None,
+            steps: vec![Step::new("Step 1")],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: Some("The Joy of Cooking".to_string()),
+            source_url: Some("https://example.com/toast".to_string()),
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        let recipe_id = repo.add_recipe(&recipe).unwrap();
+        let stored = repo.get_recipe(recipe_id as i32).unwrap().unwrap();
+        assert_eq!(recipe.source_name, stored.source_name);
+        assert_eq!(recipe.source_url, stored.source_url);
+
+        let cleared = Recipe {
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            ..stored
+        };
+        assert!(repo.update_recipe(&cleared, None).unwrap());
+        assert_eq!(vec![cleared], repo.load_recipes().unwrap());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_categories() {
+        let (repo, name) = setup_repo();
+
+        let recipe = |name: &str| Recipe {
+            id: None,
+            name: name.to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        let soup = repo.add_recipe(&recipe("Soup")).unwrap() as i32;
+        let stew = repo.add_recipe(&recipe("Stew")).unwrap() as i32;
+
+        let mains = repo.add_category("Mains", None).unwrap();
+        let winter = repo.add_category("Winter", Some(mains.id)).unwrap();
+        assert_eq!(
+            vec![mains.clone(), winter.clone()],
+            repo.load_categories().unwrap()
+        );
+        assert!(matches!(
+            repo.add_category("Mains", None),
+            Err(RepoError::Conflict)
+        ));
+        assert!(matches!(
+            repo.add_category("Spring", Some(99)),
+            Err(RepoError::NotFound)
+        ));
+
+        assert!(repo.set_recipe_category(soup, mains.id, true).unwrap());
+        assert!(repo.set_recipe_category(soup, mains.id, true).unwrap());
+        assert!(repo.set_recipe_category(soup, winter.id, true).unwrap());
+        assert!(repo.set_recipe_category(stew, winter.id, true).unwrap());
+        assert!(!repo.set_recipe_category(99, mains.id, true).unwrap());
+        assert!(!repo.set_recipe_category(soup, 99, true).unwrap());
+        let links = |repo: &dyn Repo| {
+            let mut links = repo.recipe_category_links().unwrap();
+            links.sort_unstable();
+            links
+        };
+        assert_eq!(
+            vec![
+                (soup as u32, mains.id),
+                (soup as u32, winter.id),
+                (stew as u32, winter.id)
+            ],
+            links(repo.as_ref())
+        );
+
+        assert!(repo.set_recipe_category(soup, mains.id, false).unwrap());
+        assert!(repo.set_recipe_category(soup, mains.id, false).unwrap());
+
+        // Deleting a parent detaches its recipes and lifts its children to
+        // the top level; the recipes themselves stay.
+        assert!(repo.set_recipe_category(soup, mains.id, true).unwrap());
+        assert!(repo.delete_category(mains.id).unwrap());
+        assert!(!repo.delete_category(mains.id).unwrap());
+        assert_eq!(
+            vec![Category {
+                parent_id: None,
+                ..winter.clone()
+            }],
+            repo.load_categories().unwrap()
+        );
+        assert_eq!(
+            vec![(soup as u32, winter.id), (stew as u32, winter.id)],
+            links(repo.as_ref())
+        );
+        assert_eq!(2, repo.load_recipes().unwrap().len());
+
+        assert!(repo.delete_category(winter.id).unwrap());
+        assert!(links(repo.as_ref()).is_empty());
+        assert_eq!(2, repo.load_recipes().unwrap().len());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_merge_recipes() {
+        let (repo, name) = setup_repo();
+
+        let recipe = |name: &str| Recipe {
+            id: None,
+            name: name.to_string(),
+            desc: None,
+            steps: vec![Step::new("Mix")],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        let kept_id = repo.add_recipe(&recipe("Pancakes")).unwrap();
+        let remove_id = repo.add_recipe(&recipe("Hotcakes")).unwrap();
+        let conn = rusqlite::Connection::open(&name).unwrap();
+        conn.execute_batch(
+            "INSERT INTO categories (id, name) VALUES (1, 'Breakfast'), (2, 'Sweet');
+            INSERT INTO recipe_categories (recipe_id, category_id) VALUES (1, 1), (2, 1), (2, 2);",
+        )
+        .unwrap();
+
+        let kept = Recipe {
+            steps: vec![Step::new("Mix"), Step::new("Serve")],
+            ..repo.get_recipe(kept_id as i32).unwrap().unwrap()
+        };
+        // A missing source rolls back the whole merge.
+        assert!(!repo.merge_recipes(&kept, 99, true).unwrap());
+        assert_eq!(
+            vec![Step::new("Mix")],
+            repo.get_recipe(kept_id as i32).unwrap().unwrap().steps
+        );
+
+        assert!(repo.merge_recipes(&kept, remove_id as i32, true).unwrap());
+        assert_eq!(kept, repo.get_recipe(kept_id as i32).unwrap().unwrap());
+        assert_eq!(None, repo.get_recipe(remove_id as i32).unwrap());
+        assert_eq!(1, repo.load_deleted().unwrap().len());
+        let mut links = repo.recipe_category_links().unwrap();
+        links.sort_unstable();
+        assert_eq!(vec![(1, 1), (1, 2)], links);
+        assert_eq!(
+            vec![ProvenanceLink {
+                recipe_id: kept_id as i32,
+                source_id: remove_id as i32,
+                kind: ProvenanceKind::Merge,
+            }],
+            repo.provenance_sources(kept_id as i32).unwrap()
+        );
+
+        // Already in the trash.
+        assert!(!repo.merge_recipes(&kept, remove_id as i32, false).unwrap());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_clear_all() {
+        let (repo, name) = setup_repo();
+
+        let recipe = Recipe {
+            id: None,
+            name: "Pancakes".to_string(),
+            desc: None,
+            steps: vec![Step::new("Mix"), Step::new("Fry")],
+            ingredients: vec![IngredientQuantity {
+                ingredient: "Flour".to_string(),
+                quantity: Quantity {
+                    value: 2.0,
+                    value_max: None,
+                    unit: "cups".to_string(),
+                },
+                calories_per_unit: None,
+            }],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        repo.add_recipe(&recipe).unwrap();
+        let trashed = repo.add_recipe(&recipe).unwrap();
+        repo.delete_recipe(trashed as i32).unwrap();
+
+        repo.clear_all().unwrap();
+
+        assert_eq!(Vec::<Recipe>::new(), repo.load_recipes().unwrap());
+        assert!(repo.load_deleted().unwrap().is_empty());
+        assert!(repo
+            .all_ingredients(IngredientSort::Name)
+            .unwrap()
+            .is_empty());
+        let conn = rusqlite::Connection::open(&name).unwrap();
+        for table in &["recipes", "steps", "ingredients", "recipe_ingredients"] {
+            let count: i64 = conn
+                .query_row(
+                    &format!("SELECT COUNT(*) FROM {}", table),
+                    params![],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(0, count, "{} is not empty", table);
+        }
+
+        // Ids start over once the tables are empty.
+        assert_eq!(1, repo.add_recipe(&recipe).unwrap());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_yield_description() {
+        let (repo, name) = setup_repo();
+
+        let recipe = Recipe {
+            id: None,
+            name: "Muffins".to_string(),
+            desc: None,
+            steps: vec![Step::new("Bake")],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: Some("makes 12 muffins".to_string()),
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        let recipe_id = repo.add_recipe(&recipe).unwrap();
+        let stored = repo.get_recipe(recipe_id as i32).unwrap().unwrap();
+        assert_eq!(recipe.yield_description, stored.yield_description);
+
+        let cleared = Recipe {
+            yield_description: None,
+            ..stored
+        };
+        assert!(repo.update_recipe(&cleared, None).unwrap());
+        assert_eq!(vec![cleared], repo.load_recipes().unwrap());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_repeated_ingredient_is_merged() {
+        let (repo, name) = setup_repo();
+
+        let line = |ingredient: &str, value: f64, unit: &str| IngredientQuantity {
+            ingredient: ingredient.to_string(),
+            quantity: Quantity {
+                value,
+                value_max: None,
+                unit: unit.to_string(),
+            },
+            calories_per_unit: None,
+        };
+        let recipe = Recipe {
+            id: None,
+            name: "Pretzels".to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![
+                line("Salt", 1.0, "tsp"),
+                line("Flour", 3.0, "cup"),
+                line("salt", 1.0, "tbsp"),
+            ],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        let recipe_id = repo.add_recipe(&recipe).unwrap() as i32;
+
+        let mut ingredients = repo.get_recipe(recipe_id).unwrap().unwrap().ingredients;
+        ingredients.sort_by(|a, b| a.ingredient.cmp(&b.ingredient));
+        assert_eq!(
+            vec![line("Flour", 3.0, "cup"), line("Salt", 4.0, "tsp")],
+            ingredients
+        );
+
+        // Updates take the same path.
+        assert!(repo
+            .update_recipe(
+                &Recipe {
+                    id: Some(recipe_id as u32),
+                    ..recipe
+                },
+                None
+            )
+            .unwrap());
+        assert_eq!(
+            2,
+            repo.get_recipe(recipe_id)
+                .unwrap()
+                .unwrap()
+                .ingredients
+                .len()
+        );
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_blank_desc_is_stored_as_null() {
+        let (repo, name) = setup_repo();
+
+        let recipe = Recipe {
+            id: None,
+            name: "Toast".to_string(),
+            desc: Some("".to_string()),
+            steps: vec![],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        let recipe_id = repo.add_recipe(&recipe).unwrap() as i32;
+        assert_eq!(None, repo.get_recipe(recipe_id).unwrap().unwrap().desc);
+
+        let described = Recipe {
+            id: Some(recipe_id as u32),
+            desc: Some("Crisp".to_string()),
+            ..recipe.clone()
+        };
+        assert!(repo.update_recipe(&described, None).unwrap());
+        let blanked = Recipe {
+            desc: Some("  ".to_string()),
+            ..described.clone()
+        };
+        assert!(repo.update_recipe(&blanked, None).unwrap());
+        assert_eq!(None, repo.load_recipes().unwrap()[0].desc);
+
+        let patch = RecipePatch {
+            desc: Some("".to_string()),
+            ..RecipePatch::default()
+        };
+        assert!(repo.update_recipe(&described, None).unwrap());
+        let patched = repo.patch_recipe(recipe_id, &patch).unwrap().unwrap();
+        assert_eq!(None, patched.desc);
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_notes() {
+        let (repo, name) = setup_repo();
+
+        let recipe = Recipe {
+            id: None,
+            name: "Chili".to_string(),
+            desc: Some("Weeknight chili".to_string()),
+            steps: vec![Step::new("Simmer")],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: Some("Tastes better the next day.\n\nFreezes well.".to_string()),
+            raw_source: None,
+            photos: vec![],
+        };
+        let recipe_id = repo.add_recipe(&recipe).unwrap();
+        let stored = repo.get_recipe(recipe_id as i32).unwrap().unwrap();
+        assert_eq!(recipe.notes, stored.notes);
+
+        let cleared = Recipe {
+            notes: None,
+            ..stored
+        };
+        assert!(repo.update_recipe(&cleared, None).unwrap());
+        assert_eq!(vec![cleared], repo.load_recipes().unwrap());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_raw_source() {
+        let (repo, name) = setup_repo();
+
+        let mut recipe = RecipeBuilder::default().name("Chili").build();
+        recipe.raw_source = Some("CHILI!!\n2 lbs beef... simmer a while".to_string());
+        let recipe_id = repo.add_recipe(&recipe).unwrap();
+        let stored = repo.get_recipe(recipe_id as i32).unwrap().unwrap();
+        assert_eq!(recipe.raw_source, stored.raw_source);
+        assert_eq!(
+            recipe.raw_source,
+            repo.load_recipes().unwrap()[0].raw_source
+        );
+
+        // An edit that leaves it out, e.g. one based on a listing, keeps it.
+        let renamed = Recipe {
+            name: "Beef Chili".to_string(),
+            raw_source: None,
+            ..stored.clone()
+        };
+        assert!(repo.update_recipe(&renamed, None).unwrap());
+        let stored = repo.get_recipe(recipe_id as i32).unwrap().unwrap();
+        assert_eq!("Beef Chili", stored.name);
+        assert_eq!(recipe.raw_source, stored.raw_source);
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_difficulty() {
+        let (repo, name) = setup_repo();
+
+        for difficulty in [Difficulty::Easy, Difficulty::Medium, Difficulty::Hard] {
+            let recipe = Recipe {
+                id: None,
+                name: format!("{} recipe", difficulty.as_str()),
+                desc: None,
+                steps: vec![],
+                ingredients: vec![],
+                favorite: false,
+                image_url: None,
+                cuisine: None,
+                course: None,
+                source_name: None,
+                source_url: None,
+                yield_description: None,
+                difficulty: Some(difficulty),
+                notes: None,
+                raw_source: None,
+                photos: vec![],
+            };
+            let recipe_id = repo.add_recipe(&recipe).unwrap();
+            let stored = repo.get_recipe(recipe_id as i32).unwrap().unwrap();
+            assert_eq!(Some(difficulty), stored.difficulty);
+
+            let found = repo
+                .browse_recipes(None, None, Some(difficulty), None)
+                .unwrap();
+            assert_eq!(vec![stored], found);
+        }
+
+        // A level from a newer release reads as none instead of failing.
+        let conn = rusqlite::Connection::open(&name).unwrap();
+        conn.execute(
+            "UPDATE recipes SET difficulty = 'expert' WHERE id = 1",
+            params![],
+        )
+        .unwrap();
+        assert_eq!(None, repo.get_recipe(1).unwrap().unwrap().difficulty);
+        assert_eq!(3, repo.load_recipes().unwrap().len());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_browse_and_facets() {
+        let (repo, name) = setup_repo();
+
+        let recipe = |name: &str, cuisine: Option<&str>, course: Option<&str>| Recipe {
+            id: None,
+            name: name.to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: cuisine.map(|c| c.to_string()),
+            course: course.map(|c| c.to_string()),
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        repo.add_recipe(&recipe("Lasagne", Some("Italian"), Some("Main")))
+            .unwrap();
+        repo.add_recipe(&recipe("Tiramisu", Some("italian"), Some("Dessert")))
+            .unwrap();
+        repo.add_recipe(&recipe("Tacos", Some("Mexican"), Some("Main")))
+            .unwrap();
+        repo.add_recipe(&recipe("Toast", None, None)).unwrap();
+
+        let names =
+            |found: Vec<Recipe>| -> Vec<String> { found.into_iter().map(|r| r.name).collect() };
+        assert_eq!(
+            vec!["Lasagne", "Tiramisu"],
+            names(
+                repo.browse_recipes(Some("ITALIAN"), None, None, None)
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            vec!["Lasagne"],
+            names(
+                repo.browse_recipes(Some("italian"), Some("main"), None, None)
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            4,
+            repo.browse_recipes(None, None, None, None).unwrap().len()
+        );
+
+        let facets = repo.facets().unwrap();
+        let counts = |facets: &[FacetCount]| -> Vec<(String, u32)> {
+            facets
+                .iter()
+                .map(|f| (f.value.to_lowercase(), f.count))
+                .collect()
+        };
+        assert_eq!(
+            vec![("italian".to_string(), 2), ("mexican".to_string(), 1)],
+            counts(&facets.cuisines)
+        );
+        assert_eq!(
+            vec![("dessert".to_string(), 1), ("main".to_string(), 2)],
+            counts(&facets.courses)
+        );
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_browse_by_total_time() {
+        let (repo, name) = setup_repo();
+
+        let timed = |name: &str, minutes: &[Option<u32>]| {
+            let mut recipe = RecipeBuilder::default().name(name).build();
+            recipe.steps = minutes
+                .iter()
+                .enumerate()
+                .map(|(i, duration_minutes)| Step {
+                    duration_minutes: *duration_minutes,
+                    ..Step::new(&format!("Step {}", i))
+                })
+                .collect();
+            recipe
+        };
+        repo.add_recipe(&timed("Quick", &[Some(10), Some(20)]))
+            .unwrap();
+        repo.add_recipe(&timed("Partly timed", &[Some(31), None]))
+            .unwrap();
+        repo.add_recipe(&timed("Untimed", &[None])).unwrap();
+        repo.add_recipe(&timed("No steps", &[])).unwrap();
+
+        let browse = |max_minutes: u32, include_unknown: bool| -> Vec<String> {
+            let time = TimeLimit {
+                max_minutes,
+                include_unknown,
+            };
+            repo.browse_recipes(None, None, None, Some(time))
+                .unwrap()
+                .into_iter()
+                .map(|r| r.name)
+                .collect()
+        };
+        assert_eq!(vec!["Quick"], browse(30, false));
+        assert!(browse(29, false).is_empty());
+        assert_eq!(vec!["Quick", "Partly timed"], browse(31, false));
+        assert_eq!(vec!["Quick", "Untimed", "No steps"], browse(30, true));
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_fts_query() {
+        assert_eq!("\"banana\" \"bread\"", fts_query(" banana  bread "));
+        assert_eq!("\"a\"\"b\" \"NOT\" \"c*\"", fts_query("a\"b NOT c*"));
+        assert_eq!("", fts_query("  "));
+    }
+
+    #[test]
+    fn test_full_text_search() {
+        let (repo, name) = setup_repo();
+
+        let recipe = |name: &str, desc: Option<&str>, steps: &[&str]| Recipe {
+            id: None,
+            name: name.to_string(),
+            desc: desc.map(|d| d.to_string()),
+            steps: steps.iter().map(|text| Step::new(text)).collect(),
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        let muffins = repo
+            .add_recipe(&recipe("Muffins", None, &["Add the banana", "Bake"]))
+            .unwrap();
+        let bread = repo
+            .add_recipe(&recipe(
+                "Banana bread",
+                Some("Uses ripe bananas"),
+                &["Bake"],
+            ))
+            .unwrap();
+        repo.add_recipe(&recipe("Toast", None, &["Toast the bread"]))
+            .unwrap();
+
+        let names = |query: &str| -> Vec<String> {
+            repo.full_text_search(query)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.name)
+                .collect()
+        };
+        // Stemming matches "baking" to "Bake", and a name match ranks first.
+        assert_eq!(vec!["Banana bread", "Muffins"], names("banana baking"));
+        assert_eq!(vec!["Banana bread", "Toast"], names("bread"));
+        assert!(names("\"unbalanced").is_empty());
+
+        // Edits, step changes and deletes all reach the index.
+        let edited = Recipe {
+            id: Some(muffins),
+            ..recipe("Muffins", None, &["Add the blueberries"])
+        };
+        assert!(repo.update_recipe(&edited, None).unwrap());
+        assert_eq!(vec!["Banana bread"], names("banana"));
+        assert_eq!(vec!["Muffins"], names("blueberry"));
+
+        repo.delete_recipe(bread as i32).unwrap();
+        assert!(names("banana").is_empty());
+        repo.restore_recipe(bread as i32).unwrap();
+        assert_eq!(vec!["Banana bread"], names("banana"));
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_escape_like() {
+        assert_eq!("pie", escape_like("pie"));
+        assert_eq!("100\\%", escape_like("100%"));
+        assert_eq!("a\\_b\\\\c", escape_like("a_b\\c"));
+    }
+
+    #[test]
+    fn test_search_matches_wildcards_literally() {
+        let (repo, name) = setup_repo();
+
+        for recipe_name in [
+            "100% Rye Bread",
+            "1000 Island Dressing",
+            "Pie_Crust",
+            "Pie Crust",
+        ]
+        .iter()
+        {
+            repo.add_recipe(&Recipe {
+                id: None,
+                name: recipe_name.to_string(),
+                desc: None,
+                steps: vec![],
+                ingredients: vec![],
+                favorite: false,
+                image_url: None,
+                cuisine: None,
+                course: None,
+                source_name: None,
+                source_url: None,
+                yield_description: None,
+                difficulty: None,
+                notes: None,
+                raw_source: None,
+                photos: vec![],
+            })
+            .unwrap();
+        }
+
+        let names = |query: &str| -> Vec<String> {
+            repo.search_recipes(query, SearchFields::Name)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.name)
+                .collect()
+        };
+        assert_eq!(vec!["100% Rye Bread"], names("100%"));
+        assert_eq!(vec!["Pie_Crust"], names("pie_"));
+        assert_eq!(vec!["100% Rye Bread"], names("%"));
+        assert_eq!(vec!["Pie_Crust", "Pie Crust"], names("CRUST"));
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_search_fields() {
+        let (repo, name) = setup_repo();
+
+        let recipe = Recipe {
+            id: None,
+            name: "Meatballs".to_string(),
+            desc: Some("Grandma's secret 50% beef blend".to_string()),
+            steps: vec![],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        repo.add_recipe(&recipe).unwrap();
+        repo.add_recipe(&Recipe {
+            name: "Grandma's Pie".to_string(),
+            desc: None,
+            ..recipe
+        })
+        .unwrap();
+
+        let names = |query: &str, fields: SearchFields| -> Vec<String> {
+            repo.search_recipes(query, fields)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.name)
+                .collect()
+        };
+        assert!(names("secret", SearchFields::Name).is_empty());
+        assert_eq!(vec!["Meatballs"], names("SECRET", SearchFields::Desc));
+        assert_eq!(vec!["Meatballs"], names("secret", SearchFields::All));
+        assert_eq!(vec!["Meatballs"], names("50%", SearchFields::All));
+        assert_eq!(vec!["Grandma's Pie"], names("grandma", SearchFields::Name));
+        assert_eq!(
+            vec!["Meatballs", "Grandma's Pie"],
+            names("grandma", SearchFields::All)
+        );
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_ingredient_names_are_normalized() {
+        let (repo, name) = setup_repo();
+
+        let ingredient = |name: &str| IngredientQuantity {
+            ingredient: name.to_string(),
+            quantity: Quantity {
+                value: 1.0,
+                value_max: None,
+                unit: "cup".to_string(),
+            },
+            calories_per_unit: None,
+        };
+        let recipe = Recipe {
+            id: None,
+            name: "Cake".to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![ingredient("Sugar")],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        repo.add_recipe(&recipe).unwrap();
+        let cookies_id = repo
+            .add_recipe(&Recipe {
+                name: "Cookies".to_string(),
+                ingredients: vec![ingredient("sugar ")],
+                ..recipe
+            })
+            .unwrap();
+
+        let conn = rusqlite::Connection::open(&name).unwrap();
+        let names: Vec<String> = conn
+            .prepare("SELECT name FROM ingredients")
+            .unwrap()
+            .query_map(params![], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<String>>>()
+            .unwrap();
+        assert_eq!(vec!["Sugar"], names);
+
+        let cookies = repo.get_recipe(cookies_id as i32).unwrap().unwrap();
+        assert_eq!("Sugar", cookies.ingredients[0].ingredient);
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_stats() {
+        let (repo, name) = setup_repo();
+
+        assert_eq!(
+            Stats {
+                total_recipes: 0,
+                total_ingredients: 0,
+                average_steps: None,
+                most_used_unit: None,
+            },
+            repo.stats().unwrap()
+        );
+
+        let ingredient = |name: &str, unit: &str| IngredientQuantity {
+            ingredient: name.to_string(),
+            quantity: Quantity {
+                value: 1.0,
+                value_max: None,
+                unit: unit.to_string(),
+            },
+            calories_per_unit: None,
+        };
+        let recipe = Recipe {
+            id: None,
+            name: "Bread".to_string(),
+            desc: None,
+            steps: vec![Step::new("Mix"), Step::new("Knead"), Step::new("Bake")],
+            ingredients: vec![ingredient("Flour", "cup"), ingredient("Yeast", "tsp")],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        repo.add_recipe(&recipe).unwrap();
+        repo.add_recipe(&Recipe {
+            name: "Pancakes".to_string(),
+            steps: vec![],
+            ingredients: vec![ingredient("Flour", "cup"), ingredient("Milk", "cup")],
+            ..recipe
+        })
+        .unwrap();
+
+        assert_eq!(
+            Stats {
+                total_recipes: 2,
+                total_ingredients: 3,
+                average_steps: Some(1.5),
+                most_used_unit: Some("cup".to_string()),
+            },
+            repo.stats().unwrap()
+        );
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_ingredient_usage() {
+        let (repo, name) = setup_repo();
+
+        let ingredient = |name: &str| IngredientQuantity {
+            ingredient: name.to_string(),
+            quantity: Quantity {
+                value: 1.0,
+                value_max: None,
+                unit: "cup".to_string(),
+            },
+            calories_per_unit: None,
+        };
+        let recipe = Recipe {
+            id: None,
+            name: "Bread".to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![ingredient("Flour"), ingredient("Water")],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        repo.add_recipe(&recipe).unwrap();
+        repo.add_recipe(&Recipe {
+            name: "Pancakes".to_string(),
+            ingredients: vec![ingredient("Milk"), ingredient("Flour")],
+            ..recipe
+        })
+        .unwrap();
+
+        assert_eq!(
+            vec![
+                ("Flour".to_string(), 2),
+                ("Milk".to_string(), 1),
+                ("Water".to_string(), 1),
+            ],
+            repo.ingredient_usage().unwrap()
+        );
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_all_ingredients() {
+        let (repo, name) = setup_repo();
+
+        let ingredient = |name: &str| IngredientQuantity {
+            ingredient: name.to_string(),
+            quantity: Quantity {
+                value: 1.0,
+                value_max: None,
+                unit: "cup".to_string(),
+            },
+            calories_per_unit: None,
+        };
+        let recipe = Recipe {
+            id: None,
+            name: "Bread".to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![
+                ingredient("Flour"),
+                ingredient("Water"),
+                ingredient("butter"),
+            ],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        repo.add_recipe(&recipe).unwrap();
+        let pancakes_id = repo
+            .add_recipe(&Recipe {
+                name: "Pancakes".to_string(),
+                ingredients: vec![ingredient("Milk"), ingredient("Flour")],
+                ..recipe
+            })
+            .unwrap();
+        // Trashed recipes do not count, but their ingredients stay listed.
+        repo.delete_recipe(pancakes_id as i32).unwrap();
+
+        assert_eq!(
+            vec![
+                ("butter".to_string(), 1),
+                ("Flour".to_string(), 1),
+                ("Milk".to_string(), 0),
+                ("Water".to_string(), 1),
+            ],
+            repo.all_ingredients(IngredientSort::Name).unwrap()
+        );
+        assert_eq!(
+            vec![
+                ("butter".to_string(), 1),
+                ("Flour".to_string(), 1),
+                ("Water".to_string(), 1),
+                ("Milk".to_string(), 0),
+            ],
+            repo.all_ingredients(IngredientSort::Usage).unwrap()
+        );
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_rename_ingredient() {
+        let (repo, name) = setup_repo();
+
+        let ingredient = |name: &str, value: f64| IngredientQuantity {
+            ingredient: name.to_string(),
+            quantity: Quantity {
+                value,
+                value_max: None,
+                unit: "tsp".to_string(),
+            },
+            calories_per_unit: None,
+        };
+        let names = |recipe_id: u32| -> Vec<String> {
+            repo.get_recipe(recipe_id as i32)
+                .unwrap()
+                .unwrap()
+                .ingredients
+                .into_iter()
+                .map(|ing_quant| ing_quant.ingredient)
+                .collect()
+        };
+        let recipe = Recipe {
+            id: None,
+            name: "Brine".to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![ingredient("Kosher Salt", 2.0), ingredient("Water", 1.0)],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        let brine_id = repo.add_recipe(&recipe).unwrap();
+        let before = repo.collection_version().unwrap();
+
+        assert!(!repo.rename_ingredient("Sea Salt", "Salt").unwrap());
 
-    match path.parent() {
-        Some(parent) => match std::fs::create_dir_all(parent) {
-            Ok(()) => {}
-            Err(e) => panic!("{}", e),
-        },
-        None => panic!("Unable to create database directory"),
-    }
+        // A plain rename keeps the row and changes its spelling everywhere.
+        assert!(repo
+            .rename_ingredient("kosher salt", "Salt, kosher")
+            .unwrap());
+        assert_eq!(vec!["Salt, kosher", "Water"], names(brine_id));
+        assert_ne!(before, repo.collection_version().unwrap());
 
-    let manager =
-        SqliteConnectionManager::file(name).with_init(|c| c.execute_batch("PRAGMA foreign_keys=1"));
-    let pool = match r2d2::Pool::new(manager) {
-        Ok(pool) => pool,
-        Err(e) => {
-            error!("Unable to create connection pool: {}", e);
-            panic!("{}", e);
-        }
-    };
+        // Renaming onto an existing ingredient merges the two; a recipe
+        // listing both adds the quantities into the first line.
+        let soup_id = repo
+            .add_recipe(&Recipe {
+                name: "Soup".to_string(),
+                ingredients: vec![
+                    ingredient("Broth", 2.0),
+                    ingredient("Salt", 1.0),
+                    ingredient("Water", 4.0),
+                ],
+                ..recipe.clone()
+            })
+            .unwrap();
+        assert!(repo.rename_ingredient("Broth", "water").unwrap());
+        assert_eq!(vec!["Salt, kosher", "water"], names(brine_id));
+        let soup = repo.get_recipe(soup_id as i32).unwrap().unwrap();
+        assert_eq!(
+            vec![ingredient("water", 6.0), ingredient("Salt", 1.0)],
+            soup.ingredients
+        );
 
-    let repo: Box<dyn Repo> = Box::new(SqliteRepo { conn_man: pool });
+        assert_eq!(
+            vec![
+                ("Salt".to_string(), 1),
+                ("Salt, kosher".to_string(), 1),
+                ("water".to_string(), 2)
+            ],
+            repo.all_ingredients(IngredientSort::Name).unwrap()
+        );
 
-    repo.setup();
+        // Quantities that cannot be added are a conflict, and nothing changes.
+        let stew_id = repo
+            .add_recipe(&Recipe {
+                name: "Stew".to_string(),
+                ingredients: vec![
+                    ingredient("Water", 1.0),
+                    IngredientQuantity {
+                        quantity: Quantity {
+                            value: 2.0,
+                            value_max: None,
+                            unit: "clove".to_string(),
+                        },
+                        ..ingredient("Garlic", 0.0)
+                    },
+                ],
+                ..recipe.clone()
+            })
+            .unwrap();
+        assert!(matches!(
+            repo.rename_ingredient("Garlic", "water"),
+            Err(RepoError::Conflict)
+        ));
+        assert_eq!(vec!["water", "Garlic"], names(stew_id));
 
-    repo
-}
+        cleanup_repo(&name);
+    }
 
-pub struct SqliteRepo {
-    conn_man: Pool,
-}
+    #[test]
+    fn test_add_recipes() {
+        let (repo, name) = setup_repo();
 
-impl SqliteRepo {
-    fn get_conn(&self) -> SqliteConn {
-        self.conn_man.get().unwrap()
-    }
+        let recipe = Recipe {
+            id: None,
+            name: "Bread".to_string(),
+            desc: None,
+            steps: vec![Step::new("Knead")],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        let recipes = vec![
+            recipe.clone(),
+            Recipe {
+                name: "Butter".to_string(),
+                steps: vec![Step::new("Churn")],
+                ..recipe
+            },
+        ];
 
-    pub fn create_expected_tables(&self, conn: &SqliteConn) -> Result<()> {
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS recipes (id INTEGER PRIMARY KEY ASC, name TEXT, desc TEXT)",
-            params![],
-        )?;
-        let _create_steps = conn.execute(
-          "CREATE TABLE IF NOT EXISTS steps (recipe_id INTEGER, text TEXT, CONSTRAINT COMP_K PRIMARY KEY (recipe_id, text), FOREIGN KEY (recipe_id) REFERENCES recipes (id) ON UPDATE CASCADE ON DELETE CASCADE)",
-          params![],
-      );
-        conn.execute("CREATE TABLE IF NOT EXISTS ingredients (id INTEGER PRIMARY KEY ASC, name TEXT NOT NULL UNIQUE)", params![])?;
-        conn.execute("CREATE TABLE IF NOT EXISTS recipe_ingredients (recipe_id INTEGER, ingredient_id INTEGER, quantity REAL, unit TEXT, CONSTRAINT COMP_K PRIMARY KEY (recipe_id, ingredient_id), FOREIGN KEY(recipe_id) REFERENCES recipes (id) ON UPDATE CASCADE ON DELETE CASCADE, FOREIGN KEY (ingredient_id) REFERENCES ingredients (id) ON UPDATE CASCADE ON DELETE CASCADE);", params![])?;
-        Ok(())
-    }
-}
+        let recipe_ids = repo.add_recipes(&recipes).unwrap();
+        assert_eq!(2, recipe_ids.len());
 
-impl Repo for SqliteRepo {
-    fn setup(&self) -> Result<()> {
-        let conn = self.get_conn();
-        self.create_expected_tables(&conn)?;
-        Ok(())
+        let stored = repo.load_recipes().unwrap();
+        for ((recipe, recipe_id), stored) in recipes.iter().zip(recipe_ids).zip(stored) {
+            assert_eq!(Some(recipe_id), stored.id);
+            assert_eq!(recipe.steps, stored.steps);
+        }
+
+        cleanup_repo(&name);
     }
 
-    fn add_recipe(&self, recipe: &Recipe) -> Result<()> {
-        let mut conn = self.get_conn();
-        // do nothing right now
-        let tx = conn.transaction()?;
+    #[test]
+    fn test_ratings() {
+        let (repo, name) = setup_repo();
 
-        tx.execute(
-            "INSERT INTO recipes (name, desc) VALUES (?1, ?2)",
-            params![recipe.name, recipe.desc],
-        )?;
+        let recipe_id = repo
+            .add_recipe(&Recipe {
+                id: None,
+                name: "Test Recipe".to_string(),
+                desc: None,
+                steps: vec![],
+                ingredients: vec![],
+                favorite: false,
+                image_url: None,
+                cuisine: None,
+                course: None,
+                source_name: None,
+                source_url: None,
+                yield_description: None,
+                difficulty: None,
+                notes: None,
+                raw_source: None,
+                photos: vec![],
+            })
+            .unwrap() as i32;
 
-        let recipe_id = tx.last_insert_rowid();
+        assert_eq!(
+            Some(RatingSummary {
+                average: None,
+                count: 0
+            }),
+            repo.rating_summary(recipe_id).unwrap()
+        );
 
-        let mut stmt = tx.prepare("INSERT INTO steps (recipe_id, text) VALUES (?1, ?2)")?;
-        for step in recipe.steps.iter() {
-            stmt.execute(params![recipe_id, step])?;
-        }
-        stmt.finalize()?;
+        assert!(repo.add_rating(recipe_id, 4).unwrap());
+        assert!(repo.add_rating(recipe_id, 5).unwrap());
+        assert_eq!(
+            Some(RatingSummary {
+                average: Some(4.5),
+                count: 2
+            }),
+            repo.rating_summary(recipe_id).unwrap()
+        );
 
-        let mut ing_stmt = tx.prepare("INSERT INTO ingredients (name) SELECT (?1) WHERE NOT EXISTS (SELECT 1 FROM ingredients WHERE name = (?1))")?;
-        let mut quantity_stmt = tx.prepare("INSERT INTO recipe_ingredients (recipe_id, ingredient_id, quantity, unit) VALUES (?1, (SELECT id FROM ingredients WHERE name = ?2), ?3, ?4)")?;
-        for ing_quant in recipe.ingredients.iter() {
-            ing_stmt.execute(params![ing_quant.ingredient])?;
-            let quantity = &ing_quant.quantity;
-            quantity_stmt.execute(params![
-                recipe_id,
-                ing_quant.ingredient,
-                quantity.value,
-                quantity.unit
-            ])?;
-        }
+        assert!(!repo.add_rating(99, 3).unwrap());
+        assert_eq!(None, repo.rating_summary(99).unwrap());
 
-        ing_stmt.finalize()?;
-        quantity_stmt.finalize()?;
+        repo.delete_recipe(recipe_id).unwrap();
+        purge_all(repo.as_ref());
+        let conn = rusqlite::Connection::open(&name).unwrap();
+        let remaining: u32 = conn
+            .query_row("SELECT COUNT(*) FROM ratings", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(0, remaining);
 
-        tx.commit()?;
-        Ok(())
+        cleanup_repo(&name);
     }
 
-    fn update_recipe(&self, updated_recipe: &Recipe) -> Result<()> {
-        let mut conn = self.get_conn();
-        let tx = conn.transaction()?;
+    #[test]
+    fn test_orphan_ingredients_removed() {
+        let (repo, name) = setup_repo();
 
-        let mut stmt = tx.prepare("UPDATE recipes SET name = (?1), desc = (?2) WHERE id = (?3)")?;
-        stmt.execute(params![
-            updated_recipe.name,
-            updated_recipe.desc,
-            updated_recipe.id
-        ])?;
+        let ingredient = |name: &str| IngredientQuantity {
+            ingredient: name.to_string(),
+            quantity: Quantity {
+                value: 1.0,
+                value_max: None,
+                unit: "pinch".to_string(),
+            },
+            calories_per_unit: None,
+        };
+        let paella = Recipe {
+            id: None,
+            name: "Paella".to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![ingredient("Saffron"), ingredient("Rice")],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        let risotto = Recipe {
+            name: "Risotto".to_string(),
+            ingredients: vec![ingredient("Rice")],
+            ..paella.clone()
+        };
+        let paella_id = repo.add_recipe(&paella).unwrap();
+        let risotto_id = repo.add_recipe(&risotto).unwrap();
 
-        stmt = tx.prepare("DELETE FROM steps WHERE recipe_id = (?)")?;
-        stmt.execute(params![updated_recipe.id])?;
+        let conn = rusqlite::Connection::open(&name).unwrap();
+        let ingredient_names = || -> Vec<String> {
+            let mut stmt = conn
+                .prepare("SELECT name FROM ingredients ORDER BY name")
+                .unwrap();
+            let names = stmt
+                .query_map(params![], |row| row.get(0))
+                .unwrap()
+                .map(|x| x.unwrap())
+                .collect();
+            names
+        };
+        assert_eq!(vec!["Rice", "Saffron"], ingredient_names());
 
-        stmt = tx.prepare("INSERT INTO steps (recipe_id, text) VALUES (?1, ?2)")?;
-        for step in updated_recipe.steps.iter() {
-            stmt.execute(params![updated_recipe.id, step])?;
-        }
+        // Trashed recipes keep their ingredients until they are purged.
+        repo.delete_recipe(paella_id as i32).unwrap();
+        assert_eq!(vec!["Rice", "Saffron"], ingredient_names());
+        purge_all(repo.as_ref());
+        assert_eq!(vec!["Rice"], ingredient_names());
 
-        stmt = tx.prepare("DELETE FROM recipe_ingredients WHERE recipe_id = (?)")?;
-        stmt.execute(params![updated_recipe.id])?;
+        let plain = Recipe {
+            id: Some(risotto_id),
+            ingredients: vec![ingredient("Arborio")],
+            ..risotto
+        };
+        assert!(repo.update_recipe(&plain, None).unwrap());
+        assert_eq!(vec!["Arborio"], ingredient_names());
 
-        let mut ing_stmt = tx.prepare("INSERT INTO ingredients (name) SELECT (?1) WHERE NOT EXISTS (SELECT 1 FROM ingredients WHERE name = (?1))")?;
-        let mut rec_ing_stmt = tx.prepare("INSERT INTO recipe_ingredients (recipe_id, ingredient_id, quantity, unit) VALUES (?1, (SELECT id FROM ingredients WHERE name = ?2), ?3, ?4)")?;
+        assert_eq!(0, repo.vacuum_orphan_ingredients().unwrap());
 
-        for ing_quant in updated_recipe.ingredients.iter() {
-            let quant = &ing_quant.quantity;
-            ing_stmt.execute(params![ing_quant.ingredient])?;
-            rec_ing_stmt.execute(params![
-                updated_recipe.id,
-                ing_quant.ingredient,
-                quant.value,
-                quant.unit
-            ])?;
-        }
+        cleanup_repo(&name);
+    }
 
-        stmt.finalize()?;
-        ing_stmt.finalize()?;
-        rec_ing_stmt.finalize()?;
+    #[test]
+    fn test_photos() {
+        let (repo, name) = setup_repo();
 
-        tx.commit()?;
-        Ok(())
+        let recipe = Recipe {
+            id: None,
+            name: "Test Recipe".to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![
+                "https://example.com/b.jpg".to_string(),
+                "https://example.com/a.jpg".to_string(),
+            ],
+        };
+        let recipe_id = repo.add_recipe(&recipe).unwrap();
+        let stored = repo.get_recipe(recipe_id as i32).unwrap().unwrap();
+        assert_eq!(recipe.photos, stored.photos);
+
+        let reordered = Recipe {
+            photos: vec![
+                "https://example.com/a.jpg".to_string(),
+                "https://example.com/c.jpg".to_string(),
+                "https://example.com/b.jpg".to_string(),
+            ],
+            ..stored
+        };
+        assert!(repo.update_recipe(&reordered, None).unwrap());
+        assert_eq!(vec![reordered], repo.load_recipes().unwrap());
+
+        repo.delete_recipe(recipe_id as i32).unwrap();
+        purge_all(repo.as_ref());
+        let conn = rusqlite::Connection::open(&name).unwrap();
+        let remaining: u32 = conn
+            .query_row("SELECT COUNT(*) FROM recipe_photos", params![], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(0, remaining);
+
+        cleanup_repo(&name);
     }
 
-    fn delete_recipe(&self, recipe_id: i32) -> Result<()> {
-        let mut conn = self.get_conn();
-        let tx = conn.transaction()?;
-        let mut stmt = tx.prepare("DELETE FROM recipes WHERE id = (?)")?;
-        stmt.execute(params![recipe_id])?;
-        stmt.finalize()?;
+    #[test]
+    fn test_random_recipe() {
+        let (repo, name) = setup_repo();
 
-        tx.commit()?;
+        assert_eq!(None, repo.random_recipe(None).unwrap());
 
-        Ok(())
+        let recipe = Recipe {
+            id: None,
+            name: "Carbonara".to_string(),
+            desc: None,
+            steps: vec![Step::new("Toss")],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: Some("Italian".to_string()),
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        let recipe_id = repo.add_recipe(&recipe).unwrap();
+        repo.add_recipe(&Recipe {
+            name: "Pad Thai".to_string(),
+            cuisine: Some("Thai".to_string()),
+            ..recipe.clone()
+        })
+        .unwrap();
+
+        let picked = repo.random_recipe(Some("italian")).unwrap().unwrap();
+        assert_eq!(Some(recipe_id), picked.id);
+        assert_eq!(recipe.steps, picked.steps);
+        assert!(repo.random_recipe(None).unwrap().is_some());
+        assert_eq!(None, repo.random_recipe(Some("French")).unwrap());
+
+        cleanup_repo(&name);
     }
 
-    fn load_recipes(&self) -> Result<Vec<Recipe>> {
-        let conn = self.get_conn();
-        let mut stmt = conn.prepare("SELECT * FROM recipes")?;
-        let db_recipes = stmt
-            .query_map(params![], |row| {
-                Ok(Recipe {
-                    id: row.get(0)?,
-                    name: row.get(1)?,
-                    desc: row.get(2)?,
-                    steps: load_steps(&conn, row.get(0)?)?,
-                    ingredients: load_ingredients(&conn, row.get(0)?)?,
+    #[test]
+    fn test_concurrent_adds_share_new_ingredient() {
+        // Shared-cache memory databases fail lock conflicts at once instead
+        // of waiting, so this needs a file.
+        let (repo, name) = setup_file_repo();
+        let repo: std::sync::Arc<dyn Repo> = repo.into();
+
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let repo = repo.clone();
+                std::thread::spawn(move || {
+                    for round in 0..10 {
+                        repo.add_recipe(&Recipe {
+                            id: None,
+                            name: format!("Recipe {}-{}", writer, round),
+                            desc: None,
+                            steps: vec![],
+                            ingredients: vec![IngredientQuantity {
+                                // Every writer adds the same new ingredient
+                                // each round, spelled differently.
+                                ingredient: if writer % 2 == 0 {
+                                    format!("Spice {}", round)
+                                } else {
+                                    format!("spice {} ", round)
+                                },
+                                quantity: Quantity {
+                                    value: 1.0,
+                                    value_max: None,
+                                    unit: "tsp".to_string(),
+                                },
+                                calories_per_unit: None,
+                            }],
+                            favorite: false,
+                            image_url: None,
+                            cuisine: None,
+                            course: None,
+                            source_name: None,
+                            source_url: None,
+                            yield_description: None,
+                            difficulty: None,
+                            notes: None,
+                            raw_source: None,
+                            photos: vec![],
+                        })
+                        .unwrap();
+                    }
                 })
-            })?
-            .filter_map(|x| x.ok())
+            })
             .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
 
-        Ok(db_recipes)
-    }
-}
+        let ingredients = repo.all_ingredients(IngredientSort::Name).unwrap();
+        assert_eq!(10, ingredients.len());
+        assert!(ingredients.iter().all(|(_, uses)| *uses == 8));
+        assert_eq!(80, repo.count_recipes().unwrap());
 
-fn load_steps(conn: &SqliteConn, recipe_id: u32) -> rusqlite::Result<Vec<String>> {
-    let mut stmt = conn.prepare("SELECT text FROM steps WHERE recipe_id = ?")?;
+        cleanup_repo(&name);
+    }
 
-    let steps: Vec<String> = stmt
-        .query_map(params![recipe_id], |row| row.get(0))?
-        .filter_map(|x| x.ok())
-        .collect();
+    #[test]
+    fn test_load_recipes_during_delete() {
+        // Needs the snapshot isolation between connections a file gives.
+        let (repo, name) = setup_file_repo();
+        let repo: std::sync::Arc<dyn Repo> = repo.into();
 
-    Ok(steps)
-}
+        for i in 0..200 {
+            repo.add_recipe(&Recipe {
+                id: None,
+                name: format!("Recipe {}", i),
+                desc: None,
+                steps: vec![Step::new("First"), Step::new("Second")],
+                ingredients: vec![IngredientQuantity {
+                    ingredient: format!("Ingredient {}", i),
+                    quantity: Quantity {
+                        value: 1.0,
+                        value_max: None,
+                        unit: "cup".to_string(),
+                    },
+                    calories_per_unit: None,
+                }],
+                favorite: false,
+                image_url: None,
+                cuisine: None,
+                course: None,
+                source_name: None,
+                source_url: None,
+                yield_description: None,
+                difficulty: None,
+                notes: None,
+                raw_source: None,
+                photos: vec![],
+            })
+            .unwrap();
+        }
 
-fn load_ingredients(
-    conn: &SqliteConn,
-    recipe_id: u32,
-) -> rusqlite::Result<Vec<IngredientQuantity>> {
-    let mut stmt = conn.prepare("SELECT name, quantity, unit FROM recipe_ingredients LEFT JOIN ingredients ON ingredient_id = id WHERE recipe_id = ?")?;
-    let ingredients = stmt
-        .query_map(params![recipe_id], |row| {
-            Ok(IngredientQuantity {
-                ingredient: row.get(0)?,
-                quantity: Quantity {
-                    value: row.get(1)?,
-                    unit: row.get(2)?,
-                },
+        let deleter = {
+            let repo = repo.clone();
+            std::thread::spawn(move || {
+                for recipe in repo.load_recipes().unwrap() {
+                    repo.delete_recipe(recipe.id.unwrap() as i32).unwrap();
+                }
             })
-        })?
-        .filter_map(|x| x.ok())
-        .collect();
+        };
 
-    Ok(ingredients)
-}
+        // Recipes are deleted in id order, so any single snapshot holds a
+        // run of consecutive ids; a gap means part of the load saw a later
+        // state than the rest.
+        loop {
+            let recipes = repo.load_recipes().unwrap();
+            for pair in recipes.windows(2) {
+                assert_eq!(pair[0].id.unwrap() + 1, pair[1].id.unwrap());
+            }
+            for recipe in recipes.iter() {
+                assert_eq!(2, recipe.steps.len());
+                assert_eq!(1, recipe.ingredients.len());
+            }
+            if recipes.is_empty() {
+                break;
+            }
+        }
+        deleter.join().unwrap();
 
-mod tests {
-    use super::*;
-    use rand::Rng;
+        cleanup_repo(&name);
+    }
 
-    #[allow(dead_code)]
-    fn setup_repo() -> (Box<dyn Repo>, String) {
-        let mut rng = rand::thread_rng();
+    thread_local! {
+        static STATEMENTS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
 
-        let name = format!("target/tests/recipes-{}.db", rng.gen::<u32>());
+    fn count_statement(_: &str) {
+        STATEMENTS.with(|count| count.set(count.get() + 1));
+    }
 
-        cleanup_repo(&name);
-        (create_repo_with_name(&name), name)
+    /// Run `load` against a traced connection, returning its result and how
+    /// many statements it executed.
+    fn count_statements<T>(name: &str, load: impl FnOnce(&Connection) -> T) -> (T, usize) {
+        let mut conn = Connection::open(name).unwrap();
+        conn.trace(Some(count_statement));
+        STATEMENTS.with(|count| count.set(0));
+        let result = load(&conn);
+        (result, STATEMENTS.with(|count| count.get()))
     }
 
-    fn cleanup_repo(file_loc: &str) {
-        match std::fs::remove_file(file_loc) {
-            Ok(()) => {}
-            Err(e) => error!("Testing error: {}", e),
-        };
+    #[test]
+    fn test_load_all_recipes_query_count() {
+        let (repo, name) = setup_repo();
+
+        for i in 0..10 {
+            repo.add_recipe(&Recipe {
+                id: None,
+                name: format!("Recipe {}", i),
+                desc: Some("Description".to_string()),
+                steps: vec![Step::new("Zest"), Step::new("Add")],
+                ingredients: vec![
+                    IngredientQuantity {
+                        ingredient: format!("Lemon {}", i),
+                        quantity: Quantity {
+                            value: 1.0,
+                            value_max: None,
+                            unit: "whole".to_string(),
+                        },
+                        calories_per_unit: None,
+                    },
+                    IngredientQuantity {
+                        ingredient: "Sugar".to_string(),
+                        quantity: Quantity {
+                            value: 0.5,
+                            value_max: None,
+                            unit: "cup".to_string(),
+                        },
+                        calories_per_unit: None,
+                    },
+                ],
+                favorite: i % 2 == 0,
+                image_url: None,
+                cuisine: None,
+                course: None,
+                source_name: None,
+                source_url: None,
+                yield_description: None,
+                difficulty: None,
+                notes: None,
+                raw_source: None,
+                photos: vec![
+                    "https://example.com/2.jpg".to_string(),
+                    "https://example.com/1.jpg".to_string(),
+                ],
+            })
+            .unwrap();
+        }
+        repo.add_recipe(&Recipe {
+            id: None,
+            name: "Water".to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        })
+        .unwrap();
+
+        let ids: Vec<i32> = (1..=11).collect();
+        let (per_recipe, per_recipe_count) = count_statements(&name, |conn| {
+            ids.iter()
+                .filter_map(|id| load_recipe(conn, *id).unwrap())
+                .collect::<Vec<Recipe>>()
+        });
+        let (bulk, bulk_count) = count_statements(&name, |conn| load_all_recipes(conn).unwrap());
+        let (by_id, by_id_count) =
+            count_statements(&name, |conn| load_recipes_by_id(conn, ids.clone()).unwrap());
+
+        assert_eq!(per_recipe, bulk);
+        assert_eq!(per_recipe, by_id);
+        assert_eq!(4, bulk_count);
+        assert_eq!(4, by_id_count);
+        assert_eq!(44, per_recipe_count);
+
+        cleanup_repo(&name);
     }
 
     #[test]
-    fn test_add() {
+    fn test_trash_round_trip() {
         let (repo, name) = setup_repo();
 
         let recipe = Recipe {
-            id: Some(1),
-            name: "Test Recipe".to_string(),
-            desc: Some("Test Description".to_string()),
-            steps: vec!["Step 1".to_string()],
+            id: None,
+            name: "Soup".to_string(),
+            desc: None,
+            steps: vec![Step::new("Simmer")],
             ingredients: vec![IngredientQuantity {
-                ingredient: "Potato".to_string(),
+                ingredient: "Leek".to_string(),
                 quantity: Quantity {
-                    value: 1.0,
+                    value: 2.0,
+                    value_max: None,
                     unit: "whole".to_string(),
                 },
+                calories_per_unit: None,
             }],
+            favorite: true,
+            image_url: None,
+            cuisine: Some("French".to_string()),
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
         };
+        let recipe_id = repo.add_recipe(&recipe).unwrap() as i32;
+        let stored = repo.load_recipes().unwrap();
 
-        assert_eq!(Vec::<Recipe>::new(), repo.load_recipes().unwrap());
-        repo.add_recipe(&recipe).unwrap();
-        assert_eq!(vec![recipe.clone()], repo.load_recipes().unwrap());
+        repo.delete_recipe(recipe_id).unwrap();
+        assert!(repo.load_recipes().unwrap().is_empty());
+        assert_eq!(None, repo.get_recipe(recipe_id).unwrap());
+        assert!(repo.load_favorites().unwrap().is_empty());
+        assert!(repo.facets().unwrap().cuisines.is_empty());
+        assert_eq!(0, repo.count_recipes().unwrap());
+        assert!(!repo.set_favorite(recipe_id, false).unwrap());
 
-        let recipe_2 = Recipe {
-            id: Some(2),
-            ..recipe.clone()
-        };
+        let deleted = repo.load_deleted().unwrap();
+        assert_eq!(1, deleted.len());
+        assert_eq!(stored[0], deleted[0].recipe);
 
-        repo.add_recipe(&recipe_2).unwrap();
-        assert_eq!(vec![recipe, recipe_2], repo.load_recipes().unwrap());
+        assert!(repo.restore_recipe(recipe_id).unwrap());
+        assert!(!repo.restore_recipe(recipe_id).unwrap());
+        assert_eq!(stored, repo.load_recipes().unwrap());
+        assert!(repo.load_deleted().unwrap().is_empty());
+
+        // Only recipes trashed before the cutoff are purged.
+        repo.delete_recipe(recipe_id).unwrap();
+        assert_eq!(
+            0,
+            repo.purge_deleted(Utc::now() - chrono::Duration::hours(1))
+                .unwrap()
+        );
+        assert_eq!(
+            1,
+            repo.purge_deleted(Utc::now() + chrono::Duration::seconds(1))
+                .unwrap()
+        );
+        assert!(repo.load_deleted().unwrap().is_empty());
+        assert!(!repo.restore_recipe(recipe_id).unwrap());
 
         cleanup_repo(&name);
     }
 
     #[test]
-    fn test_delete() {
+    fn test_foreign_keys_enforced() {
+        let name = file_database_name();
+        let pool = build_pool(&RepoConfig::new(&name)).unwrap();
+        let conns: Vec<SqliteConn> = (0..3).map(|_| pool.get().unwrap()).collect();
+        for conn in conns.iter() {
+            let enabled: i64 = conn
+                .query_row("PRAGMA foreign_keys", NO_PARAMS, |row| row.get(0))
+                .unwrap();
+            assert_eq!(1, enabled);
+        }
+        drop(conns);
+        drop(pool);
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_pool_timeout_is_an_error() {
+        let (_repo, name) = setup_repo();
+        let pool = r2d2::Pool::builder()
+            .max_size(1)
+            .connection_timeout(std::time::Duration::from_millis(50))
+            .build(SqliteConnectionManager::file(&name).with_init(enable_foreign_keys))
+            .unwrap();
+        let repo = SqliteRepo { conn_man: pool };
+
+        let held = repo.conn_man.get().unwrap();
+        assert!(matches!(repo.load_recipes(), Err(RepoError::Backend(_))));
+        drop(held);
+        assert!(repo.load_recipes().is_ok());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_purge_cascades() {
         let (repo, name) = setup_repo();
 
         let recipe = Recipe {
-            id: Some(1),
+            id: None,
             name: "Test Recipe".to_string(),
-            desc: Some("Test Description".to_string()),
-            steps: vec!["Step 1".to_string()],
+            desc: None,
+            steps: vec![Step::new("Step 1"), Step::new("Step 2")],
             ingredients: vec![IngredientQuantity {
-                ingredient: "Potato".to_string(),
+                ingredient: "Rice".to_string(),
                 quantity: Quantity {
                     value: 1.0,
-                    unit: "whole".to_string(),
+                    value_max: None,
+                    unit: "cup".to_string(),
                 },
+                calories_per_unit: None,
             }],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
         };
+        let recipe_id = repo.add_recipe(&recipe).unwrap();
+        repo.delete_recipe(recipe_id as i32).unwrap();
+        purge_all(repo.as_ref());
 
-        assert_eq!(Vec::<Recipe>::new(), repo.load_recipes().unwrap());
-        repo.add_recipe(&recipe).unwrap();
-        assert_eq!(vec![recipe.clone()], repo.load_recipes().unwrap());
+        // Only the foreign keys' ON DELETE CASCADE removes these rows.
+        let conn = rusqlite::Connection::open(&name).unwrap();
+        for table in ["steps", "recipe_ingredients"].iter() {
+            let remaining: i64 = conn
+                .query_row(
+                    &format!("SELECT COUNT(*) FROM {} WHERE recipe_id = ?", table),
+                    params![recipe_id],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(0, remaining, "rows left in {}", table);
+        }
 
-        repo.delete_recipe(1).unwrap();
-        assert_eq!(Vec::<Recipe>::new(), repo.load_recipes().unwrap());
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_step_details() {
+        let (repo, name) = setup_repo();
+
+        let recipe = Recipe {
+            id: None,
+            name: "Test Recipe".to_string(),
+            desc: None,
+            steps: vec![
+                Step {
+                    text: "Bake".to_string(),
+                    duration_minutes: Some(25),
+                    note: Some("Until golden".to_string()),
+                    image_url: None,
+                },
+                Step::new("Cool"),
+            ],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+        let recipe_id = repo.add_recipe(&recipe).unwrap();
+        let stored = repo.get_recipe(recipe_id as i32).unwrap().unwrap();
+        assert_eq!(recipe.steps, stored.steps);
+        assert_eq!(vec![stored], repo.load_recipes().unwrap());
 
         cleanup_repo(&name);
     }
 
     #[test]
-    fn test_update() {
+    fn test_step_images() {
+        let (repo, name) = setup_repo();
+
+        // Not in alphabetical order, so the stored position must be used.
+        let mut recipe = RecipeBuilder::default().build();
+        recipe.steps = vec![
+            Step {
+                image_url: Some("https://example.com/shape.jpg".to_string()),
+                ..Step::new("Shape")
+            },
+            Step::new("Proof"),
+            Step {
+                image_url: Some("https://example.com/bake.jpg".to_string()),
+                ..Step::new("Bake")
+            },
+        ];
+        let recipe_id = repo.add_recipe(&recipe).unwrap();
+        let stored = repo.get_recipe(recipe_id as i32).unwrap().unwrap();
+        assert_eq!(recipe.steps, stored.steps);
+        assert_eq!(vec![stored], repo.load_recipes().unwrap());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_reorder_steps() {
+        let (repo, name) = setup_repo();
+
+        let recipe = RecipeBuilder::default()
+            .step("Knead")
+            .step("Proof")
+            .step("Bake")
+            .build();
+        let recipe_id = repo.add_recipe(&recipe).unwrap() as i32;
+
+        assert!(repo.reorder_steps(recipe_id, &[2, 0, 1]).unwrap());
+        let stored = repo.get_recipe(recipe_id).unwrap().unwrap();
+        let texts: Vec<&str> = stored.steps.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(vec!["Bake", "Knead", "Proof"], texts);
+        assert_eq!(stored.steps, repo.load_recipes().unwrap()[0].steps);
+
+        // Nothing changes when the order does not fit the stored steps.
+        assert!(matches!(
+            repo.reorder_steps(recipe_id, &[1, 0]),
+            Err(RepoError::Conflict)
+        ));
+        assert!(matches!(
+            repo.reorder_steps(recipe_id, &[0, 0, 1]),
+            Err(RepoError::Conflict)
+        ));
+        assert_eq!(
+            stored.steps,
+            repo.get_recipe(recipe_id).unwrap().unwrap().steps
+        );
+
+        assert!(!repo.reorder_steps(recipe_id + 1, &[]).unwrap());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_get_recipes() {
+        let (repo, name) = setup_repo();
+
+        let toast = RecipeBuilder::default()
+            .name("Toast")
+            .step("Toast the bread")
+            .ingredient("Bread", 2.0, "slice")
+            .build();
+        let tea = RecipeBuilder::default().name("Tea").step("Steep").build();
+        let toast_id = repo.add_recipe(&toast).unwrap() as i32;
+        let tea_id = repo.add_recipe(&tea).unwrap() as i32;
+        let trashed_id = repo
+            .add_recipe(&RecipeBuilder::default().name("Burnt toast").build())
+            .unwrap() as i32;
+        repo.delete_recipe(trashed_id).unwrap();
+
+        let found = repo
+            .get_recipes(&[tea_id, 99, trashed_id, toast_id, -1])
+            .unwrap();
+        assert_eq!(
+            vec![
+                repo.get_recipe(tea_id).unwrap().unwrap(),
+                repo.get_recipe(toast_id).unwrap().unwrap(),
+            ],
+            found
+        );
+        assert!(repo.get_recipes(&[]).unwrap().is_empty());
+
+        // More ids than fit in one query.
+        let many: Vec<i32> = (0..1200)
+            .map(|i| if i % 2 == 0 { toast_id } else { 5000 + i })
+            .collect();
+        assert_eq!(600, repo.get_recipes(&many).unwrap().len());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_load_recipes_after() {
+        let (repo, name) = setup_repo();
+
+        let ids: Vec<i32> = ["Toast", "Tea", "Burnt toast", "Jam"]
+            .iter()
+            .map(|name| {
+                repo.add_recipe(&RecipeBuilder::default().name(name).build())
+                    .unwrap() as i32
+            })
+            .collect();
+        repo.delete_recipe(ids[2]).unwrap();
+
+        let names = |recipes: Vec<Recipe>| -> Vec<String> {
+            recipes.into_iter().map(|recipe| recipe.name).collect()
+        };
+        assert_eq!(
+            vec!["Toast", "Tea"],
+            names(repo.load_recipes_after(0, 2).unwrap())
+        );
+        // The trashed recipe is skipped without shortening the page.
+        assert_eq!(
+            vec!["Jam"],
+            names(repo.load_recipes_after(ids[1], 2).unwrap())
+        );
+        // Deleting a recipe already sent does not shift the next page.
+        repo.delete_recipe(ids[0]).unwrap();
+        assert_eq!(
+            vec!["Jam"],
+            names(repo.load_recipes_after(ids[1], 2).unwrap())
+        );
+        assert!(repo.load_recipes_after(ids[3], 2).unwrap().is_empty());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_append_ingredients() {
+        let (repo, name) = setup_repo();
+
+        // Salt is already known, so its ingredient id is lower than flour's.
+        repo.add_recipe(
+            &RecipeBuilder::default()
+                .ingredient("Salt", 1.0, "tsp")
+                .build(),
+        )
+        .unwrap();
+        let recipe = RecipeBuilder::default()
+            .ingredient("Flour", 1.0, "cup")
+            .build();
+        let recipe_id = repo.add_recipe(&recipe).unwrap() as i32;
+        let added = RecipeBuilder::default()
+            .ingredient("Salt", 1.0, "tsp")
+            .ingredient("flour", 2.0, "cup")
+            .build()
+            .ingredients;
+
+        assert!(repo.append_ingredients(recipe_id, &added).unwrap());
+        let stored = repo.get_recipe(recipe_id).unwrap().unwrap();
+        let amounts: Vec<(&str, f64)> = stored
+            .ingredients
+            .iter()
+            .map(|i| (i.ingredient.as_str(), i.quantity.value))
+            .collect();
+        assert_eq!(vec![("Flour", 3.0), ("Salt", 1.0)], amounts);
+        assert_eq!(
+            stored,
+            repo.get_recipes(&[recipe_id]).unwrap().remove(0),
+            "bulk loading orders ingredients the same way"
+        );
+
+        assert!(!repo.append_ingredients(recipe_id + 1, &added).unwrap());
+
+        cleanup_repo(&name);
+    }
+
+    #[test]
+    fn test_get() {
         let (repo, name) = setup_repo();
 
         let recipe = Recipe {
-            id: Some(1),
+            id: None,
             name: "Test Recipe".to_string(),
-            desc: Some("Test Description".to_string()),
-            steps: vec!["Step 1".to_string()],
+            desc: None,
+            steps: vec![Step::new("Step 1")],
             ingredients: vec![IngredientQuantity {
                 ingredient: "Potato".to_string(),
                 quantity: Quantity {
                     value: 1.0,
+                    value_max: None,
                     unit: "whole".to_string(),
                 },
+                calories_per_unit: None,
             }],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
         };
 
-        assert_eq!(Vec::<Recipe>::new(), repo.load_recipes().unwrap());
-        repo.add_recipe(&recipe).unwrap();
-        assert_eq!(vec![recipe.clone()], repo.load_recipes().unwrap());
+        assert_eq!(None, repo.get_recipe(1).unwrap());
+        let recipe_id = repo.add_recipe(&recipe).unwrap();
+        assert_eq!(1, recipe_id);
 
-        let recipe_2 = Recipe {
-            steps: vec![],
-            ..recipe.clone()
+        let expected = Recipe {
+            id: Some(recipe_id),
+            ..recipe
         };
-
-        repo.update_recipe(&recipe_2).unwrap();
-        assert_eq!(vec![recipe_2], repo.load_recipes().unwrap());
+        assert_eq!(Some(expected), repo.get_recipe(recipe_id as i32).unwrap());
 
         cleanup_repo(&name);
     }
@@ -0,0 +1,112 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::ApiError;
+use actix_web::dev::Payload;
+use actix_web::error::InternalError;
+use actix_web::{web, Error, FromRequest, HttpRequest, HttpResponse};
+use futures::future::{FutureExt, LocalBoxFuture};
+use serde::de::DeserializeOwned;
+use std::ops::Deref;
+
+/// A JSON body like `web::Json`, but a body of the wrong shape gets a 400
+/// `invalid_body` naming the field at fault, e.g. `steps: invalid type:
+/// string "Boil", expected a sequence`, instead of serde's bare message.
+///
+/// The body is first read as untyped JSON through `web::Json`, so the
+/// `json_config` limits and errors for missing content types and malformed
+/// JSON still apply.
+#[derive(Debug)]
+pub struct CheckedJson<T>(pub T);
+
+impl<T> CheckedJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for CheckedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for CheckedJson<T> {
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let value = web::Json::<serde_json::Value>::from_request(req, payload);
+        async move {
+            let value = value.await?.into_inner();
+            serde_path_to_error::deserialize(value)
+                .map(CheckedJson)
+                .map_err(|err| {
+                    let response = HttpResponse::BadRequest()
+                        .json(ApiError::new("invalid_body", &describe(&err)));
+                    InternalError::from_response(err, response).into()
+                })
+        }
+        .boxed_local()
+    }
+}
+
+/// "Invalid JSON body: " followed by the path to the bad value, if it is not
+/// the whole body, and what was wrong with it.
+fn describe(err: &serde_path_to_error::Error<serde_json::Error>) -> String {
+    let path = err.path().to_string();
+    if path == "." {
+        format!("Invalid JSON body: {}", err.inner())
+    } else {
+        format!("Invalid JSON body: {}: {}", path, err.inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Recipe;
+
+    fn describe_body(body: &str) -> String {
+        let value: serde_json::Value = serde_json::from_str(body).unwrap();
+        describe(&serde_path_to_error::deserialize::<_, Recipe>(value).unwrap_err())
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            "Invalid JSON body: steps: invalid type: string \"Boil\", expected a sequence",
+            describe_body(r#"{"name": "Tea", "desc": null, "steps": "Boil", "ingredients": []}"#)
+        );
+        assert_eq!(
+            "Invalid JSON body: ingredients[1].quantity.value: invalid type: string \"two\", expected f64",
+            describe_body(
+                r#"{"name": "Tea", "desc": null, "steps": [], "ingredients": [
+                    {"ingredient": "Water", "quantity": {"value": 1, "unit": "cup"}},
+                    {"ingredient": "Tea", "quantity": {"value": "two", "unit": "bag"}}
+                ]}"#
+            )
+        );
+        assert_eq!(
+            "Invalid JSON body: invalid type: string \"Tea\", expected struct Recipe",
+            describe_body(r#""Tea""#)
+        );
+    }
+}
@@ -0,0 +1,120 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::Recipe;
+use serde::Deserialize;
+use serde_json::Value;
+
+/// Denominators a quantity is rounded to, smallest first so that ties go to
+/// the simpler fraction.
+const DENOMINATORS: [u32; 4] = [2, 3, 4, 8];
+
+/// How quantities are written in a response.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QuantityFormat {
+    /// The stored number, e.g. `1.5`.
+    #[default]
+    Decimal,
+    /// A string with the nearest culinary fraction, e.g. `"1 1/2"`.
+    Fraction,
+}
+
+/// `value` rounded to the nearest half, third, quarter or eighth and written
+/// as a mixed number, e.g. "2 3/4", "1/3" or "2".
+pub fn to_fraction(value: f64) -> String {
+    if !value.is_finite() {
+        return value.to_string();
+    }
+    if value < 0.0 {
+        return format!("-{}", to_fraction(-value));
+    }
+
+    let mut whole = value.trunc() as u64;
+    let part = value.fract();
+    let (mut numer, mut denom) = (0, 1);
+    let mut best = part;
+    for &d in DENOMINATORS.iter() {
+        let n = (part * f64::from(d)).round() as u32;
+        let error = (part - f64::from(n) / f64::from(d)).abs();
+        if error < best {
+            best = error;
+            numer = n;
+            denom = d;
+        }
+    }
+    if numer == denom {
+        whole += 1;
+        numer = 0;
+    }
+
+    match (whole, numer) {
+        (whole, 0) => whole.to_string(),
+        (0, numer) => format!("{}/{}", numer, denom),
+        (whole, numer) => format!("{} {}/{}", whole, numer, denom),
+    }
+}
+
+/// The recipe as JSON with each ingredient quantity written by `to_fraction`.
+pub fn with_fractions(recipe: &Recipe) -> Value {
+    let mut json = serde_json::to_value(recipe).unwrap_or(Value::Null);
+    if let Some(ingredients) = json.get_mut("ingredients").and_then(Value::as_array_mut) {
+        for (json_ing, ing_quant) in ingredients.iter_mut().zip(recipe.ingredients.iter()) {
+            json_ing["quantity"]["value"] = Value::String(to_fraction(ing_quant.quantity.value));
+            if let Some(value_max) = ing_quant.quantity.value_max {
+                json_ing["quantity"]["value_max"] = Value::String(to_fraction(value_max));
+            }
+        }
+    }
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_fraction() {
+        assert_eq!("1/2", to_fraction(0.5));
+        assert_eq!("1/3", to_fraction(1.0 / 3.0));
+        assert_eq!("1/3", to_fraction(0.333));
+        assert_eq!("1/8", to_fraction(0.125));
+        assert_eq!("2 3/4", to_fraction(2.75));
+        assert_eq!("1 1/2", to_fraction(1.5));
+        assert_eq!("2 2/3", to_fraction(2.66));
+        assert_eq!("3", to_fraction(3.0));
+        assert_eq!("1", to_fraction(0.98));
+        assert_eq!("0", to_fraction(0.01));
+    }
+
+    #[test]
+    fn test_with_fractions() {
+        let recipe: Recipe = serde_json::from_str(
+            r#"{"id": 1, "name": "Toast", "desc": null, "steps": [], "ingredients": [
+                {"ingredient": "Butter", "quantity": {"value": 1.5, "unit": "tbsp"}}
+            ]}"#,
+        )
+        .unwrap();
+
+        let json = with_fractions(&recipe);
+        assert_eq!(
+            serde_json::json!({"value": "1 1/2", "unit": "tbsp"}),
+            json["ingredients"][0]["quantity"]
+        );
+        assert_eq!("Toast", json["name"]);
+    }
+}
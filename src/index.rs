@@ -0,0 +1,143 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::RecipeSummary;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Heading for recipes whose name does not start with a letter.
+pub const OTHER_HEADING: &str = "#";
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct IndexEntry {
+    pub id: u32,
+    pub name: String,
+}
+
+/// The recipes filed under one heading of the alphabetical index.
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct IndexGroup {
+    pub letter: String,
+    pub recipes: Vec<IndexEntry>,
+}
+
+/// The heading a recipe name is filed under: its first letter in upper
+/// case, or `OTHER_HEADING` when it starts with anything else.
+pub fn heading(name: &str) -> String {
+    match name.trim_start().chars().next() {
+        Some(first) if first.is_alphabetic() => first.to_uppercase().collect(),
+        _ => OTHER_HEADING.to_string(),
+    }
+}
+
+/// Group recipes by `heading`. Groups are in alphabetical order with
+/// `OTHER_HEADING` last, and recipes within a group are sorted by name,
+/// ignoring case.
+pub fn build_index(recipes: &[RecipeSummary]) -> Vec<IndexGroup> {
+    let mut letters: BTreeMap<String, Vec<IndexEntry>> = BTreeMap::new();
+    let mut other = Vec::new();
+    for recipe in recipes.iter() {
+        let entry = IndexEntry {
+            id: recipe.id,
+            name: recipe.name.clone(),
+        };
+        let letter = heading(&recipe.name);
+        if letter == OTHER_HEADING {
+            other.push(entry);
+        } else {
+            letters.entry(letter).or_default().push(entry);
+        }
+    }
+
+    let mut groups: Vec<IndexGroup> = letters
+        .into_iter()
+        .map(|(letter, recipes)| IndexGroup { letter, recipes })
+        .collect();
+    if !other.is_empty() {
+        groups.push(IndexGroup {
+            letter: OTHER_HEADING.to_string(),
+            recipes: other,
+        });
+    }
+    for group in groups.iter_mut() {
+        group
+            .recipes
+            .sort_by_cached_key(|entry| (entry.name.to_lowercase(), entry.id));
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary(id: u32, name: &str) -> RecipeSummary {
+        RecipeSummary {
+            id,
+            name: name.to_string(),
+            desc: None,
+            image_url: None,
+        }
+    }
+
+    fn entry(id: u32, name: &str) -> IndexEntry {
+        IndexEntry {
+            id,
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_heading() {
+        assert_eq!("A", heading("apple pie"));
+        assert_eq!("É", heading("éclair"));
+        assert_eq!("#", heading("7-layer dip"));
+        assert_eq!("#", heading("(Grandma's) Stew"));
+        assert_eq!("#", heading(""));
+    }
+
+    #[test]
+    fn test_build_index() {
+        let recipes = vec![
+            summary(1, "banana bread"),
+            summary(2, "7-layer dip"),
+            summary(3, "Apple pie"),
+            summary(4, "Brownies"),
+            summary(5, "10 minute soup"),
+            summary(6, "apple crumble"),
+        ];
+
+        assert_eq!(
+            vec![
+                IndexGroup {
+                    letter: "A".to_string(),
+                    recipes: vec![entry(6, "apple crumble"), entry(3, "Apple pie")],
+                },
+                IndexGroup {
+                    letter: "B".to_string(),
+                    recipes: vec![entry(1, "banana bread"), entry(4, "Brownies")],
+                },
+                IndexGroup {
+                    letter: "#".to_string(),
+                    recipes: vec![entry(5, "10 minute soup"), entry(2, "7-layer dip")],
+                },
+            ],
+            build_index(&recipes)
+        );
+        assert!(build_index(&[]).is_empty());
+    }
+}
@@ -0,0 +1,177 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::{FieldError, IngredientQuantity, Quantity};
+
+/// The columns expected in every row. A first row naming them is taken as a
+/// header and skipped.
+pub const COLUMNS: [&str; 3] = ["name", "quantity", "unit"];
+
+/// Parse `name,quantity,unit` rows into ingredients, in order. Fields are
+/// trimmed and blank lines skipped. Every bad row is reported, each as a
+/// `FieldError` whose field is `line N`, counting from 1, or `record N` for
+/// the Nth row when the reader cannot tell the line.
+pub fn parse(body: &[u8]) -> Result<Vec<IngredientQuantity>, Vec<FieldError>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(body);
+
+    let mut ingredients = Vec::new();
+    let mut problems = Vec::new();
+    for (i, result) in reader.records().enumerate() {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                let line = e.position().map(|position| position.line());
+                problems.push(line_error(line, i, &e.to_string()));
+                continue;
+            }
+        };
+        let line = record.position().map(|position| position.line());
+        if i == 0 && is_header(&record) {
+            continue;
+        }
+        match parse_row(&record) {
+            Ok(ing_quant) => ingredients.push(ing_quant),
+            Err(message) => problems.push(line_error(line, i, &message)),
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(ingredients)
+    } else {
+        Err(problems)
+    }
+}
+
+fn is_header(record: &csv::StringRecord) -> bool {
+    record.len() == COLUMNS.len()
+        && record
+            .iter()
+            .zip(COLUMNS.iter())
+            .all(|(field, column)| field.eq_ignore_ascii_case(column))
+}
+
+fn parse_row(record: &csv::StringRecord) -> Result<IngredientQuantity, String> {
+    if record.len() != COLUMNS.len() {
+        return Err(format!(
+            "expected {} columns ({}), found {}",
+            COLUMNS.len(),
+            COLUMNS.join(","),
+            record.len()
+        ));
+    }
+
+    let name = &record[0];
+    if name.is_empty() {
+        return Err("name must not be blank".to_string());
+    }
+    let value = record[1]
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite() && *value >= 0.0)
+        .ok_or_else(|| {
+            format!(
+                "quantity {:?} must be a finite number, zero or greater",
+                &record[1]
+            )
+        })?;
+
+    Ok(IngredientQuantity {
+        ingredient: name.to_string(),
+        quantity: Quantity {
+            value,
+            value_max: None,
+            unit: record[2].to_string(),
+        },
+        calories_per_unit: None,
+    })
+}
+
+/// An error for the row at `index`, counting from 0, on `line` if known.
+fn line_error(line: Option<u64>, index: usize, message: &str) -> FieldError {
+    let field = match line {
+        Some(line) => format!("line {}", line),
+        None => format!("record {}", index + 1),
+    };
+    FieldError::new(&field, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ingredient(name: &str, value: f64, unit: &str) -> IngredientQuantity {
+        IngredientQuantity {
+            ingredient: name.to_string(),
+            quantity: Quantity {
+                value,
+                value_max: None,
+                unit: unit.to_string(),
+            },
+            calories_per_unit: None,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let body = b"Name, Quantity, Unit\n Flour , 2.5, cup\n\n\"Salt, kosher\",1,tsp\r\n";
+        assert_eq!(
+            Ok(vec![
+                ingredient("Flour", 2.5, "cup"),
+                ingredient("Salt, kosher", 1.0, "tsp"),
+            ]),
+            parse(body)
+        );
+
+        // Without a header every row is an ingredient.
+        assert_eq!(
+            Ok(vec![ingredient("Egg", 2.0, "whole")]),
+            parse(b"Egg,2,whole")
+        );
+        assert_eq!(Ok(vec![]), parse(b""));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let body =
+            b"name,quantity,unit\nFlour,two,cup\nSugar,1,cup\n,1,cup\nButter,1\nMilk,-1,cup\n";
+        assert_eq!(
+            Err(vec![
+                FieldError::new(
+                    "line 2",
+                    "quantity \"two\" must be a finite number, zero or greater"
+                ),
+                FieldError::new("line 4", "name must not be blank"),
+                FieldError::new("line 5", "expected 3 columns (name,quantity,unit), found 2"),
+                FieldError::new(
+                    "line 6",
+                    "quantity \"-1\" must be a finite number, zero or greater"
+                ),
+            ]),
+            parse(body)
+        );
+
+        // Without a position the row is named by its index instead.
+        assert_eq!(
+            FieldError::new("record 3", "bad"),
+            line_error(None, 2, "bad")
+        );
+    }
+}
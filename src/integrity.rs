@@ -0,0 +1,124 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::db;
+use anyhow::Result;
+use log::{error, info};
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Result of the most recent integrity check, shared with the `/health`
+/// endpoint.
+#[derive(Serialize, Debug, Clone, PartialEq, Default)]
+pub struct IntegrityStatus {
+    pub enabled: bool,
+    /// Seconds since the Unix epoch when the last check finished.
+    pub last_checked: Option<u64>,
+    pub ok: Option<bool>,
+    pub problems: Vec<String>,
+}
+
+pub type SharedIntegrity = Arc<RwLock<IntegrityStatus>>;
+
+/// Read the check interval from `RECIPE_BOOK_INTEGRITY_CHECK_SECS`. The check
+/// is disabled when the variable is unset, zero, or not a number.
+pub fn interval_from_env() -> Option<Duration> {
+    std::env::var("RECIPE_BOOK_INTEGRITY_CHECK_SECS")
+        .ok()
+        .and_then(|secs| secs.trim().parse::<u64>().ok())
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs)
+}
+
+/// Run `check` once and record its outcome in `status`.
+pub fn run_check<F>(status: &SharedIntegrity, check: F)
+where
+    F: FnOnce() -> Result<Vec<String>>,
+{
+    let (ok, problems) = match check() {
+        Ok(problems) if problems.is_empty() => (true, problems),
+        Ok(problems) => {
+            for problem in problems.iter() {
+                error!("Database integrity problem: {}", problem);
+            }
+            (false, problems)
+        }
+        Err(e) => {
+            error!("Unable to run database integrity check: {}", e);
+            (false, vec![e.to_string()])
+        }
+    };
+
+    let last_checked = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .ok();
+
+    let mut status = status.write().unwrap();
+    status.last_checked = last_checked;
+    status.ok = Some(ok);
+    status.problems = problems;
+}
+
+/// Spawn a dedicated thread that runs `PRAGMA quick_check` every `interval`.
+/// The check uses its own connection so it never competes with request
+/// handlers for pooled connections.
+pub fn spawn_periodic_check(
+    db_backend: db::Backend,
+    db_path: String,
+    interval: Duration,
+    status: SharedIntegrity,
+) -> thread::JoinHandle<()> {
+    status.write().unwrap().enabled = true;
+    info!(
+        "Running database integrity check every {} seconds",
+        interval.as_secs()
+    );
+
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        run_check(&status, || db::check_integrity(&db_backend, &db_path));
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_run_check() {
+        let status = SharedIntegrity::default();
+
+        run_check(&status, || Ok(vec![]));
+        let current = status.read().unwrap().clone();
+        assert_eq!(Some(true), current.ok);
+        assert!(current.last_checked.is_some());
+
+        run_check(&status, || Ok(vec!["page 4 is never used".to_string()]));
+        let current = status.read().unwrap().clone();
+        assert_eq!(Some(false), current.ok);
+        assert_eq!(vec!["page 4 is never used"], current.problems);
+
+        run_check(&status, || Err(anyhow!("disk I/O error")));
+        let current = status.read().unwrap().clone();
+        assert_eq!(Some(false), current.ok);
+        assert_eq!(vec!["disk I/O error"], current.problems);
+    }
+}
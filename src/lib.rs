@@ -15,31 +15,661 @@
 /// You should have received a copy of the GNU Affero General Public License
 /// along with this program.  If not, see <https://www.gnu.org/licenses/>.
 ///
-use actix_web::{delete, get, post, put, web, Error, HttpResponse, Responder};
-use log::error;
+use actix_web::error::{BlockingError, JsonPayloadError};
+use actix_web::http::header;
+use actix_web::web::Bytes;
+use actix_web::{delete, get, patch, post, put, web, Error, HttpRequest, HttpResponse, Responder};
+use extract::CheckedJson;
+use futures::StreamExt;
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
 
+pub mod archive;
+pub mod auth;
+pub mod cache;
+pub mod conditional;
+pub mod config;
+pub mod cook;
+pub mod cors;
 pub mod db;
+pub mod extract;
+pub mod fractions;
+pub mod index;
+pub mod ingredient_csv;
+pub mod integrity;
+pub mod logging;
+pub mod markdown;
+pub mod merge;
+pub mod metrics;
+pub mod ndjson;
+pub mod negotiate;
+pub mod normalize;
+pub mod nutrition;
+pub mod print;
+pub mod provenance;
+pub mod rate_limit;
+pub mod ratings;
+pub mod rational;
+pub mod shopping;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+pub mod transfer;
+pub mod tree;
+pub mod units;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Recipe {
     pub id: Option<u32>, // Used for database.
     pub name: String,
     pub desc: Option<String>,
-    pub steps: Vec<String>,
+    pub steps: Vec<Step>,
     pub ingredients: Vec<IngredientQuantity>,
+    /// Starred by the user. Set through the favorite endpoints; full edits
+    /// leave it unchanged.
+    #[serde(default)]
+    pub favorite: bool,
+    /// Hero image. Must be an `http` or `https` URL; see `is_web_url`.
+    #[serde(default)]
+    pub image_url: Option<String>,
+    /// e.g. "Italian".
+    #[serde(default)]
+    pub cuisine: Option<String>,
+    /// e.g. "Main" or "Dessert".
+    #[serde(default)]
+    pub course: Option<String>,
+    /// Where the recipe came from, e.g. a cookbook title or website name.
+    #[serde(default)]
+    pub source_name: Option<String>,
+    /// Link to the original recipe. Must be an `http` or `https` URL.
+    #[serde(default)]
+    pub source_url: Option<String>,
+    /// What the recipe makes when a serving count does not fit, e.g.
+    /// "makes 12 muffins" or "1 loaf".
+    #[serde(default)]
+    pub yield_description: Option<String>,
+    #[serde(default)]
+    pub difficulty: Option<Difficulty>,
+    /// Longer free-form remarks for the detail page, e.g. "tastes better the
+    /// next day". Unlike `desc` it is not meant for list views.
+    #[serde(default)]
+    pub notes: Option<String>,
+    /// The original text the recipe was parsed from, e.g. a messy paste, kept
+    /// for reference. Only single-recipe responses include it; listings
+    /// leave it out to stay small. A full edit without it keeps the stored
+    /// text.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw_source: Option<String>,
+    /// Gallery image URLs in display order, each `http` or `https`.
+    #[serde(default)]
+    pub photos: Vec<String>,
+}
+
+/// How hard a recipe is to make.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Hard => "hard",
+        }
+    }
+
+    /// The level stored as `difficulty`, or `None` for anything else.
+    pub fn parse(difficulty: &str) -> Option<Difficulty> {
+        match difficulty {
+            "easy" => Some(Difficulty::Easy),
+            "medium" => Some(Difficulty::Medium),
+            "hard" => Some(Difficulty::Hard),
+            _ => None,
+        }
+    }
+}
+
+/// One instruction in a recipe. Requests may give a step as a bare string,
+/// which is read as a step with just that text; responses always use the
+/// object form.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(from = "StepInput")]
+pub struct Step {
+    pub text: String,
+    /// How long the step takes, e.g. 25 for "Bake for 25 min".
+    pub duration_minutes: Option<u32>,
+    pub note: Option<String>,
+    /// Picture of the step, for step-by-step tutorials. Must be an http or
+    /// https URL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<String>,
+}
+
+impl Step {
+    pub fn new(text: &str) -> Self {
+        Step {
+            text: text.to_string(),
+            duration_minutes: None,
+            note: None,
+            image_url: None,
+        }
+    }
+}
+
+/// The shapes a step is accepted in.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StepInput {
+    Text(String),
+    Full {
+        text: String,
+        #[serde(default)]
+        duration_minutes: Option<u32>,
+        #[serde(default)]
+        note: Option<String>,
+        #[serde(default)]
+        image_url: Option<String>,
+    },
+}
+
+impl From<StepInput> for Step {
+    fn from(input: StepInput) -> Self {
+        match input {
+            StepInput::Text(text) => Step::new(&text),
+            StepInput::Full {
+                text,
+                duration_minutes,
+                note,
+                image_url,
+            } => Step {
+                text,
+                duration_minutes,
+                note,
+                image_url,
+            },
+        }
+    }
+}
+
+/// One problem found by `Recipe::validate`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct FieldError {
+    /// Path to the offending field, e.g. `name` or `steps[2]`.
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: &str) -> Self {
+        FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Longest text accepted, in characters, for the recipe fields that could
+/// otherwise bloat every response they appear in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FieldLimits {
+    pub name: usize,
+    pub desc: usize,
+    /// Each step's text.
+    pub step: usize,
+    /// Each ingredient's name.
+    pub ingredient: usize,
+}
+
+impl Default for FieldLimits {
+    fn default() -> Self {
+        FieldLimits {
+            name: 200,
+            desc: 2000,
+            step: 1000,
+            ingredient: 100,
+        }
+    }
+}
+
+/// A `FieldError` for `field` if `text` is longer than `limit` characters.
+fn check_length(field: &str, text: &str, limit: usize) -> Option<FieldError> {
+    if text.chars().count() > limit {
+        Some(FieldError::new(
+            field,
+            &format!("must be at most {} characters", limit),
+        ))
+    } else {
+        None
+    }
+}
+
+impl Recipe {
+    /// `validate_with_limits` with the default `FieldLimits`.
+    pub fn validate(&self) -> Vec<FieldError> {
+        self.validate_with_limits(&FieldLimits::default())
+    }
+
+    /// Check the recipe's content, returning every problem found. An empty
+    /// list means it can be stored. The id is not checked, and a recipe
+    /// needs no ingredients or steps.
+    pub fn validate_with_limits(&self, limits: &FieldLimits) -> Vec<FieldError> {
+        let mut problems = Vec::new();
+
+        if self.name.trim().is_empty() {
+            problems.push(FieldError::new("name", "must not be blank"));
+        }
+        problems.extend(check_length("name", &self.name, limits.name));
+        if let Some(desc) = &self.desc {
+            problems.extend(check_length("desc", desc, limits.desc));
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            let field = format!("steps[{}]", i);
+            if step.text.trim().is_empty() {
+                problems.push(FieldError::new(&field, "must not be blank"));
+            }
+            problems.extend(check_length(&field, &step.text, limits.step));
+            if matches!(&step.image_url, Some(url) if !is_web_url(url)) {
+                problems.push(FieldError::new(
+                    &format!("steps[{}].image_url", i),
+                    "must be an http or https URL",
+                ));
+            }
+        }
+        for (i, ing_quant) in self.ingredients.iter().enumerate() {
+            let field = format!("ingredients[{}].ingredient", i);
+            if ing_quant.ingredient.trim().is_empty() {
+                problems.push(FieldError::new(&field, "must not be blank"));
+            }
+            problems.extend(check_length(
+                &field,
+                &ing_quant.ingredient,
+                limits.ingredient,
+            ));
+            let value = ing_quant.quantity.value;
+            if !value.is_finite() || value < 0.0 {
+                problems.push(FieldError::new(
+                    &format!("ingredients[{}].quantity.value", i),
+                    "must be a finite number, zero or greater",
+                ));
+            }
+            if let Some(value_max) = ing_quant.quantity.value_max {
+                if !value_max.is_finite() || value_max < value {
+                    problems.push(FieldError::new(
+                        &format!("ingredients[{}].quantity.value_max", i),
+                        "must be a finite number no less than value",
+                    ));
+                }
+            }
+        }
+        problems.extend(self.validate_repeated_ingredients());
+        if matches!(&self.image_url, Some(url) if !is_web_url(url)) {
+            problems.push(FieldError::new("image_url", "must be an http or https URL"));
+        }
+        if matches!(&self.source_url, Some(url) if !is_web_url(url)) {
+            problems.push(FieldError::new(
+                "source_url",
+                "must be an http or https URL",
+            ));
+        }
+        for (i, url) in self.photos.iter().enumerate() {
+            if !is_web_url(url) {
+                problems.push(FieldError::new(
+                    &format!("photos[{}]", i),
+                    "must be an http or https URL",
+                ));
+            }
+        }
+
+        problems
+    }
+
+    /// Ingredients listed again in a unit that cannot be added to their first
+    /// line. Repeats are added together when stored, so the units must
+    /// convert.
+    pub fn validate_repeated_ingredients(&self) -> Vec<FieldError> {
+        let mut problems = Vec::new();
+        for (i, ing_quant) in self.ingredients.iter().enumerate() {
+            let name = db::normalize_ingredient_name(&ing_quant.ingredient);
+            let first = self.ingredients[..i]
+                .iter()
+                .position(|earlier| db::normalize_ingredient_name(&earlier.ingredient) == name);
+            if let Some(first) = first {
+                if units::add_quantities(&self.ingredients[first].quantity, &ing_quant.quantity)
+                    .is_none()
+                {
+                    problems.push(FieldError::new(
+                        &format!("ingredients[{}].quantity.unit", i),
+                        &format!(
+                            "repeats ingredients[{}] in a unit that cannot be added to it",
+                            first
+                        ),
+                    ));
+                }
+            }
+        }
+        problems
+    }
+
+    /// Ingredient units missing from the `units` table, each with a
+    /// suggested spelling when one is close.
+    pub fn validate_units(&self) -> Vec<FieldError> {
+        self.ingredients
+            .iter()
+            .enumerate()
+            .filter(|(_, ing_quant)| !units::is_known_unit(&ing_quant.quantity.unit))
+            .map(|(i, ing_quant)| {
+                let unit = &ing_quant.quantity.unit;
+                let message = match units::suggest_unit(unit) {
+                    Some(suggestion) => {
+                        format!("unknown unit {:?}; did you mean {:?}?", unit, suggestion)
+                    }
+                    None => format!("unknown unit {:?}", unit),
+                };
+                FieldError::new(&format!("ingredients[{}].quantity.unit", i), &message)
+            })
+            .collect()
+    }
+}
+
+/// Whether `url` uses the `http` or `https` scheme. Anything else, such as
+/// `javascript:` or `file:`, is rejected before it can be stored and later
+/// rendered by a client.
+pub fn is_web_url(url: &str) -> bool {
+    let lower = url.trim().to_lowercase();
+    ["http://", "https://"]
+        .iter()
+        .any(|scheme| lower.len() > scheme.len() && lower.starts_with(scheme))
+}
+
+/// A recipe in the trash with the time it was deleted.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct DeletedRecipe {
+    #[serde(flatten)]
+    pub recipe: Recipe,
+    pub deleted_at: String,
+}
+
+/// The fields of a recipe needed for list views, without steps or ingredients.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct RecipeSummary {
+    pub id: u32,
+    pub name: String,
+    pub desc: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<String>,
+}
+
+impl From<&Recipe> for RecipeSummary {
+    fn from(recipe: &Recipe) -> Self {
+        RecipeSummary {
+            id: recipe.id.unwrap_or_default(),
+            name: recipe.name.clone(),
+            desc: recipe.desc.clone(),
+            image_url: recipe.image_url.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Category {
+    pub id: i32,
+    pub name: String,
+    pub parent_id: Option<i32>,
+}
+
+/// One page of a longer list, with the total needed to build page links.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: u64,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+/// A distinct cuisine or course and how many recipes have it.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: u32,
+}
+
+/// Collection-wide totals for a dashboard. An empty collection has zero
+/// counts and no average or unit.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Stats {
+    pub total_recipes: u64,
+    /// Distinct ingredients used by at least one recipe.
+    pub total_ingredients: u64,
+    pub average_steps: Option<f64>,
+    /// The unit appearing on the most ingredient lines.
+    pub most_used_unit: Option<String>,
+}
+
+/// How many recipes use an ingredient.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct IngredientUsage {
+    pub ingredient: String,
+    pub recipe_count: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct Facets {
+    pub cuisines: Vec<FacetCount>,
+    pub courses: Vec<FacetCount>,
+}
+
+/// A partial update to a recipe. Absent fields are left unchanged, while an
+/// explicit empty `steps` or `ingredients` array clears them.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct RecipePatch {
+    pub name: Option<String>,
+    pub desc: Option<String>,
+    pub steps: Option<Vec<Step>>,
+    pub ingredients: Option<Vec<IngredientQuantity>>,
+}
+
+impl RecipePatch {
+    /// The fields of `recipe` as they are after the patch, so the result can
+    /// be validated before anything is stored.
+    pub fn apply_to(&self, recipe: &mut Recipe) {
+        if let Some(name) = &self.name {
+            recipe.name = name.clone();
+        }
+        if let Some(desc) = &self.desc {
+            recipe.desc = Some(desc.clone());
+        }
+        if let Some(steps) = &self.steps {
+            recipe.steps = steps.clone();
+        }
+        if let Some(ingredients) = &self.ingredients {
+            recipe.ingredients = ingredients.clone();
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct Quantity {
     pub value: f64,
+    /// Upper end of a range such as "2-3 cloves", where `value` is the lower
+    /// end. Absent for exact amounts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value_max: Option<f64>,
     pub unit: String,
 }
 
+impl Quantity {
+    /// The amount as written in a recipe: "2", or "2-3" for a range.
+    pub fn amount(&self) -> String {
+        match self.value_max {
+            Some(value_max) => format!("{}-{}", self.value, value_max),
+            None => self.value.to_string(),
+        }
+    }
+
+    /// Multiply both ends of the amount by `factor`.
+    pub fn scale(&self, factor: f64) -> Quantity {
+        Quantity {
+            value: self.value * factor,
+            value_max: self.value_max.map(|value_max| value_max * factor),
+            unit: self.unit.clone(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct IngredientQuantity {
     pub ingredient: String,
     pub quantity: Quantity,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub calories_per_unit: Option<f64>,
+}
+
+/// The JSON body returned for every error response.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ApiError {
+    pub code: String,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(code: &str, message: &str) -> Self {
+        ApiError {
+            code: code.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn database() -> Self {
+        ApiError::new("database_error", "Database error.")
+    }
+}
+
+/// Default for `ServerConfig::max_body_bytes`: 1 MiB.
+pub const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Default for `ServerConfig::max_import_bytes`: 16 MiB, since a bulk
+/// import legitimately carries many recipes.
+pub const DEFAULT_MAX_IMPORT_BYTES: usize = 16 * 1024 * 1024;
+
+/// Settings for every `web::Json` and `CheckedJson` body, replacing actix's
+/// plain-text errors with `ApiError`s. A body not declared as JSON,
+/// including one without a `Content-Type`, gets a 415
+/// `unsupported_media_type`, as on every endpoint reading a body of another
+/// type. A body larger than `limit` bytes gets a 413 `payload_too_large`,
+/// and one that cannot be read as the expected JSON gets a 400
+/// `invalid_body` saying what was wrong, e.g. where parsing stopped.
+pub fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(move |err, _req| {
+            let response = match err {
+                JsonPayloadError::ContentType => unsupported_media_type("application/json"),
+                JsonPayloadError::Overflow => payload_too_large(limit),
+                _ => HttpResponse::BadRequest().json(ApiError::new(
+                    "invalid_body",
+                    &format!("Invalid JSON body: {}", err),
+                )),
+            };
+            actix_web::error::InternalError::from_response(err, response).into()
+        })
+}
+
+/// A 413 naming the largest body the endpoint accepts.
+fn payload_too_large(limit: usize) -> HttpResponse {
+    HttpResponse::PayloadTooLarge().json(ApiError::new(
+        "payload_too_large",
+        &format!("Request body must not exceed {} bytes.", limit),
+    ))
+}
+
+/// Read the whole request body, or `None` once it grows past `limit`
+/// bytes, without buffering more than that.
+async fn read_body(mut payload: web::Payload, limit: usize) -> Result<Option<Bytes>, Error> {
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(Some(body.freeze()))
+}
+
+/// A 415 naming the media types the endpoint accepts.
+fn unsupported_media_type(accepted: &str) -> HttpResponse {
+    HttpResponse::UnsupportedMediaType().json(ApiError::new(
+        "unsupported_media_type",
+        &format!("Content-Type must be {}.", accepted),
+    ))
+}
+
+/// A 404 `not_found` `ApiError` naming the missing recipe.
+fn recipe_not_found(recipe_id: i32) -> HttpResponse {
+    HttpResponse::NotFound().json(ApiError::new(
+        "not_found",
+        &format!("Recipe {} not found", recipe_id),
+    ))
+}
+
+/// The response for a failed repo call: 404 for `RepoError::NotFound`, 409
+/// for `RepoError::Conflict` and a 500 `database_error` for anything else.
+fn repo_failure(e: &BlockingError<db::RepoError>) -> HttpResponse {
+    match e {
+        BlockingError::Error(db::RepoError::NotFound) => {
+            HttpResponse::NotFound().json(ApiError::new("not_found", "Not found."))
+        }
+        BlockingError::Error(db::RepoError::Conflict) => HttpResponse::Conflict().json(
+            ApiError::new("conflict", "Conflicts with an existing record."),
+        ),
+        BlockingError::Error(db::RepoError::Stale) => {
+            HttpResponse::PreconditionFailed().json(ApiError::new(
+                "precondition_failed",
+                "The recipe changed after If-Unmodified-Since.",
+            ))
+        }
+        _ => HttpResponse::InternalServerError().json(ApiError::database()),
+    }
+}
+
+/// Run `f` against the repo on actix's blocking thread pool, so a slow query
+/// does not stall the other requests handled by this worker.
+async fn with_repo<F, T>(
+    config: &web::Data<AppConfig>,
+    f: F,
+) -> Result<T, BlockingError<db::RepoError>>
+where
+    F: FnOnce(&dyn db::Repo) -> db::RepoResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let config = config.clone();
+    web::block(move || f(config.repo.as_ref())).await
+}
+
+/// Look up every id with one `get_recipes` call, pairing each with its
+/// recipe if found.
+async fn load_each(
+    config: &web::Data<AppConfig>,
+    recipe_ids: Vec<i32>,
+) -> Result<Vec<(i32, Option<Recipe>)>, BlockingError<db::RepoError>> {
+    with_repo(config, move |repo| {
+        let found: std::collections::HashMap<i32, Recipe> = repo
+            .get_recipes(&recipe_ids)?
+            .into_iter()
+            .map(|recipe| (recipe.id.unwrap_or_default() as i32, recipe))
+            .collect();
+        Ok(recipe_ids
+            .into_iter()
+            .map(|recipe_id| (recipe_id, found.get(&recipe_id).cloned()))
+            .collect())
+    })
+    .await
 }
 
 #[get("/")]
@@ -47,75 +677,4831 @@ async fn hello() -> impl Responder {
     HttpResponse::Ok().body("hello, world!")
 }
 
+/// The app's `default_service`: a `not_found` `ApiError` for any request no
+/// route matches, instead of actix's empty 404.
+pub async fn not_found(req: HttpRequest) -> HttpResponse {
+    HttpResponse::NotFound().json(ApiError::new(
+        "not_found",
+        &format!("No endpoint for {} {}", req.method(), req.path()),
+    ))
+}
+
 pub struct AppConfig {
     pub repo: Box<dyn db::Repo>,
+    pub whitespace: normalize::WhitespaceOptions,
+    pub integrity: integrity::SharedIntegrity,
+    /// Reject ingredient units missing from the `units` table instead of
+    /// only logging them.
+    pub strict_units: bool,
+    /// Shared by every worker; `None` when caching is disabled.
+    pub recipe_cache: Option<Arc<cache::RecipeCache>>,
+    /// Longest NDJSON import line accepted, matching the JSON body limit.
+    pub max_body_bytes: usize,
+    /// Largest `/recipes/import` body accepted.
+    pub max_import_bytes: usize,
+    /// Where `/admin/backup` writes database copies.
+    pub backup_dir: PathBuf,
+    pub field_limits: FieldLimits,
+}
+
+impl AppConfig {
+    /// Drop the cached `/recipes/all` body after a write.
+    fn invalidate_cache(&self) {
+        if let Some(cache) = &self.recipe_cache {
+            cache.invalidate();
+        }
+    }
+}
+
+/// `Recipe::validate_units` when units are strict. Otherwise unknown units
+/// are logged and accepted, and nothing is returned.
+fn check_units(recipe: &Recipe, config: &AppConfig) -> Vec<FieldError> {
+    let problems = recipe.validate_units();
+    if config.strict_units {
+        return problems;
+    }
+    for problem in problems {
+        warn!(
+            "Recipe {:?}: {} has an {}",
+            recipe.name, problem.field, problem.message
+        );
+    }
+    Vec::new()
+}
+
+/// `Recipe::validate_with_limits` followed by `check_units`.
+fn validate_recipe(recipe: &Recipe, config: &AppConfig) -> Vec<FieldError> {
+    let mut problems = recipe.validate_with_limits(&config.field_limits);
+    problems.extend(check_units(recipe, config));
+    problems
+}
+
+/// Request counts, latencies and pool usage in the Prometheus text format.
+#[get("/metrics")]
+async fn prometheus(metrics: web::Data<metrics::Metrics>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4; charset=utf-8")
+        .body(metrics.render())
+}
+
+/// The running build: crate version, git commit and when it was compiled.
+#[get("/version")]
+async fn build_version() -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_hash": env!("RECIPE_BOOK_GIT_HASH"),
+        "build_time": env!("RECIPE_BOOK_BUILD_TIME"),
+    }))
+}
+
+#[get("/health")]
+async fn health(config: web::Data<AppConfig>) -> impl Responder {
+    let integrity = config.integrity.read().unwrap().clone();
+    let status = if integrity.ok == Some(false) {
+        "degraded"
+    } else {
+        "ok"
+    };
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": status,
+        "integrity": integrity,
+    }))
+}
+
+/// Diagnostics for chasing connection leaks: pool usage, schema version and
+/// recipe count. Always 200; a value the database could not supply is `null`
+/// and `error` says why. Needs the API key when one is configured.
+#[get("/health/detailed", wrap = "auth::RequireApiKey")]
+async fn health_detailed(config: web::Data<AppConfig>) -> impl Responder {
+    let pool = config.repo.pool_state();
+    let res = with_repo(&config, |repo| {
+        Ok((repo.schema_version()?, repo.count_recipes()?))
+    })
+    .await;
+
+    let (schema_version, recipe_count, error) = match res {
+        Ok((version, count)) => (Some(version), Some(count), None),
+        Err(e) => {
+            error!("Unable to read health details: {}", e);
+            (None, None, Some(e.to_string()))
+        }
+    };
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "pool": pool,
+        "schema_version": schema_version,
+        "recipe_count": recipe_count,
+        "error": error,
+    }))
+}
+
+/// Copy the database to a new timestamped file in the backup directory,
+/// creating the directory if needed, and answer with the copy's path. Only
+/// served when an API key is set; see `auth::RequireAdminKey`.
+#[post("/admin/backup", wrap = "auth::RequireAdminKey")]
+async fn backup(config: web::Data<AppConfig>) -> Result<HttpResponse, Error> {
+    let backup_dir = config.backup_dir.clone();
+    let path = backup_dir.join(format!(
+        "recipes-{}.db",
+        chrono::Utc::now().format("%Y%m%dT%H%M%S%.6fZ")
+    ));
+
+    let (dir, dest) = (backup_dir.clone(), path.clone());
+    let copied = with_repo(&config, move |repo| {
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| anyhow::anyhow!("Unable to create {}: {}", dir.display(), e))?;
+        repo.backup(&dest)
+    })
+    .await;
+    match copied {
+        Ok(()) => {
+            info!("Backed up the database to {}", path.display());
+            Ok(HttpResponse::Created().json(serde_json::json!({ "path": path })))
+        }
+        Err(e) => {
+            error!("Unable to back up the database: {}", e);
+            Ok(backup_failed(&backup_dir))
+        }
+    }
 }
 
-#[post("/recipes/add")]
+fn backup_failed(backup_dir: &std::path::Path) -> HttpResponse {
+    HttpResponse::InternalServerError().json(ApiError::new(
+        "backup_failed",
+        &format!(
+            "Unable to write a backup to {}; check that it is a writable directory.",
+            backup_dir.display()
+        ),
+    ))
+}
+
+/// Create a recipe. The database assigns its id, which is returned in the
+/// echoed recipe and the `Location` header; an `id` in the request body is
+/// ignored.
+#[post(
+    "/recipes/add",
+    wrap = "auth::RequireApiKey",
+    wrap = "rate_limit::RateLimit"
+)]
 async fn add(
-    recipe_json: web::Json<Recipe>,
+    recipe_json: CheckedJson<Recipe>,
     config: web::Data<AppConfig>,
 ) -> Result<HttpResponse, Error> {
-    let recipe = recipe_json.into_inner();
-    let res = config.repo.add_recipe(&recipe);
+    let mut recipe = recipe_json.into_inner();
+    normalize::normalize_recipe(&mut recipe, &config.whitespace);
+    let problems = validate_recipe(&recipe, &config);
+    if !problems.is_empty() {
+        return Ok(HttpResponse::UnprocessableEntity().json(problems));
+    }
+    // Respond with the ingredients as they are stored.
+    recipe.ingredients = normalize::merge_duplicate_ingredients(&recipe.ingredients);
+
+    let to_store = recipe.clone();
+    let res = with_repo(&config, move |repo| repo.add_recipe(&to_store)).await;
 
     match res {
-        Ok(_) => Ok(HttpResponse::Ok().json(recipe)),
+        Ok(recipe_id) => {
+            config.invalidate_cache();
+            recipe.id = Some(recipe_id);
+            Ok(HttpResponse::Created()
+                .header(header::LOCATION, format!("/recipes/{}", recipe_id))
+                .json(recipe))
+        }
         Err(e) => {
             error!("Unable to insert into database: {}", e);
-            Ok(HttpResponse::InternalServerError().json("Database error"))
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ImportQuery {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// The problems `Recipe::validate` found in one entry of an import.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ImportProblem {
+    /// Position of the entry in the request body.
+    pub index: usize,
+    pub errors: Vec<FieldError>,
+}
+
+/// Store many recipes at once, all or nothing. If any entry is invalid
+/// nothing is stored and the response is 422 with an `ImportProblem` per bad
+/// entry. With `?dry_run=true` the entries are only validated, answering
+/// `{"valid": true}` when the import would succeed; the database is not
+/// touched. The body is read as JSON or YAML as its `Content-Type` says;
+/// any other type, or none, gets 415. Bodies over `max_import_bytes` get
+/// 413.
+#[post(
+    "/recipes/import",
+    wrap = "auth::RequireApiKey",
+    wrap = "rate_limit::RateLimit"
+)]
+async fn import(
+    req: HttpRequest,
+    payload: web::Payload,
+    query: web::Query<ImportQuery>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    if !negotiate::has_json_or_yaml_body(&req) {
+        return Ok(unsupported_media_type(
+            "application/json or application/yaml",
+        ));
+    }
+    let body = match read_body(payload, config.max_import_bytes).await? {
+        Some(body) => body,
+        None => return Ok(payload_too_large(config.max_import_bytes)),
+    };
+    let mut entries: Vec<Recipe> = match negotiate::parse_body(&req, &body) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return Ok(HttpResponse::BadRequest().json(ApiError::new("invalid_body", &e)));
+        }
+    };
+    for recipe in entries.iter_mut() {
+        normalize::normalize_recipe(recipe, &config.whitespace);
+    }
+
+    let problems: Vec<ImportProblem> = entries
+        .iter()
+        .enumerate()
+        .map(|(index, recipe)| ImportProblem {
+            index,
+            errors: validate_recipe(recipe, &config),
+        })
+        .filter(|problem| !problem.errors.is_empty())
+        .collect();
+    if !problems.is_empty() {
+        return Ok(HttpResponse::UnprocessableEntity().json(problems));
+    }
+    if query.dry_run {
+        return Ok(HttpResponse::Ok().json(serde_json::json!({ "valid": true })));
+    }
+
+    let to_store = entries.clone();
+    match with_repo(&config, move |repo| repo.add_recipes(&to_store)).await {
+        Ok(recipe_ids) => {
+            for (recipe, recipe_id) in entries.iter_mut().zip(recipe_ids) {
+                recipe.id = Some(recipe_id);
+            }
+            Ok(HttpResponse::Ok().json(entries))
+        }
+        Err(e) => {
+            error!("Unable to import recipes: {}", e);
+            Ok(repo_failure(&e))
         }
     }
 }
 
-#[put("/recipes/edit")]
+/// Replace a recipe. Invalid recipes are rejected with 422 and a list of
+/// `FieldError`s; an id that does not exist gets 404. The response is the
+/// recipe as stored, which may differ from the request, e.g. in ingredient
+/// spelling or the favorite flag, which edits leave alone. With an
+/// `If-Unmodified-Since` header, a recipe changed after that time is left
+/// alone and the response is 412.
+#[put(
+    "/recipes/edit",
+    wrap = "auth::RequireApiKey",
+    wrap = "rate_limit::RateLimit"
+)]
 async fn edit(
-    recipe_json: web::Json<Recipe>,
+    req: HttpRequest,
+    recipe_json: CheckedJson<Recipe>,
     config: web::Data<AppConfig>,
 ) -> Result<HttpResponse, Error> {
-    let recipe: Recipe = recipe_json.into_inner();
+    let mut recipe: Recipe = recipe_json.into_inner();
+    normalize::normalize_recipe(&mut recipe, &config.whitespace);
 
+    let mut problems = validate_recipe(&recipe, &config);
     if recipe.id.is_none() {
-        return Ok(HttpResponse::BadRequest().body("Missing recipe ID"));
+        problems.insert(0, FieldError::new("id", "is required"));
+    }
+    if !problems.is_empty() {
+        return Ok(HttpResponse::UnprocessableEntity().json(problems));
     }
 
-    let res = config.repo.update_recipe(&recipe);
-    match res {
-        Ok(_) => Ok(HttpResponse::Ok().json(recipe)),
+    let recipe_id = recipe.id.unwrap_or_default();
+    let since = conditional::unmodified_since(&req);
+    let updated = with_repo(&config, move |repo| {
+        if repo.update_recipe(&recipe, since)? {
+            repo.get_recipe(recipe_id as i32)
+        } else {
+            Ok(None)
+        }
+    })
+    .await;
+    match updated {
+        Ok(Some(stored)) => {
+            config.invalidate_cache();
+            Ok(HttpResponse::Ok().json(stored))
+        }
+        Ok(None) => Ok(HttpResponse::NotFound().json(ApiError::new(
+            "not_found",
+            &format!("Recipe {} not found", recipe_id),
+        ))),
         Err(e) => {
             error!("Unable to update recipe: {}", e);
-            Ok(HttpResponse::InternalServerError().body("ERROR"))
+            Ok(repo_failure(&e))
         }
     }
 }
 
-#[get("/recipes/all")]
-async fn recipes(config: web::Data<AppConfig>) -> Result<HttpResponse, Error> {
-    let recipes = config.repo.load_recipes();
-    match recipes {
-        Ok(recipes) => Ok(HttpResponse::Ok().json(recipes)),
+/// Whether `order` holds each of `0..len` exactly once.
+fn is_permutation(order: &[u32], len: usize) -> bool {
+    let mut seen = vec![false; len];
+    order.len() == len
+        && order.iter().all(|&position| {
+            let position = position as usize;
+            position < len && !std::mem::replace(&mut seen[position], true)
+        })
+}
+
+/// Move a recipe's steps without resending the whole recipe. The body lists
+/// the steps' current positions in their new order, e.g. `[2, 0, 1]` to move
+/// the last of three steps to the front, and the reordered recipe is
+/// returned. Anything but a permutation of the positions gets 400.
+#[put("/recipes/{id}/steps/reorder", wrap = "auth::RequireApiKey")]
+async fn reorder_steps(
+    recipe_id: web::Path<i32>,
+    order_json: CheckedJson<Vec<u32>>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let recipe_id = recipe_id.into_inner();
+    let order = order_json.into_inner();
+
+    let step_count = match with_repo(&config, move |repo| repo.get_recipe(recipe_id)).await {
+        Ok(Some(recipe)) => recipe.steps.len(),
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(ApiError::new(
+                "not_found",
+                &format!("Recipe {} not found", recipe_id),
+            )))
+        }
         Err(e) => {
-            error!("Unable to load recipes from DB: {}", e);
-            Ok(HttpResponse::Ok().body("Database error."))
+            error!("Unable to load recipe ID {}: {}", recipe_id, e);
+            return Ok(repo_failure(&e));
+        }
+    };
+    if !is_permutation(&order, step_count) {
+        return Ok(HttpResponse::BadRequest().json(ApiError::new(
+            "invalid_order",
+            &format!(
+                "The order must list each step position from 0 to {} exactly once.",
+                step_count as i64 - 1
+            ),
+        )));
+    }
+
+    let reordered = with_repo(&config, move |repo| {
+        if repo.reorder_steps(recipe_id, &order)? {
+            repo.get_recipe(recipe_id)
+        } else {
+            Ok(None)
+        }
+    })
+    .await;
+    match reordered {
+        Ok(Some(recipe)) => {
+            config.invalidate_cache();
+            Ok(HttpResponse::Ok().json(recipe))
+        }
+        Ok(None) => Ok(HttpResponse::NotFound().json(ApiError::new(
+            "not_found",
+            &format!("Recipe {} not found", recipe_id),
+        ))),
+        Err(e) => {
+            error!("Unable to reorder steps of recipe ID {}: {}", recipe_id, e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+/// Append ingredients to a recipe from a CSV body with `name,quantity,unit`
+/// columns, e.g. a spreadsheet export. A header row naming the columns is
+/// optional. The body must be sent as `text/csv`, otherwise the response is
+/// 415. Rows that do not parse get 422 listing each by line, and the
+/// recipe with the new ingredients must still pass validation. Nothing is
+/// stored unless every row is. Returns the updated recipe.
+#[post(
+    "/recipes/{id}/ingredients/import-csv",
+    wrap = "auth::RequireApiKey",
+    wrap = "rate_limit::RateLimit"
+)]
+async fn import_ingredients_csv(
+    req: HttpRequest,
+    recipe_id: web::Path<i32>,
+    body: web::Payload,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    if !negotiate::has_body_type(&req, "text/csv") {
+        return Ok(unsupported_media_type("text/csv"));
+    }
+    let recipe_id = recipe_id.into_inner();
+    let body = match read_body(body, config.max_body_bytes).await? {
+        Some(body) => body,
+        None => return Ok(payload_too_large(config.max_body_bytes)),
+    };
+    let parsed = match ingredient_csv::parse(&body) {
+        Ok(parsed) => parsed,
+        Err(problems) => return Ok(HttpResponse::UnprocessableEntity().json(problems)),
+    };
+
+    let mut recipe = match with_repo(&config, move |repo| repo.get_recipe(recipe_id)).await {
+        Ok(Some(recipe)) => recipe,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(ApiError::new(
+                "not_found",
+                &format!("Recipe {} not found", recipe_id),
+            )))
+        }
+        Err(e) => {
+            error!("Unable to load recipe ID {}: {}", recipe_id, e);
+            return Ok(repo_failure(&e));
+        }
+    };
+    let existing = recipe.ingredients.len();
+    recipe.ingredients.extend(parsed);
+    normalize::normalize_recipe(&mut recipe, &config.whitespace);
+    let problems = validate_recipe(&recipe, &config);
+    if !problems.is_empty() {
+        return Ok(HttpResponse::UnprocessableEntity().json(problems));
+    }
+
+    let added = recipe.ingredients.split_off(existing);
+    let updated = with_repo(&config, move |repo| {
+        if repo.append_ingredients(recipe_id, &added)? {
+            repo.get_recipe(recipe_id)
+        } else {
+            Ok(None)
+        }
+    })
+    .await;
+    match updated {
+        Ok(Some(recipe)) => {
+            config.invalidate_cache();
+            Ok(HttpResponse::Ok().json(recipe))
+        }
+        Ok(None) => Ok(HttpResponse::NotFound().json(ApiError::new(
+            "not_found",
+            &format!("Recipe {} not found", recipe_id),
+        ))),
+        Err(e) => {
+            error!(
+                "Unable to import ingredients into recipe ID {}: {}",
+                recipe_id, e
+            );
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+/// Store a recipe under the id in the path whether or not it exists yet,
+/// so sync clients can repeat the request safely. Answers 201 when the
+/// recipe was created and 200 when it replaced one, with the recipe as
+/// stored either way. An `id` in the body must match the path. A trashed id
+/// gets 409 until the recipe is restored. `If-Unmodified-Since` works as
+/// for `edit`.
+#[put(
+    "/recipes/{id:\\d+}",
+    wrap = "auth::RequireApiKey",
+    wrap = "rate_limit::RateLimit"
+)]
+async fn upsert(
+    req: HttpRequest,
+    recipe_id: web::Path<u32>,
+    recipe_json: CheckedJson<Recipe>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let recipe_id = recipe_id.into_inner();
+    let mut recipe = recipe_json.into_inner();
+    normalize::normalize_recipe(&mut recipe, &config.whitespace);
+
+    let mut problems = validate_recipe(&recipe, &config);
+    if recipe.id.is_some() && recipe.id != Some(recipe_id) {
+        problems.insert(0, FieldError::new("id", "must match the id in the path"));
+    }
+    if !problems.is_empty() {
+        return Ok(HttpResponse::UnprocessableEntity().json(problems));
+    }
+    recipe.id = Some(recipe_id);
+
+    let since = conditional::unmodified_since(&req);
+    let stored = with_repo(&config, move |repo| {
+        let created = repo.upsert_recipe(&recipe, since)?;
+        let stored = repo
+            .get_recipe(recipe_id as i32)?
+            .ok_or(db::RepoError::NotFound)?;
+        Ok((created, stored))
+    })
+    .await;
+    match stored {
+        Ok((true, stored)) => Ok(HttpResponse::Created().json(stored)),
+        Ok((false, stored)) => Ok(HttpResponse::Ok().json(stored)),
+        Err(e) => {
+            error!("Unable to upsert recipe ID {}: {}", recipe_id, e);
+            Ok(repo_failure(&e))
         }
     }
 }
 
 #[derive(Deserialize)]
-struct Info {
-    recipe_id: i32,
+struct FormatQuery {
+    #[serde(default)]
+    format: fractions::QuantityFormat,
 }
 
-#[delete("/recipes/delete")]
-async fn delete(
+/// One recipe by id, as YAML if the `Accept` header asks for it. With
+/// `?format=fraction` quantities are strings such as `"1 1/2"`.
+///
+/// `Last-Modified` is set from the recipe's `updated_at`; a request whose
+/// `If-Modified-Since` is no earlier gets an empty 304.
+#[get("/recipes/{id:\\d+}")]
+async fn get_recipe(
+    req: HttpRequest,
+    recipe_id: web::Path<i32>,
+    query: web::Query<FormatQuery>,
     config: web::Data<AppConfig>,
-    info: web::Query<Info>,
 ) -> Result<HttpResponse, Error> {
-    match config.repo.delete_recipe(info.recipe_id) {
-        Ok(_) => Ok(HttpResponse::Ok().body("")),
+    let recipe_id = recipe_id.into_inner();
+    let found = with_repo(&config, move |repo| {
+        Ok((
+            repo.get_recipe(recipe_id)?,
+            repo.recipe_updated_at(recipe_id)?,
+        ))
+    })
+    .await;
+    match found {
+        Ok((Some(recipe), updated_at)) => {
+            let mut builder = HttpResponse::Ok();
+            if let Some(updated_at) = updated_at {
+                let last_modified = conditional::http_date(updated_at);
+                if conditional::not_modified_since(&req, updated_at) {
+                    return Ok(HttpResponse::NotModified()
+                        .header(header::LAST_MODIFIED, last_modified)
+                        .header(header::VARY, "Accept")
+                        .finish());
+                }
+                builder.header(header::LAST_MODIFIED, last_modified);
+            }
+            Ok(match query.format {
+                fractions::QuantityFormat::Decimal => negotiate::respond(&req, builder, &recipe),
+                fractions::QuantityFormat::Fraction => {
+                    negotiate::respond(&req, builder, &fractions::with_fractions(&recipe))
+                }
+            })
+        }
+        Ok((None, _)) => Ok(HttpResponse::NotFound().json(ApiError::new(
+            "not_found",
+            &format!("Recipe {} not found", recipe_id),
+        ))),
         Err(e) => {
-            error!("Unable to delete recipe ID {}: {}", info.recipe_id, e);
-            Ok(HttpResponse::InternalServerError().body("Database error."))
+            error!("Unable to load recipe ID {}: {}", recipe_id, e);
+            Ok(repo_failure(&e))
         }
     }
 }
+
+#[get("/recipes/{id}/provenance")]
+async fn recipe_provenance(
+    recipe_id: web::Path<i32>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let recipe_id = recipe_id.into_inner();
+    match with_repo(&config, move |repo| repo.get_recipe(recipe_id)).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return Ok(recipe_not_found(recipe_id)),
+        Err(e) => {
+            error!("Unable to load recipe ID {}: {}", recipe_id, e);
+            return Ok(repo_failure(&e));
+        }
+    }
+
+    let chain = with_repo(&config, move |repo| {
+        provenance::build_chain(recipe_id, provenance::MAX_CHAIN_LENGTH, |id| {
+            repo.provenance_sources(id)
+        })
+    })
+    .await;
+    match chain {
+        Ok(chain) => Ok(HttpResponse::Ok().json(chain)),
+        Err(e) => {
+            error!(
+                "Unable to load provenance for recipe ID {}: {}",
+                recipe_id, e
+            );
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+/// Rate a recipe with `{"score": n}`, where n is 1 to 5.
+#[post("/recipes/{id}/ratings", wrap = "auth::RequireApiKey")]
+async fn rate_recipe(
+    recipe_id: web::Path<i32>,
+    rating_json: CheckedJson<ratings::NewRating>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let recipe_id = recipe_id.into_inner();
+    let rating = rating_json.into_inner();
+    if !rating.is_valid() {
+        return Ok(HttpResponse::BadRequest().json(ApiError::new(
+            "invalid_score",
+            &format!(
+                "score must be between {} and {}",
+                ratings::MIN_SCORE,
+                ratings::MAX_SCORE
+            ),
+        )));
+    }
+
+    match with_repo(&config, move |repo| {
+        repo.add_rating(recipe_id, rating.score)
+    })
+    .await
+    {
+        Ok(true) => Ok(HttpResponse::Created().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().json(ApiError::new(
+            "not_found",
+            &format!("Recipe {} not found", recipe_id),
+        ))),
+        Err(e) => {
+            error!("Unable to rate recipe ID {}: {}", recipe_id, e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+/// The average score and number of ratings for a recipe.
+#[get("/recipes/{id}/ratings")]
+async fn recipe_ratings(
+    recipe_id: web::Path<i32>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let recipe_id = recipe_id.into_inner();
+    match with_repo(&config, move |repo| repo.rating_summary(recipe_id)).await {
+        Ok(Some(summary)) => Ok(HttpResponse::Ok().json(summary)),
+        Ok(None) => Ok(HttpResponse::NotFound().json(ApiError::new(
+            "not_found",
+            &format!("Recipe {} not found", recipe_id),
+        ))),
+        Err(e) => {
+            error!("Unable to load ratings for recipe ID {}: {}", recipe_id, e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ConvertQuery {
+    system: units::UnitSystem,
+}
+
+#[get("/recipes/{id}/convert")]
+async fn convert(
+    recipe_id: web::Path<i32>,
+    query: web::Query<ConvertQuery>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let recipe_id = recipe_id.into_inner();
+    match with_repo(&config, move |repo| repo.get_recipe(recipe_id)).await {
+        Ok(Some(recipe)) => {
+            Ok(HttpResponse::Ok().json(units::convert_recipe(&recipe, query.system)))
+        }
+        Ok(None) => Ok(recipe_not_found(recipe_id)),
+        Err(e) => {
+            error!("Unable to load recipe ID {}: {}", recipe_id, e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ShoppingListRequest {
+    recipe_ids: Vec<i32>,
+}
+
+#[derive(Deserialize)]
+struct ShoppingListQuery {
+    group_by: Option<shopping::GroupBy>,
+}
+
+#[post("/shopping-list")]
+async fn shopping_list(
+    request_json: CheckedJson<ShoppingListRequest>,
+    query: web::Query<ShoppingListQuery>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let loaded = match load_each(&config, request_json.into_inner().recipe_ids).await {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            error!("Unable to load recipes for shopping list: {}", e);
+            return Ok(repo_failure(&e));
+        }
+    };
+
+    let mut found = Vec::new();
+    for (recipe_id, recipe) in loaded.into_iter() {
+        match recipe {
+            Some(recipe) => found.push(recipe),
+            None => return Ok(recipe_not_found(recipe_id)),
+        }
+    }
+
+    let items = shopping::consolidate(&found);
+
+    match query.group_by {
+        None => Ok(HttpResponse::Ok().json(items)),
+        Some(shopping::GroupBy::Aisle) => {
+            match with_repo(&config, |repo| repo.ingredient_aisles()).await {
+                Ok(aisles) => Ok(HttpResponse::Ok().json(shopping::group_by_aisle(items, &aisles))),
+                Err(e) => {
+                    error!("Unable to load ingredient aisles: {}", e);
+                    Ok(repo_failure(&e))
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TreeQuery {
+    depth: Option<usize>,
+}
+
+/// Returns recipes grouped by category as a list of nodes shaped like
+/// `{id, name, recipes: [{id, name, desc}], children: [...], truncated}`.
+/// Nesting is capped at `depth` levels (at most `tree::MAX_TREE_DEPTH`);
+/// recipes without a category are listed under a final "Uncategorized" node.
+#[get("/recipes/tree")]
+async fn recipe_tree(
+    query: web::Query<TreeQuery>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let depth = query
+        .depth
+        .unwrap_or(tree::MAX_TREE_DEPTH)
+        .clamp(1, tree::MAX_TREE_DEPTH);
+
+    let loaded = with_repo(&config, |repo| {
+        let categories = repo.load_categories()?;
+        let links = repo.recipe_category_links()?;
+        let summaries = repo.load_recipe_summaries()?;
+        Ok((categories, links, summaries))
+    })
+    .await;
+
+    match loaded {
+        Ok((categories, links, summaries)) => {
+            Ok(HttpResponse::Ok().json(tree::build_tree(&categories, &links, &summaries, depth)))
+        }
+        Err(e) => {
+            error!("Unable to load recipe tree: {}", e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[get("/recipes/{id}/nutrition")]
+async fn recipe_nutrition(
+    recipe_id: web::Path<i32>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let recipe_id = recipe_id.into_inner();
+    match with_repo(&config, move |repo| repo.get_recipe(recipe_id)).await {
+        Ok(Some(recipe)) => Ok(HttpResponse::Ok().json(nutrition::summarize(&recipe))),
+        Ok(None) => Ok(recipe_not_found(recipe_id)),
+        Err(e) => {
+            error!("Unable to load recipe ID {}: {}", recipe_id, e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CookQuery {
+    factor: Option<f64>,
+}
+
+/// Cook mode: each step with the ingredients it mentions, quantities
+/// multiplied by `?factor=` (default 1), e.g. `?factor=2` for a double batch.
+#[get("/recipes/{id}/cook")]
+async fn cook_mode(
+    recipe_id: web::Path<i32>,
+    query: web::Query<CookQuery>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let recipe_id = recipe_id.into_inner();
+    let factor = query.factor.unwrap_or(1.0);
+    if !factor.is_finite() || factor <= 0.0 {
+        return Ok(HttpResponse::BadRequest().json(ApiError::new(
+            "invalid_factor",
+            "factor must be a number greater than zero.",
+        )));
+    }
+
+    match with_repo(&config, move |repo| repo.get_recipe(recipe_id)).await {
+        Ok(Some(recipe)) => Ok(HttpResponse::Ok().json(cook::cook_steps(&recipe, factor))),
+        Ok(None) => Ok(HttpResponse::NotFound().json(ApiError::new(
+            "not_found",
+            &format!("Recipe {} not found", recipe_id),
+        ))),
+        Err(e) => {
+            error!("Unable to load recipe ID {}: {}", recipe_id, e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+/// A single recipe as a Markdown document, for sharing.
+#[get("/recipes/{id}/markdown")]
+async fn recipe_markdown(
+    recipe_id: web::Path<i32>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let recipe_id = recipe_id.into_inner();
+    match with_repo(&config, move |repo| repo.get_recipe(recipe_id)).await {
+        Ok(Some(recipe)) => Ok(HttpResponse::Ok()
+            .content_type("text/markdown; charset=utf-8")
+            .body(markdown::render_markdown(&recipe))),
+        Ok(None) => Ok(HttpResponse::NotFound().json(ApiError::new(
+            "not_found",
+            &format!("Recipe {} not found", recipe_id),
+        ))),
+        Err(e) => {
+            error!("Unable to load recipe ID {}: {}", recipe_id, e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+/// A single recipe as a plain HTML page for printing.
+#[get("/recipes/{id}/print")]
+async fn recipe_print(
+    recipe_id: web::Path<i32>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let recipe_id = recipe_id.into_inner();
+    match with_repo(&config, move |repo| repo.get_recipe(recipe_id)).await {
+        Ok(Some(recipe)) => Ok(HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(print::render_html(&recipe))),
+        Ok(None) => Ok(HttpResponse::NotFound().json(ApiError::new(
+            "not_found",
+            &format!("Recipe {} not found", recipe_id),
+        ))),
+        Err(e) => {
+            error!("Unable to load recipe ID {}: {}", recipe_id, e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+/// Change only the fields given in a `RecipePatch`. The recipe as patched
+/// must pass the same validation as a full edit, or nothing is stored and
+/// the problems are returned with 422.
+#[patch("/recipes/{id}", wrap = "auth::RequireApiKey")]
+async fn patch_recipe(
+    recipe_id: web::Path<i32>,
+    patch_json: CheckedJson<RecipePatch>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let recipe_id = recipe_id.into_inner();
+    let mut patch = patch_json.into_inner();
+    normalize::normalize_patch(&mut patch, &config.whitespace);
+
+    let mut patched = match with_repo(&config, move |repo| repo.get_recipe(recipe_id)).await {
+        Ok(Some(recipe)) => recipe,
+        Ok(None) => {
+            return Ok(HttpResponse::NotFound().json(ApiError::new(
+                "not_found",
+                &format!("Recipe {} not found", recipe_id),
+            )))
+        }
+        Err(e) => {
+            error!("Unable to load recipe ID {}: {}", recipe_id, e);
+            return Ok(repo_failure(&e));
+        }
+    };
+    patch.apply_to(&mut patched);
+    let problems = validate_recipe(&patched, &config);
+    if !problems.is_empty() {
+        return Ok(HttpResponse::UnprocessableEntity().json(problems));
+    }
+
+    match with_repo(&config, move |repo| repo.patch_recipe(recipe_id, &patch)).await {
+        Ok(Some(recipe)) => {
+            config.invalidate_cache();
+            Ok(HttpResponse::Ok().json(recipe))
+        }
+        Ok(None) => Ok(HttpResponse::NotFound().json(ApiError::new(
+            "not_found",
+            &format!("Recipe {} not found", recipe_id),
+        ))),
+        Err(e) => {
+            error!("Unable to patch recipe ID {}: {}", recipe_id, e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+/// Recipes as listings show them, without their bulky `raw_source`.
+fn list_view(mut listed: Vec<Recipe>) -> Vec<Recipe> {
+    for recipe in listed.iter_mut() {
+        recipe.raw_source = None;
+    }
+    listed
+}
+
+/// How much of each recipe a listing includes.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+enum RecipeFields {
+    #[default]
+    Full,
+    /// Only the `RecipeSummary` fields.
+    Summary,
+}
+
+#[derive(Deserialize)]
+struct RecipesQuery {
+    #[serde(default)]
+    format: fractions::QuantityFormat,
+    since: Option<String>,
+    #[serde(default)]
+    fields: RecipeFields,
+    missing: Option<db::MissingField>,
+}
+
+/// List every recipe, as YAML if the `Accept` header asks for it, and with
+/// `?format=fraction` quantities written as fractions. For incremental sync,
+/// `?since=` with an RFC 3339 timestamp, e.g. `2021-05-01T12:00:00Z`, lists
+/// only recipes added or changed since then. `?fields=summary` lists each
+/// recipe as a `RecipeSummary`, which skips loading steps and ingredients
+/// when `since` is not given. `?missing=desc`, `ingredients` or `steps`
+/// lists only recipes lacking that field, to find incomplete entries. The
+/// response carries an `ETag`; a request whose `If-None-Match` still matches
+/// gets an empty 304 instead.
+#[get("/recipes/all")]
+async fn recipes(
+    req: HttpRequest,
+    query: web::Query<RecipesQuery>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let since = match query
+        .since
+        .as_deref()
+        .map(chrono::DateTime::parse_from_rfc3339)
+    {
+        None => None,
+        Some(Ok(since)) => Some(since.with_timezone(&chrono::Utc)),
+        Some(Err(_)) => {
+            return Ok(HttpResponse::BadRequest().json(ApiError::new(
+                "invalid_since",
+                "since must be an RFC 3339 timestamp, e.g. 2021-05-01T12:00:00Z.",
+            )));
+        }
+    };
+
+    let etag = match with_repo(&config, |repo| repo.collection_version()).await {
+        Ok(version) => conditional::collection_etag(&version, negotiate::media_type(&req)),
+        Err(e) => {
+            error!("Unable to load recipes from DB: {}", e);
+            return Ok(repo_failure(&e));
+        }
+    };
+
+    if conditional::not_modified(&req, &etag) {
+        return Ok(HttpResponse::NotModified()
+            .header(header::ETAG, etag)
+            .header(header::VARY, "Accept")
+            .finish());
+    }
+
+    let missing = query.missing;
+    if query.fields == RecipeFields::Summary {
+        let loaded = with_repo(&config, move |repo| match (missing, since) {
+            (Some(field), _) => Ok(repo
+                .recipes_missing(field, since)?
+                .iter()
+                .map(RecipeSummary::from)
+                .collect()),
+            (None, Some(since)) => Ok(repo
+                .recipes_modified_since(since)?
+                .iter()
+                .map(RecipeSummary::from)
+                .collect()),
+            (None, None) => repo.load_recipe_summaries(),
+        })
+        .await;
+        return match loaded {
+            Ok(found) => {
+                let builder = HttpResponse::Ok().header(header::ETAG, etag).take();
+                Ok(negotiate::respond(&req, builder, &found))
+            }
+            Err(e) => {
+                error!("Unable to load recipe summaries from DB: {}", e);
+                Ok(repo_failure(&e))
+            }
+        };
+    }
+
+    // Only the plain listing is cached: every recipe, as JSON, in decimals.
+    let cache = config.recipe_cache.as_ref().filter(|_| {
+        since.is_none()
+            && missing.is_none()
+            && query.format == fractions::QuantityFormat::Decimal
+            && !negotiate::wants_yaml(&req)
+    });
+    if let Some(body) = cache.and_then(|cache| cache.get(&etag)) {
+        return Ok(HttpResponse::Ok()
+            .header(header::ETAG, etag)
+            .header(header::VARY, "Accept")
+            .content_type("application/json")
+            .body(body));
+    }
+
+    let loaded = with_repo(&config, move |repo| {
+        let found = match (missing, since) {
+            (Some(field), _) => repo.recipes_missing(field, since)?,
+            (None, Some(since)) => repo.recipes_modified_since(since)?,
+            (None, None) => repo.load_recipes()?,
+        };
+        Ok(list_view(found))
+    })
+    .await;
+    match loaded {
+        Ok(found) => {
+            let mut builder = HttpResponse::Ok().header(header::ETAG, etag.clone()).take();
+            if let Some(cache) = cache {
+                let body = Bytes::from(serde_json::to_vec(&found)?);
+                cache.store(&etag, body.clone());
+                return Ok(builder
+                    .header(header::VARY, "Accept")
+                    .content_type("application/json")
+                    .body(body));
+            }
+            Ok(match query.format {
+                fractions::QuantityFormat::Decimal => negotiate::respond(&req, builder, &found),
+                fractions::QuantityFormat::Fraction => {
+                    let found: Vec<_> = found.iter().map(fractions::with_fractions).collect();
+                    negotiate::respond(&req, builder, &found)
+                }
+            })
+        }
+        Err(e) => {
+            error!("Unable to load recipes from DB: {}", e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+/// Page size used when `limit` is not given.
+pub const DEFAULT_PAGE_SIZE: u32 = 20;
+/// Largest `limit` accepted; larger values are clamped.
+pub const MAX_PAGE_SIZE: u32 = 100;
+
+#[derive(Deserialize)]
+struct PageQuery {
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+/// Recipes in id order, wrapped in a `Page`, e.g. `?limit=20&offset=40`.
+/// `/recipes/all` still returns every recipe as a bare array.
+#[get("/recipes")]
+async fn recipe_page(
+    query: web::Query<PageQuery>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let offset = query.offset.unwrap_or(0);
+
+    let loaded = with_repo(&config, move |repo| {
+        let total = repo.count_recipes()?;
+        let items = list_view(repo.load_recipes_page(limit, offset)?);
+        Ok(Page {
+            items,
+            total,
+            limit,
+            offset,
+        })
+    })
+    .await;
+
+    match loaded {
+        Ok(page) => Ok(HttpResponse::Ok().json(page)),
+        Err(e) => {
+            error!("Unable to load recipe page: {}", e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+    #[serde(default)]
+    fields: db::SearchFields,
+}
+
+/// Recipes whose name contains `q`, e.g. `?q=bread`. `%` and `_` match
+/// literally. `fields=desc` or `fields=all` searches the description too.
+#[get("/recipes/search")]
+async fn search(
+    query: web::Query<SearchQuery>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let SearchQuery { q, fields } = query.into_inner();
+    match with_repo(&config, move |repo| {
+        repo.search_recipes(&q, fields).map(list_view)
+    })
+    .await
+    {
+        Ok(found) => Ok(HttpResponse::Ok().json(found)),
+        Err(e) => {
+            error!("Unable to search recipes: {}", e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct FullTextQuery {
+    q: String,
+}
+
+/// Recipes whose name, description or steps contain every word of `q`,
+/// ranked by relevance, e.g. `?q=banana bread`. Words match their other
+/// forms, so "baking" finds "bake".
+#[get("/recipes/fts")]
+async fn full_text_search(
+    query: web::Query<FullTextQuery>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let q = query.into_inner().q;
+    match with_repo(&config, move |repo| {
+        repo.full_text_search(&q).map(list_view)
+    })
+    .await
+    {
+        Ok(found) => Ok(HttpResponse::Ok().json(found)),
+        Err(e) => {
+            error!("Unable to search recipes: {}", e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct BrowseQuery {
+    cuisine: Option<String>,
+    course: Option<String>,
+    difficulty: Option<Difficulty>,
+    max_minutes: Option<u32>,
+    #[serde(default)]
+    include_unknown: bool,
+}
+
+/// Recipes filtered by cuisine, course and/or difficulty, e.g.
+/// `?cuisine=italian&course=main&difficulty=easy`. `?max_minutes=30` keeps
+/// recipes whose step durations add up to at most 30 minutes; recipes
+/// without any durations are left out unless `include_unknown=true`.
+#[get("/recipes/browse")]
+async fn browse(
+    query: web::Query<BrowseQuery>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let BrowseQuery {
+        cuisine,
+        course,
+        difficulty,
+        max_minutes,
+        include_unknown,
+    } = query.into_inner();
+    let time = max_minutes.map(|max_minutes| db::TimeLimit {
+        max_minutes,
+        include_unknown,
+    });
+    match with_repo(&config, move |repo| {
+        repo.browse_recipes(cuisine.as_deref(), course.as_deref(), difficulty, time)
+            .map(list_view)
+    })
+    .await
+    {
+        Ok(found) => Ok(HttpResponse::Ok().json(found)),
+        Err(e) => {
+            error!("Unable to browse recipes: {}", e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RandomQuery {
+    cuisine: Option<String>,
+}
+
+/// A random recipe for "surprise me", optionally limited with `?cuisine=`.
+#[get("/recipes/random")]
+async fn random(
+    query: web::Query<RandomQuery>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let RandomQuery { cuisine } = query.into_inner();
+    match with_repo(&config, move |repo| repo.random_recipe(cuisine.as_deref())).await {
+        Ok(Some(found)) => Ok(HttpResponse::Ok().json(found)),
+        Ok(None) => Ok(HttpResponse::NotFound()
+            .json(ApiError::new("not_found", "No recipes match the request."))),
+        Err(e) => {
+            error!("Unable to pick a random recipe: {}", e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+/// Distinct cuisines and courses with recipe counts, for building filters.
+#[get("/facets")]
+async fn facets(config: web::Data<AppConfig>) -> Result<HttpResponse, Error> {
+    match with_repo(&config, |repo| repo.facets()).await {
+        Ok(found) => Ok(HttpResponse::Ok().json(found)),
+        Err(e) => {
+            error!("Unable to load facets: {}", e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[get("/stats")]
+async fn stats(config: web::Data<AppConfig>) -> Result<HttpResponse, Error> {
+    match with_repo(&config, |repo| repo.stats()).await {
+        Ok(found) => Ok(HttpResponse::Ok().json(found)),
+        Err(e) => {
+            error!("Unable to load stats: {}", e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct IngredientsQuery {
+    #[serde(default)]
+    sort: db::IngredientSort,
+}
+
+/// Every known ingredient with the number of recipes using it, sorted by
+/// name or, with `?sort=usage`, most used first. Unlike the usage report
+/// this includes ingredients no recipe uses any more.
+#[get("/ingredients")]
+async fn list_ingredients(
+    query: web::Query<IngredientsQuery>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let sort = query.sort;
+    match with_repo(&config, move |repo| repo.all_ingredients(sort)).await {
+        Ok(found) => Ok(HttpResponse::Ok().json(
+            found
+                .into_iter()
+                .map(|(ingredient, recipe_count)| IngredientUsage {
+                    ingredient,
+                    recipe_count,
+                })
+                .collect::<Vec<IngredientUsage>>(),
+        )),
+        Err(e) => {
+            error!("Unable to load ingredients: {}", e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct IngredientRename {
+    pub from: String,
+    pub to: String,
+}
+
+/// Rename an ingredient across every recipe, e.g.
+/// `{"from": "Kosher Salt", "to": "Salt, kosher"}`. Renaming to an
+/// ingredient that already exists merges the two, answering 409 if a recipe
+/// lists both in units that cannot be added.
+#[put("/ingredients/rename", wrap = "auth::RequireApiKey")]
+async fn rename_ingredient(
+    rename_json: CheckedJson<IngredientRename>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let IngredientRename { from, to } = rename_json.into_inner();
+    let to = normalize::collapse_whitespace(&to);
+    if to.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(ApiError::new(
+            "invalid_name",
+            "The new ingredient name must not be blank.",
+        )));
+    }
+    if let Some(problem) = check_length("to", &to, config.field_limits.ingredient) {
+        return Ok(HttpResponse::UnprocessableEntity().json(vec![problem]));
+    }
+
+    let from_name = from.clone();
+    match with_repo(&config, move |repo| repo.rename_ingredient(&from, &to)).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().json(ApiError::new(
+            "not_found",
+            &format!("Ingredient {:?} not found", from_name),
+        ))),
+        Err(e) => {
+            error!("Unable to rename ingredient {:?}: {}", from_name, e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+/// Ingredients with the number of recipes using each, most used first.
+#[get("/reports/ingredient-usage")]
+async fn ingredient_usage(config: web::Data<AppConfig>) -> Result<HttpResponse, Error> {
+    match with_repo(&config, |repo| repo.ingredient_usage()).await {
+        Ok(usage) => Ok(HttpResponse::Ok().json(
+            usage
+                .into_iter()
+                .map(|(ingredient, recipe_count)| IngredientUsage {
+                    ingredient,
+                    recipe_count,
+                })
+                .collect::<Vec<IngredientUsage>>(),
+        )),
+        Err(e) => {
+            error!("Unable to load ingredient usage: {}", e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[get("/categories")]
+async fn list_categories(config: web::Data<AppConfig>) -> Result<HttpResponse, Error> {
+    match with_repo(&config, |repo| repo.load_categories()).await {
+        Ok(found) => Ok(HttpResponse::Ok().json(found)),
+        Err(e) => {
+            error!("Unable to load categories: {}", e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct NewCategory {
+    name: String,
+    #[serde(default)]
+    parent_id: Option<i32>,
+}
+
+/// Create a category, answering 201 with it. Names are unique, so reusing
+/// one gets 409; an unknown `parent_id` gets 422.
+#[post(
+    "/categories",
+    wrap = "auth::RequireApiKey",
+    wrap = "rate_limit::RateLimit"
+)]
+async fn add_category(
+    category: CheckedJson<NewCategory>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let NewCategory { name, parent_id } = category.into_inner();
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Ok(HttpResponse::UnprocessableEntity()
+            .json(vec![FieldError::new("name", "must not be empty")]));
+    }
+
+    match with_repo(&config, move |repo| repo.add_category(&name, parent_id)).await {
+        Ok(category) => Ok(HttpResponse::Created().json(category)),
+        Err(BlockingError::Error(db::RepoError::NotFound)) => Ok(
+            HttpResponse::UnprocessableEntity().json(vec![FieldError::new(
+                "parent_id",
+                "must name an existing category",
+            )]),
+        ),
+        Err(e) => {
+            error!("Unable to add category: {}", e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+/// Delete a category. Its recipes are kept, only losing the assignment.
+#[delete("/categories/{id}", wrap = "auth::RequireApiKey")]
+async fn delete_category(
+    category_id: web::Path<i32>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let category_id = category_id.into_inner();
+    match with_repo(&config, move |repo| repo.delete_category(category_id)).await {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Ok(HttpResponse::NotFound().json(ApiError::new(
+            "not_found",
+            &format!("Category {} not found", category_id),
+        ))),
+        Err(e) => {
+            error!("Unable to delete category ID {}: {}", category_id, e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[put("/recipes/{id}/categories/{category_id}", wrap = "auth::RequireApiKey")]
+async fn assign_category(
+    path: web::Path<(i32, i32)>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let (recipe_id, category_id) = path.into_inner();
+    Ok(set_recipe_category(&config, recipe_id, category_id, true).await)
+}
+
+#[delete("/recipes/{id}/categories/{category_id}", wrap = "auth::RequireApiKey")]
+async fn unassign_category(
+    path: web::Path<(i32, i32)>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let (recipe_id, category_id) = path.into_inner();
+    Ok(set_recipe_category(&config, recipe_id, category_id, false).await)
+}
+
+async fn set_recipe_category(
+    config: &web::Data<AppConfig>,
+    recipe_id: i32,
+    category_id: i32,
+    assigned: bool,
+) -> HttpResponse {
+    match with_repo(config, move |repo| {
+        repo.set_recipe_category(recipe_id, category_id, assigned)
+    })
+    .await
+    {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().json(ApiError::new(
+            "not_found",
+            &format!("Recipe {} or category {} not found", recipe_id, category_id),
+        )),
+        Err(e) => {
+            error!(
+                "Unable to update category {} of recipe ID {}: {}",
+                category_id, recipe_id, e
+            );
+            repo_failure(&e)
+        }
+    }
+}
+
+/// Every recipe's id and name grouped under the first letter of the name,
+/// for an A-Z index. Names starting with anything but a letter are grouped
+/// under "#", which comes last.
+#[get("/recipes/index")]
+async fn recipe_index(config: web::Data<AppConfig>) -> Result<HttpResponse, Error> {
+    match with_repo(&config, |repo| repo.load_recipe_summaries()).await {
+        Ok(summaries) => Ok(HttpResponse::Ok().json(index::build_index(&summaries))),
+        Err(e) => {
+            error!("Unable to load recipe index: {}", e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[get("/recipes/favorites")]
+async fn favorites(config: web::Data<AppConfig>) -> Result<HttpResponse, Error> {
+    match with_repo(&config, |repo| repo.load_favorites().map(list_view)).await {
+        Ok(found) => Ok(HttpResponse::Ok().json(found)),
+        Err(e) => {
+            error!("Unable to load favorite recipes: {}", e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[put("/recipes/{id}/favorite", wrap = "auth::RequireApiKey")]
+async fn favorite(
+    recipe_id: web::Path<i32>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    Ok(set_favorite(&config, recipe_id.into_inner(), true).await)
+}
+
+#[delete("/recipes/{id}/favorite", wrap = "auth::RequireApiKey")]
+async fn unfavorite(
+    recipe_id: web::Path<i32>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    Ok(set_favorite(&config, recipe_id.into_inner(), false).await)
+}
+
+async fn set_favorite(
+    config: &web::Data<AppConfig>,
+    recipe_id: i32,
+    starred: bool,
+) -> HttpResponse {
+    match with_repo(config, move |repo| repo.set_favorite(recipe_id, starred)).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().json(ApiError::new(
+            "not_found",
+            &format!("Recipe {} not found", recipe_id),
+        )),
+        Err(e) => {
+            error!(
+                "Unable to update favorite for recipe ID {}: {}",
+                recipe_id, e
+            );
+            repo_failure(&e)
+        }
+    }
+}
+
+/// Find recipes using the given ingredients, e.g.
+/// `?ingredient=egg&ingredient=flour&match_all=true`.
+#[get("/recipes/with-ingredient")]
+async fn with_ingredient(
+    query: web::Query<Vec<(String, String)>>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let ingredients: Vec<String> = query
+        .iter()
+        .filter(|(key, _)| key == "ingredient")
+        .map(|(_, value)| value.clone())
+        .collect();
+    let match_all = query
+        .iter()
+        .any(|(key, value)| key == "match_all" && value == "true");
+
+    if ingredients.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(ApiError::new(
+            "missing_ingredient",
+            "At least one ingredient parameter is required",
+        )));
+    }
+
+    match with_repo(&config, move |repo| {
+        repo.recipes_containing(&ingredients, match_all)
+            .map(list_view)
+    })
+    .await
+    {
+        Ok(found) => Ok(HttpResponse::Ok().json(found)),
+        Err(e) => {
+            error!("Unable to search recipes by ingredient: {}", e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+/// Copy a recipe under the name `<name> (copy)`, unstarred, recording where
+/// it came from in its provenance. Answers like `add`: 201 with the copy and
+/// its `Location`, or 422 if the longer name no longer fits `FieldLimits`.
+#[post("/recipes/{id}/duplicate", wrap = "auth::RequireApiKey")]
+async fn duplicate(
+    recipe_id: web::Path<i32>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let recipe_id = recipe_id.into_inner();
+    let mut recipe = match with_repo(&config, move |repo| repo.get_recipe(recipe_id)).await {
+        Ok(Some(recipe)) => recipe,
+        Ok(None) => return Ok(recipe_not_found(recipe_id)),
+        Err(e) => {
+            error!("Unable to load recipe ID {}: {}", recipe_id, e);
+            return Ok(repo_failure(&e));
+        }
+    };
+
+    recipe.id = None;
+    recipe.name.push_str(" (copy)");
+    recipe.favorite = false;
+    let problems = validate_recipe(&recipe, &config);
+    if !problems.is_empty() {
+        return Ok(HttpResponse::UnprocessableEntity().json(problems));
+    }
+
+    let to_store = recipe.clone();
+    match with_repo(&config, move |repo| {
+        repo.add_duplicate(recipe_id, &to_store)
+    })
+    .await
+    {
+        Ok(new_id) => {
+            config.invalidate_cache();
+            recipe.id = Some(new_id);
+            Ok(HttpResponse::Created()
+                .header(header::LOCATION, format!("/recipes/{}", new_id))
+                .json(recipe))
+        }
+        Err(BlockingError::Error(db::RepoError::NotFound)) => Ok(recipe_not_found(recipe_id)),
+        Err(e) => {
+            error!("Unable to duplicate recipe ID {}: {}", recipe_id, e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct MergeRequest {
+    keep_id: i32,
+    remove_id: i32,
+    #[serde(default)]
+    mode: merge::MergeMode,
+}
+
+/// Fold the recipe `remove_id` into `keep_id`: the former goes to the trash
+/// and, with `"mode": "union"`, the latter gains whatever ingredients,
+/// steps, photos and categories it lacked. Returns the kept recipe, or 404
+/// if either is missing.
+#[post(
+    "/recipes/merge",
+    wrap = "auth::RequireApiKey",
+    wrap = "rate_limit::RateLimit"
+)]
+async fn merge_recipes(
+    request: CheckedJson<MergeRequest>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let MergeRequest {
+        keep_id,
+        remove_id,
+        mode,
+    } = request.into_inner();
+    if keep_id == remove_id {
+        return Ok(
+            HttpResponse::UnprocessableEntity().json(vec![FieldError::new(
+                "remove_id",
+                "must differ from keep_id",
+            )]),
+        );
+    }
+
+    let merged = with_repo(&config, move |repo| {
+        let (mut kept, removed) = match repo.get_recipes(&[keep_id, remove_id])?.as_slice() {
+            [kept, removed] => (kept.clone(), removed.clone()),
+            _ => return Err(db::RepoError::NotFound),
+        };
+        let union = mode == merge::MergeMode::Union;
+        if union {
+            merge::union_into(&mut kept, &removed);
+        }
+        if !repo.merge_recipes(&kept, remove_id, union)? {
+            return Err(db::RepoError::NotFound);
+        }
+        repo.get_recipe(keep_id)?.ok_or(db::RepoError::NotFound)
+    })
+    .await;
+    match merged {
+        Ok(kept) => {
+            config.invalidate_cache();
+            Ok(HttpResponse::Ok().json(kept))
+        }
+        Err(e) => {
+            error!(
+                "Unable to merge recipe ID {} into {}: {}",
+                remove_id, keep_id, e
+            );
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Info {
+    recipe_id: i32,
+}
+
+#[delete(
+    "/recipes/delete",
+    wrap = "auth::RequireApiKey",
+    wrap = "rate_limit::RateLimit"
+)]
+async fn delete(
+    config: web::Data<AppConfig>,
+    info: web::Query<Info>,
+) -> Result<HttpResponse, Error> {
+    let recipe_id = info.recipe_id;
+    match with_repo(&config, move |repo| repo.delete_recipe(recipe_id)).await {
+        Ok(_) => {
+            config.invalidate_cache();
+            Ok(HttpResponse::Ok().body(""))
+        }
+        Err(e) => {
+            error!("Unable to delete recipe ID {}: {}", info.recipe_id, e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ExportRequest {
+    ids: Vec<i32>,
+}
+
+/// Recipes in the trash, most recently deleted first.
+#[get("/recipes/trash")]
+async fn trash(config: web::Data<AppConfig>) -> Result<HttpResponse, Error> {
+    match with_repo(&config, |repo| repo.load_deleted()).await {
+        Ok(found) => Ok(HttpResponse::Ok().json(found)),
+        Err(e) => {
+            error!("Unable to load deleted recipes: {}", e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+/// Take a recipe out of the trash and return it. Recipes that are not in
+/// the trash get 404.
+#[post("/recipes/{id}/restore", wrap = "auth::RequireApiKey")]
+async fn restore(
+    recipe_id: web::Path<i32>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let recipe_id = recipe_id.into_inner();
+    let restored = with_repo(&config, move |repo| {
+        if repo.restore_recipe(recipe_id)? {
+            repo.get_recipe(recipe_id)
+        } else {
+            Ok(None)
+        }
+    })
+    .await;
+    match restored {
+        Ok(Some(found)) => Ok(HttpResponse::Ok().json(found)),
+        Ok(None) => Ok(HttpResponse::NotFound().json(ApiError::new(
+            "not_found",
+            &format!("Recipe {} is not in the trash", recipe_id),
+        ))),
+        Err(e) => {
+            error!("Unable to restore recipe ID {}: {}", recipe_id, e);
+            Ok(repo_failure(&e))
+        }
+    }
+}
+
+/// The requested recipes as a zip of Markdown files with an `index.md`.
+/// Repeated ids are exported once.
+#[post("/recipes/export/markdown-zip")]
+async fn export_markdown_zip(
+    export_json: CheckedJson<ExportRequest>,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    let mut recipe_ids = export_json.into_inner().ids;
+    let mut seen = std::collections::HashSet::new();
+    recipe_ids.retain(|recipe_id| seen.insert(*recipe_id));
+    let loaded = match load_each(&config, recipe_ids).await {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            error!("Unable to load recipes for export: {}", e);
+            return Ok(repo_failure(&e));
+        }
+    };
+
+    let mut found = Vec::new();
+    let mut missing = Vec::new();
+    for (recipe_id, recipe) in loaded.into_iter() {
+        match recipe {
+            Some(recipe) => found.push(recipe),
+            None => missing.push(recipe_id),
+        }
+    }
+
+    let filenames = markdown::markdown_filenames(&found);
+    let index = markdown::render_index(&found, &filenames, &missing);
+    let missing_ids: Vec<String> = missing.iter().map(|id| id.to_string()).collect();
+
+    // Each recipe is only rendered once the stream asks for its entry.
+    let entries = std::iter::once(("index.md".to_string(), index.into_bytes())).chain(
+        found
+            .into_iter()
+            .zip(filenames.into_iter())
+            .map(|(recipe, filename)| (filename, markdown::render_markdown(&recipe).into_bytes())),
+    );
+    let chunks = archive::ZipStream::new(entries).map(|chunk| Ok::<_, Error>(Bytes::from(chunk)));
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .header(
+            "Content-Disposition",
+            "attachment; filename=\"recipes.zip\"",
+        )
+        .header("X-Missing-Recipe-Ids", missing_ids.join(","))
+        .streaming(futures::stream::iter(chunks)))
+}
+
+/// Every recipe as newline-delimited JSON, one per line, e.g. for backups.
+/// Recipes are loaded and sent a batch at a time, so memory use does not
+/// grow with the collection. Each batch starts after the last id sent, so
+/// edits during the export cannot skip or repeat a recipe.
+#[get("/recipes/export.ndjson")]
+async fn export_ndjson(config: web::Data<AppConfig>) -> HttpResponse {
+    let batches = futures::stream::try_unfold(Some(0), move |after_id| {
+        let config = config.clone();
+        async move {
+            let after_id = match after_id {
+                Some(after_id) => after_id,
+                None => return Ok(None),
+            };
+            let found = with_repo(&config, move |repo| {
+                repo.load_recipes_after(after_id, ndjson::BATCH_SIZE)
+            })
+            .await
+            .map_err(|e| {
+                error!("Unable to load recipes for export: {}", e);
+                Error::from(e)
+            })?;
+
+            let mut chunk = Vec::new();
+            for recipe in found.iter() {
+                ndjson::write_line(&mut chunk, recipe)
+                    .map_err(actix_web::error::ErrorInternalServerError)?;
+            }
+            let next = if found.len() < ndjson::BATCH_SIZE as usize {
+                None
+            } else {
+                found
+                    .last()
+                    .and_then(|recipe| recipe.id)
+                    .map(|id| id as i32)
+            };
+            Ok::<_, Error>(Some((Bytes::from(chunk), next)))
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type(ndjson::CONTENT_TYPE)
+        .streaming(Box::pin(batches))
+}
+
+/// The first line of an NDJSON import that failed validation.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct LineProblem {
+    /// Line number in the request body, counting from 1.
+    pub line: usize,
+    /// Recipes stored from earlier lines before the import stopped.
+    pub imported: usize,
+    pub errors: Vec<FieldError>,
+}
+
+/// Store recipes sent as newline-delimited JSON, one per line; blank lines
+/// are skipped. The body is read line by line and stored in batches, so a
+/// large import never has to be held in memory. Unlike `/recipes/import`
+/// this is not all or nothing: the first malformed line gets 400 and the
+/// first invalid one 422 with a `LineProblem`, and batches stored before
+/// it are kept. A line longer than `max_body_bytes` gets 413, and a body
+/// not sent as `application/x-ndjson` gets 415. On success the response is
+/// `{"imported": n}`.
+#[post(
+    "/recipes/import.ndjson",
+    wrap = "auth::RequireApiKey",
+    wrap = "rate_limit::RateLimit"
+)]
+async fn import_ndjson(
+    req: HttpRequest,
+    mut body: web::Payload,
+    config: web::Data<AppConfig>,
+) -> Result<HttpResponse, Error> {
+    if !negotiate::has_body_type(&req, ndjson::CONTENT_TYPE) {
+        return Ok(unsupported_media_type(ndjson::CONTENT_TYPE));
+    }
+    let mut splitter = ndjson::LineSplitter::default();
+    let mut batch = Vec::new();
+    let mut line_number = 0;
+    let mut imported = 0;
+    let mut finished = false;
+
+    while !finished {
+        let lines = match body.next().await {
+            Some(chunk) => splitter.push(&chunk?),
+            None => {
+                finished = true;
+                std::mem::take(&mut splitter).finish().into_iter().collect()
+            }
+        };
+        if splitter.pending_len() > config.max_body_bytes
+            || lines.iter().any(|line| line.len() > config.max_body_bytes)
+        {
+            return Ok(payload_too_large(config.max_body_bytes));
+        }
+
+        for line in lines {
+            line_number += 1;
+            if ndjson::is_blank(&line) {
+                continue;
+            }
+            let mut recipe: Recipe = match serde_json::from_slice(&line) {
+                Ok(recipe) => recipe,
+                Err(e) => {
+                    return Ok(HttpResponse::BadRequest().json(ApiError::new(
+                        "invalid_body",
+                        &format!(
+                            "Line {}: {}. {} recipes were imported before it.",
+                            line_number, e, imported
+                        ),
+                    )));
+                }
+            };
+            normalize::normalize_recipe(&mut recipe, &config.whitespace);
+            let errors = validate_recipe(&recipe, &config);
+            if !errors.is_empty() {
+                return Ok(HttpResponse::UnprocessableEntity().json(LineProblem {
+                    line: line_number,
+                    imported,
+                    errors,
+                }));
+            }
+
+            batch.push(recipe);
+            if batch.len() >= ndjson::BATCH_SIZE as usize {
+                match store_batch(&config, std::mem::take(&mut batch)).await {
+                    Ok(stored) => imported += stored,
+                    Err(e) => {
+                        error!("Unable to import recipes: {}", e);
+                        return Ok(repo_failure(&e));
+                    }
+                }
+            }
+        }
+    }
+
+    if !batch.is_empty() {
+        match store_batch(&config, batch).await {
+            Ok(stored) => imported += stored,
+            Err(e) => {
+                error!("Unable to import recipes: {}", e);
+                return Ok(repo_failure(&e));
+            }
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "imported": imported })))
+}
+
+/// Store one batch of an import in a single transaction, returning how many
+/// recipes it held.
+async fn store_batch(
+    config: &web::Data<AppConfig>,
+    batch: Vec<Recipe>,
+) -> Result<usize, BlockingError<db::RepoError>> {
+    with_repo(config, move |repo| {
+        repo.add_recipes(&batch).map(|ids| ids.len())
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::RecipeBuilder;
+    use actix_web::{http::StatusCode, test, App};
+    use anyhow::anyhow;
+    use std::collections::HashMap;
+
+    /// A repo whose every operation fails, for exercising error paths.
+    struct BrokenRepo;
+
+    fn broken<T>() -> db::RepoResult<T> {
+        Err(anyhow!("database is unavailable").into())
+    }
+
+    impl db::Repo for BrokenRepo {
+        fn setup(&self) -> db::RepoResult<()> {
+            broken()
+        }
+        fn active_connections(&self) -> u32 {
+            0
+        }
+        fn pool_state(&self) -> db::PoolState {
+            db::PoolState {
+                connections: 0,
+                idle_connections: 0,
+                max_size: 0,
+            }
+        }
+        fn schema_version(&self) -> db::RepoResult<u32> {
+            broken()
+        }
+        fn backup(&self, _: &std::path::Path) -> db::RepoResult<()> {
+            broken()
+        }
+        fn add_recipe(&self, _: &Recipe) -> db::RepoResult<u32> {
+            broken()
+        }
+        fn add_recipes(&self, _: &[Recipe]) -> db::RepoResult<Vec<u32>> {
+            broken()
+        }
+        fn delete_recipe(&self, _: i32) -> db::RepoResult<()> {
+            broken()
+        }
+        fn load_deleted(&self) -> db::RepoResult<Vec<DeletedRecipe>> {
+            broken()
+        }
+        fn restore_recipe(&self, _: i32) -> db::RepoResult<bool> {
+            broken()
+        }
+        fn purge_deleted(&self, _: chrono::DateTime<chrono::Utc>) -> db::RepoResult<usize> {
+            broken()
+        }
+        fn vacuum_orphan_ingredients(&self) -> db::RepoResult<usize> {
+            broken()
+        }
+        fn clear_all(&self) -> db::RepoResult<()> {
+            broken()
+        }
+        fn merge_recipes(&self, _: &Recipe, _: i32, _: bool) -> db::RepoResult<bool> {
+            broken()
+        }
+        fn update_recipe(
+            &self,
+            _: &Recipe,
+            _: Option<chrono::DateTime<chrono::Utc>>,
+        ) -> db::RepoResult<bool> {
+            broken()
+        }
+        fn upsert_recipe(
+            &self,
+            _: &Recipe,
+            _: Option<chrono::DateTime<chrono::Utc>>,
+        ) -> db::RepoResult<bool> {
+            broken()
+        }
+        fn patch_recipe(&self, _: i32, _: &RecipePatch) -> db::RepoResult<Option<Recipe>> {
+            broken()
+        }
+        fn get_recipe(&self, _: i32) -> db::RepoResult<Option<Recipe>> {
+            broken()
+        }
+        fn get_recipes(&self, _: &[i32]) -> db::RepoResult<Vec<Recipe>> {
+            broken()
+        }
+        fn recipe_updated_at(
+            &self,
+            _: i32,
+        ) -> db::RepoResult<Option<chrono::DateTime<chrono::Utc>>> {
+            broken()
+        }
+        fn recipes_containing(&self, _: &[String], _: bool) -> db::RepoResult<Vec<Recipe>> {
+            broken()
+        }
+        fn load_recipes(&self) -> db::RepoResult<Vec<Recipe>> {
+            broken()
+        }
+        fn search_recipes(&self, _: &str, _: db::SearchFields) -> db::RepoResult<Vec<Recipe>> {
+            broken()
+        }
+        fn full_text_search(&self, _: &str) -> db::RepoResult<Vec<Recipe>> {
+            broken()
+        }
+        fn browse_recipes(
+            &self,
+            _: Option<&str>,
+            _: Option<&str>,
+            _: Option<Difficulty>,
+            _: Option<db::TimeLimit>,
+        ) -> db::RepoResult<Vec<Recipe>> {
+            broken()
+        }
+        fn facets(&self) -> db::RepoResult<Facets> {
+            broken()
+        }
+        fn stats(&self) -> db::RepoResult<Stats> {
+            broken()
+        }
+        fn random_recipe(&self, _: Option<&str>) -> db::RepoResult<Option<Recipe>> {
+            broken()
+        }
+        fn load_favorites(&self) -> db::RepoResult<Vec<Recipe>> {
+            broken()
+        }
+        fn set_favorite(&self, _: i32, _: bool) -> db::RepoResult<bool> {
+            broken()
+        }
+        fn load_recipes_page(&self, _: u32, _: u32) -> db::RepoResult<Vec<Recipe>> {
+            broken()
+        }
+        fn load_recipes_after(&self, _: i32, _: u32) -> db::RepoResult<Vec<Recipe>> {
+            broken()
+        }
+        fn recipes_missing(
+            &self,
+            _: db::MissingField,
+            _: Option<chrono::DateTime<chrono::Utc>>,
+        ) -> db::RepoResult<Vec<Recipe>> {
+            broken()
+        }
+        fn reorder_steps(&self, _: i32, _: &[u32]) -> db::RepoResult<bool> {
+            broken()
+        }
+        fn append_ingredients(&self, _: i32, _: &[IngredientQuantity]) -> db::RepoResult<bool> {
+            broken()
+        }
+        fn count_recipes(&self) -> db::RepoResult<u64> {
+            broken()
+        }
+        fn recipes_modified_since(
+            &self,
+            _: chrono::DateTime<chrono::Utc>,
+        ) -> db::RepoResult<Vec<Recipe>> {
+            broken()
+        }
+        fn collection_version(&self) -> db::RepoResult<db::CollectionVersion> {
+            broken()
+        }
+        fn load_recipe_summaries(&self) -> db::RepoResult<Vec<RecipeSummary>> {
+            broken()
+        }
+        fn load_categories(&self) -> db::RepoResult<Vec<Category>> {
+            broken()
+        }
+        fn add_category(&self, _: &str, _: Option<i32>) -> db::RepoResult<Category> {
+            broken()
+        }
+        fn delete_category(&self, _: i32) -> db::RepoResult<bool> {
+            broken()
+        }
+        fn set_recipe_category(&self, _: i32, _: i32, _: bool) -> db::RepoResult<bool> {
+            broken()
+        }
+        fn recipe_category_links(&self) -> db::RepoResult<Vec<(u32, i32)>> {
+            broken()
+        }
+        fn ingredient_aisles(&self) -> db::RepoResult<HashMap<String, String>> {
+            broken()
+        }
+        fn ingredient_usage(&self) -> db::RepoResult<Vec<(String, u64)>> {
+            broken()
+        }
+        fn all_ingredients(&self, _: db::IngredientSort) -> db::RepoResult<Vec<(String, u64)>> {
+            broken()
+        }
+        fn rename_ingredient(&self, _: &str, _: &str) -> db::RepoResult<bool> {
+            broken()
+        }
+        fn add_provenance(&self, _: &provenance::ProvenanceLink) -> db::RepoResult<()> {
+            broken()
+        }
+        fn provenance_sources(&self, _: i32) -> db::RepoResult<Vec<provenance::ProvenanceLink>> {
+            broken()
+        }
+        fn add_rating(&self, _: i32, _: u8) -> db::RepoResult<bool> {
+            broken()
+        }
+        fn rating_summary(&self, _: i32) -> db::RepoResult<Option<ratings::RatingSummary>> {
+            broken()
+        }
+        fn add_duplicate(&self, _: i32, _: &Recipe) -> db::RepoResult<u32> {
+            broken()
+        }
+        fn export_snapshot(&self) -> db::RepoResult<db::Snapshot> {
+            broken()
+        }
+        fn import_snapshot(&self, _: &db::Snapshot) -> db::RepoResult<()> {
+            broken()
+        }
+    }
+
+    fn app_config(repo: Box<dyn db::Repo>) -> AppConfig {
+        AppConfig {
+            repo,
+            whitespace: normalize::WhitespaceOptions::default(),
+            integrity: integrity::SharedIntegrity::default(),
+            strict_units: false,
+            recipe_cache: None,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            max_import_bytes: DEFAULT_MAX_IMPORT_BYTES,
+            backup_dir: PathBuf::from("target/tests/backups"),
+            field_limits: FieldLimits::default(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_unmatched_route() {
+        let mut app = test::init_service(
+            App::new()
+                .service(hello)
+                .default_service(web::route().to(not_found)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/does-not-exist").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        let body: ApiError = test::read_body_json(resp).await;
+        assert_eq!(
+            ApiError::new("not_found", "No endpoint for GET /does-not-exist"),
+            body
+        );
+
+        let req = test::TestRequest::get().uri("/").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_version() {
+        let mut app = test::init_service(App::new().service(build_version)).await;
+
+        let req = test::TestRequest::get().uri("/version").to_request();
+        let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(env!("CARGO_PKG_VERSION"), body["version"]);
+        assert!(!body["git_hash"].as_str().unwrap().is_empty());
+        assert!(chrono::DateTime::parse_from_rfc3339(body["build_time"].as_str().unwrap()).is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn test_health_detailed() {
+        let (repo, name) = temp_repo();
+        repo.add_recipe(&recipe("Toast")).unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(repo))
+                .app_data(web::Data::new(auth::ApiKey::new("s3cret")))
+                .service(health_detailed),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/health/detailed")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
+        let req = test::TestRequest::get()
+            .uri("/health/detailed")
+            .header("Authorization", "Bearer s3cret")
+            .to_request();
+        let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(1, body["recipe_count"]);
+        assert!(body["schema_version"].as_u64().unwrap() > 0);
+        assert_eq!(
+            body["pool"]["connections"],
+            body["pool"]["idle_connections"]
+        );
+        assert!(body["pool"]["max_size"].as_u64().unwrap() > 0);
+        assert!(body["error"].is_null());
+
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .service(health_detailed),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri("/health/detailed")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert!(body["recipe_count"].is_null());
+        assert!(body["error"].is_string());
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_browse_max_minutes() {
+        let (repo, name) = temp_repo();
+        let mut timed = recipe("Toast");
+        timed.steps = vec![Step {
+            duration_minutes: Some(5),
+            ..Step::new("Toast the bread")
+        }];
+        repo.add_recipe(&timed).unwrap();
+        repo.add_recipe(&recipe("Salad")).unwrap();
+        let mut app = test::init_service(App::new().data(app_config(repo)).service(browse)).await;
+
+        for (uri, expected) in [
+            ("/recipes/browse?max_minutes=5", vec!["Toast"]),
+            ("/recipes/browse?max_minutes=4", vec![]),
+            (
+                "/recipes/browse?max_minutes=4&include_unknown=true",
+                vec!["Salad"],
+            ),
+            ("/recipes/browse", vec!["Toast", "Salad"]),
+        ] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let found: Vec<Recipe> = test::read_response_json(&mut app, req).await;
+            let names: Vec<&str> = found.iter().map(|r| r.name.as_str()).collect();
+            assert_eq!(expected, names, "{}", uri);
+        }
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/browse?max_minutes=-1")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_cook_mode() {
+        let (repo, name) = temp_repo();
+        let recipe_id = repo
+            .add_recipe(
+                &RecipeBuilder::default()
+                    .step("Boil the eggs")
+                    .step("Peel")
+                    .ingredient("Egg", 2.0, "whole")
+                    .build(),
+            )
+            .unwrap();
+        let mut app =
+            test::init_service(App::new().data(app_config(repo)).service(cook_mode)).await;
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/recipes/{}/cook?factor=1.5", recipe_id))
+            .to_request();
+        let steps: Vec<cook::CookStep> = test::read_response_json(&mut app, req).await;
+        assert_eq!("Boil the eggs", steps[0].step_text);
+        assert_eq!(3.0, steps[0].ingredients[0].quantity.value);
+        assert!(steps[1].ingredients.is_empty());
+
+        for uri in ["/recipes/1/cook?factor=0", "/recipes/1/cook?factor=lots"].iter() {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(StatusCode::BAD_REQUEST, resp.status(), "{}", uri);
+        }
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/99/cook")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_raw_source_only_in_detail() {
+        let (repo, name) = temp_repo();
+        let recipe_id = repo
+            .add_recipe(&Recipe {
+                raw_source: Some("toast: bread, heat".to_string()),
+                ..recipe("Toast")
+            })
+            .unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(repo))
+                .service(get_recipe)
+                .service(recipes)
+                .service(search),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/recipes/{}", recipe_id))
+            .to_request();
+        let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!("toast: bread, heat", body["raw_source"]);
+
+        for uri in ["/recipes/all", "/recipes/search?q=toast"].iter() {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+            assert_eq!("Toast", body[0]["name"], "{}", uri);
+            assert!(body[0].get("raw_source").is_none(), "{}", uri);
+        }
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_edit_rejects_stale_update() {
+        let (repo, name) = temp_repo();
+        let recipe_id = repo.add_recipe(&recipe("Toast")).unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(repo))
+                .service(edit)
+                .service(upsert)
+                .service(get_recipe),
+        )
+        .await;
+
+        // The client read the recipe before someone else saved it.
+        let stale = (chrono::Utc::now() - chrono::Duration::minutes(1)).to_rfc2822();
+        let edited = Recipe {
+            id: Some(recipe_id),
+            ..recipe("Stale toast")
+        };
+        for uri in [
+            "/recipes/edit".to_string(),
+            format!("/recipes/{}", recipe_id),
+        ]
+        .iter()
+        {
+            let req = test::TestRequest::put()
+                .uri(uri)
+                .header(header::IF_UNMODIFIED_SINCE, stale.clone())
+                .set_json(&edited)
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(StatusCode::PRECONDITION_FAILED, resp.status());
+            let body: ApiError = test::read_body_json(resp).await;
+            assert_eq!("precondition_failed", body.code);
+        }
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/recipes/{}", recipe_id))
+            .to_request();
+        let stored: Recipe = test::read_response_json(&mut app, req).await;
+        assert_eq!("Toast", stored.name);
+
+        let current = (chrono::Utc::now() + chrono::Duration::minutes(1)).to_rfc2822();
+        let req = test::TestRequest::put()
+            .uri("/recipes/edit")
+            .header(header::IF_UNMODIFIED_SINCE, current)
+            .set_json(&edited)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_backup() {
+        let (repo, name) = temp_repo();
+        repo.add_recipe(&recipe("Toast")).unwrap();
+        let backup_dir = PathBuf::from(format!(
+            "target/tests/backups-{}/nested",
+            rand::random::<u32>()
+        ));
+        let mut app = test::init_service(
+            App::new()
+                .data(AppConfig {
+                    backup_dir: backup_dir.clone(),
+                    ..app_config(repo)
+                })
+                .app_data(web::Data::new(auth::ApiKey::new("s3cret")))
+                .service(backup),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/admin/backup")
+            .header(header::AUTHORIZATION, "Bearer s3cret")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::CREATED, resp.status());
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let path = PathBuf::from(body["path"].as_str().unwrap());
+        assert!(path.starts_with(&backup_dir));
+
+        let copy = db::create_repo_with_name(db::Backend::Sqlite, path.to_str().unwrap()).unwrap();
+        let names: Vec<String> = copy
+            .load_recipes()
+            .unwrap()
+            .into_iter()
+            .map(|r| r.name)
+            .collect();
+        assert_eq!(vec!["Toast"], names);
+        drop(copy);
+        std::fs::remove_dir_all(backup_dir.parent().unwrap()).unwrap();
+
+        // A directory that cannot be created, here because a file is in
+        // the way, gets a clear error.
+        let mut app = test::init_service(
+            App::new()
+                .data(AppConfig {
+                    backup_dir: PathBuf::from(&name).join("backups"),
+                    ..app_config(Box::new(BrokenRepo))
+                })
+                .app_data(web::Data::new(auth::ApiKey::new("s3cret")))
+                .service(backup),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/admin/backup")
+            .header(header::AUTHORIZATION, "Bearer s3cret")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, resp.status());
+        let body: ApiError = test::read_body_json(resp).await;
+        assert_eq!("backup_failed", body.code);
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_recipes_database_error() {
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .service(recipes),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/recipes/all").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, resp.status());
+
+        let body: ApiError = test::read_body_json(resp).await;
+        assert_eq!(ApiError::database(), body);
+    }
+
+    #[actix_rt::test]
+    async fn test_add_and_delete_database_error() {
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .service(add)
+                .service(delete),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/add")
+            .set_json(&recipe("Toast"))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, resp.status());
+        let body: ApiError = test::read_body_json(resp).await;
+        assert_eq!(ApiError::database(), body);
+
+        let req = test::TestRequest::delete()
+            .uri("/recipes/delete?recipe_id=1")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, resp.status());
+        let body: ApiError = test::read_body_json(resp).await;
+        assert_eq!(ApiError::database(), body);
+    }
+
+    #[actix_rt::test]
+    async fn test_write_rate_limit() {
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .data(rate_limit::RateLimiter::new(1))
+                .service(delete)
+                .service(recipes),
+        )
+        .await;
+
+        let delete_req = || {
+            test::TestRequest::delete()
+                .uri("/recipes/delete?recipe_id=1")
+                .peer_addr("10.0.0.1:4000".parse().unwrap())
+                .to_request()
+        };
+
+        let resp = test::call_service(&mut app, delete_req()).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, resp.status());
+
+        let resp = test::call_service(&mut app, delete_req()).await;
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, resp.status());
+        assert_eq!("60", resp.headers().get("Retry-After").unwrap());
+
+        // Reads are never limited.
+        let req = test::TestRequest::get()
+            .uri("/recipes/all")
+            .peer_addr("10.0.0.1:4000".parse().unwrap())
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, resp.status());
+    }
+
+    /// A repo backed by a fresh database file, and the file's name.
+    fn temp_repo() -> (Box<dyn db::Repo>, String) {
+        let name = format!("target/tests/handlers-{}.db", rand::random::<u32>());
+        (
+            db::create_repo_with_name(db::Backend::Sqlite, &name).unwrap(),
+            name,
+        )
+    }
+
+    fn recipe(name: &str) -> Recipe {
+        RecipeBuilder::default().name(name).build()
+    }
+
+    #[actix_rt::test]
+    async fn test_get_recipe_last_modified() {
+        let (repo, name) = temp_repo();
+        let recipe_id = repo.add_recipe(&recipe("Toast")).unwrap();
+        let mut app =
+            test::init_service(App::new().data(app_config(repo)).service(get_recipe)).await;
+
+        let uri = format!("/recipes/{}", recipe_id);
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let last_modified = resp.headers().get(header::LAST_MODIFIED).unwrap().clone();
+
+        let req = test::TestRequest::get()
+            .uri(&uri)
+            .header(header::IF_MODIFIED_SINCE, last_modified.clone())
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_MODIFIED, resp.status());
+        assert_eq!(
+            &last_modified,
+            resp.headers().get(header::LAST_MODIFIED).unwrap()
+        );
+
+        let req = test::TestRequest::get()
+            .uri(&uri)
+            .header(header::IF_MODIFIED_SINCE, "Fri, 01 Jan 2021 00:00:00 GMT")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_reorder_steps() {
+        let (repo, name) = temp_repo();
+        let recipe_id = repo
+            .add_recipe(&Recipe {
+                steps: vec![Step::new("Knead"), Step::new("Proof"), Step::new("Bake")],
+                ..recipe("Bread")
+            })
+            .unwrap();
+        let mut app =
+            test::init_service(App::new().data(app_config(repo)).service(reorder_steps)).await;
+
+        let uri = format!("/recipes/{}/steps/reorder", recipe_id);
+        let req = test::TestRequest::put()
+            .uri(&uri)
+            .set_json(&vec![2, 0, 1])
+            .to_request();
+        let reordered: Recipe = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            vec![Step::new("Bake"), Step::new("Knead"), Step::new("Proof")],
+            reordered.steps
+        );
+
+        for order in [vec![0, 1], vec![0, 1, 1], vec![0, 1, 3]].iter() {
+            let req = test::TestRequest::put()
+                .uri(&uri)
+                .set_json(order)
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(StatusCode::BAD_REQUEST, resp.status(), "{:?}", order);
+            let body: ApiError = test::read_body_json(resp).await;
+            assert_eq!("invalid_order", body.code);
+        }
+
+        let req = test::TestRequest::put()
+            .uri("/recipes/99/steps/reorder")
+            .set_json(&Vec::<u32>::new())
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_import_ingredients_csv() {
+        let (repo, name) = temp_repo();
+        let recipe_id = repo
+            .add_recipe(
+                &RecipeBuilder::default()
+                    .name("Pancakes")
+                    .ingredient("Flour", 1.0, "cup")
+                    .build(),
+            )
+            .unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(repo))
+                .service(import_ingredients_csv)
+                .service(get_recipe),
+        )
+        .await;
+        let uri = format!("/recipes/{}/ingredients/import-csv", recipe_id);
+
+        let req = test::TestRequest::post()
+            .uri(&uri)
+            .header(header::CONTENT_TYPE, "text/csv")
+            .set_payload("name,quantity,unit\n Milk , 1.5, cup\n\nEgg,2,whole\n")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let updated: Recipe = test::read_body_json(resp).await;
+        let names: Vec<&str> = updated
+            .ingredients
+            .iter()
+            .map(|i| i.ingredient.as_str())
+            .collect();
+        assert_eq!(vec!["Flour", "Milk", "Egg"], names);
+
+        let req = test::TestRequest::post()
+            .uri(&uri)
+            .header(header::CONTENT_TYPE, "text/csv")
+            .set_payload("Sugar,1,tbsp\nButter,lots,tbsp\n")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problems: Vec<FieldError> = test::read_body_json(resp).await;
+        assert_eq!(
+            vec![FieldError::new(
+                "line 2",
+                "quantity \"lots\" must be a finite number, zero or greater"
+            )],
+            problems
+        );
+
+        // The bad row kept the good one from being stored.
+        let req = test::TestRequest::get()
+            .uri(&format!("/recipes/{}", recipe_id))
+            .to_request();
+        let unchanged: Recipe = test::read_response_json(&mut app, req).await;
+        assert_eq!(3, unchanged.ingredients.len());
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/99/ingredients/import-csv")
+            .header(header::CONTENT_TYPE, "text/csv")
+            .set_payload("Egg,1,whole")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_recipes_etag() {
+        let (repo, name) = temp_repo();
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(repo))
+                .service(recipes)
+                .service(add),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/recipes/all").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        let etag = resp.headers().get(header::ETAG).unwrap().clone();
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/all")
+            .header(header::IF_NONE_MATCH, etag.clone())
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_MODIFIED, resp.status());
+        assert_eq!(&etag, resp.headers().get(header::ETAG).unwrap());
+        assert_eq!("Accept", resp.headers().get(header::VARY).unwrap());
+
+        // The YAML representation has its own tag, so a cached JSON body
+        // is not taken as current for it.
+        let req = test::TestRequest::get()
+            .uri("/recipes/all")
+            .header(header::ACCEPT, "application/yaml")
+            .header(header::IF_NONE_MATCH, etag.clone())
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_ne!(&etag, resp.headers().get(header::ETAG).unwrap());
+        assert_eq!("Accept", resp.headers().get(header::VARY).unwrap());
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/add")
+            .set_json(&Recipe {
+                steps: vec![Step::new("Toast the bread")],
+                ..recipe("Toast")
+            })
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::CREATED, resp.status());
+        assert_eq!("/recipes/1", resp.headers().get(header::LOCATION).unwrap());
+        let added: Recipe = test::read_body_json(resp).await;
+        assert_eq!(Some(1), added.id);
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/all")
+            .header(header::IF_NONE_MATCH, etag.clone())
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_ne!(&etag, resp.headers().get(header::ETAG).unwrap());
+        let body: Vec<Recipe> = test::read_body_json(resp).await;
+        assert_eq!(1, body.len());
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[test]
+    fn test_is_web_url() {
+        assert!(is_web_url("https://example.com/toast.jpg"));
+        assert!(is_web_url("HTTP://example.com/toast.jpg"));
+        assert!(!is_web_url("https://"));
+        assert!(!is_web_url("javascript:alert(1)"));
+        assert!(!is_web_url("file:///etc/passwd"));
+        assert!(!is_web_url("example.com/toast.jpg"));
+    }
+
+    #[actix_rt::test]
+    async fn test_add_rejects_image_url_scheme() {
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .service(add),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/add")
+            .set_json(&Recipe {
+                image_url: Some("javascript:alert(1)".to_string()),
+                ..recipe("Toast")
+            })
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+
+        let problems: Vec<FieldError> = test::read_body_json(resp).await;
+        assert_eq!(
+            vec![FieldError::new("image_url", "must be an http or https URL")],
+            problems
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_add_rejects_source_url_scheme() {
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .service(add),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/add")
+            .set_json(&Recipe {
+                source_name: Some("Grandma".to_string()),
+                source_url: Some("file:///etc/passwd".to_string()),
+                yield_description: None,
+                ..recipe("Toast")
+            })
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+
+        let problems: Vec<FieldError> = test::read_body_json(resp).await;
+        assert_eq!(
+            vec![FieldError::new(
+                "source_url",
+                "must be an http or https URL"
+            )],
+            problems
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_edit_validation() {
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .service(edit),
+        )
+        .await;
+
+        let blank = Recipe {
+            id: Some(1),
+            ..recipe("   ")
+        };
+        let req = test::TestRequest::put()
+            .uri("/recipes/edit")
+            .set_json(&blank)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let body: Vec<FieldError> = test::read_body_json(resp).await;
+        assert_eq!(vec![FieldError::new("name", "must not be blank")], body);
+
+        let req = test::TestRequest::put()
+            .uri("/recipes/edit")
+            .set_json(&recipe(""))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let body: Vec<FieldError> = test::read_body_json(resp).await;
+        let fields: Vec<&str> = body.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(vec!["id", "name"], fields);
+    }
+
+    #[actix_rt::test]
+    async fn test_add_rejects_repeated_ingredient_in_other_unit() {
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .service(add),
+        )
+        .await;
+
+        let line = |value: f64, unit: &str| IngredientQuantity {
+            ingredient: "Salt".to_string(),
+            quantity: Quantity {
+                value,
+                value_max: None,
+                unit: unit.to_string(),
+            },
+            calories_per_unit: None,
+        };
+        let req = test::TestRequest::post()
+            .uri("/recipes/add")
+            .set_json(&Recipe {
+                ingredients: vec![line(1.0, "tsp"), line(1.0, "pinch")],
+                ..recipe("Pretzels")
+            })
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problems: Vec<FieldError> = test::read_body_json(resp).await;
+        assert_eq!(
+            vec![FieldError::new(
+                "ingredients[1].quantity.unit",
+                "repeats ingredients[0] in a unit that cannot be added to it"
+            )],
+            problems
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_add_rejects_negative_quantity() {
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .service(add),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/add")
+            .set_json(
+                &RecipeBuilder::default()
+                    .name("Pancakes")
+                    .ingredient("Flour", -2.0, "cup")
+                    .build(),
+            )
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problems: Vec<FieldError> = test::read_body_json(resp).await;
+        assert_eq!(
+            vec![FieldError::new(
+                "ingredients[0].quantity.value",
+                "must be a finite number, zero or greater"
+            )],
+            problems
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_patch_recipe() {
+        let (repo, name) = temp_repo();
+        let recipe_id = repo.add_recipe(&recipe("Toast")).unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(repo))
+                .service(patch_recipe)
+                .service(get_recipe),
+        )
+        .await;
+        let uri = format!("/recipes/{}", recipe_id);
+
+        let req = test::TestRequest::patch()
+            .uri(&uri)
+            .set_json(&serde_json::json!({"name": "Buttered toast"}))
+            .to_request();
+        let patched: Recipe = test::read_response_json(&mut app, req).await;
+        assert_eq!("Buttered toast", patched.name);
+
+        let req = test::TestRequest::patch()
+            .uri(&uri)
+            .set_json(&serde_json::json!({
+                "name": " ",
+                "ingredients": [{"ingredient": "Butter", "quantity": {"value": -2.0, "unit": "tbsp"}}],
+            }))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problems: Vec<FieldError> = test::read_body_json(resp).await;
+        let fields: Vec<&str> = problems.iter().map(|p| p.field.as_str()).collect();
+        assert_eq!(vec!["name", "ingredients[0].quantity.value"], fields);
+
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let stored: Recipe = test::read_response_json(&mut app, req).await;
+        assert_eq!(patched, stored);
+
+        let req = test::TestRequest::patch()
+            .uri("/recipes/99")
+            .set_json(&serde_json::json!({"name": "Tea"}))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        let body: ApiError = test::read_body_json(resp).await;
+        assert_eq!("not_found", body.code);
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_field_limits_on_add_and_patch() {
+        let (repo, name) = temp_repo();
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(repo))
+                .service(add)
+                .service(patch_recipe),
+        )
+        .await;
+        let limits = FieldLimits::default();
+        let text = |len: usize| "a".repeat(len);
+
+        let at_limit = Recipe {
+            desc: Some(text(limits.desc)),
+            steps: vec![Step::new(&text(limits.step))],
+            ..RecipeBuilder::default()
+                .name(&text(limits.name))
+                .ingredient(&text(limits.ingredient), 1.0, "cup")
+                .build()
+        };
+        let req = test::TestRequest::post()
+            .uri("/recipes/add")
+            .set_json(&at_limit)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::CREATED, resp.status());
+
+        let over_limit = Recipe {
+            desc: Some(text(limits.desc + 1)),
+            steps: vec![Step::new(&text(limits.step + 1))],
+            ..RecipeBuilder::default()
+                .name(&text(limits.name + 1))
+                .ingredient(&text(limits.ingredient + 1), 1.0, "cup")
+                .build()
+        };
+        let req = test::TestRequest::post()
+            .uri("/recipes/add")
+            .set_json(&over_limit)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problems: Vec<FieldError> = test::read_body_json(resp).await;
+        let fields: Vec<&str> = problems.iter().map(|p| p.field.as_str()).collect();
+        assert_eq!(
+            vec!["name", "desc", "steps[0]", "ingredients[0].ingredient"],
+            fields
+        );
+
+        let req = test::TestRequest::patch()
+            .uri("/recipes/1")
+            .set_json(&RecipePatch {
+                steps: Some(vec![Step::new(&text(limits.step))]),
+                ..RecipePatch::default()
+            })
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let req = test::TestRequest::patch()
+            .uri("/recipes/1")
+            .set_json(&RecipePatch {
+                name: Some(text(limits.name + 1)),
+                steps: Some(vec![Step::new(&text(limits.step + 1))]),
+                ..RecipePatch::default()
+            })
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problems: Vec<FieldError> = test::read_body_json(resp).await;
+        let fields: Vec<&str> = problems.iter().map(|p| p.field.as_str()).collect();
+        assert_eq!(vec!["name", "steps[0]"], fields);
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_edit_missing_recipe() {
+        let (repo, name) = temp_repo();
+        let mut app = test::init_service(App::new().data(app_config(repo)).service(edit)).await;
+
+        let missing = Recipe {
+            id: Some(42),
+            ..recipe("Toast")
+        };
+        let req = test::TestRequest::put()
+            .uri("/recipes/edit")
+            .set_json(&missing)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_with_repo_does_not_block_worker() {
+        let config = web::Data::new(app_config(Box::new(BrokenRepo)));
+        let started = std::time::Instant::now();
+
+        let slow = with_repo(&config, |_| {
+            std::thread::sleep(std::time::Duration::from_millis(300));
+            Ok(())
+        });
+        // Polled after `slow`, so this only finishes promptly if the slow
+        // call was handed off instead of running on this thread.
+        let other = async { started.elapsed() };
+
+        let (slow_result, other_elapsed) = futures::join!(slow, other);
+        assert!(slow_result.is_ok());
+        assert!(other_elapsed < std::time::Duration::from_millis(300));
+    }
+
+    /// Before/after timing for running repo calls through `with_repo`: eight
+    /// concurrent 50 ms calls on one worker, and the latency of a cheap
+    /// request made while they run. Run with
+    /// `cargo test --release bench_with_repo -- --ignored --nocapture`.
+    #[actix_rt::test]
+    #[ignore]
+    async fn bench_with_repo() {
+        use std::time::{Duration, Instant};
+
+        const CALLS: usize = 8;
+        let slow_query = || std::thread::sleep(Duration::from_millis(50));
+        let config = web::Data::new(app_config(Box::new(BrokenRepo)));
+
+        // Before: the query ran inline on the worker thread.
+        let started = Instant::now();
+        let calls = (0..CALLS).map(|_| async move { slow_query() });
+        let fast = async { started.elapsed() };
+        let (_, inline_fast) = futures::join!(futures::future::join_all(calls), fast);
+        let inline_total = started.elapsed();
+
+        // After: the query runs on the blocking thread pool.
+        let started = Instant::now();
+        let calls = (0..CALLS).map(|_| {
+            with_repo(&config, move |_| {
+                slow_query();
+                Ok(())
+            })
+        });
+        let fast = async { started.elapsed() };
+        let (_, pooled_fast) = futures::join!(futures::future::join_all(calls), fast);
+        let pooled_total = started.elapsed();
+
+        println!(
+            "{} concurrent 50 ms calls: inline {:?} total, {:?} for a cheap request; with_repo {:?} total, {:?} for a cheap request",
+            CALLS, inline_total, inline_fast, pooled_total, pooled_fast
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_rate_recipe_rejects_out_of_range() {
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .service(rate_recipe),
+        )
+        .await;
+
+        for score in [0, 6].iter() {
+            let req = test::TestRequest::post()
+                .uri("/recipes/1/ratings")
+                .set_json(&ratings::NewRating { score: *score })
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_recipe_page() {
+        let (repo, name) = temp_repo();
+        for i in 1..=5 {
+            repo.add_recipe(&recipe(&format!("Recipe {}", i))).unwrap();
+        }
+        let mut app =
+            test::init_service(App::new().data(app_config(repo)).service(recipe_page)).await;
+
+        let mut seen = Vec::new();
+        for offset in [0, 2, 4].iter() {
+            let req = test::TestRequest::get()
+                .uri(&format!("/recipes?limit=2&offset={}", offset))
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(StatusCode::OK, resp.status());
+
+            let page: Page<Recipe> = test::read_body_json(resp).await;
+            assert_eq!(5, page.total);
+            assert_eq!(2, page.limit);
+            assert_eq!(*offset, page.offset);
+            seen.extend(page.items.into_iter().map(|r| r.id.unwrap()));
+        }
+        assert_eq!(vec![1, 2, 3, 4, 5], seen);
+
+        let req = test::TestRequest::get()
+            .uri("/recipes?offset=10")
+            .to_request();
+        let page: Page<Recipe> =
+            test::read_body_json(test::call_service(&mut app, req).await).await;
+        assert!(page.items.is_empty());
+        assert_eq!(5, page.total);
+        assert_eq!(DEFAULT_PAGE_SIZE, page.limit);
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_recipe_print() {
+        let (repo, name) = temp_repo();
+        repo.add_recipe(&recipe("Toast")).unwrap();
+        let mut app =
+            test::init_service(App::new().data(app_config(repo)).service(recipe_print)).await;
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/1/print")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            "text/html; charset=utf-8",
+            resp.headers().get(header::CONTENT_TYPE).unwrap()
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/2/print")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_export_markdown_zip_repeated_ids() {
+        let (repo, name) = temp_repo();
+        repo.add_recipe(&recipe("Toast")).unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(repo))
+                .service(export_markdown_zip),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/export/markdown-zip")
+            .set_json(&serde_json::json!({ "ids": [1, 99, 1, 99] }))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!("99", resp.headers().get("X-Missing-Recipe-Ids").unwrap());
+        // Entries are stored uncompressed, so each name appears as is: in
+        // its local header, the central directory and the index's link.
+        let body = test::read_body(resp).await;
+        let count = |needle: &[u8]| body.windows(needle.len()).filter(|w| *w == needle).count();
+        assert_eq!(3, count(b"toast.md"));
+        assert_eq!(0, count(b"toast-1.md"));
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_export_markdown_zip_database_error() {
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .service(export_markdown_zip),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/export/markdown-zip")
+            .set_json(&serde_json::json!({ "ids": [1] }))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, resp.status());
+        let error: ApiError = test::read_body_json(resp).await;
+        assert_eq!(ApiError::database(), error);
+    }
+
+    #[actix_rt::test]
+    async fn test_duplicate() {
+        let (repo, name) = temp_repo();
+        let original = Recipe {
+            favorite: true,
+            ..RecipeBuilder::default()
+                .name("Toast")
+                .step("Toast the bread")
+                .build()
+        };
+        repo.add_recipe(&original).unwrap();
+        repo.add_recipe(&recipe(&"a".repeat(FieldLimits::default().name)))
+            .unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(repo))
+                .service(duplicate)
+                .service(recipe_provenance),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/1/duplicate")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::CREATED, resp.status());
+        assert_eq!("/recipes/3", resp.headers().get(header::LOCATION).unwrap());
+        let copy: Recipe = test::read_body_json(resp).await;
+        assert_eq!(Some(3), copy.id);
+        assert_eq!("Toast (copy)", copy.name);
+        assert!(!copy.favorite);
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/3/provenance")
+            .to_request();
+        let provenance: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(1, provenance["chain"][0]["source_id"]);
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/99/provenance")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        let error: ApiError = test::read_body_json(resp).await;
+        assert_eq!("not_found", error.code);
+
+        // A name at the limit has no room for " (copy)".
+        let req = test::TestRequest::post()
+            .uri("/recipes/2/duplicate")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problems: Vec<FieldError> = test::read_body_json(resp).await;
+        assert_eq!("name", problems[0].field);
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/99/duplicate")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        let error: ApiError = test::read_body_json(resp).await;
+        assert_eq!("not_found", error.code);
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_convert_missing_recipe() {
+        let (repo, name) = temp_repo();
+        let mut app = test::init_service(App::new().data(app_config(repo)).service(convert)).await;
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/1/convert?system=metric")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        let error: ApiError = test::read_body_json(resp).await;
+        assert_eq!("not_found", error.code);
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_shopping_list_errors() {
+        let (repo, name) = temp_repo();
+        let mut app =
+            test::init_service(App::new().data(app_config(repo)).service(shopping_list)).await;
+        let req = test::TestRequest::post()
+            .uri("/shopping-list")
+            .set_json(&serde_json::json!({"recipe_ids": [7]}))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        let error: ApiError = test::read_body_json(resp).await;
+        assert_eq!("not_found", error.code);
+        std::fs::remove_file(&name).unwrap();
+
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .service(shopping_list),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/shopping-list")
+            .set_json(&serde_json::json!({"recipe_ids": [7]}))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, resp.status());
+        let error: ApiError = test::read_body_json(resp).await;
+        assert_eq!(ApiError::database(), error);
+    }
+
+    #[actix_rt::test]
+    async fn test_nutrition_missing_recipe() {
+        let (repo, name) = temp_repo();
+        let mut app =
+            test::init_service(App::new().data(app_config(repo)).service(recipe_nutrition)).await;
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/1/nutrition")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        let error: ApiError = test::read_body_json(resp).await;
+        assert_eq!("not_found", error.code);
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_recipe_tree_database_error() {
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .service(recipe_tree),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/recipes/tree").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, resp.status());
+        let error: ApiError = test::read_body_json(resp).await;
+        assert_eq!(ApiError::database(), error);
+    }
+
+    #[actix_rt::test]
+    async fn test_recipe_markdown() {
+        let (repo, name) = temp_repo();
+        repo.add_recipe(&Recipe {
+            steps: vec![Step::new("Toast the bread")],
+            ..recipe("Toast")
+        })
+        .unwrap();
+        let mut app =
+            test::init_service(App::new().data(app_config(repo)).service(recipe_markdown)).await;
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/1/markdown")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            "text/markdown; charset=utf-8",
+            resp.headers().get(header::CONTENT_TYPE).unwrap()
+        );
+        let body = test::read_body(resp).await;
+        assert_eq!(
+            &b"# Toast\n\n## Steps\n\n1. Toast the bread\n"[..],
+            &body[..]
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/2/markdown")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        let error: ApiError = test::read_body_json(resp).await;
+        assert_eq!("not_found", error.code);
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_add_rejects_photo_scheme() {
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .service(add),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/add")
+            .set_json(&Recipe {
+                photos: vec![
+                    "https://example.com/toast.jpg".to_string(),
+                    "file:///etc/passwd".to_string(),
+                ],
+                ..recipe("Toast")
+            })
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+
+        let problems: Vec<FieldError> = test::read_body_json(resp).await;
+        assert_eq!(
+            vec![FieldError::new("photos[1]", "must be an http or https URL")],
+            problems
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_random_empty_is_not_found() {
+        let (repo, name) = temp_repo();
+        let mut app = test::init_service(App::new().data(app_config(repo)).service(random)).await;
+
+        let req = test::TestRequest::get().uri("/recipes/random").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_search_fields() {
+        let (repo, name) = temp_repo();
+        repo.add_recipe(&Recipe {
+            desc: Some("Grandma's secret recipe".to_string()),
+            ..recipe("Meatballs")
+        })
+        .unwrap();
+        let mut app = test::init_service(App::new().data(app_config(repo)).service(search)).await;
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/search?q=secret")
+            .to_request();
+        let found: Vec<Recipe> = test::read_response_json(&mut app, req).await;
+        assert!(found.is_empty());
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/search?q=secret&fields=all")
+            .to_request();
+        let found: Vec<Recipe> = test::read_response_json(&mut app, req).await;
+        assert_eq!(1, found.len());
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/search?q=secret&fields=steps")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_malformed_json_body() {
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .app_data(json_config(DEFAULT_MAX_BODY_BYTES))
+                .service(add)
+                .service(edit)
+                .service(import),
+        )
+        .await;
+
+        for (method, uri) in [
+            (actix_web::http::Method::POST, "/recipes/add"),
+            (actix_web::http::Method::PUT, "/recipes/edit"),
+            (actix_web::http::Method::POST, "/recipes/import"),
+        ]
+        .iter()
+        {
+            let req = test::TestRequest::with_uri(uri)
+                .method(method.clone())
+                .header(header::CONTENT_TYPE, "application/json")
+                .set_payload("{\"name\":")
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(StatusCode::BAD_REQUEST, resp.status(), "{}", uri);
+            let body: ApiError = test::read_body_json(resp).await;
+            assert_eq!("invalid_body", body.code);
+            assert!(body.message.contains("line 1 column"), "{}", body.message);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_wrong_shape_json_body() {
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .app_data(json_config(DEFAULT_MAX_BODY_BYTES))
+                .service(add)
+                .service(patch_recipe)
+                .service(rate_recipe),
+        )
+        .await;
+
+        for (method, uri, payload, path) in [
+            (
+                actix_web::http::Method::POST,
+                "/recipes/add",
+                r#"{"name": "Tea", "desc": null, "steps": "Boil", "ingredients": []}"#,
+                "steps: ",
+            ),
+            (
+                actix_web::http::Method::POST,
+                "/recipes/add",
+                r#"{"name": "Tea", "desc": null, "steps": [], "ingredients": [{"ingredient": "Tea", "quantity": {"value": "one", "unit": "bag"}}]}"#,
+                "ingredients[0].quantity.value: ",
+            ),
+            (
+                actix_web::http::Method::POST,
+                "/recipes/add",
+                r#"{"name": 7, "desc": null, "steps": [], "ingredients": []}"#,
+                "name: ",
+            ),
+            (
+                actix_web::http::Method::PATCH,
+                "/recipes/1",
+                r#"{"steps": [{"text": "Boil", "duration_minutes": "five"}]}"#,
+                "steps[0]: ",
+            ),
+            (
+                actix_web::http::Method::POST,
+                "/recipes/1/ratings",
+                r#"{"score": "great"}"#,
+                "score: ",
+            ),
+        ]
+        .iter()
+        {
+            let req = test::TestRequest::with_uri(uri)
+                .method(method.clone())
+                .header(header::CONTENT_TYPE, "application/json")
+                .set_payload(*payload)
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(StatusCode::BAD_REQUEST, resp.status(), "{}", payload);
+            let body: ApiError = test::read_body_json(resp).await;
+            assert_eq!("invalid_body", body.code);
+            assert!(
+                body.message
+                    .starts_with(&format!("Invalid JSON body: {}", path)),
+                "{}",
+                body.message
+            );
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_body_size_limits() {
+        let mut app = test::init_service(
+            App::new()
+                .data(AppConfig {
+                    max_body_bytes: 64,
+                    max_import_bytes: 128,
+                    ..app_config(Box::new(BrokenRepo))
+                })
+                .app_data(json_config(64))
+                .service(add)
+                .service(import)
+                .service(import_ndjson),
+        )
+        .await;
+
+        // A JSON object of exactly `len` bytes.
+        let body = |len: usize| {
+            let padding = "x".repeat(len - "{\"name\":\"\"}".len());
+            format!("{{\"name\":\"{}\"}}", padding)
+        };
+
+        for (uri, content_type, limit) in [
+            ("/recipes/add", "application/json", 64),
+            ("/recipes/import", "application/json", 128),
+            ("/recipes/import.ndjson", ndjson::CONTENT_TYPE, 64),
+        ]
+        .iter()
+        {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .header(header::CONTENT_TYPE, *content_type)
+                .set_payload(body(*limit))
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_ne!(StatusCode::PAYLOAD_TOO_LARGE, resp.status(), "{}", uri);
+
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .header(header::CONTENT_TYPE, *content_type)
+                .set_payload(body(limit + 1))
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, resp.status(), "{}", uri);
+            let error: ApiError = test::read_body_json(resp).await;
+            assert_eq!("payload_too_large", error.code);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_upsert() {
+        let (repo, name) = temp_repo();
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(repo))
+                .service(upsert)
+                .service(recipes),
+        )
+        .await;
+
+        let put = |recipe: &Recipe| {
+            test::TestRequest::put()
+                .uri("/recipes/7")
+                .set_json(recipe)
+                .to_request()
+        };
+
+        let resp = test::call_service(&mut app, put(&recipe("Toast"))).await;
+        assert_eq!(StatusCode::CREATED, resp.status());
+        let created: Recipe = test::read_body_json(resp).await;
+        assert_eq!(Some(7), created.id);
+
+        let buttered = Recipe {
+            steps: vec![Step::new("Butter the toast")],
+            ..recipe("Buttered toast")
+        };
+        for _ in 0..2 {
+            let resp = test::call_service(&mut app, put(&buttered)).await;
+            assert_eq!(StatusCode::OK, resp.status());
+            let updated: Recipe = test::read_body_json(resp).await;
+            assert_eq!(
+                Recipe {
+                    id: Some(7),
+                    ..buttered.clone()
+                },
+                updated
+            );
+        }
+
+        let req = test::TestRequest::get().uri("/recipes/all").to_request();
+        let all: Vec<Recipe> = test::read_response_json(&mut app, req).await;
+        assert_eq!(1, all.len());
+        assert_eq!("Buttered toast", all[0].name);
+
+        let mismatched = Recipe {
+            id: Some(8),
+            ..recipe("Toast")
+        };
+        let resp = test::call_service(&mut app, put(&mismatched)).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_recipes_cache() {
+        let (repo, name) = temp_repo();
+        let writer = db::create_repo_with_name(db::Backend::Sqlite, &name).unwrap();
+        let mut config = app_config(repo);
+        config.recipe_cache = Some(Arc::new(cache::RecipeCache::new(cache::DEFAULT_MAX_BYTES)));
+        let mut app = test::init_service(
+            App::new()
+                .data(config)
+                .service(recipes)
+                .service(add)
+                .service(delete),
+        )
+        .await;
+
+        let all_req = || test::TestRequest::get().uri("/recipes/all").to_request();
+        let names = |found: Vec<Recipe>| found.into_iter().map(|r| r.name).collect::<Vec<_>>();
+
+        writer.add_recipe(&recipe("Toast")).unwrap();
+        let found = test::read_response_json(&mut app, all_req()).await;
+        assert_eq!(vec!["Toast"], names(found));
+        let found = test::read_response_json(&mut app, all_req()).await;
+        assert_eq!(vec!["Toast"], names(found));
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/add")
+            .set_json(&recipe("Tea"))
+            .to_request();
+        assert!(test::call_service(&mut app, req)
+            .await
+            .status()
+            .is_success());
+        let found = test::read_response_json(&mut app, all_req()).await;
+        assert_eq!(vec!["Toast", "Tea"], names(found));
+
+        // A write that bypasses the handlers, e.g. from another worker,
+        // changes the ETag the body is cached under.
+        writer
+            .update_recipe(
+                &Recipe {
+                    id: Some(1),
+                    ..recipe("Buttered toast")
+                },
+                None,
+            )
+            .unwrap();
+        let found = test::read_response_json(&mut app, all_req()).await;
+        assert_eq!(vec!["Buttered toast", "Tea"], names(found));
+
+        let req = test::TestRequest::delete()
+            .uri("/recipes/delete?recipe_id=2")
+            .to_request();
+        assert!(test::call_service(&mut app, req)
+            .await
+            .status()
+            .is_success());
+        let found = test::read_response_json(&mut app, all_req()).await;
+        assert_eq!(vec!["Buttered toast"], names(found));
+
+        writer.restore_recipe(2).unwrap();
+        let found = test::read_response_json(&mut app, all_req()).await;
+        assert_eq!(vec!["Buttered toast", "Tea"], names(found));
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_recipes_summary() {
+        let (repo, name) = temp_repo();
+        repo.add_recipe(&Recipe {
+            steps: vec![Step::new("Toast the bread")],
+            image_url: Some("https://example.com/toast.jpg".to_string()),
+            ..recipe("Toast")
+        })
+        .unwrap();
+        let mut app = test::init_service(App::new().data(app_config(repo)).service(recipes)).await;
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/all?fields=summary")
+            .to_request();
+        let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            serde_json::json!([{
+                "id": 1,
+                "name": "Toast",
+                "desc": null,
+                "image_url": "https://example.com/toast.jpg",
+            }]),
+            body
+        );
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_recipes_missing() {
+        let (repo, name) = temp_repo();
+        repo.add_recipe(&Recipe {
+            desc: Some("Crisp".to_string()),
+            steps: vec![Step::new("Toast the bread")],
+            ..recipe("Toast")
+        })
+        .unwrap();
+        repo.add_recipe(&recipe("Tea")).unwrap();
+        let mut app = test::init_service(App::new().data(app_config(repo)).service(recipes)).await;
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/all?missing=steps")
+            .to_request();
+        let found: Vec<Recipe> = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            vec![Recipe {
+                id: Some(2),
+                ..recipe("Tea")
+            }],
+            found
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/all?missing=desc&fields=summary")
+            .to_request();
+        let found: Vec<RecipeSummary> = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            vec!["Tea"],
+            found.iter().map(|r| r.name.as_str()).collect::<Vec<_>>()
+        );
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/all?missing=ingredients")
+            .to_request();
+        let found: Vec<Recipe> = test::read_response_json(&mut app, req).await;
+        assert_eq!(2, found.len());
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/all?missing=photos")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_recipes_since() {
+        let (repo, name) = temp_repo();
+        repo.add_recipe(&recipe("Toast")).unwrap();
+        let mut app = test::init_service(App::new().data(app_config(repo)).service(recipes)).await;
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/all?since=2000-01-01T00:00:00Z")
+            .to_request();
+        let found: Vec<Recipe> = test::read_response_json(&mut app, req).await;
+        assert_eq!(1, found.len());
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/all?since=2999-01-01T00:00:00%2B02:00")
+            .to_request();
+        let found: Vec<Recipe> = test::read_response_json(&mut app, req).await;
+        assert!(found.is_empty());
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/all?since=yesterday")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+        let body: ApiError = test::read_body_json(resp).await;
+        assert_eq!("invalid_since", body.code);
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_wrong_content_type() {
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .app_data(json_config(DEFAULT_MAX_BODY_BYTES))
+                .service(add)
+                .service(import)
+                .service(import_ingredients_csv)
+                .service(import_ndjson),
+        )
+        .await;
+
+        let body = serde_json::to_vec(&recipe("Toast")).unwrap();
+        let req = test::TestRequest::post()
+            .uri("/recipes/add")
+            .header(header::CONTENT_TYPE, "text/plain")
+            .set_payload(body.clone())
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNSUPPORTED_MEDIA_TYPE, resp.status());
+        let error: ApiError = test::read_body_json(resp).await;
+        assert_eq!("unsupported_media_type", error.code);
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/import?dry_run=true")
+            .header(header::CONTENT_TYPE, "text/plain")
+            .set_payload(format!("[{}]", String::from_utf8(body.clone()).unwrap()))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNSUPPORTED_MEDIA_TYPE, resp.status());
+
+        // Leaving the type out is refused the same way everywhere.
+        for (uri, payload) in [
+            ("/recipes/add", body.clone()),
+            (
+                "/recipes/import?dry_run=true",
+                [b"[", &body[..], b"]"].concat(),
+            ),
+            ("/recipes/1/ingredients/import-csv", b"Egg,2,whole".to_vec()),
+            ("/recipes/import.ndjson", body.clone()),
+        ]
+        .iter()
+        {
+            let req = test::TestRequest::post()
+                .uri(uri)
+                .set_payload(payload.clone())
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(StatusCode::UNSUPPORTED_MEDIA_TYPE, resp.status(), "{}", uri);
+            let error: ApiError = test::read_body_json(resp).await;
+            assert_eq!("unsupported_media_type", error.code);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_import_dry_run() {
+        // BrokenRepo fails every call, so a successful dry run shows the
+        // database was never touched.
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .service(import),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/import?dry_run=true")
+            .set_json(&vec![recipe("Toast"), recipe("Tea")])
+            .to_request();
+        let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(serde_json::json!({ "valid": true }), body);
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/import?dry_run=true")
+            .set_json(&vec![recipe("Toast"), recipe(" "), recipe("Tea")])
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problems: Vec<ImportProblem> = test::read_body_json(resp).await;
+        assert_eq!(
+            vec![ImportProblem {
+                index: 1,
+                errors: vec![FieldError::new("name", "must not be blank")],
+            }],
+            problems
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_strict_units() {
+        let mut salted = recipe("Salted water");
+        salted.ingredients = vec![IngredientQuantity {
+            ingredient: "salt".to_string(),
+            quantity: Quantity {
+                value: 1.0,
+                value_max: None,
+                unit: "tablespon".to_string(),
+            },
+            calories_per_unit: None,
+        }];
+
+        // BrokenRepo fails every call, so a 500 shows the unit was accepted.
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .service(add),
+        )
+        .await;
+        let req = test::TestRequest::post()
+            .uri("/recipes/add")
+            .set_json(&salted)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, resp.status());
+
+        let mut config = app_config(Box::new(BrokenRepo));
+        config.strict_units = true;
+        let mut app = test::init_service(App::new().data(config).service(add)).await;
+        let req = test::TestRequest::post()
+            .uri("/recipes/add")
+            .set_json(&salted)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problems: Vec<FieldError> = test::read_body_json(resp).await;
+        assert_eq!(
+            vec![FieldError::new(
+                "ingredients[0].quantity.unit",
+                "unknown unit \"tablespon\"; did you mean \"tbsp\"?"
+            )],
+            problems
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_import() {
+        let (repo, name) = temp_repo();
+        let mut app = test::init_service(App::new().data(app_config(repo)).service(import)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/import")
+            .set_json(&vec![recipe("Toast"), recipe("Tea")])
+            .to_request();
+        let imported: Vec<Recipe> = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            vec![Some(1), Some(2)],
+            imported.iter().map(|r| r.id).collect::<Vec<_>>()
+        );
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_ndjson_round_trip() {
+        let (repo, name) = temp_repo();
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(repo))
+                .service(import_ndjson)
+                .service(export_ndjson),
+        )
+        .await;
+
+        let mut body = Vec::new();
+        ndjson::write_line(&mut body, &recipe("Toast")).unwrap();
+        body.extend_from_slice(b"\r\n");
+        ndjson::write_line(&mut body, &recipe("Tea")).unwrap();
+        let req = test::TestRequest::post()
+            .uri("/recipes/import.ndjson")
+            .header(header::CONTENT_TYPE, ndjson::CONTENT_TYPE)
+            .set_payload(body)
+            .to_request();
+        let imported: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(serde_json::json!({ "imported": 2 }), imported);
+
+        let mut body = Vec::new();
+        ndjson::write_line(&mut body, &recipe("Jam")).unwrap();
+        ndjson::write_line(&mut body, &recipe(" ")).unwrap();
+        let req = test::TestRequest::post()
+            .uri("/recipes/import.ndjson")
+            .header(header::CONTENT_TYPE, ndjson::CONTENT_TYPE)
+            .set_payload(body)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problem: LineProblem = test::read_body_json(resp).await;
+        assert_eq!(
+            LineProblem {
+                line: 2,
+                imported: 0,
+                errors: vec![FieldError::new("name", "must not be blank")],
+            },
+            problem
+        );
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/import.ndjson")
+            .header(header::CONTENT_TYPE, ndjson::CONTENT_TYPE)
+            .set_payload("{\"name\": ")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/export.ndjson")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(
+            ndjson::CONTENT_TYPE,
+            resp.headers().get(header::CONTENT_TYPE).unwrap()
+        );
+        let body = test::read_body(resp).await;
+        let names: Vec<String> = body
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice::<Recipe>(line).unwrap().name)
+            .collect();
+        assert_eq!(vec!["Toast", "Tea"], names);
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_writes_require_api_key() {
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(Box::new(BrokenRepo)))
+                .app_data(web::Data::new(auth::ApiKey::new("s3cret")))
+                .service(add)
+                .service(recipes),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/add")
+            .set_json(&recipe("Toast"))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status());
+
+        // Reads stay public; BrokenRepo turns this one into a database error.
+        let req = test::TestRequest::get().uri("/recipes/all").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, resp.status());
+    }
+
+    #[actix_rt::test]
+    async fn test_edit_returns_stored_recipe() {
+        let (repo, name) = temp_repo();
+        let recipe_id = repo
+            .add_recipe(&Recipe {
+                favorite: true,
+                ingredients: vec![IngredientQuantity {
+                    ingredient: "Sugar".to_string(),
+                    quantity: Quantity {
+                        value: 1.0,
+                        value_max: None,
+                        unit: "cup".to_string(),
+                    },
+                    calories_per_unit: None,
+                }],
+                ..recipe("Cake")
+            })
+            .unwrap();
+        let mut app = test::init_service(App::new().data(app_config(repo)).service(edit)).await;
+
+        let submitted = Recipe {
+            id: Some(recipe_id),
+            ingredients: vec![IngredientQuantity {
+                ingredient: "sugar".to_string(),
+                quantity: Quantity {
+                    value: 2.0,
+                    value_max: None,
+                    unit: "cup".to_string(),
+                },
+                calories_per_unit: None,
+            }],
+            ..recipe("Cake")
+        };
+        let req = test::TestRequest::put()
+            .uri("/recipes/edit")
+            .set_json(&submitted)
+            .to_request();
+        let stored: Recipe = test::read_response_json(&mut app, req).await;
+
+        assert!(stored.favorite);
+        assert_eq!("Sugar", stored.ingredients[0].ingredient);
+        assert_eq!(2.0, stored.ingredients[0].quantity.value);
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    fn quantity(value: f64) -> IngredientQuantity {
+        IngredientQuantity {
+            ingredient: "Water".to_string(),
+            quantity: Quantity {
+                value,
+                value_max: None,
+                unit: "cup".to_string(),
+            },
+            calories_per_unit: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_quantities() {
+        let boil_water = Recipe {
+            steps: vec![Step::new("Boil")],
+            ..recipe("Boil Water")
+        };
+        assert!(boil_water.validate().is_empty());
+
+        let valid = Recipe {
+            ingredients: vec![quantity(0.0), quantity(1.5)],
+            ..recipe("Tea")
+        };
+        assert!(valid.validate().is_empty());
+
+        let invalid = Recipe {
+            ingredients: vec![quantity(1.0), quantity(-2.0), quantity(f64::NAN)],
+            ..recipe("Tea")
+        };
+        assert_eq!(
+            vec![
+                FieldError::new(
+                    "ingredients[1].quantity.value",
+                    "must be a finite number, zero or greater"
+                ),
+                FieldError::new(
+                    "ingredients[2].quantity.value",
+                    "must be a finite number, zero or greater"
+                ),
+            ],
+            invalid.validate()
+        );
+    }
+
+    #[test]
+    fn test_validate_lengths() {
+        let limits = FieldLimits::default();
+        let sized = |extra: usize| {
+            let text = |limit: usize| "a".repeat(limit + extra);
+            Recipe {
+                desc: Some(text(limits.desc)),
+                steps: vec![Step::new(&text(limits.step))],
+                ingredients: vec![IngredientQuantity {
+                    ingredient: text(limits.ingredient),
+                    quantity: Quantity {
+                        value: 1.0,
+                        value_max: None,
+                        unit: "cup".to_string(),
+                    },
+                    calories_per_unit: None,
+                }],
+                ..recipe(&text(limits.name))
+            }
+        };
+
+        assert!(sized(0).validate().is_empty());
+        assert_eq!(
+            vec![
+                FieldError::new("name", "must be at most 200 characters"),
+                FieldError::new("desc", "must be at most 2000 characters"),
+                FieldError::new("steps[0]", "must be at most 1000 characters"),
+                FieldError::new(
+                    "ingredients[0].ingredient",
+                    "must be at most 100 characters"
+                ),
+            ],
+            sized(1).validate()
+        );
+
+        // Characters are counted, not bytes.
+        let tight = FieldLimits { name: 4, ..limits };
+        assert_eq!(1, recipe("Crêpe").validate_with_limits(&tight).len());
+        assert!(recipe("Crêp").validate_with_limits(&tight).is_empty());
+    }
+
+    #[test]
+    fn test_validate_step_images() {
+        let steps: Vec<Step> = serde_json::from_str(
+            r#"["Knead", {"text": "Shape", "image_url": "https://example.com/shape.jpg"}, {"text": "Bake", "image_url": "ftp://example.com/bake.jpg"}]"#,
+        )
+        .unwrap();
+        assert_eq!(None, steps[0].image_url);
+        assert_eq!(
+            r#"{"text":"Knead","duration_minutes":null,"note":null}"#,
+            serde_json::to_string(&steps[0]).unwrap()
+        );
+
+        let bread = Recipe {
+            steps,
+            ..recipe("Bread")
+        };
+        assert_eq!(
+            vec![FieldError::new(
+                "steps[2].image_url",
+                "must be an http or https URL"
+            )],
+            bread.validate()
+        );
+    }
+
+    #[test]
+    fn test_quantity_ranges() {
+        let exact: Quantity = serde_json::from_str(r#"{"value": 2, "unit": "clove"}"#).unwrap();
+        assert_eq!(None, exact.value_max);
+        assert_eq!("2", exact.amount());
+        assert_eq!(
+            serde_json::json!({"value": 2.0, "unit": "clove"}),
+            serde_json::to_value(&exact).unwrap()
+        );
+
+        let range: Quantity =
+            serde_json::from_str(r#"{"value": 2, "value_max": 3, "unit": "clove"}"#).unwrap();
+        assert_eq!("2-3", range.amount());
+        assert_eq!(
+            serde_json::json!({"value": 2.0, "value_max": 3.0, "unit": "clove"}),
+            serde_json::to_value(&range).unwrap()
+        );
+
+        let doubled = range.scale(2.0);
+        assert_eq!((4.0, Some(6.0)), (doubled.value, doubled.value_max));
+        assert_eq!("1", exact.scale(0.5).amount());
+
+        let mut backwards = quantity(3.0);
+        backwards.quantity.value_max = Some(2.0);
+        let invalid = Recipe {
+            ingredients: vec![backwards],
+            ..recipe("Tea")
+        };
+        assert_eq!(
+            vec![FieldError::new(
+                "ingredients[0].quantity.value_max",
+                "must be a finite number no less than value"
+            )],
+            invalid.validate()
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_delete_and_restore() {
+        let (repo, name) = temp_repo();
+        let recipe_id = repo.add_recipe(&recipe("Toast")).unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(repo))
+                .service(recipes)
+                .service(trash)
+                .service(restore)
+                .service(delete),
+        )
+        .await;
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/recipes/delete?recipe_id={}", recipe_id))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+
+        let req = test::TestRequest::get().uri("/recipes/all").to_request();
+        let found: Vec<Recipe> = test::read_response_json(&mut app, req).await;
+        assert!(found.is_empty());
+
+        let req = test::TestRequest::get().uri("/recipes/trash").to_request();
+        let deleted: Vec<DeletedRecipe> = test::read_response_json(&mut app, req).await;
+        assert_eq!(1, deleted.len());
+        assert_eq!("Toast", deleted[0].recipe.name);
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/recipes/{}/restore", recipe_id))
+            .to_request();
+        let restored: Recipe = test::read_response_json(&mut app, req).await;
+        assert_eq!(Some(recipe_id), restored.id);
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/recipes/{}/restore", recipe_id))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        let req = test::TestRequest::get().uri("/recipes/all").to_request();
+        let found: Vec<Recipe> = test::read_response_json(&mut app, req).await;
+        assert_eq!(vec![restored], found);
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_recipe_index() {
+        let (repo, name) = temp_repo();
+        for recipe_name in ["Waffles", "7-layer dip", "waldorf salad", "Apple pie"].iter() {
+            repo.add_recipe(&recipe(recipe_name)).unwrap();
+        }
+        let mut app =
+            test::init_service(App::new().data(app_config(repo)).service(recipe_index)).await;
+
+        let req = test::TestRequest::get().uri("/recipes/index").to_request();
+        let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!(
+            serde_json::json!([
+                { "letter": "A", "recipes": [{ "id": 4, "name": "Apple pie" }] },
+                {
+                    "letter": "W",
+                    "recipes": [
+                        { "id": 1, "name": "Waffles" },
+                        { "id": 3, "name": "waldorf salad" },
+                    ],
+                },
+                { "letter": "#", "recipes": [{ "id": 2, "name": "7-layer dip" }] },
+            ]),
+            body
+        );
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_categories() {
+        let (repo, name) = temp_repo();
+        let recipe_id = repo.add_recipe(&recipe("Soup")).unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(repo))
+                .service(list_categories)
+                .service(add_category)
+                .service(delete_category)
+                .service(assign_category)
+                .service(unassign_category)
+                .service(recipe_tree),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/categories")
+            .set_json(&serde_json::json!({ "name": " Soups " }))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::CREATED, resp.status());
+        let soups: Category = test::read_body_json(resp).await;
+        assert_eq!("Soups", soups.name);
+
+        for (body, status) in [
+            (serde_json::json!({ "name": "Soups" }), StatusCode::CONFLICT),
+            (
+                serde_json::json!({ "name": " " }),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                serde_json::json!({ "name": "Cold", "parent_id": 99 }),
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+        ]
+        .iter()
+        {
+            let req = test::TestRequest::post()
+                .uri("/categories")
+                .set_json(body)
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(*status, resp.status(), "{}", body);
+        }
+
+        let assign = |method: actix_web::http::Method, recipe_id: u32, category_id: i32| {
+            test::TestRequest::with_uri(&format!(
+                "/recipes/{}/categories/{}",
+                recipe_id, category_id
+            ))
+            .method(method)
+            .to_request()
+        };
+        let resp = test::call_service(
+            &mut app,
+            assign(actix_web::http::Method::PUT, recipe_id, soups.id),
+        )
+        .await;
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        let resp = test::call_service(
+            &mut app,
+            assign(actix_web::http::Method::PUT, recipe_id, 99),
+        )
+        .await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        let req = test::TestRequest::get().uri("/recipes/tree").to_request();
+        let tree: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert!(tree.to_string().contains("Soups"), "{}", tree);
+
+        let resp = test::call_service(
+            &mut app,
+            assign(actix_web::http::Method::DELETE, recipe_id, soups.id),
+        )
+        .await;
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/categories/{}", soups.id))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+        let req = test::TestRequest::delete()
+            .uri(&format!("/categories/{}", soups.id))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        let req = test::TestRequest::get().uri("/categories").to_request();
+        let found: Vec<Category> = test::read_response_json(&mut app, req).await;
+        assert!(found.is_empty());
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_merge_recipes() {
+        let (repo, name) = temp_repo();
+        let keep_id = repo
+            .add_recipe(&Recipe {
+                steps: vec![Step::new("Mix")],
+                ..recipe("Pancakes")
+            })
+            .unwrap();
+        let remove_id = repo
+            .add_recipe(&Recipe {
+                steps: vec![Step::new("Mix"), Step::new("Serve")],
+                ..recipe("Hotcakes")
+            })
+            .unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(repo))
+                .service(recipes)
+                .service(merge_recipes),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/merge")
+            .set_json(&serde_json::json!({ "keep_id": keep_id, "remove_id": 99 }))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/merge")
+            .set_json(&serde_json::json!({ "keep_id": keep_id, "remove_id": keep_id }))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/merge")
+            .set_json(&serde_json::json!({
+                "keep_id": keep_id,
+                "remove_id": remove_id,
+                "mode": "union",
+            }))
+            .to_request();
+        let merged: Recipe = test::read_response_json(&mut app, req).await;
+        assert_eq!(Some(keep_id), merged.id);
+        assert_eq!("Pancakes", merged.name);
+        assert_eq!(vec![Step::new("Mix"), Step::new("Serve")], merged.steps);
+
+        let req = test::TestRequest::get().uri("/recipes/all").to_request();
+        let found: Vec<Recipe> = test::read_response_json(&mut app, req).await;
+        assert_eq!(vec![merged], found);
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_compression() {
+        let (repo, name) = temp_repo();
+        repo.add_recipe(&recipe("Toast")).unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .wrap(actix_web::middleware::Compress::default())
+                .data(app_config(repo))
+                .service(recipes),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/all")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::OK, resp.status());
+        assert_eq!(
+            Some("gzip"),
+            resp.headers()
+                .get(header::CONTENT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+        );
+
+        let req = test::TestRequest::get().uri("/recipes/all").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(None, resp.headers().get(header::CONTENT_ENCODING));
+        let found: Vec<Recipe> = test::read_body_json(resp).await;
+        assert_eq!("Toast", found[0].name);
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_yaml() {
+        let (repo, name) = temp_repo();
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(repo))
+                .service(import)
+                .service(recipes)
+                .service(get_recipe),
+        )
+        .await;
+
+        let yaml = serde_yaml::to_string(&vec![recipe("Toast"), recipe("Tea")]).unwrap();
+        let req = test::TestRequest::post()
+            .uri("/recipes/import")
+            .header(header::CONTENT_TYPE, "application/yaml")
+            .set_payload(yaml)
+            .to_request();
+        let imported: Vec<Recipe> = test::read_response_json(&mut app, req).await;
+        assert_eq!(2, imported.len());
+
+        let req = test::TestRequest::post()
+            .uri("/recipes/import")
+            .header(header::CONTENT_TYPE, "text/yaml")
+            .set_payload("- name: [unclosed")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/all")
+            .header(header::ACCEPT, "application/yaml")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(
+            "application/yaml",
+            resp.headers().get(header::CONTENT_TYPE).unwrap()
+        );
+        let body = test::read_body(resp).await;
+        let found: Vec<Recipe> = serde_yaml::from_slice(&body).unwrap();
+        assert_eq!(imported, found);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/recipes/{}", imported[1].id.unwrap()))
+            .header(header::ACCEPT, "text/yaml")
+            .to_request();
+        let body = test::read_response(&mut app, req).await;
+        let tea: Recipe = serde_yaml::from_slice(&body).unwrap();
+        assert_eq!(imported[1], tea);
+
+        // Without a YAML Accept header the response stays JSON.
+        let req = test::TestRequest::get()
+            .uri(&format!("/recipes/{}", imported[1].id.unwrap()))
+            .to_request();
+        let tea: Recipe = test::read_response_json(&mut app, req).await;
+        assert_eq!(imported[1], tea);
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[test]
+    fn test_repo_failure() {
+        let status = |e: db::RepoError| repo_failure(&BlockingError::Error(e)).status();
+        assert_eq!(StatusCode::NOT_FOUND, status(db::RepoError::NotFound));
+        assert_eq!(StatusCode::CONFLICT, status(db::RepoError::Conflict));
+        assert_eq!(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            status(anyhow!("disk I/O error").into())
+        );
+        assert_eq!(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            repo_failure(&BlockingError::Canceled).status()
+        );
+    }
+
+    #[test]
+    fn test_step_accepts_bare_string() {
+        let steps: Vec<Step> = serde_json::from_str(
+            r#"["Preheat the oven", {"text": "Bake", "duration_minutes": 25, "note": "Until golden"}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                Step::new("Preheat the oven"),
+                Step {
+                    text: "Bake".to_string(),
+                    duration_minutes: Some(25),
+                    note: Some("Until golden".to_string()),
+                    image_url: None,
+                },
+            ],
+            steps
+        );
+
+        assert_eq!(
+            serde_json::json!({ "text": "Preheat the oven", "duration_minutes": null, "note": null }),
+            serde_json::to_value(&steps[0]).unwrap()
+        );
+        assert!(serde_json::from_str::<Step>("{\"duration_minutes\": 5}").is_err());
+    }
+
+    #[actix_rt::test]
+    async fn test_rename_ingredient() {
+        let (repo, name) = temp_repo();
+        repo.add_recipe(&Recipe {
+            ingredients: vec![IngredientQuantity {
+                ingredient: "Kosher Salt".to_string(),
+                quantity: Quantity {
+                    value: 1.0,
+                    value_max: None,
+                    unit: "tsp".to_string(),
+                },
+                calories_per_unit: None,
+            }],
+            ..recipe("Brine")
+        })
+        .unwrap();
+        let mut app =
+            test::init_service(App::new().data(app_config(repo)).service(rename_ingredient)).await;
+
+        let rename = |from: &str, to: &str| {
+            test::TestRequest::put()
+                .uri("/ingredients/rename")
+                .set_json(&IngredientRename {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                })
+                .to_request()
+        };
+        let resp = test::call_service(&mut app, rename("Kosher Salt", "  ")).await;
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status());
+        let too_long = "a".repeat(FieldLimits::default().ingredient + 1);
+        let resp = test::call_service(&mut app, rename("Kosher Salt", &too_long)).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, resp.status());
+        let problems: Vec<FieldError> = test::read_body_json(resp).await;
+        assert_eq!("to", problems[0].field);
+        let resp = test::call_service(&mut app, rename("Sea Salt", "Salt")).await;
+        assert_eq!(StatusCode::NOT_FOUND, resp.status());
+        let resp = test::call_service(&mut app, rename("Kosher Salt", "Salt, kosher")).await;
+        assert_eq!(StatusCode::NO_CONTENT, resp.status());
+
+        std::fs::remove_file(&name).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn test_fraction_format() {
+        let (repo, name) = temp_repo();
+        let recipe_id = repo
+            .add_recipe(&Recipe {
+                ingredients: vec![IngredientQuantity {
+                    ingredient: "Flour".to_string(),
+                    quantity: Quantity {
+                        value: 2.75,
+                        value_max: None,
+                        unit: "cup".to_string(),
+                    },
+                    calories_per_unit: None,
+                }],
+                ..recipe("Bread")
+            })
+            .unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .data(app_config(repo))
+                .service(recipes)
+                .service(get_recipe),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/recipes/{}?format=fraction", recipe_id))
+            .to_request();
+        let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!("2 3/4", body["ingredients"][0]["quantity"]["value"]);
+
+        let req = test::TestRequest::get()
+            .uri("/recipes/all?format=fraction")
+            .to_request();
+        let body: serde_json::Value = test::read_response_json(&mut app, req).await;
+        assert_eq!("2 3/4", body[0]["ingredients"][0]["quantity"]["value"]);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/recipes/{}", recipe_id))
+            .to_request();
+        let stored: Recipe = test::read_response_json(&mut app, req).await;
+        assert_eq!(2.75, stored.ingredients[0].quantity.value);
+
+        std::fs::remove_file(&name).unwrap();
+    }
+}
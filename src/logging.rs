@@ -0,0 +1,104 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::Record;
+use serde::Deserialize;
+use std::io::Write;
+
+/// How log lines are written to stderr.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `env_logger`'s human-readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line with `timestamp`, `level`, `target` and
+    /// `message`, for log aggregators.
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(format: &str) -> Option<LogFormat> {
+        match format.trim().to_lowercase().as_str() {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Install the global logger. `RUST_LOG` controls verbosity as usual, with
+/// `default_filter` applying when it is unset.
+pub fn init(format: LogFormat, default_filter: &str) {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(default_filter));
+    if format == LogFormat::Json {
+        builder.format(|buf, record| writeln!(buf, "{}", json_line(Utc::now(), record)));
+    }
+    builder.init();
+}
+
+/// A record as a single line of JSON, without the trailing newline.
+pub fn json_line(timestamp: DateTime<Utc>, record: &Record) -> String {
+    serde_json::json!({
+        "timestamp": timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Some(LogFormat::Json), LogFormat::parse(" JSON "));
+        assert_eq!(Some(LogFormat::Text), LogFormat::parse("text"));
+        assert_eq!(None, LogFormat::parse("xml"));
+    }
+
+    #[test]
+    fn test_json_line() {
+        let timestamp = Utc.with_ymd_and_hms(2021, 5, 1, 12, 30, 0).unwrap()
+            + chrono::Duration::milliseconds(250);
+        let line = json_line(
+            timestamp,
+            &Record::builder()
+                .level(log::Level::Warn)
+                .target("recipe_book_backend")
+                .args(format_args!("Unknown unit \"{}\"", "cupz"))
+                .build(),
+        );
+
+        assert!(!line.contains('\n'));
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "timestamp": "2021-05-01T12:30:00.250Z",
+                "level": "WARN",
+                "target": "recipe_book_backend",
+                "message": "Unknown unit \"cupz\"",
+            }),
+            value
+        );
+    }
+}
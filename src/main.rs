@@ -15,28 +15,301 @@
 /// You should have received a copy of the GNU Affero General Public License
 /// along with this program.  If not, see <https://www.gnu.org/licenses/>.
 ///
-use actix_web::{App, HttpServer};
-use log::info;
+use actix_web::middleware::{Compress, Condition, Logger};
+use actix_web::{web, App, HttpServer};
+use log::{error, info, warn};
+use recipe_book_backend::auth::ApiKey;
+use recipe_book_backend::cache::RecipeCache;
+use recipe_book_backend::config::ServerConfig;
+use recipe_book_backend::cors::Cors;
 use recipe_book_backend::db::{self, Repo};
+use recipe_book_backend::integrity::{self, SharedIntegrity};
+use recipe_book_backend::logging;
+use recipe_book_backend::metrics::{Metrics, RecordMetrics};
+use recipe_book_backend::normalize::WhitespaceOptions;
+use recipe_book_backend::rate_limit::RateLimiter;
+use recipe_book_backend::transfer;
 use recipe_book_backend::AppConfig;
+use std::sync::Arc;
+
+/// Remote address, request line, status, response size and latency.
+const ACCESS_LOG_FORMAT: &str = "%a \"%r\" %s %b %Dms";
+
+/// Seconds to let in-flight requests finish after a shutdown signal.
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+
+fn shutdown_timeout_from_env() -> u64 {
+    match std::env::var("RECIPE_BOOK_SHUTDOWN_TIMEOUT_SECS") {
+        Ok(secs) => secs.trim().parse().unwrap_or_else(|_| {
+            warn!(
+                "Invalid RECIPE_BOOK_SHUTDOWN_TIMEOUT_SECS {:?}, using {}",
+                secs, DEFAULT_SHUTDOWN_TIMEOUT_SECS
+            );
+            DEFAULT_SHUTDOWN_TIMEOUT_SECS
+        }),
+        Err(_) => DEFAULT_SHUTDOWN_TIMEOUT_SECS,
+    }
+}
+
+/// Resolve once the process receives SIGINT or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use actix_rt::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Unable to listen for SIGTERM");
+        futures::future::select(
+            Box::pin(actix_rt::signal::ctrl_c()),
+            Box::pin(terminate.recv()),
+        )
+        .await;
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = actix_rt::signal::ctrl_c().await;
+    }
+}
+
+/// `migrate-to <db_path>`: copy everything from the configured database
+/// into an empty one at `target_path`, then exit.
+fn migrate_to(config: &ServerConfig, target_path: &str) -> std::io::Result<()> {
+    let source_config = db::RepoConfig {
+        name: config.db_path.clone(),
+        pool_size: config.pool_size,
+    };
+    let source = db::create_repo_with_config(db::Backend::Sqlite, &source_config)
+        .map_err(|e| std::io::Error::other(format!("{:#}", e)))?;
+    let target = db::create_repo_with_name(db::Backend::Sqlite, target_path)
+        .map_err(|e| std::io::Error::other(format!("{:#}", e)))?;
+
+    match transfer::copy_recipes(source.as_ref(), target.as_ref()) {
+        Ok(report) => {
+            info!(
+                "Copied {} recipes and {} trashed ones ({} steps, {} ingredients, {} photos), {} categories, {} ratings and {} provenance links from {} to {}",
+                report.recipes,
+                report.trashed,
+                report.steps,
+                report.ingredients,
+                report.photos,
+                report.categories,
+                report.ratings,
+                report.provenance,
+                config.db_path,
+                target_path
+            );
+            Ok(())
+        }
+        Err(db::RepoError::Conflict) => Err(std::io::Error::other(format!(
+            "{} already holds recipes or categories; migrate into an empty database",
+            target_path
+        ))),
+        Err(e) => Err(std::io::Error::other(format!(
+            "Unable to migrate to {}: {}",
+            target_path, e
+        ))),
+    }
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init();
+    let config = ServerConfig::load()
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{:#}", e)))?;
+
+    // RUST_LOG controls verbosity as usual; the configured level (info by
+    // default) applies when it is unset, so the access log is visible. Set
+    // `actix_web::middleware::logger=warn` to silence it.
+    logging::init(config.log_format, &config.log_level);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.as_slice() {
+        [command, target_path] if command == "migrate-to" => {
+            return migrate_to(&config, target_path);
+        }
+        [command, ..] if command == "migrate-to" => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Usage: recipe-book-backend migrate-to <db_path>",
+            ));
+        }
+        _ => {}
+    }
+
     info!("Starting up...");
 
-    HttpServer::new(move || {
-        let repo: Box<dyn Repo> = db::create_repo(db::Backend::Sqlite);
+    let whitespace = WhitespaceOptions::from_env();
+    let integrity = SharedIntegrity::default();
 
-        App::new()
-            .data(AppConfig { repo })
-            .service(recipe_book_backend::hello)
+    if let Some(interval) = integrity::interval_from_env() {
+        integrity::spawn_periodic_check(
+            db::Backend::Sqlite,
+            config.db_path.clone(),
+            interval,
+            integrity.clone(),
+        );
+    }
+
+    // Shared by every worker so limits apply per client, not per worker.
+    let rate_limiter = RateLimiter::from_env().map(web::Data::new);
+
+    let api_key = ApiKey::from_env().map(web::Data::new);
+    if api_key.is_none() {
+        warn!("RECIPE_BOOK_API_KEY is not set; write requests need no authentication and admin endpoints are disabled");
+    }
+
+    let repo_config = db::RepoConfig {
+        name: config.db_path.clone(),
+        pool_size: config.pool_size,
+    };
+    let cors_origins = config.cors_origins.clone();
+    let metrics_enabled = config.metrics_enabled;
+    let strict_units = config.strict_units;
+    let max_body_bytes = config.max_body_bytes;
+    let max_import_bytes = config.max_import_bytes;
+    let backup_dir = std::path::PathBuf::from(&config.backup_dir);
+    let field_limits = config.field_limits();
+    // Shared by every worker so a write through one clears it for all.
+    let recipe_cache = Some(config.recipe_cache_max_bytes)
+        .filter(|max_bytes| *max_bytes > 0)
+        .map(|max_bytes| Arc::new(RecipeCache::new(max_bytes)));
+    // Shared by every worker so the counters cover the whole process.
+    let metrics = web::Data::new(Metrics::default());
+
+    // Open the database once before starting any workers so a bad path or a
+    // failed migration stops startup with a readable error.
+    if let Err(e) = db::create_repo_with_config(db::Backend::Sqlite, &repo_config) {
+        error!("Unable to open database {}: {:#}", repo_config.name, e);
+        return Err(std::io::Error::other(format!("{:#}", e)));
+    }
+
+    let workers = config.workers.unwrap_or_else(|| {
+        std::thread::available_parallelism()
+            .map(usize::from)
+            .unwrap_or(1)
+    });
+    info!(
+        "Starting {} workers with up to {} database connections each",
+        workers, repo_config.pool_size
+    );
+
+    let server = HttpServer::new(move || {
+        let repo: Box<dyn Repo> = db::create_repo_with_config(db::Backend::Sqlite, &repo_config)
+            .expect("database opened at startup");
+
+        let app_config = web::Data::new(AppConfig {
+            repo,
+            whitespace,
+            integrity: integrity.clone(),
+            strict_units,
+            recipe_cache: recipe_cache.clone(),
+            max_body_bytes,
+            max_import_bytes,
+            backup_dir: backup_dir.clone(),
+            field_limits,
+        });
+        let worker_config = Arc::downgrade(&app_config.clone().into_inner());
+        metrics.add_pool(move || {
+            worker_config
+                .upgrade()
+                .map(|config| config.repo.active_connections())
+        });
+
+        let mut app = App::new()
+            // Encoding follows the client's Accept-Encoding; clients that
+            // send none get the plain body.
+            .wrap(Compress::default())
+            .wrap(Condition::new(
+                !cors_origins.is_empty(),
+                Cors::new(cors_origins.clone()),
+            ))
+            .wrap(Logger::new(ACCESS_LOG_FORMAT))
+            .wrap(Condition::new(
+                metrics_enabled,
+                RecordMetrics::new(metrics.clone().into_inner()),
+            ))
+            .app_data(app_config)
+            .app_data(recipe_book_backend::json_config(max_body_bytes));
+
+        if let Some(limiter) = rate_limiter.clone() {
+            app = app.app_data(limiter);
+        }
+        if let Some(api_key) = api_key.clone() {
+            app = app.app_data(api_key);
+        }
+        if metrics_enabled {
+            app = app
+                .app_data(metrics.clone())
+                .service(recipe_book_backend::prometheus);
+        }
+
+        app.service(recipe_book_backend::hello)
+            .service(recipe_book_backend::health)
+            .service(recipe_book_backend::health_detailed)
+            .service(recipe_book_backend::backup)
+            .service(recipe_book_backend::build_version)
             .service(recipe_book_backend::add)
+            .service(recipe_book_backend::import)
             .service(recipe_book_backend::recipes)
+            .service(recipe_book_backend::recipe_page)
+            .service(recipe_book_backend::favorites)
+            .service(recipe_book_backend::search)
+            .service(recipe_book_backend::full_text_search)
+            .service(recipe_book_backend::browse)
+            .service(recipe_book_backend::random)
+            .service(recipe_book_backend::facets)
+            .service(recipe_book_backend::ingredient_usage)
+            .service(recipe_book_backend::list_ingredients)
+            .service(recipe_book_backend::rename_ingredient)
+            .service(recipe_book_backend::stats)
+            .service(recipe_book_backend::favorite)
+            .service(recipe_book_backend::unfavorite)
+            .service(recipe_book_backend::recipe_tree)
+            .service(recipe_book_backend::recipe_index)
+            .service(recipe_book_backend::list_categories)
+            .service(recipe_book_backend::add_category)
+            .service(recipe_book_backend::delete_category)
+            .service(recipe_book_backend::assign_category)
+            .service(recipe_book_backend::unassign_category)
+            .service(recipe_book_backend::with_ingredient)
             .service(recipe_book_backend::edit)
+            .service(recipe_book_backend::upsert)
+            .service(recipe_book_backend::reorder_steps)
+            .service(recipe_book_backend::import_ingredients_csv)
             .service(recipe_book_backend::delete)
+            .service(recipe_book_backend::trash)
+            .service(recipe_book_backend::restore)
+            .service(recipe_book_backend::duplicate)
+            .service(recipe_book_backend::merge_recipes)
+            .service(recipe_book_backend::patch_recipe)
+            .service(recipe_book_backend::get_recipe)
+            .service(recipe_book_backend::recipe_provenance)
+            .service(recipe_book_backend::rate_recipe)
+            .service(recipe_book_backend::recipe_ratings)
+            .service(recipe_book_backend::convert)
+            .service(recipe_book_backend::recipe_nutrition)
+            .service(recipe_book_backend::cook_mode)
+            .service(recipe_book_backend::recipe_markdown)
+            .service(recipe_book_backend::recipe_print)
+            .service(recipe_book_backend::shopping_list)
+            .service(recipe_book_backend::export_markdown_zip)
+            .service(recipe_book_backend::export_ndjson)
+            .service(recipe_book_backend::import_ndjson)
+            .default_service(web::route().to(recipe_book_backend::not_found))
     })
-    .bind("127.0.0.1:8080")?
-    .run()
-    .await
+    .workers(workers)
+    .shutdown_timeout(shutdown_timeout_from_env())
+    .disable_signals()
+    .bind(&config.bind_address)?
+    .run();
+
+    let handle = server.clone();
+    actix_rt::spawn(async move {
+        shutdown_signal().await;
+        info!("Shutting down, draining active connections...");
+        // Workers drop their app data, and with it each r2d2 pool, as they stop.
+        handle.stop(true).await;
+    });
+
+    server.await?;
+    info!("Shutdown complete");
+    Ok(())
 }
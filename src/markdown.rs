@@ -0,0 +1,206 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::Recipe;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// Render a recipe as a standalone Markdown document.
+pub fn render_markdown(recipe: &Recipe) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(out, "# {}", recipe.name);
+
+    if let Some(desc) = &recipe.desc {
+        let _ = write!(out, "\n{}\n", desc);
+    }
+
+    if !recipe.ingredients.is_empty() {
+        out.push_str("\n## Ingredients\n\n");
+        for ing_quant in recipe.ingredients.iter() {
+            let quantity = &ing_quant.quantity;
+            let _ = writeln!(
+                out,
+                "- {} {} {}",
+                quantity.amount(),
+                quantity.unit,
+                ing_quant.ingredient
+            );
+        }
+    }
+
+    if !recipe.steps.is_empty() {
+        out.push_str("\n## Steps\n\n");
+        for (i, step) in recipe.steps.iter().enumerate() {
+            let _ = write!(out, "{}. {}", i + 1, step.text);
+            if let Some(minutes) = step.duration_minutes {
+                let _ = write!(out, " ({} min)", minutes);
+            }
+            out.push('\n');
+            if let Some(note) = &step.note {
+                let _ = writeln!(out, "   _{}_", note);
+            }
+        }
+    }
+
+    out
+}
+
+/// Turn a recipe name into a lowercase, dash-separated string safe for filenames.
+pub fn slugify(name: &str) -> String {
+    let slug = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.to_lowercase())
+        .collect::<Vec<String>>()
+        .join("-");
+
+    if slug.is_empty() {
+        "recipe".to_string()
+    } else {
+        slug
+    }
+}
+
+/// Assign each recipe a unique `<slug>.md` filename, falling back to
+/// `<slug>-<id>.md` when two recipes slugify to the same name, and then to
+/// `<slug>-<id>-2.md` and so on if another recipe's slug already took that.
+pub fn markdown_filenames(recipes: &[Recipe]) -> Vec<String> {
+    let mut taken = HashSet::new();
+    taken.insert("index".to_string());
+
+    recipes
+        .iter()
+        .map(|recipe| {
+            let slug = slugify(&recipe.name);
+            let fallback = format!("{}-{}", slug, recipe.id.unwrap_or_default());
+            let mut stem = slug;
+            let mut attempt = 1;
+            while taken.contains(&stem) {
+                stem = match attempt {
+                    1 => fallback.clone(),
+                    n => format!("{}-{}", fallback, n),
+                };
+                attempt += 1;
+            }
+            taken.insert(stem.clone());
+            format!("{}.md", stem)
+        })
+        .collect()
+}
+
+/// Render the `index.md` table of contents for an exported set of recipes.
+pub fn render_index(recipes: &[Recipe], filenames: &[String], missing: &[i32]) -> String {
+    let mut out = String::from("# Recipes\n\n");
+
+    for (recipe, filename) in recipes.iter().zip(filenames.iter()) {
+        let _ = writeln!(out, "- [{}]({})", recipe.name, filename);
+    }
+
+    if !missing.is_empty() {
+        let ids: Vec<String> = missing.iter().map(|id| id.to_string()).collect();
+        let _ = write!(
+            out,
+            "\n## Missing\n\nThe following recipe ids were not found: {}\n",
+            ids.join(", ")
+        );
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IngredientQuantity, Quantity, Step};
+
+    fn recipe(id: u32, name: &str) -> Recipe {
+        Recipe {
+            id: Some(id),
+            name: name.to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        }
+    }
+
+    #[test]
+    fn test_render_markdown() {
+        let recipe = Recipe {
+            desc: Some("Test Description".to_string()),
+            steps: vec![Step::new("Peel"), Step::new("Boil")],
+            ingredients: vec![IngredientQuantity {
+                ingredient: "Potato".to_string(),
+                quantity: Quantity {
+                    value: 1.5,
+                    value_max: None,
+                    unit: "whole".to_string(),
+                },
+                calories_per_unit: None,
+            }],
+            ..recipe(1, "Test Recipe")
+        };
+
+        assert_eq!(
+            "# Test Recipe\n\nTest Description\n\n## Ingredients\n\n- 1.5 whole Potato\n\n## Steps\n\n1. Peel\n2. Boil\n",
+            render_markdown(&recipe)
+        );
+    }
+
+    #[test]
+    fn test_render_markdown_without_desc() {
+        assert_eq!("# Toast\n", render_markdown(&recipe(1, "Toast")));
+    }
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!("grandma-s-apple-pie", slugify("Grandma's  Apple Pie!"));
+        assert_eq!("recipe", slugify("???"));
+    }
+
+    #[test]
+    fn test_markdown_filenames_are_unique() {
+        let recipes = vec![recipe(1, "Pie"), recipe(2, "pie"), recipe(3, "Index")];
+        assert_eq!(
+            vec!["pie.md", "pie-2.md", "index-3.md"],
+            markdown_filenames(&recipes)
+        );
+
+        // A fallback another slug already took, and a repeated recipe.
+        let recipes = vec![
+            recipe(7, "Pie 2"),
+            recipe(1, "Pie"),
+            recipe(2, "Pie"),
+            recipe(2, "Pie"),
+        ];
+        assert_eq!(
+            vec!["pie-2.md", "pie.md", "pie-2-2.md", "pie-2-3.md"],
+            markdown_filenames(&recipes)
+        );
+    }
+}
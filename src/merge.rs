@@ -0,0 +1,125 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::db::normalize_ingredient_name;
+use crate::Recipe;
+use serde::Deserialize;
+
+/// What merging a recipe into another keeps from the one removed.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MergeMode {
+    /// Nothing; the kept recipe is left as it is.
+    #[default]
+    Keep,
+    /// Its ingredients, steps, photos and categories that the kept recipe
+    /// does not already have.
+    Union,
+}
+
+/// Append the parts of `removed` that `kept` lacks: ingredients by
+/// normalized name, steps by text and photos by URL. Quantities of an
+/// ingredient both recipes use are left as `kept` has them.
+pub fn union_into(kept: &mut Recipe, removed: &Recipe) {
+    for ing_quant in removed.ingredients.iter() {
+        let name = normalize_ingredient_name(&ing_quant.ingredient);
+        if !kept
+            .ingredients
+            .iter()
+            .any(|existing| normalize_ingredient_name(&existing.ingredient) == name)
+        {
+            kept.ingredients.push(ing_quant.clone());
+        }
+    }
+
+    for step in removed.steps.iter() {
+        if !kept.steps.iter().any(|existing| existing.text == step.text) {
+            kept.steps.push(step.clone());
+        }
+    }
+
+    for photo in removed.photos.iter() {
+        if !kept.photos.contains(photo) {
+            kept.photos.push(photo.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IngredientQuantity, Quantity, Step};
+
+    fn ingredient(name: &str, value: f64) -> IngredientQuantity {
+        IngredientQuantity {
+            ingredient: name.to_string(),
+            quantity: Quantity {
+                value,
+                value_max: None,
+                unit: "cup".to_string(),
+            },
+            calories_per_unit: None,
+        }
+    }
+
+    fn recipe(ingredients: Vec<IngredientQuantity>, steps: Vec<Step>, photos: Vec<&str>) -> Recipe {
+        Recipe {
+            id: None,
+            name: "Pancakes".to_string(),
+            desc: None,
+            steps,
+            ingredients,
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: photos.into_iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_union_into() {
+        let mut kept = recipe(
+            vec![ingredient("Flour", 2.0)],
+            vec![Step::new("Mix")],
+            vec!["https://example.com/a.jpg"],
+        );
+        let removed = recipe(
+            vec![ingredient(" flour ", 3.0), ingredient("Milk", 1.0)],
+            vec![Step::new("Mix"), Step::new("Fry")],
+            vec!["https://example.com/a.jpg", "https://example.com/b.jpg"],
+        );
+
+        union_into(&mut kept, &removed);
+
+        assert_eq!(
+            vec![ingredient("Flour", 2.0), ingredient("Milk", 1.0)],
+            kept.ingredients
+        );
+        assert_eq!(vec![Step::new("Mix"), Step::new("Fry")], kept.steps);
+        assert_eq!(
+            vec!["https://example.com/a.jpg", "https://example.com/b.jpg"],
+            kept.photos
+        );
+    }
+}
@@ -0,0 +1,310 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::Error;
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+/// Upper bounds, in seconds, of the request latency histogram buckets.
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Route label for requests that matched no route, so unknown paths cannot
+/// create unbounded label values.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+type PoolGauge = Box<dyn Fn() -> Option<u32> + Send>;
+
+#[derive(Default)]
+struct Histogram {
+    /// Non-cumulative count per bucket in `LATENCY_BUCKETS`.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; LATENCY_BUCKETS.len()];
+        }
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|le| seconds <= *le) {
+            self.buckets[i] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+/// Process-wide request metrics, shared by every worker and rendered in the
+/// Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    /// Keyed by method, route template and status code.
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    /// Keyed by method and route template.
+    latencies: Mutex<BTreeMap<(String, String), Histogram>>,
+    /// Active connection counts of each worker's pool. A source returning
+    /// `None` belongs to a worker that has shut down.
+    pools: Mutex<Vec<PoolGauge>>,
+}
+
+impl Metrics {
+    pub fn record(&self, method: &str, route: &str, status: u16, seconds: f64) {
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry((method.to_string(), route.to_string(), status))
+            .or_insert(0) += 1;
+        self.latencies
+            .lock()
+            .unwrap()
+            .entry((method.to_string(), route.to_string()))
+            .or_default()
+            .observe(seconds);
+    }
+
+    /// Add a source of active pool connections to the gauge.
+    pub fn add_pool<F>(&self, active_connections: F)
+    where
+        F: Fn() -> Option<u32> + Send + 'static,
+    {
+        self.pools
+            .lock()
+            .unwrap()
+            .push(Box::new(active_connections));
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str("# HELP http_requests_total Requests handled, by method, route and status.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((method, route, status), count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                escape_label(method),
+                escape_label(route),
+                status,
+                count
+            );
+        }
+
+        out.push_str(
+            "# HELP http_request_duration_seconds Request latency, by method and route.\n",
+        );
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for ((method, route), histogram) in self.latencies.lock().unwrap().iter() {
+            let labels = format!(
+                "method=\"{}\",route=\"{}\"",
+                escape_label(method),
+                escape_label(route)
+            );
+            let mut cumulative = 0;
+            for (le, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, le, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_sum{{{}}} {}",
+                labels, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "http_request_duration_seconds_count{{{}}} {}",
+                labels, histogram.count
+            );
+        }
+
+        let active: u32 = self
+            .pools
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|active_connections| active_connections())
+            .sum();
+        out.push_str(
+            "# HELP db_pool_active_connections Database connections currently checked out.\n",
+        );
+        out.push_str("# TYPE db_pool_active_connections gauge\n");
+        let _ = writeln!(out, "db_pool_active_connections {}", active);
+
+        out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Middleware counting requests and timing them into `Metrics`, labelled by
+/// the matched route template rather than the raw path.
+pub struct RecordMetrics {
+    metrics: Arc<Metrics>,
+}
+
+impl RecordMetrics {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        RecordMetrics { metrics }
+    }
+}
+
+impl<S, B> Transform<S> for RecordMetrics
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RecordMetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RecordMetricsMiddleware {
+            service,
+            metrics: self.metrics.clone(),
+        })
+    }
+}
+
+pub struct RecordMetricsMiddleware<S> {
+    service: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S, B> Service for RecordMetricsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let method = req.method().to_string();
+        let metrics = self.metrics.clone();
+
+        self.service
+            .call(req)
+            .map(move |res| {
+                let seconds = started.elapsed().as_secs_f64();
+                match &res {
+                    Ok(resp) => {
+                        // The route is only known once the router has run.
+                        let route = resp
+                            .request()
+                            .match_pattern()
+                            .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+                        metrics.record(&method, &route, resp.status().as_u16(), seconds);
+                    }
+                    Err(e) => {
+                        let status = e.as_response_error().status_code().as_u16();
+                        metrics.record(&method, UNMATCHED_ROUTE, status, seconds);
+                    }
+                }
+                res
+            })
+            .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.record("GET", "/recipes/{id}", 200, 0.02);
+        metrics.record("GET", "/recipes/{id}", 200, 3.0);
+        metrics.add_pool(|| Some(2));
+        metrics.add_pool(|| None);
+
+        let text = metrics.render();
+        assert!(text.contains(
+            "http_requests_total{method=\"GET\",route=\"/recipes/{id}\",status=\"200\"} 2\n"
+        ));
+        assert!(text.contains(
+            "http_request_duration_seconds_bucket{method=\"GET\",route=\"/recipes/{id}\",le=\"0.025\"} 1\n"
+        ));
+        assert!(text.contains(
+            "http_request_duration_seconds_bucket{method=\"GET\",route=\"/recipes/{id}\",le=\"5\"} 2\n"
+        ));
+        assert!(text.contains(
+            "http_request_duration_seconds_count{method=\"GET\",route=\"/recipes/{id}\"} 2\n"
+        ));
+        assert!(text.contains("db_pool_active_connections 2\n"));
+    }
+
+    #[actix_rt::test]
+    async fn test_route_template_label() {
+        let metrics = Arc::new(Metrics::default());
+        let mut app =
+            test::init_service(App::new().wrap(RecordMetrics::new(metrics.clone())).route(
+                "/recipes/{id}",
+                web::get().to(|| HttpResponse::Ok().finish()),
+            ))
+            .await;
+
+        for uri in ["/recipes/1", "/recipes/2", "/nowhere"].iter() {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            test::call_service(&mut app, req).await;
+        }
+
+        let text = metrics.render();
+        assert!(text.contains(
+            "http_requests_total{method=\"GET\",route=\"/recipes/{id}\",status=\"200\"} 2\n"
+        ));
+        assert!(text.contains(
+            "http_requests_total{method=\"GET\",route=\"unmatched\",status=\"404\"} 1\n"
+        ));
+        assert!(!text.contains("/recipes/1"));
+    }
+}
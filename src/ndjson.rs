@@ -0,0 +1,116 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use serde::Serialize;
+
+/// Media type of newline-delimited JSON.
+pub const CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Recipes loaded per query while streaming an export, and stored per
+/// transaction while importing one.
+pub const BATCH_SIZE: u32 = 100;
+
+/// Append `value` to `out` as one line of JSON, newline included.
+pub fn write_line<T: Serialize>(out: &mut Vec<u8>, value: &T) -> serde_json::Result<()> {
+    serde_json::to_writer(&mut *out, value)?;
+    out.push(b'\n');
+    Ok(())
+}
+
+/// Splits a body into lines as its chunks arrive, so only the line being
+/// read has to be held in memory. `\r\n` endings are accepted.
+#[derive(Default)]
+pub struct LineSplitter {
+    partial: Vec<u8>,
+}
+
+impl LineSplitter {
+    /// Add the next chunk of the body, returning the lines it completes.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        let mut lines = Vec::new();
+        let mut rest = chunk;
+        while let Some(end) = rest.iter().position(|&b| b == b'\n') {
+            self.partial.extend_from_slice(&rest[..end]);
+            lines.push(trim_cr(std::mem::take(&mut self.partial)));
+            rest = &rest[end + 1..];
+        }
+        self.partial.extend_from_slice(rest);
+        lines
+    }
+
+    /// Bytes held for the line not yet ended by a newline.
+    pub fn pending_len(&self) -> usize {
+        self.partial.len()
+    }
+
+    /// The last line, if the body did not end with a newline.
+    pub fn finish(self) -> Option<Vec<u8>> {
+        if self.partial.is_empty() {
+            None
+        } else {
+            Some(trim_cr(self.partial))
+        }
+    }
+}
+
+fn trim_cr(mut line: Vec<u8>) -> Vec<u8> {
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    line
+}
+
+/// Whether a line holds nothing but whitespace. Such lines are skipped.
+pub fn is_blank(line: &[u8]) -> bool {
+    line.iter().all(u8::is_ascii_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_line() {
+        let mut out = Vec::new();
+        write_line(&mut out, &vec![1, 2]).unwrap();
+        write_line(&mut out, &"a\nb").unwrap();
+        assert_eq!(b"[1,2]\n\"a\\nb\"\n".to_vec(), out);
+    }
+
+    #[test]
+    fn test_line_splitter() {
+        let mut splitter = LineSplitter::default();
+        assert!(splitter.push(b"{\"a\"").is_empty());
+        assert_eq!(
+            vec![b"{\"a\":1}".to_vec(), b"".to_vec()],
+            splitter.push(b":1}\r\n\n{\"b\"")
+        );
+        assert_eq!(vec![b"{\"b\":2}".to_vec()], splitter.push(b":2}\n"));
+        assert_eq!(None, splitter.finish());
+
+        let mut splitter = LineSplitter::default();
+        assert!(splitter.push(b"[1]").is_empty());
+        assert_eq!(Some(b"[1]".to_vec()), splitter.finish());
+    }
+
+    #[test]
+    fn test_is_blank() {
+        assert!(is_blank(b""));
+        assert!(is_blank(b" \t"));
+        assert!(!is_blank(b" {}"));
+    }
+}
@@ -0,0 +1,189 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Media types treated as YAML, in both `Accept` and `Content-Type`.
+const YAML_TYPES: [&str; 2] = ["application/yaml", "text/yaml"];
+
+fn is_yaml_type(media_type: &str) -> bool {
+    let essence = media_type.split(';').next().unwrap_or("").trim();
+    YAML_TYPES
+        .iter()
+        .any(|yaml| essence.eq_ignore_ascii_case(yaml))
+}
+
+/// Whether the request's `Accept` header names a YAML media type. Anything
+/// else, including no header at all, means JSON.
+pub fn wants_yaml(req: &HttpRequest) -> bool {
+    req.headers()
+        .get_all(header::ACCEPT)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(is_yaml_type)
+}
+
+/// The media type `respond` will answer the request with.
+pub fn media_type(req: &HttpRequest) -> &'static str {
+    if wants_yaml(req) {
+        YAML_TYPES[0]
+    } else {
+        "application/json"
+    }
+}
+
+/// Whether the request body is declared as YAML by its `Content-Type`.
+pub fn has_yaml_body(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(is_yaml_type)
+}
+
+/// Finish `builder` with `body` serialized as YAML if the client asked for
+/// it, otherwise as JSON. `Vary: Accept` tells caches the two differ.
+pub fn respond<T: Serialize>(
+    req: &HttpRequest,
+    mut builder: HttpResponseBuilder,
+    body: &T,
+) -> HttpResponse {
+    builder.header(header::VARY, "Accept");
+    if !wants_yaml(req) {
+        return builder.json(body);
+    }
+    match serde_yaml::to_string(body) {
+        Ok(yaml) => builder.content_type("application/yaml").body(yaml),
+        Err(e) => HttpResponse::from_error(actix_web::error::ErrorInternalServerError(e)),
+    }
+}
+
+/// The request's `Content-Type` without parameters, lowercased, e.g.
+/// `application/json`. Empty when the header is missing or unreadable.
+fn body_type(req: &HttpRequest) -> String {
+    req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .unwrap_or("")
+        .trim()
+        .to_lowercase()
+}
+
+/// Whether the request body is declared as JSON, including `+json` types
+/// such as `application/merge-patch+json`, or YAML. As with `web::Json`, a
+/// body without a `Content-Type` fails: every endpoint taking a body
+/// requires its type to be declared.
+pub fn has_json_or_yaml_body(req: &HttpRequest) -> bool {
+    let essence = body_type(req);
+    essence == "application/json" || essence.ends_with("+json") || is_yaml_type(&essence)
+}
+
+/// Whether the request body is declared as `media_type`, ignoring
+/// parameters and case. A body without a `Content-Type` fails.
+pub fn has_body_type(req: &HttpRequest, media_type: &str) -> bool {
+    body_type(req).eq_ignore_ascii_case(media_type)
+}
+
+/// Parse a request body as YAML or JSON according to its `Content-Type`.
+pub fn parse_body<T: DeserializeOwned>(req: &HttpRequest, body: &[u8]) -> Result<T, String> {
+    if has_yaml_body(req) {
+        serde_yaml::from_slice(body).map_err(|e| e.to_string())
+    } else {
+        serde_json::from_slice(body).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_wants_yaml() {
+        let req = TestRequest::default().to_http_request();
+        assert!(!wants_yaml(&req));
+
+        let req = TestRequest::default()
+            .header(header::ACCEPT, "application/json")
+            .to_http_request();
+        assert!(!wants_yaml(&req));
+
+        let req = TestRequest::default()
+            .header(header::ACCEPT, "text/html, Application/YAML;q=0.9")
+            .to_http_request();
+        assert!(wants_yaml(&req));
+
+        let req = TestRequest::default()
+            .header(header::ACCEPT, "text/yaml")
+            .to_http_request();
+        assert!(wants_yaml(&req));
+    }
+
+    #[test]
+    fn test_has_json_or_yaml_body() {
+        let with_type = |content_type: &str| {
+            TestRequest::default()
+                .header(header::CONTENT_TYPE, content_type)
+                .to_http_request()
+        };
+        assert!(!has_json_or_yaml_body(
+            &TestRequest::default().to_http_request()
+        ));
+        assert!(has_json_or_yaml_body(&with_type(
+            "Application/JSON; charset=utf-8"
+        )));
+        assert!(has_json_or_yaml_body(&with_type(
+            "application/merge-patch+json"
+        )));
+        assert!(has_json_or_yaml_body(&with_type("text/yaml")));
+        assert!(!has_json_or_yaml_body(&with_type("text/plain")));
+    }
+
+    #[test]
+    fn test_has_body_type() {
+        let req = TestRequest::default()
+            .header(header::CONTENT_TYPE, "Text/CSV; charset=utf-8")
+            .to_http_request();
+        assert!(has_body_type(&req, "text/csv"));
+        assert!(!has_body_type(&req, "application/x-ndjson"));
+        assert!(!has_body_type(
+            &TestRequest::default().to_http_request(),
+            "text/csv"
+        ));
+    }
+
+    #[test]
+    fn test_parse_body() {
+        let req = TestRequest::default()
+            .header(header::CONTENT_TYPE, "application/yaml; charset=utf-8")
+            .to_http_request();
+        let parsed: Vec<u32> = parse_body(&req, b"- 1\n- 2\n").unwrap();
+        assert_eq!(vec![1, 2], parsed);
+        assert!(parse_body::<Vec<u32>>(&req, b"[1, oops").is_err());
+
+        let req = TestRequest::default()
+            .header(header::CONTENT_TYPE, "application/json")
+            .to_http_request();
+        let parsed: Vec<u32> = parse_body(&req, b"[1, 2]").unwrap();
+        assert_eq!(vec![1, 2], parsed);
+        assert!(parse_body::<Vec<u32>>(&req, b"- 1\n").is_err());
+    }
+}
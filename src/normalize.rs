@@ -0,0 +1,257 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::db::normalize_ingredient_name;
+use crate::units;
+use crate::{IngredientQuantity, Recipe, RecipePatch, Step};
+
+/// Controls how whitespace in user-supplied text is cleaned up before it is
+/// stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WhitespaceOptions {
+    /// Collapse runs of whitespace and trim the ends of every text field.
+    pub enabled: bool,
+    /// Keep line breaks inside step text instead of folding them into spaces.
+    pub preserve_step_line_breaks: bool,
+}
+
+impl Default for WhitespaceOptions {
+    fn default() -> Self {
+        WhitespaceOptions {
+            enabled: true,
+            preserve_step_line_breaks: false,
+        }
+    }
+}
+
+impl WhitespaceOptions {
+    /// Read the options from `RECIPE_NORMALIZE_WHITESPACE` (default on) and
+    /// `RECIPE_PRESERVE_STEP_LINE_BREAKS` (default off).
+    pub fn from_env() -> Self {
+        let defaults = WhitespaceOptions::default();
+        WhitespaceOptions {
+            enabled: env_flag("RECIPE_NORMALIZE_WHITESPACE", defaults.enabled),
+            preserve_step_line_breaks: env_flag(
+                "RECIPE_PRESERVE_STEP_LINE_BREAKS",
+                defaults.preserve_step_line_breaks,
+            ),
+        }
+    }
+}
+
+fn env_flag(name: &str, default: bool) -> bool {
+    match std::env::var(name) {
+        Ok(value) => !matches!(
+            value.trim().to_lowercase().as_str(),
+            "0" | "false" | "off" | "no"
+        ),
+        Err(_) => default,
+    }
+}
+
+/// Collapse every run of whitespace into a single space and trim both ends.
+pub fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Like `collapse_whitespace`, but keeps line breaks between non-empty lines.
+pub fn collapse_whitespace_by_line(text: &str) -> String {
+    text.lines()
+        .map(collapse_whitespace)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Apply whitespace normalization to every text field of a recipe in place.
+pub fn normalize_recipe(recipe: &mut Recipe, options: &WhitespaceOptions) {
+    if !options.enabled {
+        return;
+    }
+
+    recipe.name = collapse_whitespace(&recipe.name);
+    recipe.desc = recipe.desc.as_deref().map(collapse_whitespace);
+    recipe.cuisine = recipe.cuisine.as_deref().map(collapse_whitespace);
+    recipe.course = recipe.course.as_deref().map(collapse_whitespace);
+    recipe.yield_description = recipe.yield_description.as_deref().map(collapse_whitespace);
+    normalize_steps(&mut recipe.steps, options);
+    normalize_ingredients(&mut recipe.ingredients);
+}
+
+/// Apply whitespace normalization to the fields present in a partial update.
+pub fn normalize_patch(patch: &mut RecipePatch, options: &WhitespaceOptions) {
+    if !options.enabled {
+        return;
+    }
+
+    patch.name = patch.name.as_deref().map(collapse_whitespace);
+    patch.desc = patch.desc.as_deref().map(collapse_whitespace);
+    if let Some(steps) = patch.steps.as_mut() {
+        normalize_steps(steps, options);
+    }
+    if let Some(ingredients) = patch.ingredients.as_mut() {
+        normalize_ingredients(ingredients);
+    }
+}
+
+fn normalize_steps(steps: &mut [Step], options: &WhitespaceOptions) {
+    for step in steps.iter_mut() {
+        step.text = if options.preserve_step_line_breaks {
+            collapse_whitespace_by_line(&step.text)
+        } else {
+            collapse_whitespace(&step.text)
+        };
+        step.note = step.note.as_deref().map(collapse_whitespace);
+    }
+}
+
+fn normalize_ingredients(ingredients: &mut [IngredientQuantity]) {
+    for ing_quant in ingredients.iter_mut() {
+        ing_quant.ingredient = collapse_whitespace(&ing_quant.ingredient);
+    }
+}
+
+/// Fold repeated lines for one ingredient, compared as stored names are,
+/// into the first of them by adding their quantities, e.g. salt for the
+/// dough and salt for the topping. Lines whose units cannot be added to the
+/// first are kept as they are; `Recipe::validate` reports those.
+pub fn merge_duplicate_ingredients(ingredients: &[IngredientQuantity]) -> Vec<IngredientQuantity> {
+    let mut merged: Vec<IngredientQuantity> = Vec::with_capacity(ingredients.len());
+    for ing_quant in ingredients.iter() {
+        let name = normalize_ingredient_name(&ing_quant.ingredient);
+        let sum = merged
+            .iter()
+            .position(|existing| normalize_ingredient_name(&existing.ingredient) == name)
+            .and_then(|i| {
+                units::add_quantities(&merged[i].quantity, &ing_quant.quantity).map(|sum| (i, sum))
+            });
+        match sum {
+            Some((i, sum)) => merged[i].quantity = sum,
+            None => merged.push(ing_quant.clone()),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Quantity;
+
+    fn messy_recipe() -> Recipe {
+        Recipe {
+            id: None,
+            name: "  Mashed\tPotatoes \n".to_string(),
+            desc: Some("Creamy  and\t\tbuttery\n".to_string()),
+            steps: vec![Step::new("Boil  the potatoes.\nThen   mash them.\n\n")],
+            ingredients: vec![IngredientQuantity {
+                ingredient: "Russet \t Potato ".to_string(),
+                quantity: Quantity {
+                    value: 2.0,
+                    value_max: None,
+                    unit: "whole".to_string(),
+                },
+                calories_per_unit: None,
+            }],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        }
+    }
+
+    #[test]
+    fn test_collapse_whitespace() {
+        assert_eq!("a b c", collapse_whitespace("\ta  b\t\tc \n"));
+        assert_eq!("", collapse_whitespace(" \t\n"));
+    }
+
+    #[test]
+    fn test_normalize_recipe() {
+        let mut recipe = messy_recipe();
+        normalize_recipe(&mut recipe, &WhitespaceOptions::default());
+
+        assert_eq!("Mashed Potatoes", recipe.name);
+        assert_eq!(Some("Creamy and buttery".to_string()), recipe.desc);
+        assert_eq!(
+            vec![Step::new("Boil the potatoes. Then mash them.")],
+            recipe.steps
+        );
+        assert_eq!("Russet Potato", recipe.ingredients[0].ingredient);
+    }
+
+    #[test]
+    fn test_normalize_preserves_step_line_breaks() {
+        let mut recipe = messy_recipe();
+        let options = WhitespaceOptions {
+            preserve_step_line_breaks: true,
+            ..WhitespaceOptions::default()
+        };
+        normalize_recipe(&mut recipe, &options);
+
+        assert_eq!(
+            vec![Step::new("Boil the potatoes.\nThen mash them.")],
+            recipe.steps
+        );
+    }
+
+    #[test]
+    fn test_normalize_disabled() {
+        let mut recipe = messy_recipe();
+        let options = WhitespaceOptions {
+            enabled: false,
+            ..WhitespaceOptions::default()
+        };
+        normalize_recipe(&mut recipe, &options);
+
+        assert_eq!(messy_recipe(), recipe);
+    }
+
+    #[test]
+    fn test_merge_duplicate_ingredients() {
+        let line = |ingredient: &str, value: f64, unit: &str| IngredientQuantity {
+            ingredient: ingredient.to_string(),
+            quantity: Quantity {
+                value,
+                value_max: None,
+                unit: unit.to_string(),
+            },
+            calories_per_unit: None,
+        };
+
+        assert_eq!(
+            vec![
+                line("Salt", 1.5, "tsp"),
+                line("Flour", 2.0, "cup"),
+                line("flour", 100.0, "g"),
+            ],
+            merge_duplicate_ingredients(&[
+                line("Salt", 1.0, "tsp"),
+                line("Flour", 2.0, "cup"),
+                line(" salt", 0.5, "tsp"),
+                line("flour", 100.0, "g"),
+            ])
+        );
+    }
+}
@@ -0,0 +1,101 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::Recipe;
+use serde::Serialize;
+
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct NutritionSummary {
+    /// Calories summed over the ingredients that have nutrition data.
+    pub total_calories: f64,
+    /// True when every ingredient contributed to the total.
+    pub complete: bool,
+    /// Ingredients without `calories_per_unit`.
+    pub missing: Vec<String>,
+}
+
+/// Sum `calories_per_unit * quantity` across a recipe's ingredients.
+pub fn summarize(recipe: &Recipe) -> NutritionSummary {
+    let mut total_calories = 0.0;
+    let mut missing = Vec::new();
+
+    for ing_quant in recipe.ingredients.iter() {
+        match ing_quant.calories_per_unit {
+            Some(calories) => total_calories += calories * ing_quant.quantity.value,
+            None => missing.push(ing_quant.ingredient.clone()),
+        }
+    }
+
+    NutritionSummary {
+        total_calories,
+        complete: missing.is_empty(),
+        missing,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IngredientQuantity, Quantity};
+
+    fn ingredient(name: &str, value: f64, calories_per_unit: Option<f64>) -> IngredientQuantity {
+        IngredientQuantity {
+            ingredient: name.to_string(),
+            quantity: Quantity {
+                value,
+                value_max: None,
+                unit: "whole".to_string(),
+            },
+            calories_per_unit,
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let recipe = Recipe {
+            id: None,
+            name: "Egg Salad".to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![
+                ingredient("Egg", 3.0, Some(78.0)),
+                ingredient("Mayonnaise", 2.0, Some(94.0)),
+                ingredient("Chives", 1.0, None),
+            ],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        };
+
+        assert_eq!(
+            NutritionSummary {
+                total_calories: 422.0,
+                complete: false,
+                missing: vec!["Chives".to_string()],
+            },
+            summarize(&recipe)
+        );
+    }
+}
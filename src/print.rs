@@ -0,0 +1,174 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::Recipe;
+use std::fmt::Write;
+
+/// Kept small so the page prints cleanly without any external assets.
+const STYLE: &str = "body{font-family:serif;max-width:40em;margin:auto}\
+table{border-collapse:collapse}td{padding:0.2em 1em 0.2em 0}";
+
+/// Escape the characters that are special in HTML text and attribute
+/// values, so user-provided fields cannot inject markup.
+pub fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Render a recipe as a minimal printable HTML page: the title and
+/// description, an ingredients table, numbered steps and any notes.
+pub fn render_html(recipe: &Recipe) -> String {
+    let name = escape_html(&recipe.name);
+    let mut out = String::new();
+
+    let _ = write!(
+        out,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+        name, STYLE, name
+    );
+
+    if let Some(desc) = &recipe.desc {
+        let _ = writeln!(out, "<p>{}</p>", escape_html(desc));
+    }
+
+    if !recipe.ingredients.is_empty() {
+        out.push_str("<h2>Ingredients</h2>\n<table>\n");
+        for ing_quant in recipe.ingredients.iter() {
+            let quantity = &ing_quant.quantity;
+            let _ = writeln!(
+                out,
+                "<tr><td>{} {}</td><td>{}</td></tr>",
+                quantity.amount(),
+                escape_html(&quantity.unit),
+                escape_html(&ing_quant.ingredient)
+            );
+        }
+        out.push_str("</table>\n");
+    }
+
+    if !recipe.steps.is_empty() {
+        out.push_str("<h2>Steps</h2>\n<ol>\n");
+        for step in recipe.steps.iter() {
+            let _ = write!(out, "<li>{}", escape_html(&step.text));
+            if let Some(minutes) = step.duration_minutes {
+                let _ = write!(out, " ({} min)", minutes);
+            }
+            if let Some(note) = &step.note {
+                let _ = write!(out, "<br><em>{}</em>", escape_html(note));
+            }
+            out.push_str("</li>\n");
+        }
+        out.push_str("</ol>\n");
+    }
+
+    if let Some(notes) = &recipe.notes {
+        let _ = writeln!(out, "<h2>Notes</h2>\n<p>{}</p>", escape_html(notes));
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{IngredientQuantity, Quantity, Step};
+
+    fn recipe(name: &str) -> Recipe {
+        Recipe {
+            id: Some(1),
+            name: name.to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients: vec![],
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        }
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(
+            "&lt;script&gt;alert(&quot;x&quot;)&lt;/script&gt; &amp; &#39;",
+            escape_html("<script>alert(\"x\")</script> & '")
+        );
+    }
+
+    #[test]
+    fn test_render_html() {
+        let recipe = Recipe {
+            desc: Some("Crisp & golden".to_string()),
+            steps: vec![
+                Step::new("Slice"),
+                Step {
+                    duration_minutes: Some(3),
+                    note: Some("Watch it".to_string()),
+                    ..Step::new("Toast")
+                },
+            ],
+            ingredients: vec![IngredientQuantity {
+                ingredient: "Bread".to_string(),
+                quantity: Quantity {
+                    value: 2.0,
+                    value_max: None,
+                    unit: "slice".to_string(),
+                },
+                calories_per_unit: None,
+            }],
+            notes: Some("Best with <butter>".to_string()),
+            ..recipe("<b>Toast</b>")
+        };
+
+        let html = render_html(&recipe);
+        assert!(html.contains("<title>&lt;b&gt;Toast&lt;/b&gt;</title>"));
+        assert!(html.contains("<h1>&lt;b&gt;Toast&lt;/b&gt;</h1>"));
+        assert!(html.contains("<p>Crisp &amp; golden</p>"));
+        assert!(html.contains("<tr><td>2 slice</td><td>Bread</td></tr>"));
+        assert!(html
+            .contains("<ol>\n<li>Slice</li>\n<li>Toast (3 min)<br><em>Watch it</em></li>\n</ol>"));
+        assert!(html.contains("<h2>Notes</h2>\n<p>Best with &lt;butter&gt;</p>"));
+        assert!(!html.contains("<b>"));
+    }
+
+    #[test]
+    fn test_render_html_without_sections() {
+        let html = render_html(&recipe("Water"));
+        assert!(!html.contains("<table>"));
+        assert!(!html.contains("<ol>"));
+        assert!(!html.contains("Notes"));
+        assert!(html.ends_with("</body>\n</html>\n"));
+    }
+}
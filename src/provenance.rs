@@ -0,0 +1,157 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+
+/// Upper bound on the number of links returned for a single provenance chain.
+pub const MAX_CHAIN_LENGTH: usize = 64;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ProvenanceKind {
+    Duplicate,
+    Merge,
+}
+
+impl ProvenanceKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProvenanceKind::Duplicate => "duplicate",
+            ProvenanceKind::Merge => "merge",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Option<ProvenanceKind> {
+        match kind {
+            "duplicate" => Some(ProvenanceKind::Duplicate),
+            "merge" => Some(ProvenanceKind::Merge),
+            _ => None,
+        }
+    }
+}
+
+/// Records that `recipe_id` was derived from `source_id`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ProvenanceLink {
+    pub recipe_id: i32,
+    pub source_id: i32,
+    pub kind: ProvenanceKind,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Provenance {
+    pub recipe_id: i32,
+    pub chain: Vec<ProvenanceLink>,
+    /// Set when the chain was cut off at `MAX_CHAIN_LENGTH`.
+    pub truncated: bool,
+}
+
+/// Walk the provenance graph breadth-first starting at `recipe_id`, using
+/// `sources` to look up the direct sources of a recipe. Each recipe is only
+/// expanded once, so cycles terminate, and at most `max_len` links are
+/// returned.
+pub fn build_chain<F, E>(recipe_id: i32, max_len: usize, mut sources: F) -> Result<Provenance, E>
+where
+    F: FnMut(i32) -> Result<Vec<ProvenanceLink>, E>,
+{
+    let mut chain = Vec::new();
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::new();
+    let mut truncated = false;
+
+    visited.insert(recipe_id);
+    queue.push_back(recipe_id);
+
+    'walk: while let Some(current) = queue.pop_front() {
+        for link in sources(current)? {
+            if chain.len() == max_len {
+                truncated = true;
+                break 'walk;
+            }
+
+            if visited.insert(link.source_id) {
+                queue.push_back(link.source_id);
+            }
+            chain.push(link);
+        }
+    }
+
+    Ok(Provenance {
+        recipe_id,
+        chain,
+        truncated,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn link(recipe_id: i32, source_id: i32, kind: ProvenanceKind) -> ProvenanceLink {
+        ProvenanceLink {
+            recipe_id,
+            source_id,
+            kind,
+        }
+    }
+
+    fn graph(links: Vec<ProvenanceLink>) -> impl FnMut(i32) -> anyhow::Result<Vec<ProvenanceLink>> {
+        move |recipe_id| {
+            Ok(links
+                .iter()
+                .filter(|link| link.recipe_id == recipe_id)
+                .cloned()
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_build_chain() {
+        let links = vec![
+            link(3, 2, ProvenanceKind::Duplicate),
+            link(2, 1, ProvenanceKind::Merge),
+            link(2, 4, ProvenanceKind::Merge),
+        ];
+
+        let provenance = build_chain(3, MAX_CHAIN_LENGTH, graph(links.clone())).unwrap();
+        assert_eq!(links, provenance.chain);
+        assert!(!provenance.truncated);
+    }
+
+    #[test]
+    fn test_build_chain_with_cycle() {
+        let links = vec![
+            link(1, 2, ProvenanceKind::Duplicate),
+            link(2, 1, ProvenanceKind::Duplicate),
+        ];
+
+        let provenance = build_chain(1, MAX_CHAIN_LENGTH, graph(links.clone())).unwrap();
+        assert_eq!(links, provenance.chain);
+    }
+
+    #[test]
+    fn test_build_chain_is_bounded() {
+        let links = (1..10)
+            .map(|id| link(id, id + 1, ProvenanceKind::Duplicate))
+            .collect();
+
+        let provenance = build_chain(1, 3, graph(links)).unwrap();
+        assert_eq!(3, provenance.chain.len());
+        assert!(provenance.truncated);
+    }
+}
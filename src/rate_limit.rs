@@ -0,0 +1,183 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::ApiError;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, Error, HttpResponse};
+use futures::future::{ok, Either, Ready};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Default number of write requests a single client may make per minute.
+pub const DEFAULT_PER_MINUTE: u32 = 60;
+
+/// Once this many clients are tracked, buckets that have refilled completely
+/// are dropped, since they are indistinguishable from a fresh bucket.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Per-IP token buckets. Each client may burst up to `per_minute` requests,
+/// refilling at `per_minute` tokens per minute.
+pub struct RateLimiter {
+    per_minute: u32,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        RateLimiter {
+            per_minute,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Build a limiter from `RECIPE_BOOK_RATE_LIMIT_PER_MINUTE` (default 60).
+    /// Returns `None` when the limit is set to 0, which disables limiting.
+    pub fn from_env() -> Option<Self> {
+        let per_minute = std::env::var("RECIPE_BOOK_RATE_LIMIT_PER_MINUTE")
+            .ok()
+            .and_then(|limit| limit.trim().parse().ok())
+            .unwrap_or(DEFAULT_PER_MINUTE);
+
+        if per_minute == 0 {
+            None
+        } else {
+            Some(RateLimiter::new(per_minute))
+        }
+    }
+
+    /// Take a token for `ip`. When the bucket is empty, returns how long the
+    /// client should wait before retrying.
+    pub fn check(&self, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let capacity = f64::from(self.per_minute);
+        let per_second = capacity / 60.0;
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| {
+                let elapsed = now.saturating_duration_since(bucket.updated);
+                bucket.tokens + elapsed.as_secs_f64() * per_second < capacity
+            });
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * per_second).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+}
+
+/// Middleware enforcing the `RateLimiter` found in app data. Requests pass
+/// through untouched when no limiter is registered.
+pub struct RateLimit;
+
+impl<S, B> Transform<S> for RateLimit
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimitMiddleware { service })
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for RateLimitMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+        let ip = req.peer_addr().map(|addr| addr.ip());
+
+        if let (Some(limiter), Some(ip)) = (limiter, ip) {
+            if let Err(retry_after) = limiter.check(ip, Instant::now()) {
+                let seconds = retry_after.as_secs_f64().ceil() as u64;
+                let resp = HttpResponse::TooManyRequests()
+                    .header("Retry-After", seconds.to_string())
+                    .json(ApiError::new(
+                        "rate_limited",
+                        "Too many requests, try again later.",
+                    ));
+                return Either::Right(ok(req.into_response(resp.into_body())));
+            }
+        }
+
+        Either::Left(self.service.call(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_refills() {
+        let limiter = RateLimiter::new(2);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        let start = Instant::now();
+
+        assert_eq!(Ok(()), limiter.check(ip, start));
+        assert_eq!(Ok(()), limiter.check(ip, start));
+        assert_eq!(Err(Duration::from_secs(30)), limiter.check(ip, start));
+        assert_eq!(Ok(()), limiter.check(other, start));
+
+        assert_eq!(Ok(()), limiter.check(ip, start + Duration::from_secs(30)));
+        assert!(limiter.check(ip, start + Duration::from_secs(30)).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use serde::{Deserialize, Serialize};
+
+pub const MIN_SCORE: u8 = 1;
+pub const MAX_SCORE: u8 = 5;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct NewRating {
+    pub score: u8,
+}
+
+impl NewRating {
+    pub fn is_valid(&self) -> bool {
+        (MIN_SCORE..=MAX_SCORE).contains(&self.score)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct RatingSummary {
+    /// Mean score, or `None` when the recipe has not been rated.
+    pub average: Option<f64>,
+    pub count: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid() {
+        assert!(!NewRating { score: 0 }.is_valid());
+        assert!(NewRating { score: 1 }.is_valid());
+        assert!(NewRating { score: 5 }.is_valid());
+        assert!(!NewRating { score: 6 }.is_valid());
+    }
+}
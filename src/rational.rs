@@ -0,0 +1,217 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use std::fmt;
+
+/// Largest denominator `Rational::from_f64` will use. Comfortably covers
+/// culinary fractions and decimals with up to four places.
+pub const MAX_DENOMINATOR: i64 = 10_000;
+
+/// How close the fraction must be to the float it replaces.
+const TOLERANCE: f64 = 1e-9;
+
+/// An exact fraction, always stored in lowest terms with a positive
+/// denominator.
+///
+/// Quantities are stored and sent as `f64`, which represents values like
+/// 1/3 or 0.1 only approximately. Summing those approximations drifts (three
+/// lots of 0.1 make 0.30000000000000004), so arithmetic on quantities
+/// recovers the intended fraction first and converts back to `f64` once at
+/// the end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rational {
+    numer: i64,
+    denom: i64,
+}
+
+impl Rational {
+    /// `None` if `denom` is zero.
+    pub fn new(numer: i64, denom: i64) -> Option<Self> {
+        if denom == 0 {
+            return None;
+        }
+
+        let divisor = gcd(numer, denom);
+        let sign = if denom < 0 { -1 } else { 1 };
+        Some(Rational {
+            numer: sign * numer / divisor,
+            denom: sign * denom / divisor,
+        })
+    }
+
+    pub fn numer(&self) -> i64 {
+        self.numer
+    }
+
+    pub fn denom(&self) -> i64 {
+        self.denom
+    }
+
+    /// The simplest fraction with a denominator up to `MAX_DENOMINATOR` that
+    /// `value` is an approximation of, found by walking its continued
+    /// fraction. `None` when the value is not finite or no such fraction is
+    /// close enough.
+    pub fn from_f64(value: f64) -> Option<Self> {
+        if !value.is_finite() || value.abs() > (i64::MAX / MAX_DENOMINATOR) as f64 {
+            return None;
+        }
+
+        let target = value.abs();
+        let (mut h_prev, mut h) = (0i64, 1i64);
+        let (mut k_prev, mut k) = (1i64, 0i64);
+        let mut x = target;
+
+        loop {
+            let a = x.floor() as i64;
+            let h_next = a.checked_mul(h)?.checked_add(h_prev)?;
+            let k_next = a.checked_mul(k)?.checked_add(k_prev)?;
+            if k_next > MAX_DENOMINATOR {
+                return None;
+            }
+
+            h_prev = h;
+            h = h_next;
+            k_prev = k;
+            k = k_next;
+
+            if (h as f64 / k as f64 - target).abs() <= TOLERANCE * target.max(1.0) {
+                let numer = if value < 0.0 { -h } else { h };
+                return Rational::new(numer, k);
+            }
+
+            let frac = x - a as f64;
+            if frac <= 0.0 {
+                return None;
+            }
+            x = 1.0 / frac;
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.numer as f64 / self.denom as f64
+    }
+
+    /// `None` on overflow.
+    pub fn checked_add(&self, other: &Rational) -> Option<Rational> {
+        let divisor = gcd(self.denom, other.denom);
+        let denom = (self.denom / divisor).checked_mul(other.denom)?;
+        let numer = self
+            .numer
+            .checked_mul(other.denom / divisor)?
+            .checked_add(other.numer.checked_mul(self.denom / divisor)?)?;
+        Rational::new(numer, denom)
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.denom == 1 {
+            write!(f, "{}", self.numer)
+        } else {
+            write!(f, "{}/{}", self.numer, self.denom)
+        }
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    a.max(1)
+}
+
+/// Sums `f64` quantities exactly while every addend is a recognizable
+/// fraction, falling back to plain floating-point addition otherwise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuantitySum {
+    exact: Option<Rational>,
+    float: f64,
+}
+
+impl QuantitySum {
+    pub fn new(value: f64) -> Self {
+        QuantitySum {
+            exact: Rational::from_f64(value),
+            float: value,
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        self.float += value;
+        self.exact = match (self.exact, Rational::from_f64(value)) {
+            (Some(sum), Some(addend)) => sum.checked_add(&addend),
+            _ => None,
+        };
+    }
+
+    pub fn value(&self) -> f64 {
+        match self.exact {
+            Some(sum) => sum.to_f64(),
+            None => self.float,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_f64() {
+        assert_eq!(Rational::new(1, 3), Rational::from_f64(1.0 / 3.0));
+        assert_eq!(Rational::new(1, 10), Rational::from_f64(0.1));
+        assert_eq!(Rational::new(-3, 2), Rational::from_f64(-1.5));
+        assert_eq!(Rational::new(0, 1), Rational::from_f64(0.0));
+        assert_eq!(Rational::new(250, 1), Rational::from_f64(250.0));
+        assert_eq!(None, Rational::from_f64(std::f64::consts::PI));
+        assert_eq!(None, Rational::from_f64(f64::NAN));
+    }
+
+    #[test]
+    fn test_new_reduces() {
+        let half = Rational::new(-2, -4).unwrap();
+        assert_eq!((1, 2), (half.numer(), half.denom()));
+        assert_eq!("1/2", half.to_string());
+        assert_eq!("3", Rational::new(6, 2).unwrap().to_string());
+        assert_eq!(None, Rational::new(1, 0));
+    }
+
+    #[test]
+    fn test_quantity_sum_is_exact() {
+        let third = 1.0 / 3.0;
+        let mut sum = QuantitySum::new(third);
+        sum.add(third);
+        sum.add(third);
+        assert_eq!(1.0, sum.value());
+
+        assert_ne!(0.3, 0.1 + 0.1 + 0.1);
+        let mut sum = QuantitySum::new(0.1);
+        sum.add(0.1);
+        sum.add(0.1);
+        assert_eq!(0.3, sum.value());
+    }
+
+    #[test]
+    fn test_quantity_sum_falls_back_to_float() {
+        let mut sum = QuantitySum::new(std::f64::consts::PI);
+        sum.add(1.0);
+        assert_eq!(std::f64::consts::PI + 1.0, sum.value());
+    }
+}
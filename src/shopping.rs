@@ -0,0 +1,272 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::rational::QuantitySum;
+use crate::{IngredientQuantity, Recipe};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Aisle used for ingredients without a known grocery category.
+pub const OTHER_AISLE: &str = "other";
+
+/// Common ingredient to aisle assignments seeded into a new database.
+pub const DEFAULT_AISLES: &[(&str, &str)] = &[
+    ("apple", "produce"),
+    ("banana", "produce"),
+    ("carrot", "produce"),
+    ("celery", "produce"),
+    ("garlic", "produce"),
+    ("lemon", "produce"),
+    ("lettuce", "produce"),
+    ("lime", "produce"),
+    ("onion", "produce"),
+    ("potato", "produce"),
+    ("tomato", "produce"),
+    ("butter", "dairy"),
+    ("cheese", "dairy"),
+    ("cream", "dairy"),
+    ("egg", "dairy"),
+    ("eggs", "dairy"),
+    ("milk", "dairy"),
+    ("yogurt", "dairy"),
+    ("baking powder", "baking"),
+    ("baking soda", "baking"),
+    ("brown sugar", "baking"),
+    ("flour", "baking"),
+    ("sugar", "baking"),
+    ("vanilla extract", "baking"),
+    ("yeast", "baking"),
+    ("beef", "meat"),
+    ("chicken", "meat"),
+    ("pork", "meat"),
+    ("bacon", "meat"),
+    ("black pepper", "spices"),
+    ("cinnamon", "spices"),
+    ("pepper", "spices"),
+    ("salt", "spices"),
+    ("olive oil", "pantry"),
+    ("pasta", "pantry"),
+    ("rice", "pantry"),
+    ("vegetable oil", "pantry"),
+];
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    Aisle,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AisleGroup {
+    pub aisle: String,
+    pub items: Vec<IngredientQuantity>,
+}
+
+/// Combine the ingredients of several recipes into one list, summing the
+/// quantities of lines that share an ingredient name and unit (compared
+/// case-insensitively). Items keep the order they first appear in. Sums are
+/// exact for fractional amounts like 1/3 cup.
+pub fn consolidate(recipes: &[Recipe]) -> Vec<IngredientQuantity> {
+    let mut items: Vec<IngredientQuantity> = Vec::new();
+    let mut sums: Vec<QuantitySum> = Vec::new();
+    // Upper ends, counting exact amounts as their value. Only used for items
+    // where some line is a range.
+    let mut max_sums: Vec<(QuantitySum, bool)> = Vec::new();
+    let mut positions: HashMap<(String, String), usize> = HashMap::new();
+
+    for ing_quant in recipes.iter().flat_map(|recipe| recipe.ingredients.iter()) {
+        let key = (
+            ing_quant.ingredient.to_lowercase(),
+            ing_quant.quantity.unit.to_lowercase(),
+        );
+        let quantity = &ing_quant.quantity;
+        let value_max = quantity.value_max.unwrap_or(quantity.value);
+
+        match positions.get(&key) {
+            Some(&pos) => {
+                sums[pos].add(quantity.value);
+                max_sums[pos].0.add(value_max);
+                max_sums[pos].1 |= quantity.value_max.is_some();
+            }
+            None => {
+                positions.insert(key, items.len());
+                items.push(ing_quant.clone());
+                sums.push(QuantitySum::new(quantity.value));
+                max_sums.push((QuantitySum::new(value_max), quantity.value_max.is_some()));
+            }
+        }
+    }
+
+    for ((item, sum), (max_sum, ranged)) in items.iter_mut().zip(sums.iter()).zip(max_sums.iter()) {
+        item.quantity.value = sum.value();
+        item.quantity.value_max = if *ranged { Some(max_sum.value()) } else { None };
+    }
+
+    items
+}
+
+/// Group a consolidated list by grocery aisle. `aisles` maps lowercase
+/// ingredient names to aisles. Groups are sorted by name with "other" last.
+pub fn group_by_aisle(
+    items: Vec<IngredientQuantity>,
+    aisles: &HashMap<String, String>,
+) -> Vec<AisleGroup> {
+    let mut groups: BTreeMap<String, Vec<IngredientQuantity>> = BTreeMap::new();
+
+    for item in items.into_iter() {
+        let aisle = aisles
+            .get(&item.ingredient.to_lowercase())
+            .cloned()
+            .unwrap_or_else(|| OTHER_AISLE.to_string());
+        groups.entry(aisle).or_default().push(item);
+    }
+
+    let other = groups.remove(OTHER_AISLE);
+    let mut grouped: Vec<AisleGroup> = groups
+        .into_iter()
+        .map(|(aisle, items)| AisleGroup { aisle, items })
+        .collect();
+
+    if let Some(items) = other {
+        grouped.push(AisleGroup {
+            aisle: OTHER_AISLE.to_string(),
+            items,
+        });
+    }
+
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Quantity;
+
+    fn item(ingredient: &str, value: f64, unit: &str) -> IngredientQuantity {
+        IngredientQuantity {
+            ingredient: ingredient.to_string(),
+            quantity: Quantity {
+                value,
+                value_max: None,
+                unit: unit.to_string(),
+            },
+            calories_per_unit: None,
+        }
+    }
+
+    fn recipe(ingredients: Vec<IngredientQuantity>) -> Recipe {
+        Recipe {
+            id: None,
+            name: "Test Recipe".to_string(),
+            desc: None,
+            steps: vec![],
+            ingredients,
+            favorite: false,
+            image_url: None,
+            cuisine: None,
+            course: None,
+            source_name: None,
+            source_url: None,
+            yield_description: None,
+            difficulty: None,
+            notes: None,
+            raw_source: None,
+            photos: vec![],
+        }
+    }
+
+    #[test]
+    fn test_consolidate() {
+        let recipes = vec![
+            recipe(vec![item("Flour", 2.0, "cup"), item("Egg", 1.0, "whole")]),
+            recipe(vec![item("flour", 1.0, "Cup"), item("Flour", 100.0, "g")]),
+        ];
+
+        assert_eq!(
+            vec![
+                item("Flour", 3.0, "cup"),
+                item("Egg", 1.0, "whole"),
+                item("Flour", 100.0, "g"),
+            ],
+            consolidate(&recipes)
+        );
+    }
+
+    #[test]
+    fn test_consolidate_fractions_exactly() {
+        let third = 1.0 / 3.0;
+        let recipes = vec![
+            recipe(vec![item("Sugar", third, "cup"), item("Salt", 0.1, "tsp")]),
+            recipe(vec![item("Sugar", third, "cup"), item("Salt", 0.1, "tsp")]),
+            recipe(vec![item("Sugar", third, "cup"), item("Salt", 0.1, "tsp")]),
+        ];
+
+        assert_eq!(
+            vec![item("Sugar", 1.0, "cup"), item("Salt", 0.3, "tsp")],
+            consolidate(&recipes)
+        );
+    }
+
+    #[test]
+    fn test_consolidate_ranges() {
+        let mut garlic = item("Garlic", 2.0, "clove");
+        garlic.quantity.value_max = Some(3.0);
+        let recipes = vec![
+            recipe(vec![item("Garlic", 1.0, "clove"), item("Salt", 1.0, "tsp")]),
+            recipe(vec![garlic, item("Salt", 1.0, "tsp")]),
+        ];
+
+        let mut expected = item("Garlic", 3.0, "clove");
+        expected.quantity.value_max = Some(4.0);
+        assert_eq!(
+            vec![expected, item("Salt", 2.0, "tsp")],
+            consolidate(&recipes)
+        );
+    }
+
+    #[test]
+    fn test_group_by_aisle() {
+        let aisles: HashMap<String, String> = DEFAULT_AISLES
+            .iter()
+            .map(|(name, aisle)| (name.to_string(), aisle.to_string()))
+            .collect();
+        let items = vec![
+            item("Saffron", 1.0, "pinch"),
+            item("Milk", 1.0, "cup"),
+            item("Potato", 2.0, "whole"),
+            item("Butter", 2.0, "tbsp"),
+        ];
+
+        assert_eq!(
+            vec![
+                AisleGroup {
+                    aisle: "dairy".to_string(),
+                    items: vec![item("Milk", 1.0, "cup"), item("Butter", 2.0, "tbsp")],
+                },
+                AisleGroup {
+                    aisle: "produce".to_string(),
+                    items: vec![item("Potato", 2.0, "whole")],
+                },
+                AisleGroup {
+                    aisle: OTHER_AISLE.to_string(),
+                    items: vec![item("Saffron", 1.0, "pinch")],
+                },
+            ],
+            group_by_aisle(items, &aisles)
+        );
+    }
+}
@@ -0,0 +1,130 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::{IngredientQuantity, Quantity, Recipe, Step};
+
+/// Builds recipes for tests. The default is the smallest recipe that passes
+/// validation: a name and nothing else.
+///
+/// ```ignore
+/// let recipe = RecipeBuilder::default()
+///     .name("Mashed Potatoes")
+///     .step("Boil")
+///     .ingredient("Potato", 2.0, "whole")
+///     .build();
+/// ```
+#[derive(Debug, Clone)]
+pub struct RecipeBuilder {
+    recipe: Recipe,
+}
+
+impl Default for RecipeBuilder {
+    fn default() -> Self {
+        RecipeBuilder {
+            recipe: Recipe {
+                id: None,
+                name: "Test Recipe".to_string(),
+                desc: None,
+                steps: vec![],
+                ingredients: vec![],
+                favorite: false,
+                image_url: None,
+                cuisine: None,
+                course: None,
+                source_name: None,
+                source_url: None,
+                yield_description: None,
+                difficulty: None,
+                notes: None,
+                raw_source: None,
+                photos: vec![],
+            },
+        }
+    }
+}
+
+impl RecipeBuilder {
+    pub fn id(mut self, id: u32) -> Self {
+        self.recipe.id = Some(id);
+        self
+    }
+
+    pub fn name(mut self, name: &str) -> Self {
+        self.recipe.name = name.to_string();
+        self
+    }
+
+    pub fn desc(mut self, desc: &str) -> Self {
+        self.recipe.desc = Some(desc.to_string());
+        self
+    }
+
+    /// Append a step.
+    pub fn step(mut self, text: &str) -> Self {
+        self.recipe.steps.push(Step::new(text));
+        self
+    }
+
+    /// Append an ingredient without calorie information.
+    pub fn ingredient(mut self, ingredient: &str, value: f64, unit: &str) -> Self {
+        self.recipe.ingredients.push(IngredientQuantity {
+            ingredient: ingredient.to_string(),
+            quantity: Quantity {
+                value,
+                value_max: None,
+                unit: unit.to_string(),
+            },
+            calories_per_unit: None,
+        });
+        self
+    }
+
+    pub fn build(self) -> Recipe {
+        self.recipe
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_is_valid() {
+        let recipe = RecipeBuilder::default().build();
+        assert_eq!(None, recipe.id);
+        assert!(recipe.validate().is_empty());
+    }
+
+    #[test]
+    fn test_builder() {
+        let recipe = RecipeBuilder::default()
+            .id(3)
+            .name("Toast")
+            .desc("Crunchy")
+            .step("Slice")
+            .step("Toast")
+            .ingredient("Bread", 2.0, "slice")
+            .build();
+
+        assert_eq!(Some(3), recipe.id);
+        assert_eq!("Toast", recipe.name);
+        assert_eq!(Some("Crunchy".to_string()), recipe.desc);
+        assert_eq!(vec![Step::new("Slice"), Step::new("Toast")], recipe.steps);
+        assert_eq!("Bread", recipe.ingredients[0].ingredient);
+        assert_eq!(2.0, recipe.ingredients[0].quantity.value);
+    }
+}
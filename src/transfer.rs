@@ -0,0 +1,169 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::db::{Repo, RepoResult};
+use serde::Serialize;
+
+/// What `copy_recipes` wrote to the target.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct TransferReport {
+    pub recipes: usize,
+    /// Recipes copied into the target's trash.
+    pub trashed: usize,
+    pub steps: usize,
+    pub ingredients: usize,
+    pub photos: usize,
+    pub categories: usize,
+    pub ratings: usize,
+    pub provenance: usize,
+}
+
+/// Copy everything from `source` into `target`, e.g. when moving to another
+/// database backend: recipes with their ids and timestamps, trashed ones
+/// included, along with categories, aisles, provenance and ratings. It is
+/// written in one transaction, so the target gets all of it or none of it.
+///
+/// A target that already holds recipes or categories is refused with
+/// `Conflict` rather than filled with duplicates.
+pub fn copy_recipes(source: &dyn Repo, target: &dyn Repo) -> RepoResult<TransferReport> {
+    let snapshot = source.export_snapshot()?;
+    target.import_snapshot(&snapshot)?;
+
+    let recipes = snapshot.recipes.iter().map(|stored| &stored.recipe);
+    let trashed = snapshot
+        .recipes
+        .iter()
+        .filter(|stored| stored.deleted_at.is_some())
+        .count();
+    Ok(TransferReport {
+        recipes: snapshot.recipes.len() - trashed,
+        trashed,
+        steps: recipes.clone().map(|r| r.steps.len()).sum(),
+        ingredients: recipes.clone().map(|r| r.ingredients.len()).sum(),
+        photos: recipes.map(|r| r.photos.len()).sum(),
+        categories: snapshot.categories.len(),
+        ratings: snapshot.ratings.len(),
+        provenance: snapshot.provenance.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{self, Backend, RepoError};
+    use crate::provenance::{ProvenanceKind, ProvenanceLink};
+    use crate::testing::RecipeBuilder;
+    use crate::Recipe;
+
+    fn temp_repo() -> (Box<dyn Repo>, String) {
+        let name = format!("target/tests/transfer-{}.db", rand::random::<u32>());
+        (
+            db::create_repo_with_name(Backend::Sqlite, &name).unwrap(),
+            name,
+        )
+    }
+
+    #[test]
+    fn test_copy_recipes() {
+        let (source, source_name) = temp_repo();
+        let (target, target_name) = temp_repo();
+
+        let toast = RecipeBuilder::default()
+            .name("Toast")
+            .step("Toast the bread")
+            .step("Butter it")
+            .ingredient("Bread", 2.0, "slice")
+            .build();
+        let tea = Recipe {
+            favorite: true,
+            photos: vec!["https://example.com/tea.jpg".to_string()],
+            ..RecipeBuilder::default().name("Tea").step("Steep").build()
+        };
+        source.add_recipes(&[toast, tea]).unwrap();
+        // Purging leaves a gap in the ids, which the copy must keep.
+        let gone = source
+            .add_recipe(&RecipeBuilder::default().name("Gone").build())
+            .unwrap();
+        let buttered = source
+            .add_recipe(&RecipeBuilder::default().name("Buttered toast").build())
+            .unwrap();
+        source.delete_recipe(gone as i32).unwrap();
+        source
+            .purge_deleted(chrono::Utc::now() + chrono::Duration::days(1))
+            .unwrap();
+        let trashed = source
+            .add_recipe(&RecipeBuilder::default().name("Burnt toast").build())
+            .unwrap();
+        source.delete_recipe(trashed as i32).unwrap();
+
+        let breakfast = source.add_category("Breakfast", None).unwrap();
+        let drinks = source.add_category("Drinks", Some(breakfast.id)).unwrap();
+        source.set_recipe_category(1, breakfast.id, true).unwrap();
+        source.set_recipe_category(2, drinks.id, true).unwrap();
+        source.add_rating(1, 4).unwrap();
+        source.add_rating(1, 5).unwrap();
+        source
+            .add_provenance(&ProvenanceLink {
+                recipe_id: buttered as i32,
+                source_id: 1,
+                kind: ProvenanceKind::Duplicate,
+            })
+            .unwrap();
+
+        let report = copy_recipes(source.as_ref(), target.as_ref()).unwrap();
+        assert_eq!(
+            TransferReport {
+                recipes: 3,
+                trashed: 1,
+                steps: 3,
+                ingredients: 1,
+                photos: 1,
+                categories: 2,
+                ratings: 2,
+                provenance: 1,
+            },
+            report
+        );
+        assert_eq!(
+            source.export_snapshot().unwrap(),
+            target.export_snapshot().unwrap()
+        );
+        assert_eq!(
+            "Buttered toast",
+            target.get_recipe(buttered as i32).unwrap().unwrap().name
+        );
+        assert_eq!(
+            source.rating_summary(1).unwrap(),
+            target.rating_summary(1).unwrap()
+        );
+        assert_eq!(
+            source.recipe_category_links().unwrap(),
+            target.recipe_category_links().unwrap()
+        );
+        assert_eq!(1, target.load_deleted().unwrap().len());
+
+        // A second run would duplicate everything, so it is refused.
+        assert!(matches!(
+            copy_recipes(source.as_ref(), target.as_ref()),
+            Err(RepoError::Conflict)
+        ));
+        assert_eq!(3, target.count_recipes().unwrap());
+
+        std::fs::remove_file(&source_name).unwrap();
+        std::fs::remove_file(&target_name).unwrap();
+    }
+}
@@ -0,0 +1,238 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::{Category, RecipeSummary};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Deepest level of nesting returned by the category tree.
+pub const MAX_TREE_DEPTH: usize = 8;
+
+/// Name of the node holding recipes without a category.
+pub const UNCATEGORIZED: &str = "Uncategorized";
+
+/// One category in the tree. `id` is `None` only for the "Uncategorized"
+/// node. `truncated` is set when subcategories were cut off by the depth
+/// limit.
+#[derive(Serialize, Debug, PartialEq, Clone)]
+pub struct CategoryNode {
+    pub id: Option<i32>,
+    pub name: String,
+    pub recipes: Vec<RecipeSummary>,
+    pub children: Vec<CategoryNode>,
+    pub truncated: bool,
+}
+
+/// Arrange recipes into a tree of categories.
+///
+/// `links` pairs recipe ids with category ids; a recipe in several categories
+/// appears under each. Categories whose parent is missing, or that sit on a
+/// parent cycle, become roots. Nesting stops at `max_depth` levels. Siblings
+/// are sorted by name and the "Uncategorized" node, if any, comes last.
+pub fn build_tree(
+    categories: &[Category],
+    links: &[(u32, i32)],
+    recipes: &[RecipeSummary],
+    max_depth: usize,
+) -> Vec<CategoryNode> {
+    let by_id: HashMap<i32, &Category> = categories.iter().map(|c| (c.id, c)).collect();
+    let recipes_by_id: HashMap<u32, &RecipeSummary> = recipes.iter().map(|r| (r.id, r)).collect();
+
+    let mut children: HashMap<i32, Vec<&Category>> = HashMap::new();
+    let mut roots: Vec<&Category> = Vec::new();
+    for category in categories.iter() {
+        match category.parent_id {
+            Some(parent_id) if by_id.contains_key(&parent_id) && parent_id != category.id => {
+                children.entry(parent_id).or_default().push(category)
+            }
+            _ => roots.push(category),
+        }
+    }
+
+    let mut category_recipes: HashMap<i32, Vec<RecipeSummary>> = HashMap::new();
+    let mut categorized: HashSet<u32> = HashSet::new();
+    for (recipe_id, category_id) in links.iter() {
+        if let (Some(recipe), true) = (
+            recipes_by_id.get(recipe_id),
+            by_id.contains_key(category_id),
+        ) {
+            category_recipes
+                .entry(*category_id)
+                .or_default()
+                .push((*recipe).clone());
+            categorized.insert(*recipe_id);
+        }
+    }
+
+    let mut builder = TreeBuilder {
+        children: &children,
+        category_recipes: &mut category_recipes,
+        visited: HashSet::new(),
+        max_depth,
+    };
+
+    let mut tree = builder.build_level(roots, 1);
+
+    // Anything not reached from a root is part of a parent cycle.
+    let unreached: Vec<&Category> = categories
+        .iter()
+        .filter(|c| !builder.visited.contains(&c.id))
+        .collect();
+    for category in unreached.into_iter() {
+        if !builder.visited.contains(&category.id) {
+            tree.extend(builder.build_level(vec![category], 1));
+        }
+    }
+    tree.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let uncategorized: Vec<RecipeSummary> = recipes
+        .iter()
+        .filter(|r| !categorized.contains(&r.id))
+        .cloned()
+        .collect();
+    if !uncategorized.is_empty() {
+        tree.push(CategoryNode {
+            id: None,
+            name: UNCATEGORIZED.to_string(),
+            recipes: uncategorized,
+            children: vec![],
+            truncated: false,
+        });
+    }
+
+    tree
+}
+
+struct TreeBuilder<'a> {
+    children: &'a HashMap<i32, Vec<&'a Category>>,
+    category_recipes: &'a mut HashMap<i32, Vec<RecipeSummary>>,
+    visited: HashSet<i32>,
+    max_depth: usize,
+}
+
+impl<'a> TreeBuilder<'a> {
+    fn build_level(&mut self, mut level: Vec<&'a Category>, depth: usize) -> Vec<CategoryNode> {
+        level.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut nodes = Vec::new();
+        for category in level.into_iter() {
+            if !self.visited.insert(category.id) {
+                continue;
+            }
+
+            let subcategories = self.children.get(&category.id).cloned().unwrap_or_default();
+            let truncated = depth >= self.max_depth && !subcategories.is_empty();
+            let children = if truncated {
+                self.skip_descendants(category.id);
+                vec![]
+            } else {
+                self.build_level(subcategories, depth + 1)
+            };
+
+            nodes.push(CategoryNode {
+                id: Some(category.id),
+                name: category.name.clone(),
+                recipes: self
+                    .category_recipes
+                    .remove(&category.id)
+                    .unwrap_or_default(),
+                children,
+                truncated,
+            });
+        }
+
+        nodes
+    }
+
+    /// Mark everything below a truncated category as visited so it is not
+    /// mistaken for part of a parent cycle and promoted to a root.
+    fn skip_descendants(&mut self, category_id: i32) {
+        let mut pending = vec![category_id];
+        while let Some(id) = pending.pop() {
+            for child in self.children.get(&id).into_iter().flatten() {
+                if self.visited.insert(child.id) {
+                    pending.push(child.id);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn category(id: i32, name: &str, parent_id: Option<i32>) -> Category {
+        Category {
+            id,
+            name: name.to_string(),
+            parent_id,
+        }
+    }
+
+    fn summary(id: u32, name: &str) -> RecipeSummary {
+        RecipeSummary {
+            id,
+            name: name.to_string(),
+            desc: None,
+            image_url: None,
+        }
+    }
+
+    #[test]
+    fn test_build_tree() {
+        let categories = vec![
+            category(1, "Desserts", None),
+            category(2, "Cakes", Some(1)),
+            category(3, "Breakfast", None),
+        ];
+        let recipes = vec![
+            summary(1, "Carrot Cake"),
+            summary(2, "Pancakes"),
+            summary(3, "Toast"),
+        ];
+        let links = vec![(1, 2), (2, 3)];
+
+        let tree = build_tree(&categories, &links, &recipes, MAX_TREE_DEPTH);
+
+        let names: Vec<&str> = tree.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(vec!["Breakfast", "Desserts", UNCATEGORIZED], names);
+        assert_eq!(vec![summary(2, "Pancakes")], tree[0].recipes);
+        assert!(tree[1].recipes.is_empty());
+        assert_eq!(vec![summary(1, "Carrot Cake")], tree[1].children[0].recipes);
+        assert_eq!(None, tree[2].id);
+        assert_eq!(vec![summary(3, "Toast")], tree[2].recipes);
+    }
+
+    #[test]
+    fn test_build_tree_with_cycle_and_depth_limit() {
+        let categories = vec![
+            category(1, "A", Some(2)),
+            category(2, "B", Some(1)),
+            category(3, "C", None),
+            category(4, "D", Some(3)),
+        ];
+
+        let tree = build_tree(&categories, &[], &[], 1);
+
+        let names: Vec<&str> = tree.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(vec!["A", "C"], names);
+        assert!(tree[0].truncated);
+        assert!(tree[1].truncated);
+        assert!(tree[1].children.is_empty());
+    }
+}
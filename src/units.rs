@@ -0,0 +1,419 @@
+///
+/// Recipe Book Backend - A small recipe server
+/// Copyright (C) 2021 Austin Ray <austin@austinray.io>
+///
+/// This program is free software: you can redistribute it and/or modify
+/// it under the terms of the GNU Affero General Public License as published
+/// by the Free Software Foundation, either version 3 of the License, or
+/// (at your option) any later version.
+///
+/// This program is distributed in the hope that it will be useful,
+/// but WITHOUT ANY WARRANTY; without even the implied warranty of
+/// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+/// GNU Affero General Public License for more details.
+///
+/// You should have received a copy of the GNU Affero General Public License
+/// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+///
+use crate::{Quantity, Recipe};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dimension {
+    Volume,
+    Mass,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UnitSystem {
+    Metric,
+    Imperial,
+}
+
+/// Known units as `(canonical name, dimension, size in ml or g)`.
+const UNITS: &[(&str, Dimension, f64)] = &[
+    ("ml", Dimension::Volume, 1.0),
+    ("l", Dimension::Volume, 1000.0),
+    ("tsp", Dimension::Volume, 4.928_921_593_75),
+    ("tbsp", Dimension::Volume, 14.786_764_781_25),
+    ("fl oz", Dimension::Volume, 29.573_529_562_5),
+    ("cup", Dimension::Volume, 236.588_236_5),
+    ("pint", Dimension::Volume, 473.176_473),
+    ("quart", Dimension::Volume, 946.352_946),
+    ("gallon", Dimension::Volume, 3_785.411_784),
+    ("mg", Dimension::Mass, 0.001),
+    ("g", Dimension::Mass, 1.0),
+    ("kg", Dimension::Mass, 1000.0),
+    ("oz", Dimension::Mass, 28.349_523_125),
+    ("lb", Dimension::Mass, 453.592_37),
+];
+
+/// Alternate spellings mapped to their canonical unit name.
+const ALIASES: &[(&str, &str)] = &[
+    ("milliliter", "ml"),
+    ("millilitre", "ml"),
+    ("liter", "l"),
+    ("litre", "l"),
+    ("teaspoon", "tsp"),
+    ("tablespoon", "tbsp"),
+    ("tbs", "tbsp"),
+    ("fluid ounce", "fl oz"),
+    ("floz", "fl oz"),
+    ("cups", "cup"),
+    ("c", "cup"),
+    ("pt", "pint"),
+    ("qt", "quart"),
+    ("gal", "gallon"),
+    ("milligram", "mg"),
+    ("gram", "g"),
+    ("kilogram", "kg"),
+    ("ounce", "oz"),
+    ("pound", "lb"),
+    ("lbs", "lb"),
+];
+
+/// Map a unit string to its canonical name, ignoring case, surrounding
+/// whitespace, a trailing period, and a plural "s".
+pub fn canonical_unit(unit: &str) -> Option<&'static str> {
+    let unit = unit.trim().trim_end_matches('.').to_lowercase();
+    let singular = unit.strip_suffix('s').unwrap_or(&unit);
+
+    for candidate in [unit.as_str(), singular].iter() {
+        if let Some((name, _, _)) = UNITS.iter().find(|(name, _, _)| name == candidate) {
+            return Some(name);
+        }
+        if let Some((_, name)) = ALIASES.iter().find(|(alias, _)| alias == candidate) {
+            return Some(name);
+        }
+    }
+
+    None
+}
+
+/// Units that count things rather than measure them. They are accepted by
+/// strict validation but never converted.
+const COUNT_UNITS: &[&str] = &[
+    "whole", "piece", "clove", "slice", "pinch", "dash", "can", "bunch", "sprig", "stick",
+    "handful", "package",
+];
+
+/// Map a count unit to its name, with the same leniency as `canonical_unit`.
+fn canonical_count(unit: &str) -> Option<&'static str> {
+    let unit = unit.trim().trim_end_matches('.').to_lowercase();
+    let singular = unit.strip_suffix('s').unwrap_or(&unit);
+    COUNT_UNITS
+        .iter()
+        .find(|name| **name == unit || **name == singular)
+        .copied()
+}
+
+/// Whether `unit` is in the curated table: a measured unit or one of its
+/// aliases, a count such as "clove", or empty for a bare number.
+pub fn is_known_unit(unit: &str) -> bool {
+    unit.trim().is_empty() || canonical_unit(unit).is_some() || canonical_count(unit).is_some()
+}
+
+/// Most edits a misspelling may be from a known unit to be suggested.
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+/// The known unit an unrecognised one was most likely meant to be, e.g.
+/// "tbsp" for "tablespon", or `None` if nothing is close.
+pub fn suggest_unit(unit: &str) -> Option<&'static str> {
+    let unit = unit.trim().trim_end_matches('.').to_lowercase();
+    let singular = unit.strip_suffix('s').unwrap_or(&unit);
+
+    let spellings = UNITS
+        .iter()
+        .map(|(name, _, _)| (*name, *name))
+        .chain(ALIASES.iter().copied())
+        .chain(COUNT_UNITS.iter().map(|name| (*name, *name)));
+    spellings
+        .map(|(spelling, name)| {
+            let distance = edit_distance(&unit, spelling).min(edit_distance(singular, spelling));
+            (distance, name)
+        })
+        .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
+/// Levenshtein distance between two strings, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+fn lookup(unit: &str) -> Option<(Dimension, f64)> {
+    let name = canonical_unit(unit)?;
+    UNITS
+        .iter()
+        .find(|(candidate, _, _)| *candidate == name)
+        .map(|(_, dimension, size)| (*dimension, *size))
+}
+
+/// Convert `value` from one unit to another. Returns `None` when either unit
+/// is unknown or the units measure different things (e.g. cups to grams).
+pub fn convert(value: f64, from: &str, to: &str) -> Option<f64> {
+    let (from_dim, from_size) = lookup(from)?;
+    let (to_dim, to_size) = lookup(to)?;
+
+    if from_dim != to_dim {
+        return None;
+    }
+
+    Some(value * from_size / to_size)
+}
+
+/// `a + b` in `a`'s unit, or `None` when `b`'s unit cannot be converted to
+/// it. Units that name the same thing, e.g. "clove" and "cloves" or two
+/// empty units, add without conversion.
+pub fn add_quantities(a: &Quantity, b: &Quantity) -> Option<Quantity> {
+    let same_unit = a.unit.trim().eq_ignore_ascii_case(b.unit.trim())
+        || matches!(
+            (canonical_unit(&a.unit), canonical_unit(&b.unit)),
+            (Some(x), Some(y)) if x == y
+        )
+        || matches!(
+            (canonical_count(&a.unit), canonical_count(&b.unit)),
+            (Some(x), Some(y)) if x == y
+        );
+    let in_a_unit = |value: f64| {
+        if same_unit {
+            Some(value)
+        } else {
+            convert(value, &b.unit, &a.unit)
+        }
+    };
+    let value = in_a_unit(b.value)?;
+    let b_max = match b.value_max {
+        Some(b_max) => Some(in_a_unit(b_max)?),
+        None => None,
+    };
+
+    // Adding a range to anything gives a range: 1-2 + 3 is 4-5.
+    let value_max = match (a.value_max, b_max) {
+        (None, None) => None,
+        (a_max, b_max) => Some(a_max.unwrap_or(a.value) + b_max.unwrap_or(value)),
+    };
+    Some(Quantity {
+        value: a.value + value,
+        value_max,
+        unit: a.unit.clone(),
+    })
+}
+
+/// Pick the unit in `system` that reads most naturally for the given amount.
+fn target_unit(dimension: Dimension, base_amount: f64, system: UnitSystem) -> &'static str {
+    match (system, dimension) {
+        (UnitSystem::Metric, Dimension::Volume) if base_amount >= 1000.0 => "l",
+        (UnitSystem::Metric, Dimension::Volume) => "ml",
+        (UnitSystem::Metric, Dimension::Mass) if base_amount >= 1000.0 => "kg",
+        (UnitSystem::Metric, Dimension::Mass) => "g",
+        (UnitSystem::Imperial, Dimension::Volume) if base_amount < 14.0 => "tsp",
+        (UnitSystem::Imperial, Dimension::Volume) if base_amount < 59.0 => "tbsp",
+        (UnitSystem::Imperial, Dimension::Volume) => "cup",
+        (UnitSystem::Imperial, Dimension::Mass) if base_amount < 453.0 => "oz",
+        (UnitSystem::Imperial, Dimension::Mass) => "lb",
+    }
+}
+
+/// Convert a quantity into `system`, leaving counts and unknown units as-is.
+pub fn convert_quantity(quantity: &Quantity, system: UnitSystem) -> Quantity {
+    let (dimension, size) = match lookup(&quantity.unit) {
+        Some(found) => found,
+        None => return quantity.clone(),
+    };
+
+    let to = target_unit(dimension, quantity.value * size, system);
+    let convert_to = |value: f64| {
+        convert(value, &quantity.unit, to).map(|value| (value * 100.0).round() / 100.0)
+    };
+    match convert_to(quantity.value) {
+        Some(value) => Quantity {
+            value,
+            value_max: quantity.value_max.and_then(convert_to),
+            unit: to.to_string(),
+        },
+        None => quantity.clone(),
+    }
+}
+
+/// Return a copy of `recipe` with every convertible ingredient in `system`.
+pub fn convert_recipe(recipe: &Recipe, system: UnitSystem) -> Recipe {
+    let mut converted = recipe.clone();
+    for ing_quant in converted.ingredients.iter_mut() {
+        ing_quant.quantity = convert_quantity(&ing_quant.quantity, system);
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quantity(value: f64, unit: &str) -> Quantity {
+        Quantity {
+            value,
+            value_max: None,
+            unit: unit.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_add_quantities() {
+        assert_eq!(
+            Some(quantity(3.0, "clove")),
+            add_quantities(&quantity(1.0, "clove"), &quantity(2.0, "Cloves"))
+        );
+        assert_eq!(
+            Some(quantity(5.0, "")),
+            add_quantities(&quantity(2.0, ""), &quantity(3.0, ""))
+        );
+        assert_eq!(
+            Some(quantity(1.5, "tbsp")),
+            add_quantities(&quantity(1.0, "tbsp"), &quantity(1.5, "tsp"))
+        );
+        assert_eq!(
+            None,
+            add_quantities(&quantity(1.0, "cup"), &quantity(100.0, "g"))
+        );
+        assert_eq!(
+            None,
+            add_quantities(&quantity(1.0, "pinch"), &quantity(1.0, "tsp"))
+        );
+    }
+
+    #[test]
+    fn test_add_quantity_ranges() {
+        let range = |value: f64, value_max: f64, unit: &str| Quantity {
+            value_max: Some(value_max),
+            ..quantity(value, unit)
+        };
+        assert_eq!(
+            Some(range(4.0, 5.0, "clove")),
+            add_quantities(&range(1.0, 2.0, "clove"), &quantity(3.0, "clove"))
+        );
+        assert_eq!(
+            Some(range(2.0, 3.0, "tbsp")),
+            add_quantities(&quantity(1.0, "tbsp"), &range(3.0, 6.0, "tsp"))
+        );
+    }
+
+    fn assert_close(expected: f64, actual: f64) {
+        assert!(
+            (expected - actual).abs() < 1e-9,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn test_cups_ml_round_trip() {
+        let ml = convert(2.0, "cups", "ml").unwrap();
+        assert_close(473.176_473, ml);
+        assert_close(2.0, convert(ml, "ml", "cup").unwrap());
+    }
+
+    #[test]
+    fn test_oz_g_round_trip() {
+        let grams = convert(16.0, "oz", "g").unwrap();
+        assert_close(453.592_37, grams);
+        assert_close(16.0, convert(grams, "grams", "ounces").unwrap());
+    }
+
+    #[test]
+    fn test_incompatible_and_unknown_units() {
+        assert_eq!(None, convert(1.0, "cup", "g"));
+        assert_eq!(None, convert(1.0, "whole", "g"));
+    }
+
+    #[test]
+    fn test_known_units() {
+        assert!(is_known_unit("Tablespoons"));
+        assert!(is_known_unit("fl oz"));
+        assert!(is_known_unit("cloves"));
+        assert!(is_known_unit(""));
+        assert!(!is_known_unit("tablespon"));
+        assert!(!is_known_unit("gramms"));
+    }
+
+    #[test]
+    fn test_suggest_unit() {
+        assert_eq!(Some("tbsp"), suggest_unit("tablespon"));
+        assert_eq!(Some("g"), suggest_unit("gramms"));
+        assert_eq!(Some("clove"), suggest_unit("clov"));
+        assert_eq!(None, suggest_unit("fistful of"));
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(0, edit_distance("cup", "cup"));
+        assert_eq!(1, edit_distance("cup", "cups"));
+        assert_eq!(3, edit_distance("kitten", "sitting"));
+        assert_eq!(3, edit_distance("", "tsp"));
+    }
+
+    #[test]
+    fn test_convert_quantity() {
+        let cup = Quantity {
+            value: 1.0,
+            value_max: None,
+            unit: "Cup".to_string(),
+        };
+        assert_eq!(
+            Quantity {
+                value: 236.59,
+                value_max: None,
+                unit: "ml".to_string()
+            },
+            convert_quantity(&cup, UnitSystem::Metric)
+        );
+
+        let cups = Quantity {
+            value_max: Some(2.0),
+            ..cup.clone()
+        };
+        assert_eq!(
+            Quantity {
+                value: 236.59,
+                value_max: Some(473.18),
+                unit: "ml".to_string()
+            },
+            convert_quantity(&cups, UnitSystem::Metric)
+        );
+
+        let whole = Quantity {
+            value: 3.0,
+            value_max: None,
+            unit: "whole".to_string(),
+        };
+        assert_eq!(whole, convert_quantity(&whole, UnitSystem::Imperial));
+
+        let flour = Quantity {
+            value: 1.5,
+            value_max: None,
+            unit: "kg".to_string(),
+        };
+        assert_eq!(
+            Quantity {
+                value: 3.31,
+                value_max: None,
+                unit: "lb".to_string()
+            },
+            convert_quantity(&flour, UnitSystem::Imperial)
+        );
+    }
+}